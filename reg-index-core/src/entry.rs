@@ -0,0 +1,209 @@
+use anyhow::{Context, Error};
+use cargo_metadata::DependencyKind;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use url::Url;
+
+/// An entry for a single version of a package in the index.
+///
+/// Fields that are not known here, such as ones added by newer versions of
+/// Cargo, are kept in `other` and written back unchanged.
+#[derive(Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct IndexPackage {
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub vers: Version,
+    /// List of direct dependencies of the package.
+    pub deps: Vec<IndexDependency>,
+    /// Cargo features defined in the package.
+    pub features: BTreeMap<String, Vec<String>>,
+    /// Cargo features2 defined in the package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features2: Option<BTreeMap<String, Vec<String>>>,
+    /// Checksum of the `.crate` file.
+    pub cksum: String,
+    /// Whether or not this package is yanked.
+    pub yanked: bool,
+    /// Optional string that is the name of a native library the package is
+    /// linking to.
+    pub links: Option<String>,
+    /// Versioning of Index Package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v: Option<u8>,
+    /// Other fields in the entry, which are written after the known ones.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl IndexPackage {
+    /// Creates an entry with no dependencies or features that is not
+    /// yanked.
+    pub fn new(name: &str, vers: Version, cksum: &str) -> IndexPackage {
+        IndexPackage {
+            name: name.to_string(),
+            vers,
+            deps: Vec::new(),
+            features: BTreeMap::new(),
+            features2: None,
+            cksum: cksum.to_string(),
+            yanked: false,
+            links: None,
+            v: None,
+            other: BTreeMap::new(),
+        }
+    }
+
+    /// Parses a single line of a package file.
+    pub fn from_line(line: &str) -> Result<IndexPackage, serde_json::Error> {
+        serde_json::from_str(line)
+    }
+
+    /// Serializes the entry as a line of a package file, without the
+    /// trailing newline.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).expect("entries always serialize")
+    }
+}
+
+/// A dependency of a package.
+///
+/// As with [`IndexPackage`], unknown fields are kept in `other`.
+///
+/// [`IndexPackage`]: struct.IndexPackage.html
+#[derive(Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct IndexDependency {
+    /// Name of the dependency.
+    ///
+    /// If the dependency is renamed from the original package name,
+    /// this is the new name. The original package name is stored in
+    /// the `package` field.
+    pub name: String,
+    /// The semver requirement for this dependency.
+    pub req: VersionReq,
+    /// List of features enabled for this dependency.
+    pub features: Vec<String>,
+    /// Whether or not this is an optional dependency.
+    pub optional: bool,
+    /// Whether or not default features are enabled.
+    pub default_features: bool,
+    /// The target platform for the dependency.
+    pub target: Option<String>,
+    /// The dependency kind.
+    // Required, but crates.io has some broken missing entries.
+    #[serde(default, deserialize_with = "parse_dependency_kind")]
+    pub kind: DependencyKind,
+    /// The URL of the index of the registry where this dependency is from.
+    ///
+    /// If not specified or null, it is assumed the dependency is in the
+    /// current registry.
+    #[serde(default)]
+    pub registry: Option<Url>,
+    /// If the dependency is renamed, this is a string of the actual package
+    /// name. If None, this dependency is not renamed.
+    pub package: Option<String>,
+    /// For an artifact dependency, the kinds of artifacts to build, such as
+    /// `bin`, `cdylib`, `staticlib`, or `bin:name`.
+    ///
+    /// This is `None` for a regular dependency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<Vec<String>>,
+    /// For an artifact dependency, the target platform to build the
+    /// artifacts for, or `target` for the target of the package being built.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bindep_target: Option<String>,
+    /// For an artifact dependency, whether the library of the dependency is
+    /// also available to the package.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub lib: bool,
+    /// Other fields of the dependency, which are written after the known
+    /// ones.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl IndexDependency {
+    /// Creates a normal dependency from the same registry with default
+    /// features enabled.
+    pub fn new(name: &str, req: VersionReq) -> IndexDependency {
+        IndexDependency {
+            name: name.to_string(),
+            req,
+            features: Vec::new(),
+            optional: false,
+            default_features: true,
+            target: None,
+            kind: DependencyKind::Normal,
+            registry: None,
+            package: None,
+            artifact: None,
+            bindep_target: None,
+            lib: false,
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+fn parse_dependency_kind<'de, D>(d: D) -> Result<DependencyKind, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(d).map(|x: Option<_>| x.unwrap_or_default())
+}
+
+/// The configuration file of the index.
+///
+/// This is stored in the root of the index repo as `config.json`.
+#[derive(Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct IndexConfig {
+    /// URL that Cargo uses to download crates.
+    ///
+    /// This can have the markers `{crate}`, `{version}`, `{prefix}`,
+    /// `{lowerprefix}`, and `{sha256-checksum}`. If no markers are present, Cargo automatically appends
+    /// `/{crate}/{version}/download` to the end.
+    pub dl: Url,
+    /// URL that Cargo uses for the web API (publish/yank/search/etc.).
+    ///
+    /// This is optional. If not specified, Cargo will refuse to publish to
+    /// this registry.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<Url>,
+    /// Whether Cargo must send a token for every request to the registry,
+    /// including fetching the index and downloading crates.
+    ///
+    /// This is the `auth-required` field used by Cargo's registry
+    /// authentication.
+    #[serde(default, rename = "auth-required", skip_serializing_if = "is_false")]
+    pub auth_required: bool,
+}
+
+/// Parses the contents of a package file into its entries.
+///
+/// Blank lines are skipped. If `version_req` is set, only entries matching
+/// it are returned.
+pub fn parse_entries(
+    contents: &str,
+    version_req: Option<&VersionReq>,
+) -> Result<Vec<IndexPackage>, Error> {
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let pkg = IndexPackage::from_line(line)
+            .with_context(|| format!("Could not deserialize line {}:\n{}", i + 1, line))?;
+        if version_req.map_or(true, |req| req.matches(&pkg.vers)) {
+            entries.push(pkg);
+        }
+    }
+    Ok(entries)
+}
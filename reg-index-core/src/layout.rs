@@ -0,0 +1,35 @@
+use anyhow::{bail, Error};
+
+/// Path of the package file for a package, relative to the root of the
+/// index.
+///
+/// The components are always separated with `/`, which is also what a
+/// sparse index uses in its URLs. Returns an error if the name is not a
+/// valid package name (see [`validate_package_name`]), since anything else
+/// could build a path outside of the index.
+pub fn pkg_path(name: &str) -> Result<String, Error> {
+    validate_package_name(name, "package name")?;
+    let name = name.to_ascii_lowercase();
+    Ok(match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[0..2], &name[2..4], name),
+    })
+}
+
+/// Checks that a name only has ASCII letters, digits, `_`, and `-`.
+///
+/// `what` describes the name in the error, such as `"package name"`.
+pub fn validate_package_name(name: &str, what: &str) -> Result<(), Error> {
+    if name.is_empty() {
+        bail!("The {} cannot be empty.", what);
+    }
+    if let Some(ch) = name
+        .chars()
+        .find(|ch| !ch.is_ascii_alphanumeric() && *ch != '_' && *ch != '-')
+    {
+        bail!("Invalid character `{}` in {}: `{}`", ch, what, name);
+    }
+    Ok(())
+}
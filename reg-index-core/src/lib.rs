@@ -0,0 +1,40 @@
+#![warn(missing_docs)]
+
+/*!
+The data model of a Cargo registry index.
+
+These are the entry types and file layout used by [`reg-index`], without
+any filesystem, git, or process access. It builds for
+`wasm32-unknown-unknown`, so tools such as browser-based registry
+dashboards can read the same entries from index files fetched over HTTP.
+
+```rust
+# fn main() -> Result<(), anyhow::Error> {
+use reg_index_core::{parse_entries, pkg_path};
+
+// The URL of the package file in a sparse index.
+let url = format!("https://index.example.com/{}", pkg_path("serde").unwrap());
+assert_eq!(url, "https://index.example.com/se/rd/serde");
+
+let contents = r#"{"name":"serde","vers":"1.0.0","deps":[],"features":{},"cksum":"abc","yanked":false,"links":null}"#;
+let req = semver::VersionReq::parse("1.0")?;
+let entries = parse_entries(contents, Some(&req))?;
+assert_eq!(entries[0].vers.to_string(), "1.0.0");
+# Ok(())
+# }
+```
+
+See https://doc.rust-lang.org/cargo/reference/registry-index.html for
+documentation about the index format.
+
+[`reg-index`]: https://docs.rs/reg-index
+*/
+
+mod entry;
+mod layout;
+mod version;
+
+pub use cargo_metadata::DependencyKind;
+pub use entry::{parse_entries, IndexConfig, IndexDependency, IndexPackage};
+pub use layout::{pkg_path, validate_package_name};
+pub use version::BuildMetadataPolicy;
@@ -0,0 +1,31 @@
+use semver::Version;
+
+/// How build metadata, such as the `+abc` in `1.2.3+abc`, is treated when
+/// looking for a version in the index.
+///
+/// Cargo and crates.io ignore build metadata when comparing versions, so
+/// normally an index does not have two versions that only differ in build
+/// metadata.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildMetadataPolicy {
+    /// Versions that only differ in build metadata are the same version.
+    #[default]
+    Ignore,
+    /// Versions are only the same if their build metadata is the same.
+    Require,
+}
+
+impl BuildMetadataPolicy {
+    /// Whether two versions are the same under this policy.
+    pub fn eq(self, v1: &Version, v2: &Version) -> bool {
+        let same_release = v1.major == v2.major
+            && v1.minor == v2.minor
+            && v1.patch == v2.patch
+            && v1.pre == v2.pre;
+        match self {
+            BuildMetadataPolicy::Ignore => same_release,
+            BuildMetadataPolicy::Require => same_release && v1.build == v2.build,
+        }
+    }
+}
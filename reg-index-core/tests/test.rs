@@ -0,0 +1,96 @@
+use reg_index_core::{
+    parse_entries, pkg_path, BuildMetadataPolicy, DependencyKind, IndexDependency, IndexPackage,
+};
+use semver::{Version, VersionReq};
+
+#[test]
+fn test_pkg_path() {
+    assert_eq!(pkg_path("a").unwrap(), "1/a");
+    assert_eq!(pkg_path("ab").unwrap(), "2/ab");
+    assert_eq!(pkg_path("abc").unwrap(), "3/a/abc");
+    assert_eq!(pkg_path("Serde_Json").unwrap(), "se/rd/serde_json");
+    for name in [
+        "", "é", "aéb", "abcé", "..", "..ab", "....", "a/b", "ab\\cd", "a.b",
+    ] {
+        assert!(pkg_path(name).is_err(), "{:?}", name);
+    }
+}
+
+#[test]
+fn test_entries_round_trip() {
+    let mut pkg = IndexPackage::new("foo", Version::parse("0.1.0").unwrap(), "1234");
+    let mut dep = IndexDependency::new("bar", VersionReq::parse("^1.0").unwrap());
+    dep.kind = DependencyKind::Development;
+    pkg.deps.push(dep);
+    let line = pkg.to_line();
+    assert_eq!(
+        line,
+        "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[{\"name\":\"bar\",\"req\":\"^1.0\",\
+         \"features\":[],\"optional\":false,\"default_features\":true,\"target\":null,\
+         \"kind\":\"dev\",\"registry\":null,\"package\":null}],\"features\":{},\
+         \"cksum\":\"1234\",\"yanked\":false,\"links\":null}"
+    );
+
+    let mut yanked = IndexPackage::new("foo", Version::parse("0.2.0").unwrap(), "5678");
+    yanked.yanked = true;
+    let contents = format!("{}\n\n{}\n", line, yanked.to_line());
+    let entries = parse_entries(&contents, None).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].deps[0].kind, DependencyKind::Development);
+    assert!(entries[1].yanked);
+
+    let req = VersionReq::parse("0.2").unwrap();
+    let entries = parse_entries(&contents, Some(&req)).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].cksum, "5678");
+
+    let err = match parse_entries("{\"name\":\"foo\"}\n", None) {
+        Ok(_) => panic!("expected an error"),
+        Err(e) => e,
+    };
+    assert!(format!("{}", err).starts_with("Could not deserialize line 1:"));
+}
+
+#[test]
+fn test_artifact_dependency() {
+    let mut pkg = IndexPackage::new("foo", Version::parse("0.1.0").unwrap(), "1234");
+    let mut dep = IndexDependency::new("bar", VersionReq::parse("^1.0").unwrap());
+    dep.artifact = Some(vec!["bin".to_string(), "cdylib".to_string()]);
+    dep.bindep_target = Some("target".to_string());
+    dep.lib = true;
+    pkg.deps.push(dep);
+    let line = pkg.to_line();
+    assert!(line.contains(
+        "\"package\":null,\"artifact\":[\"bin\",\"cdylib\"],\"bindep_target\":\"target\",\"lib\":true}"
+    ));
+    let parsed = IndexPackage::from_line(&line).unwrap();
+    assert_eq!(
+        parsed.deps[0].artifact.as_deref(),
+        Some(&["bin".to_string(), "cdylib".to_string()][..])
+    );
+    assert_eq!(parsed.deps[0].bindep_target.as_deref(), Some("target"));
+    assert!(parsed.deps[0].lib);
+    assert_eq!(parsed.to_line(), line);
+}
+
+#[test]
+fn test_unknown_fields() {
+    let line = "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[{\"name\":\"bar\",\"req\":\"^1.0\",\
+                \"features\":[],\"optional\":false,\"default_features\":true,\"target\":null,\
+                \"kind\":\"normal\",\"registry\":null,\"package\":null,\"public\":true}],\
+                \"features\":{},\"cksum\":\"1234\",\"yanked\":false,\"links\":null,\
+                \"rust_version\":\"1.70\"}";
+    let pkg = IndexPackage::from_line(line).unwrap();
+    assert_eq!(pkg.other["rust_version"], "1.70");
+    assert_eq!(pkg.deps[0].other["public"], true);
+    assert_eq!(pkg.to_line(), line);
+}
+
+#[test]
+fn test_build_metadata_policy() {
+    let v1 = Version::parse("1.0.0+a").unwrap();
+    let v2 = Version::parse("1.0.0+b").unwrap();
+    assert!(BuildMetadataPolicy::Ignore.eq(&v1, &v2));
+    assert!(!BuildMetadataPolicy::Require.eq(&v1, &v2));
+    assert!(BuildMetadataPolicy::Require.eq(&v1, &v1));
+}
@@ -0,0 +1,70 @@
+use crate::registry::TestRegistry;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A builder for a `.cargo/config.toml` file.
+#[derive(Default)]
+pub struct CargoConfig {
+    result: Vec<String>,
+}
+
+impl CargoConfig {
+    /// Creates an empty configuration.
+    pub fn new() -> CargoConfig {
+        CargoConfig::default()
+    }
+
+    /// Adds the registry, so packages can depend on it with
+    /// `registry = "<name>"`.
+    ///
+    /// The table comes from [`reg_index::config_snippet`].
+    ///
+    /// [`reg_index::config_snippet`]: https://docs.rs/reg-index/*/reg_index/fn.config_snippet.html
+    pub fn registry(self, registry: &TestRegistry) -> Self {
+        self.snippet(registry, false)
+    }
+
+    /// Adds the registry and replaces crates.io with it, so packages
+    /// without a `registry` come from it.
+    ///
+    /// This includes the table added by [`registry`], so don't use both
+    /// with the same registry.
+    ///
+    /// [`registry`]: #method.registry
+    pub fn replace_crates_io(self, registry: &TestRegistry) -> Self {
+        self.snippet(registry, true)
+    }
+
+    /// Adds other configuration, as TOML.
+    pub fn raw(mut self, toml: &str) -> Self {
+        self.result.push(toml.to_string());
+        self
+    }
+
+    /// Writes the configuration to `.cargo/config.toml` in `dir`.
+    ///
+    /// Returns the path of the file.
+    pub fn write(self, dir: impl AsRef<Path>) -> PathBuf {
+        let dot_cargo = dir.as_ref().join(".cargo");
+        fs::create_dir_all(&dot_cargo)
+            .unwrap_or_else(|e| panic!("failed to create {:?}: {}", dot_cargo, e));
+        let path = dot_cargo.join("config.toml");
+        fs::write(&path, self.result.join("\n"))
+            .unwrap_or_else(|e| panic!("failed to write {:?}: {}", path, e));
+        path
+    }
+
+    fn snippet(mut self, registry: &TestRegistry, replace_crates_io: bool) -> Self {
+        let snippet = reg_index::config_snippet(
+            registry.index_path(),
+            registry.index_url(),
+            registry.name(),
+            replace_crates_io,
+        )
+        .unwrap_or_else(|e| panic!("failed to generate config: {:?}", e));
+        self.result.push(snippet);
+        self
+    }
+}
@@ -0,0 +1,38 @@
+#![warn(missing_docs)]
+
+/*!
+Throwaway Cargo registries for testing code built on [`reg-index`].
+
+A [`TestRegistry`] is a new index with a `file:` download location, in a
+temporary directory that is removed when it is dropped. Packages made with
+[`PackageBuilder`] can be added to it, and [`CargoConfig`] writes the Cargo
+configuration for projects that use it.
+
+```rust
+use reg_index_testkit::{CargoConfig, PackageBuilder, RegistryBuilder};
+
+let registry = RegistryBuilder::new().name("internal").build();
+let pkg = PackageBuilder::new("foo", "0.1.0")
+    .file("src/lib.rs", "pub fn foo() {}")
+    .build(registry.root());
+let entry = registry.add(&pkg);
+assert_eq!(entry.name, "foo");
+
+// Configure a project to use the registry with `registry = "internal"`.
+let project = registry.root().join("project");
+CargoConfig::new().registry(&registry).write(&project);
+```
+
+Functions panic if something fails, since they are meant to be used in
+tests.
+
+[`reg-index`]: https://docs.rs/reg-index
+*/
+
+mod config;
+mod package;
+mod registry;
+
+pub use config::CargoConfig;
+pub use package::{Package, PackageBuilder};
+pub use registry::{RegistryBuilder, TestRegistry};
@@ -0,0 +1,87 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A builder for a Cargo package on disk.
+pub struct PackageBuilder {
+    name: String,
+    version: String,
+    files: BTreeMap<PathBuf, String>,
+}
+
+/// A Cargo package created by [`PackageBuilder`].
+///
+/// [`PackageBuilder`]: struct.PackageBuilder.html
+pub struct Package {
+    path: PathBuf,
+}
+
+impl PackageBuilder {
+    /// Creates a builder for a package with the given name and version.
+    ///
+    /// Unless they are set with [`file`], the package has a minimal
+    /// `Cargo.toml` and an empty `src/lib.rs`.
+    ///
+    /// [`file`]: #method.file
+    pub fn new(name: &str, version: &str) -> PackageBuilder {
+        PackageBuilder {
+            name: name.to_string(),
+            version: version.to_string(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a file to the package, relative to the package root.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file was already added.
+    pub fn file(mut self, path: impl AsRef<Path>, body: &str) -> Self {
+        let path = path.as_ref().to_path_buf();
+        if self.files.insert(path.clone(), body.to_string()).is_some() {
+            panic!("{:?} is already set", path);
+        }
+        self
+    }
+
+    /// Writes the package to a `<name>-<version>` directory in `dir`.
+    pub fn build(mut self, dir: impl AsRef<Path>) -> Package {
+        let pkg_root = dir.as_ref().join(format!("{}-{}", self.name, self.version));
+        if !self.files.contains_key(Path::new("src/lib.rs"))
+            && !self.files.contains_key(Path::new("src/main.rs"))
+        {
+            self.files
+                .insert(PathBuf::from("src/lib.rs"), String::new());
+        }
+        if !self.files.contains_key(Path::new("Cargo.toml")) {
+            self.files.insert(
+                PathBuf::from("Cargo.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\nversion = \"{}\"\nedition = \"2021\"\n",
+                    self.name, self.version
+                ),
+            );
+        }
+        for (path, body) in self.files {
+            let abs = pkg_root.join(path);
+            fs::create_dir_all(abs.parent().unwrap())
+                .unwrap_or_else(|e| panic!("failed to create {:?}: {}", abs.parent(), e));
+            fs::write(&abs, body).unwrap_or_else(|e| panic!("failed to write {:?}: {}", abs, e));
+        }
+        Package { path: pkg_root }
+    }
+}
+
+impl Package {
+    /// The root directory of the package.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path to the `Cargo.toml` of the package.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.path.join("Cargo.toml")
+    }
+}
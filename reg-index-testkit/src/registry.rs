@@ -0,0 +1,154 @@
+use crate::package::{Package, PackageBuilder};
+use reg_index::{AddOptions, CommitOptions, IndexPackage, InitOptions, MetadataOptions};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use url::Url;
+
+/// A builder for a [`TestRegistry`].
+///
+/// [`TestRegistry`]: struct.TestRegistry.html
+pub struct RegistryBuilder {
+    name: String,
+    api: bool,
+}
+
+impl Default for RegistryBuilder {
+    fn default() -> RegistryBuilder {
+        RegistryBuilder::new()
+    }
+}
+
+impl RegistryBuilder {
+    /// Creates a builder for a registry named `test` without an API.
+    pub fn new() -> RegistryBuilder {
+        RegistryBuilder {
+            name: "test".to_string(),
+            api: false,
+        }
+    }
+
+    /// Sets the name that Cargo uses for the registry.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sets whether `config.json` has an `api` URL.
+    ///
+    /// The URL is a `file:` URL to an empty directory, so Cargo allows
+    /// `cargo publish` to be attempted but nothing serves it.
+    pub fn api(mut self, api: bool) -> Self {
+        self.api = api;
+        self
+    }
+
+    /// Creates the registry in a new temporary directory.
+    pub fn build(self) -> TestRegistry {
+        let tmp_dir = tempfile::tempdir().expect("failed to create temporary directory");
+        let root = tmp_dir.path();
+        let index_path = root.join("index");
+        let dl_path = root.join("dl");
+        std::fs::create_dir_all(&dl_path).expect("failed to create dl directory");
+        let mut dl = Url::from_file_path(&dl_path).unwrap().to_string();
+        dl.push_str("/{crate}/{crate}-{version}.crate");
+        let api = if self.api {
+            let api_path = root.join("api");
+            std::fs::create_dir_all(&api_path).expect("failed to create api directory");
+            Some(Url::from_file_path(&api_path).unwrap().to_string())
+        } else {
+            None
+        };
+        let options = InitOptions::default().commit(commit_options());
+        reg_index::init_with_options(&index_path, &dl, api.as_deref(), &options)
+            .unwrap_or_else(|e| panic!("failed to initialize index: {:?}", e));
+        TestRegistry {
+            name: self.name,
+            index_url: Url::from_file_path(&index_path).unwrap().to_string(),
+            index_path,
+            dl_path,
+            tmp_dir,
+        }
+    }
+}
+
+/// A registry index in a temporary directory.
+///
+/// The `.crate` files are stored in a `dl` directory next to the index,
+/// and downloaded with a `file:` URL. Everything is removed when this is
+/// dropped.
+pub struct TestRegistry {
+    name: String,
+    index_path: PathBuf,
+    index_url: String,
+    dl_path: PathBuf,
+    tmp_dir: TempDir,
+}
+
+impl TestRegistry {
+    /// The temporary directory that holds the registry.
+    ///
+    /// Other files for a test, such as packages, can be placed here so they
+    /// are removed with the registry.
+    pub fn root(&self) -> &Path {
+        self.tmp_dir.path()
+    }
+
+    /// The name that Cargo uses for the registry.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Path to the index.
+    pub fn index_path(&self) -> &Path {
+        &self.index_path
+    }
+
+    /// The `file:` URL of the index.
+    pub fn index_url(&self) -> &str {
+        &self.index_url
+    }
+
+    /// Path to the directory where `.crate` files are stored.
+    pub fn dl_path(&self) -> &Path {
+        &self.dl_path
+    }
+
+    /// The `upload` path for the [`add`] family of functions that places
+    /// `.crate` files where the registry downloads them from.
+    ///
+    /// [`add`]: https://docs.rs/reg-index/*/reg_index/fn.add.html
+    pub fn upload(&self) -> String {
+        self.dl_path.join("{crate}").to_str().unwrap().to_string()
+    }
+
+    /// Adds a package to the index with [`reg_index::add_with_options`].
+    ///
+    /// The `.crate` file is placed in [`dl_path`]. Dependencies from this
+    /// registry must already be in the index.
+    ///
+    /// [`reg_index::add_with_options`]: https://docs.rs/reg-index/*/reg_index/fn.add_with_options.html
+    /// [`dl_path`]: #method.dl_path
+    pub fn add(&self, pkg: &Package) -> IndexPackage {
+        let options = AddOptions::default()
+            .manifest_path(pkg.manifest_path())
+            .upload(self.upload())
+            .package_args(["--allow-dirty"])
+            .metadata(MetadataOptions::default().commit(commit_options()));
+        reg_index::add_with_options(&self.index_path, &self.index_url, &options)
+            .unwrap_or_else(|e| panic!("failed to add `{}`: {:?}", pkg.path().display(), e))
+    }
+
+    /// Creates a package with no dependencies and adds it to the index.
+    pub fn add_package(&self, name: &str, version: &str) -> IndexPackage {
+        let pkg = PackageBuilder::new(name, version).build(self.root());
+        self.add(&pkg)
+    }
+}
+
+/// Commits are made with a fixed identity, since the git config of the
+/// machine running the tests may not have one.
+fn commit_options() -> CommitOptions {
+    CommitOptions::default()
+        .name("Test Registry")
+        .email("test-registry@example.com")
+}
@@ -0,0 +1,45 @@
+use reg_index_testkit::{CargoConfig, PackageBuilder, RegistryBuilder};
+use std::fs;
+
+#[test]
+fn test_registry() {
+    let registry = RegistryBuilder::new().name("internal").build();
+    let config = CargoConfig::new()
+        .registry(&registry)
+        .write(registry.root());
+    assert_eq!(
+        fs::read_to_string(config).unwrap(),
+        format!(
+            "[registries.internal]\n\
+             index = \"{}\"\n\
+             # This index has no `api` in config.json, so `cargo publish` will not work.\n",
+            registry.index_url()
+        )
+    );
+    let foo = registry.add_package("foo", "0.1.0");
+    assert_eq!(foo.vers.to_string(), "0.1.0");
+    assert!(registry.dl_path().join("foo/foo-0.1.0.crate").exists());
+
+    let bar = PackageBuilder::new("bar", "1.0.0")
+        .file(
+            "Cargo.toml",
+            "[package]\n\
+             name = \"bar\"\n\
+             version = \"1.0.0\"\n\
+             \n\
+             [dependencies]\n\
+             foo = { version = \"0.1\", registry = \"internal\" }\n",
+        )
+        .build(registry.root());
+    let entry = registry.add(&bar);
+    assert_eq!(entry.deps[0].name, "foo");
+    let version = reg_index::selftest(
+        registry.index_path(),
+        registry.index_url(),
+        "bar",
+        None,
+        true,
+    )
+    .unwrap();
+    assert_eq!(version.to_string(), "1.0.0");
+}
@@ -1,14 +1,30 @@
 use crate::{
+    lfs::{self, LfsObject, LFS_CONFIG_FILES, LFS_PREFIX},
     list::_list,
+    load_config,
     lock::Lock,
-    metadata::{metadata_reg, MetaInfo},
-    util::{extract_crate, pkg_path, signature},
-    IndexPackage,
+    metadata::{metadata_from_crate_with_options, metadata_reg, MetaInfo, MetadataOptions},
+    settings::PackageSettings,
+    store::{fetch, store_for, CrateStore},
+    util::{
+        cksum, crate_walker, dl_url, extract_crate, git_commit, normalize_name, open_repo,
+        pkg_path, worktree_status, CommitOptions,
+    },
+    validate::validate_package_name,
+    BuildMetadataPolicy, DependencyKind, IndexError, IndexPackage,
 };
 use anyhow::{bail, Context, Error};
-use semver::{Comparator, Op, VersionReq};
 use std::fs::File;
-use std::{fs, io::Write, path::Path};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt, fs,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+use url::Url;
 
 /// Add a new entry to the index.
 ///
@@ -25,8 +41,15 @@ use std::{fs, io::Write, path::Path};
 /// pre-existing `.crate` file.
 ///
 /// `upload` is an optional path to a directory to copy the `.crate` file to
-/// after it has been added to the index. It may contain `{crate}` and
-/// `{version}` markers.
+/// after it has been added to the index. It may contain `{crate}`,
+/// `{version}`, `{prefix}`, `{lowerprefix}`, and `{sha256-checksum}`
+/// markers, the same as the `dl` setting in `config.json`. If `upload`
+/// starts with `lfs:`, the rest is a path within the index, and the `.crate`
+/// file is committed to the index with Git LFS. Its first directory can't
+/// have markers, and is added to `.gitattributes` and `.indexignore`.
+/// An `http://` or `https://` URL uploads the file with an HTTP PUT using
+/// `curl`, and an `s3://bucket/path` URL uploads it with `aws s3 cp`, see
+/// [`UPLOAD_AUTHORIZATION_ENV`] for credentials.
 ///
 /// This only performs minimal validity checks on the crate. Callers should
 /// consider adding more validation before calling. For example, placing
@@ -34,7 +57,15 @@ use std::{fs, io::Write, path::Path};
 /// set, limit category names, etc. See the [crates.io code] for examples
 /// of the many checks it applies.
 ///
+/// See [`add_with_options`] to change how the entry is generated with
+/// [`MetadataOptions`]. A package can also have its own
+/// [`PackageSettings`] in its manifest.
+///
 /// [`add_from_crate`]: fn.add_from_crate.html
+/// [`add_with_options`]: fn.add_with_options.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
+/// [`PackageSettings`]: struct.PackageSettings.html
+/// [`UPLOAD_AUTHORIZATION_ENV`]: constant.UPLOAD_AUTHORIZATION_ENV.html
 /// [crates.io code]: https://github.com/rust-lang/crates.io
 pub fn add(
     index_path: impl AsRef<Path>,
@@ -43,13 +74,17 @@ pub fn add(
     upload: Option<&str>,
     package_args: Option<&Vec<String>>,
 ) -> Result<IndexPackage, Error> {
+    let index_path = index_path.as_ref();
     add_reg(
         index_path,
+        open_repo(index_path)?.as_ref(),
         index_url,
         manifest_path,
         None,
         upload,
+        None,
         package_args,
+        &MetadataOptions::default(),
     )
 }
 
@@ -68,150 +103,778 @@ pub fn force_add(
     upload: Option<&str>,
     package_args: Option<&Vec<String>>,
 ) -> Result<IndexPackage, Error> {
+    let index_path = index_path.as_ref();
     force_add_reg(
         index_path,
+        open_repo(index_path)?.as_ref(),
         index_url,
         manifest_path,
         None,
         upload,
+        None,
         package_args,
+        &MetadataOptions::default(),
+        None,
     )
 }
 
-pub(crate) fn add_reg(
+/// Options for [`add_with_options`], which select what to add and how.
+///
+/// New options may be added in future versions, so create this with
+/// `AddOptions::default()` and set the fields that are needed, such as with
+/// the setter methods:
+///
+/// ```rust
+/// let options = reg_index::AddOptions::default()
+///     .manifest_path("foo/Cargo.toml")
+///     .upload("/srv/crates")
+///     .force(true);
+/// assert!(options.force);
+/// ```
+///
+/// [`add_with_options`]: fn.add_with_options.html
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct AddOptions {
+    /// Path to the `Cargo.toml` of the package to add.
+    ///
+    /// If neither this nor `crate_file` is set, the current directory is
+    /// searched for the manifest.
+    pub manifest_path: Option<PathBuf>,
+    /// A `.crate` file to add, instead of running `cargo package`.
+    ///
+    /// This can't be used with `manifest_path` or `package_args`.
+    pub crate_file: Option<PathBuf>,
+    /// A directory or URL to copy the `.crate` file to, as described in
+    /// [`add`].
+    ///
+    /// [`add`]: fn.add.html
+    pub upload: Option<String>,
+    /// Where to put the `.crate` file, instead of `upload`.
+    ///
+    /// The `upload-subpath` setting of a package does not apply to it.
+    pub store: Option<Arc<dyn CrateStore>>,
+    /// If `true`, overwrite the existing entry for the version, as
+    /// [`force_add`] does.
+    ///
+    /// [`force_add`]: fn.force_add.html
+    pub force: bool,
+    /// Arguments passed as-is to `cargo package`.
+    pub package_args: Option<Vec<String>>,
+    /// Settings for how the entry is generated and committed.
+    pub metadata: MetadataOptions,
+    /// Called by a forced add that replaces an existing entry, with the
+    /// existing entry and the new one, before anything is changed.
+    /// Returning an error stops the add.
+    ///
+    /// This is called while the index is locked, so the entry that is
+    /// replaced is the one that was passed.
+    pub confirm_replace: Option<Arc<ConfirmReplace>>,
+}
+
+/// The type of [`AddOptions::confirm_replace`].
+///
+/// [`AddOptions::confirm_replace`]: struct.AddOptions.html#structfield.confirm_replace
+pub type ConfirmReplace = dyn Fn(&IndexPackage, &IndexPackage) -> Result<(), Error> + Send + Sync;
+
+impl fmt::Debug for AddOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AddOptions")
+            .field("manifest_path", &self.manifest_path)
+            .field("crate_file", &self.crate_file)
+            .field("upload", &self.upload)
+            .field("store", &self.store)
+            .field("force", &self.force)
+            .field("package_args", &self.package_args)
+            .field("metadata", &self.metadata)
+            .field("confirm_replace", &self.confirm_replace.is_some())
+            .finish()
+    }
+}
+
+impl AddOptions {
+    /// Sets the path to the `Cargo.toml` of the package to add.
+    pub fn manifest_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest_path = Some(path.into());
+        self
+    }
+
+    /// Sets the `.crate` file to add.
+    pub fn crate_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.crate_file = Some(path.into());
+        self
+    }
+
+    /// Sets the directory or URL to copy the `.crate` file to.
+    pub fn upload(mut self, upload: impl Into<String>) -> Self {
+        self.upload = Some(upload.into());
+        self
+    }
+
+    /// Sets where to put the `.crate` file, instead of `upload`.
+    pub fn store(mut self, store: Arc<dyn CrateStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sets whether to overwrite the existing entry for the version.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Sets the arguments passed as-is to `cargo package`.
+    pub fn package_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.package_args = Some(args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets how the entry is generated and committed.
+    pub fn metadata(mut self, metadata: MetadataOptions) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the function called before a forced add replaces an entry.
+    pub fn confirm_replace(
+        mut self,
+        f: impl Fn(&IndexPackage, &IndexPackage) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.confirm_replace = Some(Arc::new(f));
+        self
+    }
+}
+
+/// Add a new entry to the index, as selected by `options`.
+///
+/// This is the same as [`add`], [`force_add`], or [`add_from_crate`],
+/// depending on the options, and takes new settings without changing its
+/// signature.
+///
+/// [`add`]: fn.add.html
+/// [`force_add`]: fn.force_add.html
+/// [`add_from_crate`]: fn.add_from_crate.html
+pub fn add_with_options(
     index_path: impl AsRef<Path>,
     index_url: &str,
-    manifest_path: Option<&Path>,
-    crate_path: Option<&Path>,
-    upload: Option<&str>,
-    package_args: Option<&Vec<String>>,
+    options: &AddOptions,
 ) -> Result<IndexPackage, Error> {
-    let meta_info = metadata_reg(index_url, manifest_path, crate_path, package_args)?;
-    let index_pkg = meta_info.index_pkg;
     let index_path = index_path.as_ref();
-    let matching_pkgs = _list(
+    _add_with_options(
         index_path,
-        &index_pkg.name,
-        Some(&VersionReq {
-            comparators: vec![Comparator {
-                op: Op::Exact,
-                major: index_pkg.vers.major,
-                minor: Some(index_pkg.vers.minor),
-                patch: Some(index_pkg.vers.patch),
-                pre: index_pkg.vers.pre.clone(),
-            }],
-        }),
+        open_repo(index_path)?.as_ref(),
+        index_url,
+        options,
+    )
+}
+
+/// [`add_with_options`] with a repository that is already open.
+///
+/// [`add_with_options`]: fn.add_with_options.html
+pub(crate) fn _add_with_options(
+    index_path: &Path,
+    repo: Option<&git2::Repository>,
+    index_url: &str,
+    options: &AddOptions,
+) -> Result<IndexPackage, Error> {
+    let upload = options.upload.as_deref();
+    if upload.is_some() && options.store.is_some() {
+        bail!("Only one of `upload` and `store` can be set.");
+    }
+    let store = options.store.as_deref();
+    let (_tmp_dir, manifest_path, crate_path, package_args) = match &options.crate_file {
+        Some(crate_path) => {
+            if options.manifest_path.is_some() || options.package_args.is_some() {
+                bail!("A `.crate` file can't be added with a manifest path or package arguments.");
+            }
+            let (tmp_dir, pkg_path) = extract_crate(crate_path)?;
+            (
+                Some(tmp_dir),
+                Some(pkg_path.join("Cargo.toml")),
+                Some(crate_path.as_path()),
+                None,
+            )
+        }
+        None => (
+            None,
+            options.manifest_path.clone(),
+            None,
+            options.package_args.as_ref(),
+        ),
+    };
+    if options.force {
+        force_add_reg(
+            index_path,
+            repo,
+            index_url,
+            manifest_path.as_deref(),
+            crate_path,
+            upload,
+            store,
+            package_args,
+            &options.metadata,
+            options.confirm_replace.as_deref(),
+        )
+    } else {
+        add_reg(
+            index_path,
+            repo,
+            index_url,
+            manifest_path.as_deref(),
+            crate_path,
+            upload,
+            store,
+            package_args,
+            &options.metadata,
+        )
+    }
+}
+
+/// Check that the index has an up-to-date entry for a package.
+///
+/// This generates the entry the same as [`add`], but instead of changing the
+/// index, returns an error if the version is not in the index, or if the
+/// entry in the index is different, such as having a different checksum.
+/// The yanked status is not compared. Returns the entry in the index.
+///
+/// This can be used in CI to verify that the index reflects the source.
+/// See [`check_from_crate`] for a variant that takes a `.crate` file.
+///
+/// [`add`]: fn.add.html
+/// [`check_from_crate`]: fn.check_from_crate.html
+pub fn check(
+    index_path: impl AsRef<Path>,
+    index_url: &str,
+    manifest_path: Option<&Path>,
+    package_args: Option<&Vec<String>>,
+    options: &MetadataOptions,
+) -> Result<IndexPackage, Error> {
+    let meta_info = metadata_reg(index_url, manifest_path, None, package_args, options)?;
+    check_reg(index_path.as_ref(), &meta_info.index_pkg, options)
+}
+
+/// Check that the index has an up-to-date entry for a `.crate` file.
+///
+/// See [`check`] for more details.
+///
+/// [`check`]: fn.check.html
+pub fn check_from_crate(
+    index_path: impl AsRef<Path>,
+    index_url: &str,
+    crate_path: impl AsRef<Path>,
+    options: &MetadataOptions,
+) -> Result<IndexPackage, Error> {
+    let crate_path = crate_path.as_ref();
+    let (_tmp_dir, pkg_path) = extract_crate(crate_path)?;
+    let meta_info = metadata_reg(
+        index_url,
+        Some(&pkg_path.join("Cargo.toml")),
+        Some(crate_path),
+        None,
+        options,
     )?;
-    if !matching_pkgs.is_empty() {
-        bail!(
-            "Package `{}` version `{}` is already in the index.",
+    check_reg(index_path.as_ref(), &meta_info.index_pkg, options)
+}
+
+fn check_reg(
+    index_path: &Path,
+    index_pkg: &IndexPackage,
+    options: &MetadataOptions,
+) -> Result<IndexPackage, Error> {
+    let lock = Lock::new_shared(index_path)?;
+    let existing = find_version(index_path, index_pkg, options.build_metadata)?
+        .into_iter()
+        .next();
+    drop(lock);
+    let mut existing = match existing {
+        Some(existing) => existing,
+        None => bail!(
+            "Package `{}` version `{}` is not in the index.",
             index_pkg.name,
             index_pkg.vers
+        ),
+    };
+    if existing.cksum != index_pkg.cksum {
+        bail!(
+            "Checksum for package `{}` version `{}` does not match the index.\n\
+             index: {}\nactual: {}",
+            index_pkg.name,
+            index_pkg.vers,
+            existing.cksum,
+            index_pkg.cksum
+        );
+    }
+    let yanked = existing.yanked;
+    existing.yanked = index_pkg.yanked;
+    if serde_json::to_string(&existing)? != serde_json::to_string(index_pkg)? {
+        bail!(
+            "Entry for package `{}` version `{}` does not match the index.\n\
+             index: {}\nactual: {}",
+            index_pkg.name,
+            index_pkg.vers,
+            serde_json::to_string(&existing)?,
+            serde_json::to_string(index_pkg)?
         );
     }
+    existing.yanked = yanked;
+    Ok(existing)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn add_reg(
+    index_path: &Path,
+    repo: Option<&git2::Repository>,
+    index_url: &str,
+    manifest_path: Option<&Path>,
+    crate_path: Option<&Path>,
+    upload: Option<&str>,
+    store: Option<&dyn CrateStore>,
+    package_args: Option<&Vec<String>>,
+    options: &MetadataOptions,
+) -> Result<IndexPackage, Error> {
+    let meta_info = metadata_reg(index_url, manifest_path, crate_path, package_args, options)?;
+    let settings = &meta_info.settings;
+    if options.semver_checks || settings.semver_checks {
+        semver_checks(
+            index_path,
+            manifest_path,
+            &meta_info.index_pkg,
+            settings.upload(upload).as_deref(),
+            store,
+        )?;
+    }
     update_crate_index(
         index_path,
-        index_url,
-        manifest_path,
-        crate_path,
+        repo,
+        meta_info,
         upload,
-        package_args,
+        store,
+        true,
+        &options.commit,
+        |index_path, index_pkg, settings| {
+            check_new_version(index_path, index_pkg, options.build_metadata)?;
+            if !options.allow_name_conflicts {
+                check_name_conflict(index_path, &index_pkg.name)?;
+            }
+            if options.require_clean {
+                check_clean(index_path)?;
+            }
+            if options.require_newer || settings.require_newer {
+                check_newer(index_path, index_pkg)?;
+            }
+            Ok(())
+        },
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn force_add_reg(
-    index_path: impl AsRef<Path>,
+    index_path: &Path,
+    repo: Option<&git2::Repository>,
     index_url: &str,
     manifest_path: Option<&Path>,
     crate_path: Option<&Path>,
     upload: Option<&str>,
+    store: Option<&dyn CrateStore>,
     package_args: Option<&Vec<String>>,
+    options: &MetadataOptions,
+    confirm: Option<&ConfirmReplace>,
 ) -> Result<IndexPackage, Error> {
+    let meta_info = metadata_reg(index_url, manifest_path, crate_path, package_args, options)?;
     update_crate_index(
         index_path,
-        index_url,
-        manifest_path,
-        crate_path,
+        repo,
+        meta_info,
         upload,
-        package_args,
+        store,
+        false,
+        &options.commit,
+        |index_path, index_pkg, _| {
+            if let Some(confirm) = confirm {
+                // The same entry that `write_entry` replaces.
+                let existing = _list(index_path, &index_pkg.name, None)?
+                    .into_iter()
+                    .find(|pkg| pkg.vers == index_pkg.vers);
+                if let Some(existing) = existing {
+                    confirm(&existing, index_pkg)?;
+                }
+            }
+            Ok(())
+        },
     )
 }
 
-fn update_crate_index(
-    index_path: impl AsRef<Path>,
-    index_url: &str,
+/// Returns an error if the version of the package is already in the index.
+pub(crate) fn check_new_version(
+    index_path: &Path,
+    index_pkg: &IndexPackage,
+    build_metadata: BuildMetadataPolicy,
+) -> Result<(), Error> {
+    let matching_pkgs = find_version(index_path, index_pkg, build_metadata)?;
+    if !matching_pkgs.is_empty() {
+        return Err(IndexError::VersionAlreadyExists {
+            name: index_pkg.name.clone(),
+            version: index_pkg.vers.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns an error if the working tree of the index differs from `HEAD`.
+fn check_clean(index_path: &Path) -> Result<(), Error> {
+    let problems = worktree_status(index_path)?;
+    if !problems.is_empty() {
+        bail!(
+            "The index at `{}` has uncommitted changes:\n{}",
+            index_path.display(),
+            problems.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// The most `-` and `_` characters in a name for which every spelling is
+/// looked up, instead of reading the names of all packages.
+const MAX_SEPARATORS: usize = 8;
+
+/// Returns an error if another package in the index has a name that differs
+/// from `name` only by `-` and `_` or by case.
+fn check_name_conflict(index_path: &Path, name: &str) -> Result<(), Error> {
+    let lower = name.to_lowercase();
+    let separators: Vec<usize> = lower.match_indices(['-', '_']).map(|(i, _)| i).collect();
+    let candidates: Vec<String> = if separators.len() <= MAX_SEPARATORS {
+        (0..1u32 << separators.len())
+            .map(|bits| {
+                let mut candidate = lower.clone().into_bytes();
+                for (bit, &i) in separators.iter().enumerate() {
+                    candidate[i] = if bits & (1 << bit) == 0 { b'-' } else { b'_' };
+                }
+                String::from_utf8(candidate).unwrap()
+            })
+            .collect()
+    } else {
+        let normalized = normalize_name(name);
+        crate_walker(index_path)
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+            .filter(|file_name| normalize_name(file_name) == normalized)
+            .collect()
+    };
+    for candidate in candidates {
+        if let Some(existing) = _list(index_path, &candidate, None)?.first() {
+            if existing.name != name {
+                return Err(IndexError::NameConflict {
+                    name: name.to_string(),
+                    existing: existing.name.clone(),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if the index has a version of the package that is the
+/// same or newer than the given package.
+fn check_newer(index_path: &Path, index_pkg: &IndexPackage) -> Result<(), Error> {
+    let pkgs = _list(index_path, &index_pkg.name, None)?;
+    let max = pkgs
+        .iter()
+        .map(|pkg| &pkg.vers)
+        .max_by(|a, b| a.cmp_precedence(b));
+    if let Some(max) = max {
+        if index_pkg.vers.cmp_precedence(max) != Ordering::Greater {
+            bail!(
+                "Package `{}` version `{}` is not newer than version `{}` in the index.",
+                index_pkg.name,
+                index_pkg.vers,
+                max
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs `cargo semver-checks` to compare the package with the newest
+/// non-yanked version in the index that is older than it.
+///
+/// The `.crate` file of the older version is found in the `upload`
+/// directory if set, otherwise at the `dl` location if it is a `file:` URL.
+fn semver_checks(
+    index_path: &Path,
     manifest_path: Option<&Path>,
-    crate_path: Option<&Path>,
+    index_pkg: &IndexPackage,
     upload: Option<&str>,
-    package_args: Option<&Vec<String>>,
+    store: Option<&dyn CrateStore>,
+) -> Result<(), Error> {
+    let pkgs = _list(index_path, &index_pkg.name, None)?;
+    let baseline = pkgs
+        .iter()
+        .filter(|pkg| !pkg.yanked && pkg.vers.cmp_precedence(&index_pkg.vers) == Ordering::Less)
+        .max_by(|a, b| a.vers.cmp_precedence(&b.vers));
+    let baseline = match baseline {
+        Some(baseline) => baseline,
+        None => return Ok(()),
+    };
+    let download_dir = tempfile::tempdir()?;
+    let crate_path = match (store, upload) {
+        (Some(store), _) => fetch(store, baseline, download_dir.path()).ok(),
+        (None, Some(upload)) if upload.starts_with(LFS_PREFIX) => Some(
+            lfs::upload_dir(index_path, upload, baseline)
+                .join(format!("{}-{}.crate", baseline.name, baseline.vers)),
+        ),
+        (None, Some(upload)) => fetch(&*store_for(upload), baseline, download_dir.path()).ok(),
+        (None, None) => {
+            let config = load_config(index_path)?;
+            Url::parse(&dl_url(config.dl.as_str(), baseline))
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+        }
+    };
+    let crate_path = match crate_path {
+        Some(path) if path.exists() => path,
+        _ => bail!(
+            "Could not find the `.crate` file for `{}:{}` to compare with using \
+             `cargo semver-checks`, use `--upload` to specify where `.crate` files are stored.",
+            baseline.name,
+            baseline.vers
+        ),
+    };
+    let installed = Command::new("cargo")
+        .args(["semver-checks", "--version"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !installed {
+        bail!(
+            "`cargo semver-checks` is not installed, \
+             install it with `cargo install cargo-semver-checks`."
+        );
+    }
+    let (_tmp_dir, baseline_dir) = extract_crate(&crate_path)?;
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "semver-checks",
+        "check-release",
+        "--package",
+        &index_pkg.name,
+    ])
+    .arg("--baseline-root")
+    .arg(&baseline_dir);
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    let status = cmd
+        .status()
+        .with_context(|| "Could not run `cargo semver-checks`.")?;
+    if !status.success() {
+        bail!(
+            "`cargo semver-checks` failed comparing `{}:{}` with version `{}`.",
+            index_pkg.name,
+            index_pkg.vers,
+            baseline.vers
+        );
+    }
+    Ok(())
+}
+
+/// Entries in the index with the same version as the given package.
+fn find_version(
+    index_path: &Path,
+    index_pkg: &IndexPackage,
+    build_metadata: BuildMetadataPolicy,
+) -> Result<Vec<IndexPackage>, Error> {
+    let mut pkgs = _list(index_path, &index_pkg.name, None)?;
+    pkgs.retain(|pkg| build_metadata.eq(&pkg.vers, &index_pkg.vers));
+    Ok(pkgs)
+}
+
+/// Write the entry to the index and commit it.
+///
+/// `check` is called while the index is locked, so that checks of what is
+/// already in the index, such as whether the version exists, can't race
+/// with another process adding the same entry. If `append` is true, `check`
+/// must verify that this version is not in the index, and the entry is
+/// appended to the end of the file without touching the existing lines.
+#[allow(clippy::too_many_arguments)]
+fn update_crate_index(
+    index_path: &Path,
+    repo: Option<&git2::Repository>,
+    meta_info: MetaInfo,
+    upload: Option<&str>,
+    store: Option<&dyn CrateStore>,
+    append: bool,
+    commit: &CommitOptions,
+    check: impl FnOnce(&Path, &IndexPackage, &PackageSettings) -> Result<(), Error>,
 ) -> Result<IndexPackage, Error> {
     let MetaInfo {
         index_pkg,
         crate_path,
-    } = metadata_reg(index_url, manifest_path, crate_path, package_args)?;
+        settings,
+    } = meta_info;
+    settings.check_reviewers(&index_pkg.name, commit)?;
+    let upload = settings.upload(upload);
+    let upload = upload.as_deref();
+    let mut commit = commit.clone();
+    commit.trailers.extend(settings.trailers(&index_pkg.name));
     // Add to git repo.
-    let index_path = index_path.as_ref();
-    let repo = git2::Repository::open(index_path)
-        .with_context(|| format!("Could not open index at `{}`.", index_path.display()))?;
     let lock = Lock::new_exclusive(index_path)?;
-    let all_pkg_vers = _list(index_path, &index_pkg.name, None)?;
-    let pkg_vers_exists = all_pkg_vers
-        .iter()
-        .any(|pkg_vers| pkg_vers.vers == index_pkg.vers);
+    check(index_path, &index_pkg, &settings)?;
+    if !settings.skip_dep_check {
+        check_deps(index_path, &index_pkg)?;
+    }
+    lfs::check_upload(upload)?;
+    // Upload first, so a failed upload doesn't leave an entry behind.
+    let lfs_object = upload_crate(index_path, &index_pkg, &crate_path, upload, store)?;
+    let repo_path = write_entry(index_path, &index_pkg, append)?;
+    let msg = format!("Updating crate `{}#{}`", index_pkg.name, index_pkg.vers);
+    let mut paths = vec![repo_path];
+    if let Some(lfs_object) = lfs_object {
+        lfs::stage(repo, &[lfs_object])?;
+        paths.extend(LFS_CONFIG_FILES.iter().map(PathBuf::from));
+    }
+    git_add(repo, &paths, &msg, &commit).with_context(|| "Failed to add to git repo.")?;
+    drop(lock);
+    Ok(index_pkg)
+}
+
+/// Returns an error if a dependency from the same registry is not in the
+/// index.
+pub(crate) fn check_deps(index_path: &Path, index_pkg: &IndexPackage) -> Result<(), Error> {
+    check_deps_with(index_path, index_pkg, &[])
+}
+
+/// [`check_deps`], where a dependency may also be one of `pending`, which
+/// are about to be added with it.
+fn check_deps_with(
+    index_path: &Path,
+    index_pkg: &IndexPackage,
+    pending: &[&IndexPackage],
+) -> Result<(), Error> {
     for dep in &index_pkg.deps {
         if dep.registry.is_none() {
             let dep_name = dep.package.as_ref().unwrap_or(&dep.name);
-            let matching_deps = _list(index_path, dep_name, Some(&dep.req))?;
-            if matching_deps.is_empty() {
-                bail!(
-                    "Package `{}` dependency `{}:{}` not found in index.",
-                    index_pkg.name,
-                    dep_name,
-                    dep.req
-                );
+            let in_pending = pending
+                .iter()
+                .any(|pkg| pkg.name.eq_ignore_ascii_case(dep_name) && dep.req.matches(&pkg.vers));
+            if !in_pending && _list(index_path, dep_name, Some(&dep.req))?.is_empty() {
+                return Err(IndexError::DependencyMissing {
+                    name: index_pkg.name.clone(),
+                    dependency: dep_name.clone(),
+                    req: dep.req.to_string(),
+                }
+                .into());
             }
         }
     }
-    let repo_path = pkg_path(&index_pkg.name);
+    Ok(())
+}
+
+/// Write the entry to its package file.
+///
+/// If `append` is false, an entry with exactly the same version, including
+/// build metadata, is replaced. Returns the repo-relative path of the file
+/// that was written. The caller is responsible for locking, checking
+/// dependencies, and committing.
+pub(crate) fn write_entry(
+    index_path: &Path,
+    index_pkg: &IndexPackage,
+    append: bool,
+) -> Result<PathBuf, Error> {
+    let repo_path = pkg_path(&index_pkg.name)?;
     let path = index_path.join(&repo_path);
     let dir_path = path.parent().unwrap();
-    fs::create_dir_all(&dir_path)
+    fs::create_dir_all(dir_path)
         .with_context(|| format!("Failed to create directory `{}`.", dir_path.display()))?;
-    let mut f = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&path)
-        .with_context(|| format!("Failed to create or open `{}`.", path.display()))?;
+    if append {
+        append_index_pkg(&path, index_pkg)?;
+    } else {
+        let all_pkg_vers = _list(index_path, &index_pkg.name, None)?;
+        // `Version` equality includes the build metadata.
+        let pkg_vers_count = all_pkg_vers
+            .iter()
+            .filter(|pkg_vers| pkg_vers.vers == index_pkg.vers)
+            .count();
+        if pkg_vers_count > 1 {
+            bail!(
+                "Version `{}` for package `{}` found multiple times, is the index corrupt?",
+                index_pkg.vers,
+                index_pkg.name
+            );
+        }
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create or open `{}`.", path.display()))?;
 
-    for pkg_vers in all_pkg_vers {
-        if pkg_vers.vers == index_pkg.vers {
-            // Replace the existing version of the package with the new one of the
-            // same version.
-            write_index_pkg(&mut f, &index_pkg)
-        } else {
-            write_index_pkg(&mut f, &pkg_vers)
+        for pkg_vers in all_pkg_vers {
+            if pkg_vers.vers == index_pkg.vers {
+                // Replace the existing version of the package with the new one of the
+                // same version.
+                write_index_pkg(&mut f, index_pkg)
+            } else {
+                write_index_pkg(&mut f, &pkg_vers)
+            }
+            .with_context(|| format!("Failed to write json entry at `{}`.", path.display()))?;
+        }
+        if pkg_vers_count == 0 {
+            write_index_pkg(&mut f, index_pkg)
+                .with_context(|| format!("Failed to write json entry at `{}`.", path.display()))?;
         }
-        .with_context(|| format!("Failed to write json entry at `{}`.", path.display()))?;
     }
-    if !pkg_vers_exists {
-        write_index_pkg(&mut f, &index_pkg)
-            .with_context(|| format!("Failed to write json entry at `{}`.", path.display()))?;
+    Ok(repo_path)
+}
+
+/// Put the `.crate` file in `store`, or the upload directory or URL, if
+/// set.
+///
+/// For an `lfs:` upload, returns the object that must be staged before
+/// committing.
+pub(crate) fn upload_crate(
+    index_path: &Path,
+    index_pkg: &IndexPackage,
+    crate_path: &Path,
+    upload: Option<&str>,
+    store: Option<&dyn CrateStore>,
+) -> Result<Option<LfsObject>, Error> {
+    if let Some(store) = store {
+        store.put(index_pkg, crate_path)?;
+        return Ok(None);
     }
+    match upload {
+        Some(upload) => match upload.strip_prefix(LFS_PREFIX) {
+            Some(template) => lfs::store(index_path, template, index_pkg, crate_path).map(Some),
+            None => {
+                store_for(upload).put(index_pkg, crate_path)?;
+                Ok(None)
+            }
+        },
+        None => Ok(None),
+    }
+}
 
-    let msg = format!("Updating crate `{}#{}`", index_pkg.name, index_pkg.vers);
-    // Upload.
-    if let Some(upload) = upload {
-        let replaced = upload
-            .replace("{crate}", &index_pkg.name)
-            .replace("{version}", &index_pkg.vers.to_string());
-        let upload = Path::new(&replaced);
-        fs::create_dir_all(upload)?;
-        fs::copy(&crate_path, upload.join(&crate_path.file_name().unwrap()))?;
-    }
-    git_add(&repo, &repo_path, &msg).with_context(|| "Failed to add to git repo.")?;
-    drop(lock);
-    Ok(index_pkg)
+/// Append a single entry to the end of a package file, creating it if needed.
+fn append_index_pkg(path: &Path, index_pkg: &IndexPackage) -> Result<(), Error> {
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to create or open `{}`.", path.display()))?;
+    let len = f.metadata()?.len();
+    if len > 0 {
+        // Guard against a hand-edited file missing the final newline.
+        let mut last = [0; 1];
+        f.seek(SeekFrom::Start(len - 1))?;
+        f.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            f.write_all(b"\n")?;
+        }
+    }
+    write_index_pkg(&mut f, index_pkg)
+        .with_context(|| format!("Failed to write json entry at `{}`.", path.display()))
 }
 
 fn write_index_pkg(f: &mut File, index_pkg: &IndexPackage) -> std::io::Result<()> {
@@ -220,17 +883,37 @@ fn write_index_pkg(f: &mut File, index_pkg: &IndexPackage) -> std::io::Result<()
     f.write_all(meta_json.as_bytes())
 }
 
-/// Add and commit a file to a git repo.
-pub(crate) fn git_add(repo: &git2::Repository, path: &Path, msg: &str) -> Result<(), Error> {
+/// Add and commit files to a git repo.
+///
+/// All of the paths are staged in the git index before writing a single
+/// tree and commit, so bulk operations only pay for one commit. Paths that
+/// no longer exist are removed from the repo. Does nothing for a sparse
+/// index, which has no repo.
+pub(crate) fn git_add(
+    repo: Option<&git2::Repository>,
+    paths: &[impl AsRef<Path>],
+    msg: &str,
+    commit: &CommitOptions,
+) -> Result<(), Error> {
+    let Some(repo) = repo else {
+        return Ok(());
+    };
+    let workdir = repo.workdir().unwrap();
     let mut index = repo.index()?;
-    index.add_path(path)?;
+    for path in paths {
+        let path = path.as_ref();
+        if workdir.join(path).exists() {
+            index.add_path(path)?;
+        } else {
+            index.remove_path(path)?;
+        }
+    }
     index.write()?;
     let id = index.write_tree()?;
     let tree = repo.find_tree(id)?;
     let head = repo.head()?;
     let parent = repo.find_commit(head.target().unwrap())?;
-    let sig = signature(repo)?;
-    repo.commit(Some("HEAD"), &sig, &sig, msg, &tree, &[&parent])?;
+    git_commit(repo, &tree, &[&parent], msg, commit)?;
     Ok(())
 }
 
@@ -240,22 +923,437 @@ pub(crate) fn git_add(repo: &git2::Repository, path: &Path, msg: &str) -> Result
 /// [`add`] for a variant that takes a path to a `Cargo.toml` manifest, and
 /// for more details on how this works.
 ///
+/// The `.crate` file is checked against the default [`CrateLimits`] while
+/// it is extracted, and links that point outside of the package are
+/// rejected. Use [`verify_crate`] for stricter checks.
+///
+/// See [`add_with_options`] to set [`MetadataOptions`].
+///
 /// [`add`]: fn.add.html
+/// [`add_with_options`]: fn.add_with_options.html
+/// [`CrateLimits`]: struct.CrateLimits.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
+/// [`verify_crate`]: fn.verify_crate.html
 pub fn add_from_crate(
     index_path: impl AsRef<Path>,
     index_url: &str,
     crate_path: impl AsRef<Path>,
     upload: Option<&str>,
 ) -> Result<IndexPackage, Error> {
-    let crate_path = crate_path.as_ref();
+    _add_from_crate(
+        index_path.as_ref(),
+        index_url,
+        crate_path.as_ref(),
+        upload,
+        &MetadataOptions::default(),
+    )
+}
+
+/// [`add_from_crate`] with [`MetadataOptions`].
+///
+/// [`add_from_crate`]: fn.add_from_crate.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
+pub(crate) fn _add_from_crate(
+    index_path: &Path,
+    index_url: &str,
+    crate_path: &Path,
+    upload: Option<&str>,
+    options: &MetadataOptions,
+) -> Result<IndexPackage, Error> {
     let (_tmp_dir, pkg_path) = extract_crate(crate_path)?;
     let manifest_path = pkg_path.join("Cargo.toml");
     add_reg(
         index_path,
+        open_repo(index_path)?.as_ref(),
         index_url,
         Some(&manifest_path),
         Some(crate_path),
         upload,
         None,
+        None,
+        options,
+    )
+}
+
+/// Add an entry that was generated earlier, such as by [`metadata`].
+///
+/// This does not run Cargo or look inside the `.crate` file. It only checks
+/// that the file name of `crate_path` matches the entry and that its
+/// checksum matches the `cksum` of the entry before writing the entry to
+/// the index. This allows the entry to be generated on a build machine,
+/// and then added by a separate publishing host.
+///
+/// Returns an error if the version of the package is already in the index,
+/// or if another package in the index has a name that differs only by `-`
+/// and `_` or by case.
+///
+/// [`metadata`]: fn.metadata.html
+pub fn add_from_metadata(
+    index_path: impl AsRef<Path>,
+    index_pkg: &IndexPackage,
+    crate_path: impl AsRef<Path>,
+    upload: Option<&str>,
+    commit: &CommitOptions,
+) -> Result<IndexPackage, Error> {
+    let index_path = index_path.as_ref();
+    let crate_path = crate_path.as_ref();
+    validate_package_name(&index_pkg.name, "package name")?;
+    let expected_name = format!("{}-{}.crate", index_pkg.name, index_pkg.vers);
+    if crate_path.file_name().and_then(|name| name.to_str()) != Some(expected_name.as_str()) {
+        bail!(
+            "Expected the .crate file for `{}:{}` to be named `{}`, found `{}`.",
+            index_pkg.name,
+            index_pkg.vers,
+            expected_name,
+            crate_path.display()
+        );
+    }
+    let actual = cksum(crate_path)?;
+    if actual != index_pkg.cksum {
+        bail!(
+            "Checksum of `{}` does not match the entry.\nentry: {}\nactual: {}",
+            crate_path.display(),
+            index_pkg.cksum,
+            actual
+        );
+    }
+    let mut index_pkg = index_pkg.clone();
+    index_pkg.yanked = false;
+    let meta_info = MetaInfo {
+        index_pkg,
+        crate_path: crate_path.to_path_buf(),
+        settings: PackageSettings::default(),
+    };
+    update_crate_index(
+        index_path,
+        open_repo(index_path)?.as_ref(),
+        meta_info,
+        upload,
+        None,
+        true,
+        commit,
+        |index_path, index_pkg, _| {
+            check_new_version(index_path, index_pkg, BuildMetadataPolicy::default())?;
+            check_name_conflict(index_path, &index_pkg.name)
+        },
     )
 }
+
+/// Add several `.crate` files to the index in a single commit.
+///
+/// The crates are added in the order given, so a crate that depends on
+/// another crate in the same batch must come after it. Every crate is
+/// checked before any is uploaded, and every crate is uploaded before any
+/// entry is written. If any step fails, none of the entries are committed,
+/// the package files are restored to their previous contents, and `.crate`
+/// files that were uploaded to a local directory are removed. Files already
+/// uploaded to an HTTP or S3 URL are left there, since those stores can't
+/// delete them.
+///
+/// See [`add_from_crate`] for more details on how each crate is added.
+///
+/// [`add_from_crate`]: fn.add_from_crate.html
+pub fn add_from_crates(
+    index_path: impl AsRef<Path>,
+    index_url: &str,
+    crate_paths: &[impl AsRef<Path>],
+    upload: Option<&str>,
+    options: &MetadataOptions,
+) -> Result<Vec<IndexPackage>, Error> {
+    let index_path = index_path.as_ref();
+    let repo = open_repo(index_path)?;
+    let lock = Lock::new_exclusive(index_path)?;
+    lfs::check_upload(upload)?;
+    if options.require_clean {
+        check_clean(index_path)?;
+    }
+    // Check everything first, against the index and the crates before it
+    // in the batch, so a crate that fails doesn't leave anything behind.
+    let mut pending: Vec<MetaInfo> = Vec::new();
+    for crate_path in crate_paths {
+        let crate_path = crate_path.as_ref();
+        let (_tmp_dir, pkg_dir) = extract_crate(crate_path)?;
+        let manifest_path = pkg_dir.join("Cargo.toml");
+        let meta_info = metadata_reg(
+            index_url,
+            Some(&manifest_path),
+            Some(crate_path),
+            None,
+            options,
+        )?;
+        let index_pkg = &meta_info.index_pkg;
+        let settings = &meta_info.settings;
+        let added: Vec<&IndexPackage> = pending.iter().map(|info| &info.index_pkg).collect();
+        settings.check_reviewers(&index_pkg.name, &options.commit)?;
+        check_new_version(index_path, index_pkg, options.build_metadata)?;
+        if !options.allow_name_conflicts {
+            check_name_conflict(index_path, &index_pkg.name)?;
+        }
+        if options.require_newer || settings.require_newer {
+            check_newer(index_path, index_pkg)?;
+        }
+        if !settings.skip_dep_check {
+            check_deps_with(index_path, index_pkg, &added)?;
+        }
+        check_batch(&added, index_pkg, options, settings)?;
+        pending.push(meta_info);
+    }
+    let mut backup = FileBackup::new(index_path);
+    if upload.is_some_and(|upload| upload.starts_with(LFS_PREFIX)) {
+        for path in LFS_CONFIG_FILES {
+            backup.save(PathBuf::from(path));
+        }
+    }
+    let mut uploaded = Vec::new();
+    let mut add_all = || -> Result<(), Error> {
+        let mut lfs_objects = Vec::new();
+        for MetaInfo {
+            index_pkg,
+            crate_path,
+            settings,
+        } in &pending
+        {
+            let upload = settings.upload(upload);
+            let upload = upload.as_deref();
+            if let Some(path) = upload_local_path(index_path, index_pkg, crate_path, upload) {
+                if !path.exists() {
+                    uploaded.push(path);
+                }
+            }
+            lfs_objects.extend(upload_crate(
+                index_path, index_pkg, crate_path, upload, None,
+            )?);
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut trailers = Vec::new();
+        let mut msg = format!("Updating {} crates\n\n", pending.len());
+        for MetaInfo {
+            index_pkg,
+            settings,
+            ..
+        } in &pending
+        {
+            backup.save(pkg_path(&index_pkg.name)?);
+            write_entry(index_path, index_pkg, true)?;
+            trailers.extend(settings.trailers(&index_pkg.name));
+            msg.push_str(&format!("{}#{}\n", index_pkg.name, index_pkg.vers));
+        }
+        let mut commit = options.commit.clone();
+        commit.trailers.extend(trailers);
+        lfs::stage(repo.as_ref(), &lfs_objects)?;
+        git_add(repo.as_ref(), &backup.paths(), &msg, &commit)
+            .with_context(|| "Failed to add to git repo.")
+    };
+    if let Err(e) = add_all() {
+        backup.restore();
+        if let Some(repo) = &repo {
+            // Take back anything `git_add` staged before failing.
+            let _ = repo
+                .head()
+                .and_then(|head| head.peel(git2::ObjectType::Commit))
+                .and_then(|head| repo.reset_default(Some(&head), backup.paths()));
+        }
+        for path in &uploaded {
+            let _ = fs::remove_file(path);
+        }
+        return Err(e);
+    }
+    drop(lock);
+    Ok(pending.into_iter().map(|info| info.index_pkg).collect())
+}
+
+/// The checks of [`add_from_crates`] against the crates before `index_pkg`
+/// in the batch, whose entries are not written yet.
+///
+/// [`add_from_crates`]: fn.add_from_crates.html
+fn check_batch(
+    added: &[&IndexPackage],
+    index_pkg: &IndexPackage,
+    options: &MetadataOptions,
+    settings: &PackageSettings,
+) -> Result<(), Error> {
+    let same_pkg = || {
+        added
+            .iter()
+            .filter(|pkg| pkg.name.eq_ignore_ascii_case(&index_pkg.name))
+    };
+    if same_pkg().any(|pkg| options.build_metadata.eq(&pkg.vers, &index_pkg.vers)) {
+        return Err(IndexError::VersionAlreadyExists {
+            name: index_pkg.name.clone(),
+            version: index_pkg.vers.to_string(),
+        }
+        .into());
+    }
+    if !options.allow_name_conflicts {
+        let normalized = normalize_name(&index_pkg.name);
+        if let Some(existing) = added
+            .iter()
+            .find(|pkg| pkg.name != index_pkg.name && normalize_name(&pkg.name) == normalized)
+        {
+            return Err(IndexError::NameConflict {
+                name: index_pkg.name.clone(),
+                existing: existing.name.clone(),
+            }
+            .into());
+        }
+    }
+    if options.require_newer || settings.require_newer {
+        let max = same_pkg()
+            .map(|pkg| &pkg.vers)
+            .max_by(|a, b| a.cmp_precedence(b));
+        if let Some(max) = max {
+            if index_pkg.vers.cmp_precedence(max) != Ordering::Greater {
+                bail!(
+                    "Package `{}` version `{}` is not newer than version `{}` earlier in the batch.",
+                    index_pkg.name,
+                    index_pkg.vers,
+                    max
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The local file that [`upload_crate`] writes the `.crate` file of `pkg`
+/// to, if there is one.
+fn upload_local_path(
+    index_path: &Path,
+    pkg: &IndexPackage,
+    crate_path: &Path,
+    upload: Option<&str>,
+) -> Option<PathBuf> {
+    let upload = upload?;
+    if upload.starts_with(LFS_PREFIX) {
+        Some(lfs::upload_dir(index_path, upload, pkg).join(crate_path.file_name()?))
+    } else {
+        store_for(upload).local_path(pkg)
+    }
+}
+
+/// Find the `.crate` files in a directory, ordered by their dependencies.
+///
+/// Each crate comes after the crates in the directory that it depends on
+/// from the same registry, so the result can be passed to
+/// [`add_from_crates`], or to [`add_from_crate`] one at a time. Crates that
+/// do not depend on one another are ordered by file name. Dev-dependencies
+/// are only used to order crates when they don't form a cycle.
+///
+/// `index_url` and `options` are used to generate the entries as in
+/// [`metadata_from_crate_with_options`], which decides which dependencies
+/// are from the same registry. Returns an error if the crates depend on each
+/// other in a cycle.
+///
+/// [`add_from_crates`]: fn.add_from_crates.html
+/// [`add_from_crate`]: fn.add_from_crate.html
+/// [`metadata_from_crate_with_options`]: fn.metadata_from_crate_with_options.html
+pub fn sort_crates(
+    index_url: &str,
+    dir: impl AsRef<Path>,
+    options: &MetadataOptions,
+) -> Result<Vec<PathBuf>, Error> {
+    let dir = dir.as_ref();
+    let mut paths = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read `{}`.", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "crate") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut remaining = paths
+        .into_iter()
+        .map(|path| {
+            let index_pkg = metadata_from_crate_with_options(index_url, &path, options)
+                .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+            Ok((path, index_pkg))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let depends_on = |pkg: &IndexPackage, other: &IndexPackage, dev: bool| {
+        pkg.deps.iter().any(|dep| {
+            (dev || dep.kind != DependencyKind::Development)
+                && dep.registry.is_none()
+                && dep.package.as_ref().unwrap_or(&dep.name) == &other.name
+                && dep.req.matches(&other.vers)
+        })
+    };
+    let mut ordered = Vec::new();
+    while !remaining.is_empty() {
+        let is_ready = |dev: bool, pkg: &IndexPackage| {
+            !remaining
+                .iter()
+                .any(|(_, other)| !std::ptr::eq(pkg, other) && depends_on(pkg, other, dev))
+        };
+        let mut ready: Vec<usize> = (0..remaining.len())
+            .filter(|i| is_ready(true, &remaining[*i].1))
+            .collect();
+        if ready.is_empty() {
+            ready = (0..remaining.len())
+                .filter(|i| is_ready(false, &remaining[*i].1))
+                .collect();
+        }
+        if ready.is_empty() {
+            let names: Vec<String> = remaining
+                .iter()
+                .map(|(_, pkg)| format!("{}:{}", pkg.name, pkg.vers))
+                .collect();
+            bail!(
+                "Could not order the crates, there is a dependency cycle between: {}",
+                names.join(", ")
+            );
+        }
+        let start = ordered.len();
+        for i in ready.into_iter().rev() {
+            ordered.push(remaining.remove(i).0);
+        }
+        ordered[start..].reverse();
+    }
+    Ok(ordered)
+}
+
+/// Remembers the original contents of package files modified by an
+/// operation that touches several entries, so they can be restored if it
+/// fails part way through.
+pub(crate) struct FileBackup<'a> {
+    index_path: &'a Path,
+    files: BTreeMap<PathBuf, Option<Vec<u8>>>,
+}
+
+impl<'a> FileBackup<'a> {
+    pub(crate) fn new(index_path: &'a Path) -> FileBackup<'a> {
+        FileBackup {
+            index_path,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Save the contents of the given repo-relative path, if it has not
+    /// already been saved.
+    pub(crate) fn save(&mut self, repo_path: PathBuf) {
+        let index_path = self.index_path;
+        self.files
+            .entry(repo_path)
+            .or_insert_with_key(|repo_path| fs::read(index_path.join(repo_path)).ok());
+    }
+
+    /// Put back the original contents of every saved file.
+    pub(crate) fn restore(&self) {
+        for (repo_path, contents) in &self.files {
+            let path = self.index_path.join(repo_path);
+            let _ = match contents {
+                Some(contents) => fs::write(&path, contents),
+                None => fs::remove_file(&path),
+            };
+        }
+    }
+
+    /// The repo-relative paths of all saved files.
+    pub(crate) fn paths(&self) -> Vec<&PathBuf> {
+        self.files.keys().collect()
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    history::{format_time, history, HistoryEventKind},
+    lock::Lock,
+    util::open_git,
+};
+use anyhow::Error;
+use semver::Version;
+use serde_json::json;
+use std::{collections::BTreeMap, path::Path};
+
+/// The commit trailer that records why a version was yanked.
+///
+/// Set it with [`CommitOptions::trailers`] when yanking, and it is
+/// reported by [`yank_advisories`].
+///
+/// [`CommitOptions::trailers`]: struct.CommitOptions.html#structfield.trailers
+/// [`yank_advisories`]: fn.yank_advisories.html
+pub const YANK_REASON_TRAILER: &str = "Yank-Reason";
+
+/// A version that is currently yanked, returned by [`yank_advisories`].
+///
+/// [`yank_advisories`]: fn.yank_advisories.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct YankAdvisory {
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub vers: Version,
+    /// The hex id of the commit that yanked the version.
+    pub commit: String,
+    /// The commit time, in seconds since the Unix epoch.
+    pub time: i64,
+    /// The name of the author of the commit.
+    pub author_name: String,
+    /// The email of the author of the commit.
+    pub author_email: String,
+    /// The [`YANK_REASON_TRAILER`] of the commit, if it has one.
+    ///
+    /// [`YANK_REASON_TRAILER`]: constant.YANK_REASON_TRAILER.html
+    pub reason: Option<String>,
+}
+
+impl YankAdvisory {
+    /// The advisory in the [OSV format](https://ossf.github.io/osv-schema/).
+    ///
+    /// The id is `id_prefix` followed by the name and version, such as
+    /// `MYREG-YANK-foo-1.0.0`. The ecosystem is `crates.io`, so scanners
+    /// match it against Cargo dependencies by name and version.
+    pub fn to_osv(&self, id_prefix: &str) -> serde_json::Value {
+        let time = format_time(self.time);
+        let mut osv = json!({
+            "schema_version": "1.6.0",
+            "id": format!("{}{}-{}", id_prefix, self.name, self.vers),
+            "modified": time,
+            "published": time,
+            "summary": format!("{} {} was yanked", self.name, self.vers),
+            "affected": [{
+                "package": {
+                    "ecosystem": "crates.io",
+                    "name": self.name,
+                },
+                "versions": [self.vers.to_string()],
+            }],
+            "database_specific": {
+                "commit": self.commit,
+            },
+        });
+        if let Some(reason) = &self.reason {
+            osv["details"] = reason.clone().into();
+        }
+        osv
+    }
+}
+
+/// Find the versions in the index that are currently yanked, and the commit
+/// that yanked each of them.
+///
+/// This uses the git history of the index, see [`history`]. If `pkg_name`
+/// is set, only that package is included. The results are sorted by name
+/// and version.
+///
+/// [`history`]: fn.history.html
+pub fn yank_advisories(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+) -> Result<Vec<YankAdvisory>, Error> {
+    let index = index.as_ref();
+    let mut yanked = BTreeMap::new();
+    for event in history(index, pkg_name)? {
+        let key = (event.name.clone(), event.vers.clone());
+        match event.kind {
+            HistoryEventKind::Yank => {
+                yanked.insert(key, event);
+            }
+            HistoryEventKind::Unyank | HistoryEventKind::Remove => {
+                yanked.remove(&key);
+            }
+            HistoryEventKind::Add | HistoryEventKind::Update => {}
+        }
+    }
+    let repo = open_git(index)?;
+    let lock = Lock::new_shared(index)?;
+    let mut advisories = Vec::new();
+    for event in yanked.into_values() {
+        let commit = repo.find_commit(git2::Oid::from_str(&event.commit)?)?;
+        let reason = match commit.message() {
+            Some(message) => git2::message_trailers_strs(message)?
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(YANK_REASON_TRAILER))
+                .map(|(_, value)| value.to_string()),
+            None => None,
+        };
+        advisories.push(YankAdvisory {
+            name: event.name,
+            vers: event.vers,
+            commit: event.commit,
+            time: event.time,
+            author_name: event.author_name,
+            author_email: event.author_email,
+            reason,
+        });
+    }
+    drop(lock);
+    Ok(advisories)
+}
@@ -0,0 +1,147 @@
+use anyhow::{bail, format_err, Context, Error};
+use cargo_metadata::{DependencyKind, Metadata, Package};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Find the workspace members that changed since a git revision.
+///
+/// `manifest_path` is the path to a `Cargo.toml` in the workspace, or the
+/// current directory if `None`. `since` is any revision git understands,
+/// such as a tag or commit hash. The files of the revision are compared
+/// with the working directory, and a package has changed if any file
+/// within its directory was added, modified, or deleted. Files of a nested
+/// package only count for the innermost package.
+///
+/// Packages with `publish = false` are skipped. Returns the manifest paths
+/// of the changed packages, ordered so that each package comes after the
+/// packages it depends on through a `path` dependency. Packages that do not
+/// depend on one another are ordered by name.
+pub fn changed_packages(manifest_path: Option<&Path>, since: &str) -> Result<Vec<PathBuf>, Error> {
+    let metadata = workspace_metadata(manifest_path)?;
+    let members: Vec<&Package> = metadata.workspace_packages();
+
+    let root = metadata.workspace_root.as_std_path();
+    let repo = git2::Repository::discover(root)
+        .with_context(|| format!("Could not find a git repository at `{}`.", root.display()))?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| format_err!("The git repository at `{}` is bare.", root.display()))?;
+    let workdir = fs::canonicalize(workdir)?;
+    let tree = repo
+        .revparse_single(since)
+        .and_then(|obj| obj.peel_to_tree())
+        .with_context(|| format!("Could not find git revision `{}`.", since))?;
+    let diff = repo.diff_tree_to_workdir_with_index(Some(&tree), None)?;
+    let mut changed_files = BTreeSet::new();
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path() {
+                changed_files.insert(workdir.join(path));
+            }
+        }
+    }
+
+    // Files belong to the innermost package, even if it can't be published.
+    let mut dirs = Vec::new();
+    for pkg in &members {
+        if pkg.publish.as_ref().is_some_and(|p| p.is_empty()) {
+            continue;
+        }
+        let dir = pkg.manifest_path.parent().unwrap().as_std_path();
+        let dir = fs::canonicalize(dir)
+            .with_context(|| format!("Failed to find package directory `{}`.", dir.display()))?;
+        dirs.push((dir, *pkg));
+    }
+    let all_dirs: Vec<PathBuf> = members
+        .iter()
+        .filter_map(|pkg| pkg.manifest_path.parent())
+        .filter_map(|dir| fs::canonicalize(dir).ok())
+        .collect();
+    let mut changed = BTreeMap::new();
+    for file in &changed_files {
+        let owner = all_dirs
+            .iter()
+            .filter(|dir| file.starts_with(dir))
+            .max_by_key(|dir| dir.components().count());
+        if let Some(owner) = owner {
+            if let Some((_, pkg)) = dirs.iter().find(|(dir, _)| dir == owner) {
+                changed.insert(pkg.name.as_str(), *pkg);
+            }
+        }
+    }
+    dependency_order(changed)
+}
+
+/// Find every workspace member that can be published.
+///
+/// `manifest_path` is the path to a `Cargo.toml` in the workspace, or the
+/// current directory if `None`. Packages with `publish = false` are skipped.
+/// Returns the manifest paths of the packages in the same order as
+/// [`changed_packages`], so each one can be added after the packages it
+/// depends on.
+///
+/// [`changed_packages`]: fn.changed_packages.html
+pub fn workspace_packages(manifest_path: Option<&Path>) -> Result<Vec<PathBuf>, Error> {
+    let metadata = workspace_metadata(manifest_path)?;
+    let members = metadata
+        .workspace_packages()
+        .into_iter()
+        .filter(|pkg| !pkg.publish.as_ref().is_some_and(|p| p.is_empty()))
+        .map(|pkg| (pkg.name.as_str(), pkg))
+        .collect();
+    dependency_order(members)
+}
+
+fn workspace_metadata(manifest_path: Option<&Path>) -> Result<Metadata, Error> {
+    let mut cmd = cargo_metadata::MetadataCommand::new();
+    if let Some(path) = manifest_path {
+        // Run in the package directory so its `.cargo/config` is used.
+        match path.parent() {
+            Some(parent) if parent != Path::new("") => cmd.current_dir(parent),
+            _ => cmd.manifest_path(path),
+        };
+    }
+    cmd.no_deps();
+    cmd.exec()
+        .map_err(|e| format_err!("{}", e))
+        .with_context(|| match manifest_path {
+            Some(path) => format_err!("Failed to read manifest at `{}`.", path.display()),
+            None => format_err!("Failed to read manifest from current directory."),
+        })
+}
+
+/// Sorts packages so that each comes after its `path` dependencies.
+///
+/// Dev-dependencies are ignored, since Cargo removes them when packaging if
+/// they don't have a version.
+fn dependency_order(mut remaining: BTreeMap<&str, &Package>) -> Result<Vec<PathBuf>, Error> {
+    let mut ordered = Vec::new();
+    while !remaining.is_empty() {
+        let ready: Vec<&str> = remaining
+            .iter()
+            .filter(|(_, pkg)| {
+                !pkg.dependencies.iter().any(|dep| {
+                    dep.kind != DependencyKind::Development
+                        && dep.path.is_some()
+                        && remaining.contains_key(dep.name.as_str())
+                })
+            })
+            .map(|(name, _)| *name)
+            .collect();
+        if ready.is_empty() {
+            let names: Vec<&str> = remaining.keys().copied().collect();
+            bail!(
+                "Could not order the packages, there is a dependency cycle between: {}",
+                names.join(", ")
+            );
+        }
+        for name in ready {
+            let pkg = remaining.remove(name).unwrap();
+            ordered.push(pkg.manifest_path.clone().into_std_path_buf());
+        }
+    }
+    Ok(ordered)
+}
@@ -0,0 +1,224 @@
+use crate::{list::list_all, IndexPackage};
+use anyhow::Error;
+use parquet::{
+    basic::Compression,
+    data_type::{BoolType, ByteArray, ByteArrayType},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use std::{io::Write, path::Path, sync::Arc};
+
+/// Number of rows buffered before a row group is written.
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+const ENTRIES_SCHEMA: &str = "
+message entry {
+    required binary name (STRING);
+    required binary vers (STRING);
+    required binary cksum (STRING);
+    required boolean yanked;
+    optional binary links (STRING);
+}
+";
+
+const DEPENDENCIES_SCHEMA: &str = "
+message dependency {
+    required binary name (STRING);
+    required binary vers (STRING);
+    required binary dep_name (STRING);
+    optional binary package (STRING);
+    required binary req (STRING);
+    required binary kind (STRING);
+    required boolean optional;
+    required boolean default_features;
+    optional binary target (STRING);
+    optional binary registry (STRING);
+}
+";
+
+/// The table written by [`export_parquet`].
+///
+/// [`export_parquet`]: fn.export_parquet.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParquetTable {
+    /// One row per entry with the name, version, checksum, yanked flag, and
+    /// links.
+    Entries,
+    /// One row per dependency edge, with the name and version of the
+    /// package that has the dependency, and the dependency's fields.
+    Dependencies,
+}
+
+/// Export entries in the index as a Parquet file.
+///
+/// `pkg_name` and `version_req` filter the entries the same as
+/// [`list_all`]. Returns the number of rows written.
+///
+/// This requires the `parquet` feature.
+///
+/// [`list_all`]: fn.list_all.html
+pub fn export_parquet(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+    version_req: Option<&str>,
+    table: ParquetTable,
+    out: impl Write + Send,
+) -> Result<usize, Error> {
+    let schema = match table {
+        ParquetTable::Entries => ENTRIES_SCHEMA,
+        ParquetTable::Dependencies => DEPENDENCIES_SCHEMA,
+    };
+    let schema = Arc::new(parse_message_type(schema)?);
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = SerializedFileWriter::new(out, schema, Arc::new(props))?;
+    let mut columns = match table {
+        ParquetTable::Entries => vec![
+            Column::Str(vec![]),
+            Column::Str(vec![]),
+            Column::Str(vec![]),
+            Column::Bool(vec![]),
+            Column::OptStr(vec![], vec![]),
+        ],
+        ParquetTable::Dependencies => vec![
+            Column::Str(vec![]),
+            Column::Str(vec![]),
+            Column::Str(vec![]),
+            Column::OptStr(vec![], vec![]),
+            Column::Str(vec![]),
+            Column::Str(vec![]),
+            Column::Bool(vec![]),
+            Column::Bool(vec![]),
+            Column::OptStr(vec![], vec![]),
+            Column::OptStr(vec![], vec![]),
+        ],
+    };
+    let mut count = 0;
+    let mut buffered = 0;
+    let mut result = Ok(());
+    list_all(index, pkg_name, version_req, |entries| {
+        if result.is_err() {
+            return;
+        }
+        for entry in &entries {
+            buffered += push_rows(&mut columns, entry, table);
+        }
+        if buffered >= ROW_GROUP_SIZE {
+            count += buffered;
+            buffered = 0;
+            result = write_row_group(&mut writer, &mut columns);
+        }
+    })?;
+    result?;
+    if buffered > 0 {
+        count += buffered;
+        write_row_group(&mut writer, &mut columns)?;
+    }
+    writer.close()?;
+    Ok(count)
+}
+
+/// Buffered values for a single column.
+enum Column {
+    Str(Vec<ByteArray>),
+    /// Values, and the definition levels (0 for null, 1 for present).
+    OptStr(Vec<ByteArray>, Vec<i16>),
+    Bool(Vec<bool>),
+}
+
+impl Column {
+    fn push_str(&mut self, value: &str) {
+        match self {
+            Column::Str(values) => values.push(ByteArray::from(value)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn push_opt_str(&mut self, value: Option<&str>) {
+        match self {
+            Column::OptStr(values, defs) => match value {
+                Some(value) => {
+                    values.push(ByteArray::from(value));
+                    defs.push(1);
+                }
+                None => defs.push(0),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        match self {
+            Column::Bool(values) => values.push(value),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Add the rows for an entry to the column buffers, returning the number
+/// of rows added.
+fn push_rows(columns: &mut [Column], entry: &IndexPackage, table: ParquetTable) -> usize {
+    let vers = entry.vers.to_string();
+    match table {
+        ParquetTable::Entries => {
+            columns[0].push_str(&entry.name);
+            columns[1].push_str(&vers);
+            columns[2].push_str(&entry.cksum);
+            columns[3].push_bool(entry.yanked);
+            columns[4].push_opt_str(entry.links.as_deref());
+            1
+        }
+        ParquetTable::Dependencies => {
+            for dep in &entry.deps {
+                columns[0].push_str(&entry.name);
+                columns[1].push_str(&vers);
+                columns[2].push_str(&dep.name);
+                columns[3].push_opt_str(dep.package.as_deref());
+                columns[4].push_str(&dep.req.to_string());
+                columns[5].push_str(&dep.kind.to_string());
+                columns[6].push_bool(dep.optional);
+                columns[7].push_bool(dep.default_features);
+                columns[8].push_opt_str(dep.target.as_deref());
+                columns[9].push_opt_str(dep.registry.as_ref().map(|r| r.as_str()));
+            }
+            entry.deps.len()
+        }
+    }
+}
+
+/// Write the buffered columns as a row group and clear the buffers.
+fn write_row_group<W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    columns: &mut [Column],
+) -> Result<(), Error> {
+    let mut row_group = writer.next_row_group()?;
+    let mut columns = columns.iter_mut();
+    while let Some(mut col_writer) = row_group.next_column()? {
+        match columns.next().unwrap() {
+            Column::Str(values) => {
+                col_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, None, None)?;
+                values.clear();
+            }
+            Column::OptStr(values, defs) => {
+                col_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, Some(defs), None)?;
+                values.clear();
+                defs.clear();
+            }
+            Column::Bool(values) => {
+                col_writer
+                    .typed::<BoolType>()
+                    .write_batch(values, None, None)?;
+                values.clear();
+            }
+        }
+        col_writer.close()?;
+    }
+    row_group.close()?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use crate::{
+    history::{diff_entries, parse_entries, read_entries, HistoryEventKind},
+    lock::Lock,
+    util::{crate_walker, open_git},
+};
+use anyhow::{Context, Error};
+use semver::Version;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A difference in one entry between two indexes, or two revisions of an
+/// index, returned by [`diff_indexes`] and [`diff_revisions`].
+///
+/// [`diff_indexes`]: fn.diff_indexes.html
+/// [`diff_revisions`]: fn.diff_revisions.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct IndexChange {
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub vers: Version,
+    /// How the entry differs, going from the old index to the new one.
+    ///
+    /// A version whose checksum changed is [`HistoryEventKind::Update`], and
+    /// may also be yanked or unyanked.
+    ///
+    /// [`HistoryEventKind::Update`]: enum.HistoryEventKind.html#variant.Update
+    pub kind: HistoryEventKind,
+}
+
+/// Compare the entries of two indexes on disk, such as an index and a
+/// mirror of it.
+///
+/// Returns the changes that turn `old` into `new`, sorted by name and
+/// version. Only the entries are compared, not `config.json`.
+pub fn diff_indexes(
+    old: impl AsRef<Path>,
+    new: impl AsRef<Path>,
+) -> Result<Vec<IndexChange>, Error> {
+    let old = old.as_ref();
+    let new = new.as_ref();
+    let old_lock = Lock::new_shared(old)?;
+    let new_lock = Lock::new_shared(new)?;
+    let mut paths = BTreeSet::new();
+    for index in [old, new] {
+        for file in crate_walker(index) {
+            let file = file?;
+            paths.insert(file.path().strip_prefix(index).unwrap().to_path_buf());
+        }
+    }
+    let read = |index: &Path, path: &PathBuf| -> Result<String, Error> {
+        let path = index.join(path);
+        if !path.exists() {
+            return Ok(String::new());
+        }
+        fs::read_to_string(&path).with_context(|| format!("Failed to read `{}`.", path.display()))
+    };
+    let mut changes = Vec::new();
+    for path in &paths {
+        let old_entries = parse_entries(&read(old, path)?);
+        let new_entries = parse_entries(&read(new, path)?);
+        for (kind, entry) in diff_entries(&old_entries, &new_entries) {
+            changes.push(IndexChange {
+                name: entry.name.clone(),
+                vers: entry.vers.clone(),
+                kind,
+            });
+        }
+    }
+    drop(new_lock);
+    drop(old_lock);
+    sort(&mut changes);
+    Ok(changes)
+}
+
+/// Compare the entries of an index at two git revisions.
+///
+/// `old_rev` and `new_rev` are anything `git rev-parse` accepts, such as
+/// `origin/master` and `HEAD`. This can be used to review a batch of
+/// changes before pushing them. Returns the changes that turn `old_rev`
+/// into `new_rev`, sorted by name and version.
+pub fn diff_revisions(
+    index: impl AsRef<Path>,
+    old_rev: &str,
+    new_rev: &str,
+) -> Result<Vec<IndexChange>, Error> {
+    let index = index.as_ref();
+    let repo = open_git(index)?;
+    let lock = Lock::new_shared(index)?;
+    let tree = |rev: &str| -> Result<git2::Tree<'_>, Error> {
+        repo.revparse_single(rev)
+            .and_then(|object| object.peel_to_tree())
+            .with_context(|| format!("Could not find revision `{}`.", rev))
+    };
+    let old_tree = tree(old_rev)?;
+    let new_tree = tree(new_rev)?;
+    let diff = repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+    let mut changes = Vec::new();
+    for delta in diff.deltas() {
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path,
+            None => continue,
+        };
+        if path.components().count() < 2 {
+            // config.json and other files at the root.
+            continue;
+        }
+        let old_entries = read_entries(&repo, delta.old_file().id(), path)?;
+        let new_entries = read_entries(&repo, delta.new_file().id(), path)?;
+        for (kind, entry) in diff_entries(&old_entries, &new_entries) {
+            changes.push(IndexChange {
+                name: entry.name.clone(),
+                vers: entry.vers.clone(),
+                kind,
+            });
+        }
+    }
+    drop(lock);
+    sort(&mut changes);
+    Ok(changes)
+}
+
+fn sort(changes: &mut [IndexChange]) {
+    changes.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+}
@@ -0,0 +1,75 @@
+/// Failures of index operations that callers may want to handle.
+///
+/// The functions in this library return [`anyhow::Error`], which holds an
+/// `IndexError` for these failures. Check for them with
+/// `err.downcast_ref::<IndexError>()` instead of matching the message.
+/// Other failures, such as I/O or git errors, are only described by their
+/// message.
+///
+/// [`anyhow::Error`]: https://docs.rs/anyhow/1/anyhow/struct.Error.html
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum IndexError {
+    /// The version being added is already in the index.
+    #[error("Package `{name}` version `{version}` is already in the index.")]
+    VersionAlreadyExists {
+        /// Name of the package.
+        name: String,
+        /// Version of the package.
+        version: String,
+    },
+    /// The package name differs from the name of another package in the
+    /// index only by `-` and `_` or by case, which Cargo treats as the same
+    /// package.
+    #[error(
+        "Package name `{name}` conflicts with `{existing}` in the index, \
+         they differ only by `-` and `_` or by case."
+    )]
+    NameConflict {
+        /// Name of the package being added.
+        name: String,
+        /// Name of the package already in the index.
+        existing: String,
+    },
+    /// The package has no entries in the index.
+    #[error("Package `{name}` is not in the index.")]
+    PackageNotFound {
+        /// Name of the package.
+        name: String,
+    },
+    /// The package is in the index, but not the requested version.
+    #[error("Version `{version}` for package `{name}` not found.")]
+    VersionNotFound {
+        /// Name of the package.
+        name: String,
+        /// The requested version.
+        version: String,
+    },
+    /// A dependency from the same registry has no matching version in the
+    /// index.
+    #[error("Package `{name}` dependency `{dependency}:{req}` not found in index.")]
+    DependencyMissing {
+        /// Name of the package being added.
+        name: String,
+        /// Name of the dependency.
+        dependency: String,
+        /// The version requirement of the dependency.
+        req: String,
+    },
+    /// The version to yank is already yanked.
+    #[error("`{name}:{version}` is already yanked!")]
+    AlreadyYanked {
+        /// Name of the package.
+        name: String,
+        /// Version of the package.
+        version: String,
+    },
+    /// The version to unyank is not yanked.
+    #[error("`{name}:{version}` is not yanked!")]
+    NotYanked {
+        /// Name of the package.
+        name: String,
+        /// Version of the package.
+        version: String,
+    },
+}
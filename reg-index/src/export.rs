@@ -0,0 +1,101 @@
+use crate::{list::list_all, DependencyKind, IndexPackage};
+use anyhow::{bail, Error};
+use std::{borrow::Cow, io::Write, path::Path, str::FromStr};
+
+/// The format used by [`export`].
+///
+/// [`export`]: fn.export.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExportFormat {
+    /// One JSON entry per line, the same as the index files.
+    Jsonl,
+    /// Comma-separated values with a header row, one row per entry.
+    ///
+    /// The columns are `name`, `vers`, `cksum`, `yanked`, `links`, and the
+    /// number of normal, dev, and build dependencies.
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExportFormat, Error> {
+        match s {
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => bail!("Unknown export format `{}`.", s),
+        }
+    }
+}
+
+/// Export entries in the index to a single stream.
+///
+/// `pkg_name` and `version_req` filter the entries the same as
+/// [`list_all`]. Entries are written to `out` as they are read, so the whole
+/// index is never held in memory. Returns the number of entries written.
+///
+/// [`list_all`]: fn.list_all.html
+pub fn export(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+    version_req: Option<&str>,
+    format: ExportFormat,
+    mut out: impl Write,
+) -> Result<usize, Error> {
+    if format == ExportFormat::Csv {
+        out.write_all(b"name,vers,cksum,yanked,links,deps,dev_deps,build_deps\n")?;
+    }
+    let mut count = 0;
+    let mut result = Ok(());
+    list_all(index, pkg_name, version_req, |entries| {
+        if result.is_err() {
+            return;
+        }
+        result = entries.iter().try_for_each(|entry| {
+            count += 1;
+            write_entry(&mut out, entry, format)
+        });
+    })?;
+    result?;
+    out.flush()?;
+    Ok(count)
+}
+
+fn write_entry(
+    out: &mut impl Write,
+    entry: &IndexPackage,
+    format: ExportFormat,
+) -> Result<(), Error> {
+    match format {
+        ExportFormat::Jsonl => {
+            serde_json::to_writer(&mut *out, entry)?;
+            out.write_all(b"\n")?;
+        }
+        ExportFormat::Csv => {
+            let count_kind = |kind| entry.deps.iter().filter(|dep| dep.kind == kind).count();
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                csv_field(&entry.name),
+                csv_field(&entry.vers.to_string()),
+                csv_field(&entry.cksum),
+                entry.yanked,
+                csv_field(entry.links.as_deref().unwrap_or("")),
+                count_kind(DependencyKind::Normal),
+                count_kind(DependencyKind::Development),
+                count_kind(DependencyKind::Build),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if necessary.
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{
+    list::list_all,
+    metadata::canonical_url,
+    util::{cksum, crate_walker, dl_url, expand_markers},
+    IndexConfig, IndexPackage,
+};
+use anyhow::{bail, format_err, Context, Error};
+use reg_index_core::{parse_entries, pkg_path};
+use semver::VersionReq;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use url::Url;
+
+/// The sparse index used in place of the crates.io git index, since the git
+/// index is very large to clone.
+const CRATES_IO_SPARSE: &str = "https://index.crates.io";
+
+/// The result of [`fetch_deps`].
+///
+/// [`fetch_deps`]: fn.fetch_deps.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct FetchReport {
+    /// Packages that were downloaded, as `name:version`.
+    pub fetched: Vec<String>,
+    /// Packages that were already in the download directory, as
+    /// `name:version`.
+    pub present: Vec<String>,
+    /// Dependencies that could not be fetched, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Download the `.crate` files of dependencies from other registries.
+///
+/// This looks at every entry in the index (or only `pkg_name` if set), and
+/// for each dependency with a `registry` set, finds the newest version in
+/// that registry matching the requirement and downloads it. The
+/// dependencies of downloaded packages are followed as well, so that every
+/// package needed to build the entries in the index is available. Dev
+/// dependencies are skipped.
+///
+/// `dl_dir` is the directory to place the `.crate` files in, which supports
+/// the same markers as the `upload` argument of [`add`]. Files that already
+/// exist are not downloaded again. The checksum of each download is
+/// verified against the registry's index.
+///
+/// `file:` URLs are read directly. Other registries are read with `curl`,
+/// which must be installed. The crates.io git index is read through its
+/// sparse index.
+///
+/// Failures to fetch individual packages are recorded in the report
+/// instead of returning an error.
+///
+/// [`add`]: fn.add.html
+pub fn fetch_deps(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+    dl_dir: &str,
+) -> Result<FetchReport, Error> {
+    let mut queue = Vec::new();
+    list_all(index, pkg_name, None, |entries| {
+        for entry in entries {
+            for dep in &entry.deps {
+                if dep.kind == cargo_metadata::DependencyKind::Development {
+                    continue;
+                }
+                if let Some(registry) = &dep.registry {
+                    let name = dep.package.as_ref().unwrap_or(&dep.name);
+                    queue.push((registry.to_string(), name.clone(), dep.req.clone()));
+                }
+            }
+        }
+    })?;
+
+    let mut report = FetchReport::default();
+    let mut registries: HashMap<String, Registry> = HashMap::new();
+    let mut seen = BTreeSet::new();
+    while let Some((registry_url, name, req)) = queue.pop() {
+        let key = format!("{} {} {}", canonical_url(&registry_url), name, req);
+        if !seen.insert(key) {
+            continue;
+        }
+        let dep_desc = format!("{}@{} from {}", name, req, registry_url);
+        let registry = match registries.get_mut(&registry_url) {
+            Some(registry) => registry,
+            None => match Registry::open(&registry_url) {
+                Ok(registry) => registries.entry(registry_url.clone()).or_insert(registry),
+                Err(e) => {
+                    report.failed.push((dep_desc, format!("{:#}", e)));
+                    continue;
+                }
+            },
+        };
+        let pkg = match registry.find(&name, &req) {
+            Ok(pkg) => pkg,
+            Err(e) => {
+                report.failed.push((dep_desc, format!("{:#}", e)));
+                continue;
+            }
+        };
+        let pkg_desc = format!("{}:{}", pkg.name, pkg.vers);
+        let dest = Path::new(&expand_markers(dl_dir, &pkg))
+            .join(format!("{}-{}.crate", pkg.name, pkg.vers));
+        if dest.exists() {
+            report.present.push(pkg_desc);
+        } else {
+            match registry.download(&pkg, &dest) {
+                Ok(()) => report.fetched.push(pkg_desc),
+                Err(e) => {
+                    report.failed.push((pkg_desc, format!("{:#}", e)));
+                    continue;
+                }
+            }
+        }
+        for dep in &pkg.deps {
+            if dep.kind == cargo_metadata::DependencyKind::Development {
+                continue;
+            }
+            // `None` means the same registry as the package.
+            let dep_registry = dep
+                .registry
+                .as_ref()
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| registry_url.clone());
+            let name = dep.package.as_ref().unwrap_or(&dep.name);
+            queue.push((dep_registry, name.clone(), dep.req.clone()));
+        }
+    }
+    Ok(report)
+}
+
+/// Where the index of another registry is read from.
+enum Source {
+    /// A local checkout of the index.
+    Local(PathBuf),
+    /// A sparse index, with the base URL.
+    Sparse(String),
+    /// A git index cloned to a temporary directory.
+    Cloned(tempfile::TempDir),
+}
+
+/// Another registry whose index and `.crate` files can be read.
+pub(crate) struct Registry {
+    source: Source,
+    config: IndexConfig,
+}
+
+impl Registry {
+    pub(crate) fn open(url: &str) -> Result<Registry, Error> {
+        let source = if let Some(sparse) = url.strip_prefix("sparse+") {
+            Source::Sparse(sparse.trim_end_matches('/').to_string())
+        } else if canonical_url(url)
+            == canonical_url("https://github.com/rust-lang/crates.io-index")
+        {
+            Source::Sparse(CRATES_IO_SPARSE.to_string())
+        } else {
+            let parsed = Url::parse(url.strip_prefix("registry+").unwrap_or(url))
+                .with_context(|| format!("Invalid registry URL `{}`.", url))?;
+            if parsed.scheme() == "file" {
+                let path = parsed
+                    .to_file_path()
+                    .map_err(|_| format_err!("Invalid file URL `{}`.", url))?;
+                Source::Local(path)
+            } else {
+                let tmp_dir = tempfile::tempdir()?;
+                git2::Repository::clone(parsed.as_str(), tmp_dir.path())
+                    .with_context(|| format!("Failed to clone the index at `{}`.", url))?;
+                Source::Cloned(tmp_dir)
+            }
+        };
+        let config = read_file(&source, "config.json")?;
+        let config: IndexConfig = serde_json::from_slice(&config)
+            .with_context(|| format!("Failed to parse config.json of `{}`.", url))?;
+        Ok(Registry { source, config })
+    }
+
+    /// Find the newest version matching `req`, preferring versions that
+    /// are not yanked.
+    fn find(&self, name: &str, req: &VersionReq) -> Result<IndexPackage, Error> {
+        self.entries(name)?
+            .into_iter()
+            .filter(|pkg| req.matches(&pkg.vers))
+            .max_by(|a, b| (!a.yanked, &a.vers).cmp(&(!b.yanked, &b.vers)))
+            .ok_or_else(|| format_err!("No version of `{}` matches `{}`.", name, req))
+    }
+
+    /// All entries of a package, in the order they appear in the index.
+    pub(crate) fn entries(&self, name: &str) -> Result<Vec<IndexPackage>, Error> {
+        let path = pkg_path(name)?;
+        let contents = read_file(&self.source, &path)?;
+        let contents = String::from_utf8(contents)
+            .with_context(|| format!("Index file for `{}` is not UTF-8.", name))?;
+        parse_entries(&contents, None)
+            .with_context(|| format!("Failed to parse index entry for `{}`.", name))
+    }
+
+    /// The names of all packages in the registry.
+    ///
+    /// A local or cloned index is walked. A sparse index can't be listed,
+    /// so the search endpoint of the web API is paged through instead,
+    /// using `api` or else the `api` of its `config.json`.
+    pub(crate) fn names(&self, api: Option<&str>) -> Result<BTreeSet<String>, Error> {
+        let root = match &self.source {
+            Source::Local(root) => root.as_path(),
+            Source::Cloned(tmp_dir) => tmp_dir.path(),
+            Source::Sparse(base) => {
+                let api = match api {
+                    Some(api) => api.to_string(),
+                    None => match &self.config.api {
+                        Some(api) => api.to_string(),
+                        None => bail!(
+                            "The sparse index at `{}` has no `api` in its config.json, \
+                             so its packages can't be listed. Pass the URL of its web API.",
+                            base
+                        ),
+                    },
+                };
+                return search_names(&api);
+            }
+        };
+        let mut names = BTreeSet::new();
+        for entry in crate_walker(root) {
+            let entry = entry?;
+            names.insert(entry.file_name().to_string_lossy().into_owned());
+        }
+        Ok(names)
+    }
+
+    /// Download the `.crate` file of `pkg` to `dest`, verifying its checksum.
+    pub(crate) fn download(&self, pkg: &IndexPackage, dest: &Path) -> Result<(), Error> {
+        let url = dl_url(self.config.dl.as_str(), pkg);
+        let parent = dest.parent().unwrap();
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create `{}`.", parent.display()))?;
+        // Download to a temporary name so a failure doesn't leave a partial
+        // file that looks complete.
+        let tmp_dest = dest.with_extension("crate-partial");
+        let parsed =
+            Url::parse(&url).with_context(|| format!("Invalid download URL `{}`.", url))?;
+        if parsed.scheme() == "file" {
+            let path = parsed
+                .to_file_path()
+                .map_err(|_| format_err!("Invalid file URL `{}`.", url))?;
+            fs::copy(&path, &tmp_dest)
+                .with_context(|| format!("Failed to copy `{}`.", path.display()))?;
+        } else {
+            curl(&url, Some(&tmp_dest))?;
+        }
+        let actual = cksum(&tmp_dest)?;
+        if actual != pkg.cksum {
+            let _ = fs::remove_file(&tmp_dest);
+            bail!(
+                "Checksum of `{}` does not match the index, expected {} got {}.",
+                url,
+                pkg.cksum,
+                actual
+            );
+        }
+        fs::rename(&tmp_dest, dest)
+            .with_context(|| format!("Failed to rename to `{}`.", dest.display()))?;
+        Ok(())
+    }
+}
+
+/// Number of results requested from each page of the search API.
+const SEARCH_PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    crates: Vec<SearchCrate>,
+}
+
+#[derive(Deserialize)]
+struct SearchCrate {
+    name: String,
+}
+
+/// List package names with the `/api/v1/crates` search endpoint.
+///
+/// Registries differ in whether they support the `page` parameter beyond
+/// what Cargo uses, so this stops when a page is short or has nothing new.
+fn search_names(api: &str) -> Result<BTreeSet<String>, Error> {
+    let api = api.trim_end_matches('/');
+    let mut names = BTreeSet::new();
+    for page in 1.. {
+        let url = format!(
+            "{}/api/v1/crates?q=&per_page={}&page={}",
+            api, SEARCH_PAGE_SIZE, page
+        );
+        let body = curl(&url, None)?;
+        let response: SearchResponse = serde_json::from_slice(&body)
+            .with_context(|| format!("Failed to parse the search results from `{}`.", url))?;
+        let count = response.crates.len();
+        let mut new = false;
+        for krate in response.crates {
+            new |= names.insert(krate.name);
+        }
+        if count < SEARCH_PAGE_SIZE || !new {
+            break;
+        }
+    }
+    Ok(names)
+}
+
+fn read_file(source: &Source, path: &str) -> Result<Vec<u8>, Error> {
+    let local = match source {
+        Source::Local(root) => root.join(path),
+        Source::Cloned(tmp_dir) => tmp_dir.path().join(path),
+        Source::Sparse(base) => return curl(&format!("{}/{}", base, path), None),
+    };
+    fs::read(&local).with_context(|| format!("Failed to read `{}`.", local.display()))
+}
+
+/// Download a URL with `curl`, either to a file or returning the contents.
+fn curl(url: &str, dest: Option<&Path>) -> Result<Vec<u8>, Error> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--silent", "--show-error", "--fail", "--location"]);
+    if let Some(dest) = dest {
+        cmd.arg("--output").arg(dest);
+    }
+    cmd.arg(url);
+    let output = cmd
+        .output()
+        .with_context(|| "Could not run `curl`, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to download `{}`: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,397 @@
+use crate::{
+    lock::Lock,
+    util::{open_git, pkg_path},
+};
+use anyhow::{bail, format_err, Context, Error};
+use semver::Version;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// A change to a single entry, found in the git history of the index.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct HistoryEvent {
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub vers: Version,
+    /// What happened to the entry.
+    pub kind: HistoryEventKind,
+    /// The hex id of the commit that made the change.
+    pub commit: String,
+    /// The commit time, in seconds since the Unix epoch.
+    pub time: i64,
+    /// The name of the author of the commit.
+    pub author_name: String,
+    /// The email of the author of the commit.
+    pub author_email: String,
+}
+
+/// The kind of change in a [`HistoryEvent`].
+///
+/// [`HistoryEvent`]: struct.HistoryEvent.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HistoryEventKind {
+    /// The version was added to the index.
+    Add,
+    /// The version was yanked.
+    Yank,
+    /// The version was unyanked.
+    Unyank,
+    /// The checksum of an existing version was changed, such as with a
+    /// forced add.
+    Update,
+    /// The version was removed from the index.
+    Remove,
+}
+
+/// The subset of an entry needed to tell what changed between commits.
+#[derive(Deserialize)]
+pub(crate) struct HistoryEntry {
+    pub(crate) name: String,
+    pub(crate) vers: Version,
+    cksum: String,
+    yanked: bool,
+}
+
+/// Find every change to entries in the git history of the index, oldest
+/// first.
+///
+/// If `pkg_name` is set, only changes to that package are returned.
+///
+/// Only the first-parent history of `HEAD` is followed, so changes brought
+/// in by a merge are attributed to the merge commit.
+pub fn history(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+) -> Result<Vec<HistoryEvent>, Error> {
+    let index = index.as_ref();
+    let repo = open_git(index)?;
+    let lock = Lock::new_shared(index)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.simplify_first_parent()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    let mut diff_opts = git2::DiffOptions::new();
+    if let Some(pkg_name) = pkg_name {
+        diff_opts.pathspec(pkg_path(pkg_name)?);
+        diff_opts.disable_pathspec_match(true);
+    }
+    let mut events = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff =
+            repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut diff_opts))?;
+        for delta in diff.deltas() {
+            let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+                Some(path) => path,
+                None => continue,
+            };
+            if path.components().count() < 2 {
+                // config.json and other files at the root.
+                continue;
+            }
+            let old = read_entries(&repo, delta.old_file().id(), path)?;
+            let new = read_entries(&repo, delta.new_file().id(), path)?;
+            for (kind, entry) in diff_entries(&old, &new) {
+                events.push(HistoryEvent {
+                    name: entry.name.clone(),
+                    vers: entry.vers.clone(),
+                    kind,
+                    commit: commit.id().to_string(),
+                    time: commit.time().seconds(),
+                    author_name: commit.author().name().unwrap_or_default().to_string(),
+                    author_email: commit.author().email().unwrap_or_default().to_string(),
+                });
+            }
+        }
+    }
+    drop(lock);
+    Ok(events)
+}
+
+/// Read the entries of a package file blob, keyed by version.
+pub(crate) fn read_entries(
+    repo: &git2::Repository,
+    id: git2::Oid,
+    path: &Path,
+) -> Result<BTreeMap<String, HistoryEntry>, Error> {
+    if id.is_zero() {
+        return Ok(BTreeMap::new());
+    }
+    let blob = repo.find_blob(id)?;
+    let contents = match std::str::from_utf8(blob.content()) {
+        Ok(contents) => contents,
+        Err(_) => bail!("File `{}` in commit history is not UTF-8.", path.display()),
+    };
+    Ok(parse_entries(contents))
+}
+
+/// Parse the entries of a package file, keyed by version.
+pub(crate) fn parse_entries(contents: &str) -> BTreeMap<String, HistoryEntry> {
+    let mut entries = BTreeMap::new();
+    for line in contents.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Ignore lines that don't look like entries, they may be files
+        // that aren't packages.
+        if let Ok(entry) = serde_json::from_str::<HistoryEntry>(line) {
+            entries.insert(entry.vers.to_string(), entry);
+        }
+    }
+    entries
+}
+
+pub(crate) fn diff_entries<'a>(
+    old: &'a BTreeMap<String, HistoryEntry>,
+    new: &'a BTreeMap<String, HistoryEntry>,
+) -> Vec<(HistoryEventKind, &'a HistoryEntry)> {
+    let mut changes = Vec::new();
+    for (vers, entry) in new {
+        match old.get(vers) {
+            None => changes.push((HistoryEventKind::Add, entry)),
+            Some(old_entry) => {
+                if old_entry.cksum != entry.cksum {
+                    changes.push((HistoryEventKind::Update, entry));
+                }
+                if !old_entry.yanked && entry.yanked {
+                    changes.push((HistoryEventKind::Yank, entry));
+                } else if old_entry.yanked && !entry.yanked {
+                    changes.push((HistoryEventKind::Unyank, entry));
+                }
+            }
+        }
+    }
+    for (vers, entry) in old {
+        if !new.contains_key(vers) {
+            changes.push((HistoryEventKind::Remove, entry));
+        }
+    }
+    changes
+}
+
+/// The commit that added a version to the index, returned by
+/// [`who_published`].
+///
+/// [`who_published`]: fn.who_published.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Publication {
+    /// The hex id of the commit that added the version.
+    pub commit: String,
+    /// The commit time, in seconds since the Unix epoch.
+    pub time: i64,
+    /// The name of the author of the commit.
+    pub author_name: String,
+    /// The email of the author of the commit.
+    pub author_email: String,
+    /// The name of the committer of the commit.
+    pub committer_name: String,
+    /// The email of the committer of the commit.
+    pub committer_email: String,
+    /// Trailers at the end of the commit message, such as
+    /// `Signed-off-by`, as key/value pairs in order.
+    pub trailers: Vec<(String, String)>,
+}
+
+/// Find the commit that added a version of a package to the index.
+///
+/// If the version was removed and added again, this is the latest add. The
+/// `version` must match exactly, including any build metadata.
+pub fn who_published(
+    index: impl AsRef<Path>,
+    pkg_name: &str,
+    version: &str,
+) -> Result<Publication, Error> {
+    let index = index.as_ref();
+    let vers =
+        Version::parse(version).with_context(|| format!("Invalid version `{}`.", version))?;
+    let event = history(index, Some(pkg_name))?
+        .into_iter()
+        .rev()
+        .find(|event| event.kind == HistoryEventKind::Add && event.vers == vers)
+        .ok_or_else(|| {
+            format_err!(
+                "Could not find when `{}:{}` was added in the index history.",
+                pkg_name,
+                version
+            )
+        })?;
+    let repo = open_git(index)?;
+    let commit = repo.find_commit(git2::Oid::from_str(&event.commit)?)?;
+    let trailers = match commit.message() {
+        Some(message) => git2::message_trailers_strs(message)?
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        None => Vec::new(),
+    };
+    let committer = commit.committer();
+    Ok(Publication {
+        commit: event.commit,
+        time: event.time,
+        author_name: event.author_name,
+        author_email: event.author_email,
+        committer_name: committer.name().unwrap_or_default().to_string(),
+        committer_email: committer.email().unwrap_or_default().to_string(),
+        trailers,
+    })
+}
+
+/// When an entry was added and last yanked or unyanked, returned by
+/// [`entry_dates`].
+///
+/// Times are the commit time, in seconds since the Unix epoch.
+///
+/// [`entry_dates`]: fn.entry_dates.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct EntryDates {
+    /// When the version was added to the index.
+    ///
+    /// If the version was removed and added again, this is the latest add.
+    pub added: Option<i64>,
+    /// When the version was last yanked or unyanked, if ever.
+    pub yank_changed: Option<i64>,
+}
+
+/// Find when each entry was added and last yanked or unyanked, using the
+/// git history of the index.
+///
+/// The index format has no timestamps, so this looks at the commits that
+/// changed each entry, see [`history`]. If `pkg_name` is set, only that
+/// package is included. Entries that were removed are not included.
+///
+/// [`history`]: fn.history.html
+pub fn entry_dates(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+) -> Result<HashMap<(String, Version), EntryDates>, Error> {
+    let mut dates: HashMap<(String, Version), EntryDates> = HashMap::new();
+    for event in history(index, pkg_name)? {
+        let key = (event.name, event.vers);
+        match event.kind {
+            HistoryEventKind::Add => {
+                dates.insert(
+                    key,
+                    EntryDates {
+                        added: Some(event.time),
+                        yank_changed: None,
+                    },
+                );
+            }
+            HistoryEventKind::Yank | HistoryEventKind::Unyank => {
+                dates.entry(key).or_default().yank_changed = Some(event.time);
+            }
+            HistoryEventKind::Remove => {
+                dates.remove(&key);
+            }
+            HistoryEventKind::Update => {}
+        }
+    }
+    Ok(dates)
+}
+
+/// How to group the counts returned by [`activity`].
+///
+/// [`activity`]: fn.activity.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ActivityGrouping {
+    /// Group by the UTC day of the commit, as `YYYY-MM-DD`.
+    Day,
+    /// Group by the UTC week of the commit, as the `YYYY-MM-DD` date of the
+    /// Monday that starts the week.
+    Week,
+    /// Group by the commit author, as `name <email>`.
+    Author,
+}
+
+/// Counts of changes for one group returned by [`activity`].
+///
+/// [`activity`]: fn.activity.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Activity {
+    /// The day, week, or author of this group.
+    pub key: String,
+    /// Number of versions added.
+    pub added: usize,
+    /// Number of versions yanked.
+    pub yanked: usize,
+    /// Number of versions unyanked.
+    pub unyanked: usize,
+}
+
+/// Count the publishes and yanks in the git history of the index.
+///
+/// Groups by date are returned in chronological order, and groups by author
+/// are sorted by the author.
+pub fn activity(
+    index: impl AsRef<Path>,
+    grouping: ActivityGrouping,
+) -> Result<Vec<Activity>, Error> {
+    let mut groups: HashMap<String, Activity> = HashMap::new();
+    for event in history(index, None)? {
+        let key = match grouping {
+            ActivityGrouping::Day => format_date(event.time),
+            ActivityGrouping::Week => {
+                // 1970-01-01 was a Thursday.
+                let days = event.time.div_euclid(86400);
+                format_date((days - (days + 3).rem_euclid(7)) * 86400)
+            }
+            ActivityGrouping::Author => format!("{} <{}>", event.author_name, event.author_email),
+        };
+        let group = groups.entry(key.clone()).or_insert_with(|| Activity {
+            key,
+            ..Activity::default()
+        });
+        match event.kind {
+            HistoryEventKind::Add => group.added += 1,
+            HistoryEventKind::Yank => group.yanked += 1,
+            HistoryEventKind::Unyank => group.unyanked += 1,
+            _ => {}
+        }
+    }
+    let mut groups: Vec<Activity> = groups.into_values().collect();
+    groups.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(groups)
+}
+
+/// Format a Unix timestamp as an RFC 3339 UTC time, such as
+/// `2020-01-31T12:34:56Z`.
+pub fn format_time(time: i64) -> String {
+    let secs = time.rem_euclid(86400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(time),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Format a Unix timestamp as a `YYYY-MM-DD` UTC date.
+pub(crate) fn format_date(time: i64) -> String {
+    // Howard Hinnant's `civil_from_days` algorithm.
+    let z = time.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,82 @@
+use crate::{
+    add::{check_deps, check_new_version, git_add, write_entry, FileBackup},
+    lock::Lock,
+    util::{open_repo, pkg_path, CommitOptions},
+    validate::validate_package_name,
+    BuildMetadataPolicy, IndexPackage,
+};
+use anyhow::{bail, Context, Error};
+use std::{collections::HashSet, io::BufRead, path::Path};
+
+/// Import entries from a JSON-lines dump, such as one created by
+/// [`export`].
+///
+/// Each line is validated as an index entry, and must not already be in the
+/// index. Dependencies from the same registry must be satisfied either by
+/// the index or by other entries in the dump, in any order. All entries are
+/// written in a single commit. If anything fails, the index is left
+/// unchanged.
+///
+/// Returns the entries that were imported.
+///
+/// [`export`]: fn.export.html
+pub fn import(
+    index: impl AsRef<Path>,
+    reader: impl BufRead,
+    commit: &CommitOptions,
+) -> Result<Vec<IndexPackage>, Error> {
+    let index = index.as_ref();
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let pkg: IndexPackage = serde_json::from_str(&line)
+            .with_context(|| format!("Could not deserialize line {}:\n{}", i + 1, line))?;
+        validate_package_name(&pkg.name, "package name")?;
+        for dep in &pkg.deps {
+            validate_package_name(
+                &dep.name,
+                &format!("dependency of `{}:{}`", pkg.name, pkg.vers),
+            )?;
+        }
+        if !seen.insert((pkg.name.clone(), pkg.vers.to_string())) {
+            bail!(
+                "Version `{}` of `{}` appears multiple times in the dump.",
+                pkg.vers,
+                pkg.name
+            );
+        }
+        entries.push(pkg);
+    }
+
+    let repo = open_repo(index)?;
+    let lock = Lock::new_exclusive(index)?;
+    let mut backup = FileBackup::new(index);
+    let mut write_all = || -> Result<(), Error> {
+        for pkg in &entries {
+            check_new_version(index, pkg, BuildMetadataPolicy::default())?;
+            backup.save(pkg_path(&pkg.name)?);
+            write_entry(index, pkg, true)?;
+        }
+        // Dependencies are checked after everything is written, since the
+        // dump is not necessarily in dependency order.
+        for pkg in &entries {
+            check_deps(index, pkg)?;
+        }
+        Ok(())
+    };
+    if let Err(e) = write_all() {
+        backup.restore();
+        return Err(e);
+    }
+    if !entries.is_empty() {
+        let msg = format!("Importing {} entries", entries.len());
+        git_add(repo.as_ref(), &backup.paths(), &msg, commit)
+            .with_context(|| "Failed to add to git repo.")?;
+    }
+    drop(lock);
+    Ok(entries)
+}
@@ -0,0 +1,178 @@
+use crate::{
+    add::_add_with_options,
+    init::{index_protocol, Protocol},
+    list::{list, list_names},
+    load_config,
+    lock::HeldLock,
+    store::store_for,
+    util::{open_repo, CommitOptions},
+    validate::{print_problems, validate_config, ValidateOptions},
+    write_config,
+    yank::{_set_yank, YankOptions},
+    AddOptions, IndexConfig, IndexPackage,
+};
+use anyhow::{bail, Error};
+use std::path::{Path, PathBuf};
+
+/// An index that is opened once for a series of operations.
+///
+/// This holds the exclusive lock of the index until it is dropped, so other
+/// processes can't change the index in between, and each operation doesn't
+/// wait for the lock again. The git repository is opened and the
+/// `config.json` file is read once when the index is opened, and are reused
+/// by each operation.
+///
+/// The methods are the same as the functions of the same name. The handle
+/// can't be sent to another thread, and operations on the same index from
+/// other threads wait until it is dropped.
+///
+/// ```rust
+/// # fn main() -> Result<(), anyhow::Error> {
+/// # std::env::set_var("GIT_AUTHOR_NAME", "Index Admin");
+/// # std::env::set_var("GIT_AUTHOR_EMAIL", "admin@example.com");
+/// # let tmp_dir = tempfile::tempdir().unwrap();
+/// # let index_path = tmp_dir.path().join("index");
+/// # reg_index::init(&index_path, "https://example.com", None)?;
+/// let index = reg_index::Index::open(&index_path)?;
+/// println!("dl = {}", index.config().dl);
+/// for name in index.list_names()? {
+///     println!("{}: {}", name, index.list(&name, None)?.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Index {
+    path: PathBuf,
+    /// `None` for a sparse index.
+    repo: Option<git2::Repository>,
+    config: IndexConfig,
+    protocol: Protocol,
+    #[allow(unused)]
+    lock: HeldLock,
+}
+
+impl Index {
+    /// Open the index at `path`, waiting for the lock if another process
+    /// holds it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Index, Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            bail!("Index does not exist at `{}`.", path.display());
+        }
+        let lock = HeldLock::new(path)?;
+        let repo = open_repo(path)?;
+        let config = load_config(path)?;
+        Ok(Index {
+            path: path.to_path_buf(),
+            repo,
+            config,
+            protocol: index_protocol(path),
+            lock,
+        })
+    }
+
+    /// The path of the index.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The configuration in `config.json`.
+    pub fn config(&self) -> &IndexConfig {
+        &self.config
+    }
+
+    /// How Cargo accesses the index.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Modify `config.json` and commit it, see [`update_config`].
+    ///
+    /// [`update_config`]: fn.update_config.html
+    pub fn update_config(
+        &mut self,
+        commit: &CommitOptions,
+        f: impl FnOnce(&mut IndexConfig),
+    ) -> Result<&IndexConfig, Error> {
+        let mut config = self.config.clone();
+        f(&mut config);
+        write_config(&self.path, self.repo.as_ref(), &config, commit)?;
+        self.config = config;
+        Ok(&self.config)
+    }
+
+    /// Add a new entry to the index, see [`add_with_options`].
+    ///
+    /// [`add_with_options`]: fn.add_with_options.html
+    pub fn add(&self, index_url: &str, options: &AddOptions) -> Result<IndexPackage, Error> {
+        _add_with_options(&self.path, self.repo.as_ref(), index_url, options)
+    }
+
+    /// Yank a version, see [`yank`].
+    ///
+    /// [`yank`]: fn.yank.html
+    pub fn yank(&self, pkg_name: &str, version: &str) -> Result<(), Error> {
+        let options = YankOptions::default();
+        _set_yank(
+            &self.path,
+            self.repo.as_ref(),
+            pkg_name,
+            version,
+            true,
+            &options,
+        )
+    }
+
+    /// Unyank a version, see [`unyank`].
+    ///
+    /// [`unyank`]: fn.unyank.html
+    pub fn unyank(&self, pkg_name: &str, version: &str) -> Result<(), Error> {
+        let options = YankOptions::default();
+        _set_yank(
+            &self.path,
+            self.repo.as_ref(),
+            pkg_name,
+            version,
+            false,
+            &options,
+        )
+    }
+
+    /// List the entries of a package, see [`list`].
+    ///
+    /// [`list`]: fn.list.html
+    pub fn list(
+        &self,
+        pkg_name: &str,
+        version_req: Option<&str>,
+    ) -> Result<Vec<IndexPackage>, Error> {
+        list(&self.path, pkg_name, version_req)
+    }
+
+    /// List the names of all packages, see [`list_names`].
+    ///
+    /// [`list_names`]: fn.list_names.html
+    pub fn list_names(&self) -> Result<Vec<String>, Error> {
+        list_names(&self.path)
+    }
+
+    /// Validate the index, see [`validate`].
+    ///
+    /// [`validate`]: fn.validate.html
+    pub fn validate(&self, crates: Option<&str>) -> Result<(), Error> {
+        print_problems(&self.validate_report(crates)?)
+    }
+
+    /// Return the problems with the index, see [`validate_report`].
+    ///
+    /// [`validate_report`]: fn.validate_report.html
+    pub fn validate_report(&self, crates: Option<&str>) -> Result<Vec<String>, Error> {
+        let store = crates.map(store_for);
+        validate_config(
+            &self.path,
+            &self.config,
+            store.as_deref(),
+            &ValidateOptions::default(),
+        )
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{add::_add_from_crate, metadata::MetadataOptions, IndexPackage};
+use anyhow::{Context, Error};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Directory in the drop directory that added `.crate` files are moved to.
+pub const PROCESSED_DIR: &str = "processed";
+
+/// Directory in the drop directory that `.crate` files that could not be
+/// added are moved to.
+pub const FAILED_DIR: &str = "failed";
+
+/// The result of [`ingest_dir`].
+///
+/// [`ingest_dir`]: fn.ingest_dir.html
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct IngestReport {
+    /// Entries that were added, with the file they were added from.
+    pub added: Vec<(PathBuf, IndexPackage)>,
+    /// Files that could not be added, with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Add every `.crate` file in a drop directory to the index.
+///
+/// Each file is added with [`add_from_crate`], with one commit each, and
+/// then moved to the [`PROCESSED_DIR`] directory inside `dir`. Files that
+/// fail are moved to [`FAILED_DIR`] instead, next to a `<file>.error` file
+/// with the reason. Files are tried again while others are being added, so
+/// a crate may arrive in the same batch as the crates it depends on.
+///
+/// Only files ending in `.crate` that don't start with `.` are read, so
+/// writers should copy to another name and rename it into place when it is
+/// complete.
+///
+/// `upload` and `options` are used as for [`add_from_crate`], so the
+/// commits are pushed if [`CommitOptions::push`] is set. An error is only
+/// returned if the directory itself can't be read or written.
+///
+/// [`add_from_crate`]: fn.add_from_crate.html
+/// [`CommitOptions::push`]: struct.CommitOptions.html#structfield.push
+/// [`PROCESSED_DIR`]: constant.PROCESSED_DIR.html
+/// [`FAILED_DIR`]: constant.FAILED_DIR.html
+pub fn ingest_dir(
+    index_path: impl AsRef<Path>,
+    index_url: &str,
+    dir: impl AsRef<Path>,
+    upload: Option<&str>,
+    options: &MetadataOptions,
+) -> Result<IngestReport, Error> {
+    let index_path = index_path.as_ref();
+    let dir = dir.as_ref();
+    let mut pending = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read `{}`.", dir.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        if path.is_file() && name.ends_with(".crate") && !name.starts_with('.') {
+            pending.push(path);
+        }
+    }
+    pending.sort();
+
+    let mut report = IngestReport::default();
+    let mut errors = Vec::new();
+    while !pending.is_empty() {
+        let count = pending.len();
+        errors.clear();
+        for path in pending {
+            match _add_from_crate(index_path, index_url, &path, upload, options) {
+                Ok(index_pkg) => {
+                    let dest = move_to(dir, PROCESSED_DIR, &path)?;
+                    report.added.push((dest, index_pkg));
+                }
+                Err(e) => errors.push((path, format!("{:#}", e))),
+            }
+        }
+        if errors.len() == count {
+            break;
+        }
+        pending = errors.iter().map(|(path, _)| path.clone()).collect();
+    }
+    for (path, reason) in errors {
+        let dest = move_to(dir, FAILED_DIR, &path)?;
+        let mut error_path = dest.clone().into_os_string();
+        error_path.push(".error");
+        fs::write(&error_path, format!("{}\n", reason))
+            .with_context(|| format!("Failed to write `{:?}`.", error_path))?;
+        report.failed.push((dest, reason));
+    }
+    Ok(report)
+}
+
+/// Move a file into the `sub_dir` directory of `dir`, returning the new
+/// path.
+fn move_to(dir: &Path, sub_dir: &str, path: &Path) -> Result<PathBuf, Error> {
+    let dest_dir = dir.join(sub_dir);
+    fs::create_dir_all(&dest_dir)
+        .with_context(|| format!("Failed to create `{}`.", dest_dir.display()))?;
+    let dest = dest_dir.join(path.file_name().unwrap());
+    fs::rename(path, &dest).with_context(|| {
+        format!(
+            "Failed to move `{}` to `{}`.",
+            path.display(),
+            dest.display()
+        )
+    })?;
+    Ok(dest)
+}
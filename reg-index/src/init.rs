@@ -1,39 +1,360 @@
-use crate::util::signature;
+use crate::{
+    util::{find_markers, git_commit, CommitOptions, DL_MARKERS},
+    validate::validate_package_name,
+};
 use anyhow::{bail, Context, Error};
 use std::{fs, path::Path};
+use url::Url;
 
 /// Initialize a new registry index.
 ///
 /// See [`IndexConfig`] for a description of the `dl` and `api` parameters.
+/// See [`init_with_options`] to set [`InitOptions`].
 ///
 /// [`IndexConfig`]: struct.IndexConfig.html
+/// [`init_with_options`]: fn.init_with_options.html
+/// [`InitOptions`]: struct.InitOptions.html
 pub fn init(path: impl AsRef<Path>, dl: &str, api: Option<&str>) -> Result<(), Error> {
-    let path = path.as_ref();
-    if path.exists() {
-        bail!(
-            "Path `{}` already exists. This command requires a non-existent path to create.",
-            path.display()
-        );
+    init_with_options(path, dl, api, &InitOptions::default())
+}
+
+/// Options for creating an index with [`init_with_options`] and
+/// [`init_with_readme`].
+///
+/// [`init_with_options`]: fn.init_with_options.html
+/// [`init_with_readme`]: fn.init_with_readme.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct InitOptions {
+    /// How the index is stored.
+    pub protocol: Protocol,
+    /// Sets `auth-required` in `config.json`, so Cargo sends a token with
+    /// every request to the registry.
+    pub auth_required: bool,
+    /// Options for the initial commit. Not used for a sparse index.
+    pub commit: CommitOptions,
+}
+
+impl InitOptions {
+    /// Sets [`protocol`](#structfield.protocol).
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Sets [`auth_required`](#structfield.auth_required).
+    pub fn auth_required(mut self, auth_required: bool) -> Self {
+        self.auth_required = auth_required;
+        self
+    }
+
+    /// Sets [`commit`](#structfield.commit).
+    pub fn commit(mut self, commit: CommitOptions) -> Self {
+        self.commit = commit;
+        self
+    }
+}
+
+/// Initialize a new registry index with [`InitOptions`].
+///
+/// This is the same as [`init`], and everything in `config.json` is part of
+/// the initial commit.
+///
+/// [`init`]: fn.init.html
+/// [`InitOptions`]: struct.InitOptions.html
+pub fn init_with_options(
+    path: impl AsRef<Path>,
+    dl: &str,
+    api: Option<&str>,
+    options: &InitOptions,
+) -> Result<(), Error> {
+    _init(path.as_ref(), dl, api, None, options)
+}
+
+/// Initialize a new registry index with a `README.md` describing it.
+///
+/// This is the same as [`init`], but the initial commit also has a
+/// `README.md` with the registry `name`, its URLs, and instructions for
+/// using it with Cargo, generated by [`readme`]. The git `description`
+/// file, shown by tools such as gitweb, is set as well, unless the index is
+/// sparse.
+///
+/// [`init`]: fn.init.html
+/// [`readme`]: fn.readme.html
+pub fn init_with_readme(
+    path: impl AsRef<Path>,
+    dl: &str,
+    api: Option<&str>,
+    name: &str,
+    index_url: Option<&str>,
+    options: &InitOptions,
+) -> Result<(), Error> {
+    validate_package_name(name, "registry name")?;
+    _init(path.as_ref(), dl, api, Some((name, index_url)), options)
+}
+
+/// The way Cargo reads an index, which decides how it is stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Protocol {
+    /// A git repository, where every change is a commit. Cargo clones it
+    /// from a `registry+` or plain URL.
+    #[default]
+    Git,
+    /// Plain files without git, for serving from static file storage.
+    /// Cargo fetches them from a `sparse+` URL.
+    ///
+    /// The files are laid out the same as in a git index. Functions that
+    /// need the git history, such as [`history`] and [`snapshot_create`],
+    /// return an error, and the git settings of [`CommitOptions`] are
+    /// ignored.
+    ///
+    /// [`history`]: fn.history.html
+    /// [`snapshot_create`]: fn.snapshot_create.html
+    /// [`CommitOptions`]: struct.CommitOptions.html
+    Sparse,
+}
+
+/// Name of the file that marks an index created by [`init_sparse`].
+///
+/// [`init_sparse`]: fn.init_sparse.html
+pub(crate) const SPARSE_MARKER: &str = ".cargo-index-sparse";
+
+/// Initialize a new registry index for the sparse protocol, without a git
+/// repository.
+///
+/// The index remembers this, so the other functions in this library change
+/// its files without committing them. See [`Protocol::Sparse`] for what is
+/// not supported.
+///
+/// This is the same as [`init_with_options`] with [`Protocol::Sparse`].
+///
+/// [`Protocol::Sparse`]: enum.Protocol.html#variant.Sparse
+/// [`init_with_options`]: fn.init_with_options.html
+pub fn init_sparse(path: impl AsRef<Path>, dl: &str, api: Option<&str>) -> Result<(), Error> {
+    init_with_options(
+        path,
+        dl,
+        api,
+        &InitOptions::default().protocol(Protocol::Sparse),
+    )
+}
+
+fn init_sparse_files(
+    path: &Path,
+    dl: &str,
+    api: Option<&str>,
+    readme_info: Option<(&str, Option<&str>)>,
+    options: &InitOptions,
+) -> Result<(), Error> {
+    fs::create_dir_all(path).with_context(|| format!("Failed to create `{}`.", path.display()))?;
+    write_config_json(path, dl, api, options.auth_required)?;
+    if let Some((name, index_url)) = readme_info {
+        fs::write(path.join("README.md"), readme(name, index_url, dl, api))
+            .with_context(|| "Failed to write README.md")?;
+    }
+    fs::write(
+        path.join(SPARSE_MARKER),
+        "This index is served with the sparse protocol, and is not a git repository.\n",
+    )
+    .with_context(|| format!("Failed to write {}", SPARSE_MARKER))?;
+    Ok(())
+}
+
+/// Returns the protocol an index was created for.
+pub fn index_protocol(index: impl AsRef<Path>) -> Protocol {
+    if index.as_ref().join(SPARSE_MARKER).exists() {
+        Protocol::Sparse
+    } else {
+        Protocol::Git
+    }
+}
+
+/// Generate the `README.md` written by [`init_with_readme`].
+///
+/// `index_url` is the public URL of the index. If `None`, a placeholder is
+/// used in the example configuration.
+///
+/// [`init_with_readme`]: fn.init_with_readme.html
+pub fn readme(name: &str, index_url: Option<&str>, dl: &str, api: Option<&str>) -> String {
+    let url = toml::Value::String(index_url.unwrap_or("<URL of this repository>").to_string());
+    let mut readme = format!(
+        "# `{name}` registry index\n\
+         \n\
+         This repository is the index of the `{name}` Cargo registry. It is managed\n\
+         with [cargo-index](https://github.com/ehuss/cargo-index), entries should not\n\
+         be edited by hand.\n\
+         \n\
+         - Download URL: `{dl}`\n",
+    );
+    match api {
+        Some(api) => readme.push_str(&format!("- API URL: `{}`\n", api.trim_end_matches('/'))),
+        None => readme.push_str("- API URL: none, `cargo publish` is not supported.\n"),
+    }
+    readme.push_str(&format!(
+        "\n\
+         ## Usage\n\
+         \n\
+         Add the registry to `.cargo/config.toml`:\n\
+         \n\
+         ```toml\n\
+         [registries.{name}]\n\
+         index = {url}\n\
+         ```\n\
+         \n\
+         Then depend on packages from the registry in `Cargo.toml`:\n\
+         \n\
+         ```toml\n\
+         [dependencies]\n\
+         example = {{ version = \"1.0\", registry = \"{name}\" }}\n\
+         ```\n",
+    ));
+    if api.is_some() {
+        readme.push_str(&format!(
+            "\nPublish packages with `cargo publish --registry {}`.\n",
+            name
+        ));
+    }
+    readme
+}
+
+fn _init(
+    path: &Path,
+    dl: &str,
+    api: Option<&str>,
+    readme_info: Option<(&str, Option<&str>)>,
+    options: &InitOptions,
+) -> Result<(), Error> {
+    check_new_path(path)?;
+    if options.protocol == Protocol::Sparse {
+        return init_sparse_files(path, dl, api, readme_info, options);
     }
     let repo = git2::Repository::init(path)
         .with_context(|| format!("git failed to initialize `{}`", path.display()))?;
-    let config_json = match api {
-        Some(api) => format!(
-            "{{\n  \"dl\": \"{}\",\n  \"api\": \"{}\"\n}}",
-            dl,
-            api.trim_end_matches('/')
-        ),
-        None => format!("{{\n  \"dl\": \"{}\"\n}}", dl),
-    };
-    let json_path = path.join("config.json");
-    fs::write(&json_path, config_json).with_context(|| "Failed to write config.json")?;
+    write_config_json(path, dl, api, options.auth_required)?;
+    // Keep git from converting entries to CRLF on checkout, such as on
+    // Windows with `core.autocrlf`, which corrupts the index.
+    fs::write(path.join(".gitattributes"), "* text eol=lf\n")
+        .with_context(|| "Failed to write .gitattributes")?;
 
     let mut index = repo.index()?;
+    if let Some((name, index_url)) = readme_info {
+        fs::write(path.join("README.md"), readme(name, index_url, dl, api))
+            .with_context(|| "Failed to write README.md")?;
+        fs::write(
+            repo.path().join("description"),
+            format!("Index of the `{}` Cargo registry.\n", name),
+        )
+        .with_context(|| "Failed to write the git description")?;
+        index.add_path(Path::new("README.md"))?;
+    }
+    index.add_path(Path::new(".gitattributes"))?;
     index.add_path(Path::new("config.json"))?;
     index.write()?;
     let id = index.write_tree()?;
     let tree = repo.find_tree(id)?;
-    let sig = signature(&repo)?;
-    repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])?;
+    git_commit(&repo, &tree, &[], "Initial commit", &options.commit)?;
     Ok(())
 }
+
+fn check_new_path(path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        bail!(
+            "Path `{}` already exists. This command requires a non-existent path to create.",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+fn write_config_json(
+    path: &Path,
+    dl: &str,
+    api: Option<&str>,
+    auth_required: bool,
+) -> Result<(), Error> {
+    let mut fields = vec![format!("\"dl\": \"{}\"", dl)];
+    if let Some(api) = api {
+        fields.push(format!("\"api\": \"{}\"", api.trim_end_matches('/')));
+    }
+    if auth_required {
+        fields.push("\"auth-required\": true".to_string());
+    }
+    let config_json = format!("{{\n  {}\n}}", fields.join(",\n  "));
+    let json_path = path.join("config.json");
+    fs::write(&json_path, config_json).with_context(|| "Failed to write config.json")
+}
+
+/// Check the `dl` and `api` URLs before passing them to [`init`].
+///
+/// Returns an error if a URL is clearly broken, such as not being an
+/// absolute URL or using an unknown marker. Otherwise returns a list of
+/// warnings about common mistakes, which may be empty.
+///
+/// [`init`]: fn.init.html
+pub fn check_urls(dl: &str, api: Option<&str>) -> Result<Vec<String>, Error> {
+    let mut warnings = Vec::new();
+    let dl_url = parse_url(dl, "dl")?;
+    let markers = find_markers(dl)?;
+    if let Some(marker) = markers.iter().find(|m| !DL_MARKERS.contains(m)) {
+        bail!(
+            "Unknown marker `{}` in `--dl` URL `{}`, supported markers are: {}",
+            marker,
+            dl,
+            DL_MARKERS.join(", ")
+        );
+    }
+    if markers.is_empty() && dl_url.path().trim_end_matches('/').ends_with("/download") {
+        warnings.push(format!(
+            "The `--dl` URL `{}` ends with `/download` but has no markers, \
+             Cargo will append `/{{crate}}/{{version}}/download` to it.",
+            dl
+        ));
+    }
+    check_scheme(&dl_url, dl, "--dl", &mut warnings);
+    if let Some(api) = api {
+        let api_url = parse_url(api, "api")?;
+        let path = api_url.path().trim_end_matches('/');
+        if path.ends_with("/api/v1") {
+            warnings.push(format!(
+                "The `--api` URL `{}` should not include the `/api/v1` path, \
+                 Cargo will append `/api/v1` to it.",
+                api
+            ));
+        }
+        check_scheme(&api_url, api, "--api", &mut warnings);
+        if dl_url.scheme() == "file" && api_url.scheme() != "file" {
+            warnings.push(format!(
+                "The `--dl` URL `{}` is a `file:` URL, but the registry has a \
+                 network API, other machines will not be able to download crates.",
+                dl
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+fn parse_url(url: &str, what: &str) -> Result<Url, Error> {
+    let parsed = Url::parse(url)
+        .with_context(|| format!("`--{}` value `{}` is not a valid absolute URL", what, url))?;
+    match parsed.scheme() {
+        "http" | "https" | "file" => Ok(parsed),
+        scheme => bail!(
+            "`--{}` value `{}` has unsupported scheme `{}`, expected `https`, `http`, or `file`.",
+            what,
+            url,
+            scheme
+        ),
+    }
+}
+
+fn check_scheme(parsed: &Url, url: &str, what: &str, warnings: &mut Vec<String>) {
+    if parsed.scheme() == "http"
+        && !matches!(parsed.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+    {
+        warnings.push(format!(
+            "The `{}` URL `{}` uses `http`, consider using `https`.",
+            what, url
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{
+    util::{expand_markers, open_git, IGNORE_FILE},
+    IndexPackage,
+};
+use anyhow::{bail, Context, Error};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// Prefix of an `upload` path that stores `.crate` files in the index with
+/// Git LFS.
+pub(crate) const LFS_PREFIX: &str = "lfs:";
+
+/// Files changed by [`store`] that must be committed with the upload.
+pub(crate) const LFS_CONFIG_FILES: [&str; 2] = [".gitattributes", IGNORE_FILE];
+
+/// A `.crate` file stored with Git LFS, waiting to be staged.
+pub(crate) struct LfsObject {
+    /// Repo-relative path of the `.crate` file.
+    repo_path: PathBuf,
+    /// Contents of the LFS pointer file that is committed in its place.
+    pointer: String,
+}
+
+/// Directory that `upload` places the `.crate` file of `pkg` in.
+///
+/// An `lfs:` path is relative to the index.
+pub(crate) fn upload_dir(index_path: &Path, upload: &str, pkg: &IndexPackage) -> PathBuf {
+    match upload.strip_prefix(LFS_PREFIX) {
+        Some(template) => index_path.join(expand_markers(template, pkg)),
+        None => PathBuf::from(expand_markers(upload, pkg)),
+    }
+}
+
+/// Returns an error if `upload` is an `lfs:` path that can't be used.
+///
+/// This is checked before changing the index, since [`store`] is only
+/// called after the entry is written.
+pub(crate) fn check_upload(upload: Option<&str>) -> Result<(), Error> {
+    if let Some(template) = upload.and_then(|upload| upload.strip_prefix(LFS_PREFIX)) {
+        top_dir(template)?;
+    }
+    Ok(())
+}
+
+/// Stores a `.crate` file in the index for an `lfs:` upload path.
+///
+/// The file is written to the working directory and to the LFS object
+/// store in the git directory, the way `git lfs` does when it is added.
+/// `.gitattributes` and `.indexignore` are updated to cover the top
+/// directory of the template, so the caller must commit
+/// [`LFS_CONFIG_FILES`] along with the entry and call [`stage`].
+pub(crate) fn store(
+    index_path: &Path,
+    template: &str,
+    pkg: &IndexPackage,
+    crate_path: &Path,
+) -> Result<LfsObject, Error> {
+    let top = top_dir(template)?;
+    let dir = Path::new(&expand_markers(template, pkg)).to_path_buf();
+    if dir.components().any(|c| !matches!(c, Component::Normal(_))) {
+        bail!(
+            "The `{}` upload path `{}` must stay inside the index.",
+            LFS_PREFIX,
+            template
+        );
+    }
+    let repo_path = dir.join(crate_path.file_name().unwrap());
+    let contents = fs::read(crate_path)
+        .with_context(|| format!("Failed to read `{}`.", crate_path.display()))?;
+    let dest = index_path.join(&repo_path);
+    fs::create_dir_all(dest.parent().unwrap())?;
+    fs::write(&dest, &contents)
+        .with_context(|| format!("Failed to write `{}`.", dest.display()))?;
+    let oid = &pkg.cksum;
+    let git_dir = open_git(index_path)?.path().to_path_buf();
+    let object = git_dir
+        .join("lfs/objects")
+        .join(&oid[..2])
+        .join(&oid[2..4])
+        .join(oid);
+    fs::create_dir_all(object.parent().unwrap())?;
+    fs::write(&object, &contents)
+        .with_context(|| format!("Failed to write `{}`.", object.display()))?;
+    add_line(
+        &index_path.join(".gitattributes"),
+        &format!("{}/** filter=lfs diff=lfs merge=lfs -text", top),
+    )?;
+    add_line(&index_path.join(IGNORE_FILE), top)?;
+    Ok(LfsObject {
+        repo_path,
+        pointer: format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+            oid,
+            contents.len()
+        ),
+    })
+}
+
+/// Adds the LFS pointers of the objects to the git index.
+pub(crate) fn stage(repo: Option<&git2::Repository>, objects: &[LfsObject]) -> Result<(), Error> {
+    let Some(repo) = repo else {
+        return Ok(());
+    };
+    if objects.is_empty() {
+        return Ok(());
+    }
+    let mut index = repo.index()?;
+    for object in objects {
+        let entry = git2::IndexEntry {
+            ctime: git2::IndexTime::new(0, 0),
+            mtime: git2::IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            file_size: object.pointer.len() as u32,
+            id: git2::Oid::zero(),
+            flags: 0,
+            flags_extended: 0,
+            path: object
+                .repo_path
+                .to_str()
+                .unwrap()
+                .replace('\\', "/")
+                .into_bytes(),
+        };
+        index.add_frombuffer(&entry, object.pointer.as_bytes())?;
+    }
+    index.write()?;
+    Ok(())
+}
+
+/// The first directory of an `lfs:` template.
+///
+/// It can't have markers, and must be longer than the one and two
+/// character directories used by entries.
+fn top_dir(template: &str) -> Result<&str, Error> {
+    let top = template.split('/').next().unwrap_or_default();
+    if top.len() <= 2 || top.contains('{') {
+        bail!(
+            "The `{}` upload path `{}` must start with a directory that has no markers \
+             and is longer than two characters, such as `{}crates/{{crate}}`.",
+            LFS_PREFIX,
+            template,
+            LFS_PREFIX
+        );
+    }
+    Ok(top)
+}
+
+/// Appends `line` to the file at `path` unless it already has it.
+fn add_line(path: &Path, line: &str) -> Result<(), Error> {
+    let mut contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(Error::from(e).context(format!("Failed to read `{}`.", path.display())))
+        }
+    };
+    if contents.lines().any(|l| l.trim() == line) {
+        return Ok(());
+    }
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(line);
+    contents.push('\n');
+    fs::write(path, contents).with_context(|| format!("Failed to write `{}`.", path.display()))
+}
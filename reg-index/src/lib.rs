@@ -37,126 +37,112 @@ println!("{}", serde_json::to_string(&pkgs[0])?);
 See https://doc.rust-lang.org/cargo/reference/registries.html for
 documentation about Cargo registries.
 
+## Errors
+Functions return [`anyhow::Error`]. Failures that callers may want to
+handle, such as a version that is already in the index, hold an
+[`IndexError`] that can be found with `downcast_ref`.
+
+[`anyhow::Error`]: https://docs.rs/anyhow/1/anyhow/struct.Error.html
+[`IndexError`]: enum.IndexError.html
+
 ## Locking
 The functions here perform simple filesystem locking to ensure multiple
 commands running at the same time do not interfere with one another. This
-requires that the filesystem supports locking.
+requires that the filesystem supports locking. An [`Index`] holds the lock
+across several operations.
+
+[`Index`]: struct.Index.html
 */
 
 use anyhow::{Context, Error};
-use semver::{Version, VersionReq};
-use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fs, path::Path};
-use url::Url;
+use std::{fs, path::Path};
 
 mod add;
+mod advisory;
+mod changed;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod diff;
+mod error;
+mod export;
+mod fetch;
+mod history;
+mod import;
+mod index;
+mod ingest;
 mod init;
+mod lfs;
 mod list;
 mod lock;
 mod metadata;
+mod migrate;
+mod prune;
+mod rdeps;
+mod remote;
+mod schema;
+mod selftest;
+mod serve;
+mod settings;
+mod snapshot;
+mod snippet;
+mod stats;
+mod store;
 mod util;
 mod validate;
+mod verify;
 mod yank;
 
-pub use add::{add, add_from_crate, force_add};
-pub use cargo_metadata::DependencyKind;
-pub use init::init;
-pub use list::{list, list_all};
-pub use metadata::{metadata, metadata_from_crate};
-pub use validate::validate;
-pub use yank::{set_yank, unyank, yank};
-
-/// An entry for a single version of a package in the index.
-#[derive(Clone, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-#[non_exhaustive]
-pub struct IndexPackage {
-    /// The name of the package.
-    pub name: String,
-    /// The version of the package.
-    pub vers: Version,
-    /// List of direct dependencies of the package.
-    pub deps: Vec<IndexDependency>,
-    /// Cargo features defined in the package.
-    pub features: BTreeMap<String, Vec<String>>,
-    /// Cargo features2 defined in the package.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub features2: Option<BTreeMap<String, Vec<String>>>,
-    /// Checksum of the `.crate` file.
-    pub cksum: String,
-    /// Whether or not this package is yanked.
-    pub yanked: bool,
-    /// Optional string that is the name of a native library the package is
-    /// linking to.
-    pub links: Option<String>,
-    /// Versioning of Index Package
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub v: Option<u8>,
-}
-
-/// A dependency of a package.
-#[derive(Clone, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-#[non_exhaustive]
-pub struct IndexDependency {
-    /// Name of the dependency.
-    ///
-    /// If the dependency is renamed from the original package name,
-    /// this is the new name. The original package name is stored in
-    /// the `package` field.
-    pub name: String,
-    /// The semver requirement for this dependency.
-    pub req: VersionReq,
-    /// List of features enabled for this dependency.
-    pub features: Vec<String>,
-    /// Whether or not this is an optional dependency.
-    pub optional: bool,
-    /// Whether or not default features are enabled.
-    pub default_features: bool,
-    /// The target platform for the dependency.
-    pub target: Option<String>,
-    /// The dependency kind.
-    // Required, but crates.io has some broken missing entries.
-    #[serde(default, deserialize_with = "parse_dependency_kind")]
-    pub kind: DependencyKind,
-    /// The URL of the index of the registry where this dependency is from.
-    ///
-    /// If not specified or null, it is assumed the dependency is in the
-    /// current registry.
-    #[serde(default)]
-    pub registry: Option<Url>,
-    /// If the dependency is renamed, this is a string of the actual package
-    /// name. If None, this dependency is not renamed.
-    pub package: Option<String>,
-}
-
-fn parse_dependency_kind<'de, D>(d: D) -> Result<DependencyKind, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    serde::Deserialize::deserialize(d).map(|x: Option<_>| x.unwrap_or_default())
-}
-
-/// The configuration file of the index.
-///
-/// This is stored in the root of the index repo as `config.json`.
-#[derive(Serialize, Deserialize)]
-#[non_exhaustive]
-pub struct IndexConfig {
-    /// URL that Cargo uses to download crates.
-    ///
-    /// This can have the markers `{crate}` and `{version}`. If the markers
-    /// are not present, Cargo automatically appends
-    /// `/{crate}/{version}/download` to the end.
-    pub dl: Url,
-    /// URL that Cargo uses for the web API (publish/yank/search/etc.).
-    ///
-    /// This is optional. If not specified, Cargo will refuse to publish to
-    /// this registry.
-    #[serde(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api: Option<Url>,
-}
+pub use add::{
+    add, add_from_crate, add_from_crates, add_from_metadata, add_with_options, check,
+    check_from_crate, force_add, sort_crates, AddOptions, ConfirmReplace,
+};
+pub use advisory::{yank_advisories, YankAdvisory, YANK_REASON_TRAILER};
+pub use changed::{changed_packages, workspace_packages};
+#[cfg(feature = "parquet")]
+pub use columnar::{export_parquet, ParquetTable};
+pub use diff::{diff_indexes, diff_revisions, IndexChange};
+pub use error::IndexError;
+pub use export::{export, ExportFormat};
+pub use fetch::{fetch_deps, FetchReport};
+pub use history::{
+    activity, entry_dates, format_time, history, who_published, Activity, ActivityGrouping,
+    EntryDates, HistoryEvent, HistoryEventKind, Publication,
+};
+pub use import::import;
+pub use index::Index;
+pub use ingest::{ingest_dir, IngestReport, FAILED_DIR, PROCESSED_DIR};
+pub use init::{
+    check_urls, index_protocol, init, init_sparse, init_with_options, init_with_readme, readme,
+    InitOptions, Protocol,
+};
+pub use list::{
+    list, list_all, list_filtered, list_names, list_with_options, ListOptions, Prerelease, Yanked,
+};
+pub use metadata::{
+    canonical_url, metadata, metadata_from_crate, metadata_from_crate_with_options,
+    metadata_with_options, read_registry_rewrites, MetadataOptions,
+};
+pub use migrate::{migrate_from, MigrateReport};
+pub use prune::prune_yanked;
+pub use rdeps::{rdeps, ReverseDependency};
+pub use reg_index_core::{
+    BuildMetadataPolicy, DependencyKind, IndexConfig, IndexDependency, IndexPackage,
+};
+pub use remote::{is_remote_index, with_remote_index};
+pub use schema::{schema_features, schema_report, CargoVersion, SchemaFeature, SchemaReport};
+pub use selftest::selftest;
+pub use serve::serve;
+pub use settings::{PackageSettings, INDEX_SETTING_TRAILER, REVIEWED_BY_TRAILER};
+pub use snapshot::{snapshot_create, snapshot_list, snapshot_restore, Snapshot};
+pub use snippet::config_snippet;
+pub use stats::{stats, IndexStats, PackageStats};
+pub use store::{CrateStore, DirStore, UrlStore, UPLOAD_AUTHORIZATION_ENV};
+pub use util::CommitOptions;
+pub use validate::{
+    validate, validate_report, validate_store, validate_with_options, ValidateOptions,
+};
+pub use verify::{verify_crate, CrateLimits};
+pub use yank::{set_yank, set_yank_matching, set_yank_with_options, unyank, yank, YankOptions};
 
 /// Return the configuration file in an index.
 pub fn load_config(index: impl AsRef<Path>) -> Result<IndexConfig, Error> {
@@ -167,3 +153,83 @@ pub fn load_config(index: impl AsRef<Path>) -> Result<IndexConfig, Error> {
         .with_context(|| format!("Failed to deserialize `{}`.", path.display()))?;
     Ok(index_cfg)
 }
+
+/// Write the configuration file of an index and commit it.
+///
+/// Fields in `config.json` that are not part of [`IndexConfig`] are kept
+/// as-is. See [`update_config`] to modify the existing configuration.
+///
+/// [`IndexConfig`]: struct.IndexConfig.html
+/// [`update_config`]: fn.update_config.html
+pub fn save_config(
+    index: impl AsRef<Path>,
+    config: &IndexConfig,
+    commit: &CommitOptions,
+) -> Result<(), Error> {
+    let index = index.as_ref();
+    let lock = lock::Lock::new_exclusive(index)?;
+    write_config(index, util::open_repo(index)?.as_ref(), config, commit)?;
+    drop(lock);
+    Ok(())
+}
+
+/// Modify the configuration file of an index and commit it.
+///
+/// This loads `config.json`, calls `f` to change it, and then writes it
+/// back while holding the index lock, such as to change the `dl` or `api`
+/// URLs.
+pub fn update_config(
+    index: impl AsRef<Path>,
+    commit: &CommitOptions,
+    f: impl FnOnce(&mut IndexConfig),
+) -> Result<IndexConfig, Error> {
+    let index = index.as_ref();
+    let lock = lock::Lock::new_exclusive(index)?;
+    let mut config = load_config(index)?;
+    f(&mut config);
+    write_config(index, util::open_repo(index)?.as_ref(), &config, commit)?;
+    drop(lock);
+    Ok(config)
+}
+
+/// Write `config.json` and commit it. The caller is responsible for locking.
+pub(crate) fn write_config(
+    index: &Path,
+    repo: Option<&git2::Repository>,
+    config: &IndexConfig,
+    commit: &CommitOptions,
+) -> Result<(), Error> {
+    let path = index.join("config.json");
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+    let mut other: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to deserialize `{}`.", path.display()))?;
+    other.remove("dl");
+    other.remove("api");
+    other.remove("auth-required");
+    // Write the same layout as `init`, with `Url`'s encoding of the markers
+    // undone.
+    let mut fields = vec![(
+        "dl".to_string(),
+        serde_json::Value::String(util::decode_braces(config.dl.as_str())),
+    )];
+    if let Some(api) = &config.api {
+        fields.push((
+            "api".to_string(),
+            serde_json::Value::String(api.as_str().trim_end_matches('/').to_string()),
+        ));
+    }
+    if config.auth_required {
+        fields.push(("auth-required".to_string(), serde_json::Value::Bool(true)));
+    }
+    fields.extend(other);
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("  {}: {}", serde_json::Value::from(key.as_str()), value))
+        .collect();
+    let contents = format!("{{\n{}\n}}", fields.join(",\n"));
+    fs::write(&path, contents).with_context(|| format!("Failed to write `{}`.", path.display()))?;
+    add::git_add(repo, &["config.json"], "Update config.json", commit)
+        .with_context(|| "Failed to add to git repo.")?;
+    Ok(())
+}
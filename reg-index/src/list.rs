@@ -2,9 +2,11 @@ use super::IndexPackage;
 use crate::{
     lock::Lock,
     util::{crate_walker, pkg_path},
+    BuildMetadataPolicy,
 };
 use anyhow::{Context, Error};
-use semver::VersionReq;
+use reg_index_core::parse_entries;
+use semver::{BuildMetadata, Prerelease as SemverPrerelease, Version, VersionReq};
 use std::{fs, path::Path};
 
 /// List entries in the index.
@@ -62,35 +64,202 @@ pub fn list_all(
     Ok(())
 }
 
+/// How to treat prerelease versions, such as `1.0.0-beta.1`, when matching
+/// a version requirement.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Prerelease {
+    /// Match the way Cargo does. Prereleases only match if the requirement
+    /// explicitly mentions a prerelease of the same `major.minor.patch`,
+    /// such as `>=1.0.0-alpha`. Without a requirement, prereleases are
+    /// excluded.
+    #[default]
+    Cargo,
+    /// Include prereleases, matching them as if they were the release they
+    /// precede.
+    Include,
+    /// Never include prereleases.
+    Exclude,
+}
+
+impl Prerelease {
+    /// Whether `vers` matches the optional requirement under this policy.
+    pub fn matches(self, version_req: Option<&VersionReq>, vers: &Version) -> bool {
+        match self {
+            Prerelease::Cargo => match version_req {
+                Some(req) => req.matches(vers),
+                None => vers.pre.is_empty(),
+            },
+            Prerelease::Include => match version_req {
+                Some(req) => {
+                    let mut release = vers.clone();
+                    release.pre = SemverPrerelease::EMPTY;
+                    release.build = BuildMetadata::EMPTY;
+                    req.matches(vers) || req.matches(&release)
+                }
+                None => true,
+            },
+            Prerelease::Exclude => {
+                vers.pre.is_empty() && version_req.map_or(true, |req| req.matches(vers))
+            }
+        }
+    }
+}
+
+/// List entries in the index, with control over prereleases.
+///
+/// This is the same as [`list_all`], except that prerelease versions are
+/// handled according to `prerelease`. Note that [`list_all`] includes
+/// prereleases when no requirement is given, whereas the default
+/// [`Prerelease::Cargo`] excludes them.
+///
+/// [`list_all`]: fn.list_all.html
+/// [`Prerelease::Cargo`]: enum.Prerelease.html#variant.Cargo
+pub fn list_filtered(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+    version_req: Option<&str>,
+    prerelease: Prerelease,
+    cb: impl FnMut(Vec<IndexPackage>),
+) -> Result<(), Error> {
+    let options = ListOptions {
+        prerelease,
+        ..ListOptions::default()
+    };
+    list_with_options(index, pkg_name, version_req, &options, cb)
+}
+
+/// Which entries to include based on whether they are yanked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Yanked {
+    /// Include both yanked and un-yanked entries.
+    #[default]
+    Include,
+    /// Only include entries that are not yanked.
+    Exclude,
+    /// Only include yanked entries.
+    Only,
+}
+
+/// Options for [`list_with_options`], which filter the entries listed.
+///
+/// New options may be added in future versions, so create this with
+/// `ListOptions::default()` and set the fields that are needed.
+///
+/// [`list_with_options`]: fn.list_with_options.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ListOptions {
+    /// How to treat prerelease versions, as in [`list_filtered`].
+    ///
+    /// [`list_filtered`]: fn.list_filtered.html
+    pub prerelease: Prerelease,
+    /// How to treat build metadata in the version requirement.
+    ///
+    /// Version requirements never look at build metadata. With
+    /// [`BuildMetadataPolicy::Require`], if the version requirement is a
+    /// version with build metadata, such as `=1.2.3+abc`, only entries with
+    /// the same build metadata are included.
+    ///
+    /// [`BuildMetadataPolicy::Require`]: enum.BuildMetadataPolicy.html#variant.Require
+    pub build_metadata: BuildMetadataPolicy,
+    /// Whether to include yanked entries.
+    pub yanked: Yanked,
+    /// Only include the newest of the matching entries of each package.
+    pub latest: bool,
+}
+
+/// List entries in the index, filtered by `options`.
+///
+/// This is the same as [`list_filtered`], with the additional filters of
+/// [`ListOptions`]. With `latest`, the newest entry is picked after the
+/// other filters, so with [`Yanked::Exclude`] it is the newest un-yanked
+/// version.
+///
+/// [`list_filtered`]: fn.list_filtered.html
+/// [`ListOptions`]: struct.ListOptions.html
+/// [`Yanked::Exclude`]: enum.Yanked.html#variant.Exclude
+pub fn list_with_options(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+    version_req: Option<&str>,
+    options: &ListOptions,
+    mut cb: impl FnMut(Vec<IndexPackage>),
+) -> Result<(), Error> {
+    let build = match (options.build_metadata, version_req) {
+        (BuildMetadataPolicy::Require, Some(version_req)) => {
+            Version::parse(version_req.trim().trim_start_matches('=').trim())
+                .ok()
+                .map(|vers| vers.build)
+                .filter(|build| !build.is_empty())
+        }
+        _ => None,
+    };
+    let version_req = if let Some(version_req) = version_req {
+        Some(VersionReq::parse(version_req)?)
+    } else {
+        None
+    };
+    list_all(index, pkg_name, None, |entries| {
+        let mut entries: Vec<IndexPackage> = entries
+            .into_iter()
+            .filter(|entry| {
+                options
+                    .prerelease
+                    .matches(version_req.as_ref(), &entry.vers)
+            })
+            .filter(|entry| {
+                build
+                    .as_ref()
+                    .map_or(true, |build| entry.vers.build == *build)
+            })
+            .filter(|entry| match options.yanked {
+                Yanked::Include => true,
+                Yanked::Exclude => !entry.yanked,
+                Yanked::Only => entry.yanked,
+            })
+            .collect();
+        if options.latest {
+            entries = entries
+                .into_iter()
+                .max_by(|a, b| a.vers.cmp(&b.vers))
+                .into_iter()
+                .collect();
+        }
+        cb(entries)
+    })
+}
+
+/// List the names of all packages in the index.
+///
+/// This only looks at the file names in the index, without reading or
+/// parsing any entries, so it is fast even for very large indexes. Since
+/// the file names are lowercase, the names returned are lowercase. The
+/// names are sorted.
+pub fn list_names(index: impl AsRef<Path>) -> Result<Vec<String>, Error> {
+    let index = index.as_ref();
+    let lock = Lock::new_shared(index)?;
+    let mut names = crate_walker(index)
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<String>, Error>>()?;
+    names.sort();
+    drop(lock);
+    Ok(names)
+}
+
 pub(crate) fn _list(
     index: &Path,
     pkg_name: &str,
     version_req: Option<&VersionReq>,
 ) -> Result<Vec<IndexPackage>, Error> {
-    let repo_path = pkg_path(pkg_name);
+    let repo_path = pkg_path(pkg_name)?;
     let path = index.join(repo_path);
     if !path.exists() {
         return Ok(vec![]);
     }
     let contents = fs::read_to_string(&path)
         .with_context(|| format!("Failed to read `{}`.", path.display()))?;
-    contents
-        .lines()
-        .map(|line| {
-            Ok(serde_json::from_str(line).with_context(|| {
-                format!("Could not deserialize `{}` line:\n{}", path.display(), line)
-            })?)
-        })
-        .filter(|index_pkg: &Result<IndexPackage, Error>| -> bool {
-            if let Some(version_req) = &version_req {
-                if let Ok(index_pkg) = index_pkg {
-                    version_req.matches(&index_pkg.vers)
-                } else {
-                    true
-                }
-            } else {
-                true
-            }
-        })
-        .collect::<Result<Vec<IndexPackage>, Error>>()
+    parse_entries(&contents, version_req)
+        .with_context(|| format!("Failed to parse `{}`.", path.display()))
 }
@@ -1,33 +1,99 @@
 use anyhow::Error;
 use fs2::FileExt;
 use std::{
-    fs::{File, OpenOptions},
-    path::Path,
+    cell::RefCell,
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    marker::PhantomData,
+    path::{Path, PathBuf},
 };
 
+thread_local! {
+    /// Indexes whose exclusive lock is held on this thread by a
+    /// [`HeldLock`], with how many handles hold it.
+    static HELD: RefCell<HashMap<PathBuf, usize>> = RefCell::new(HashMap::new());
+}
+
 pub struct Lock {
     #[allow(unused)]
-    file: File,
+    file: Option<File>,
 }
 
 impl Lock {
     pub fn new_exclusive(path: impl AsRef<Path>) -> Result<Lock, Error> {
-        let file = OpenOptions::new()
-            .read(true)
-            .create(true)
-            .write(true)
-            .open(path.as_ref().join(".cargo-index-lock"))?;
-        file.lock_exclusive()?;
-        Ok(Lock { file })
+        if is_held(path.as_ref()) {
+            return Ok(Lock { file: None });
+        }
+        let file = open_lock_file(path.as_ref())?;
+        FileExt::lock_exclusive(&file)?;
+        Ok(Lock { file: Some(file) })
     }
 
     pub fn new_shared(path: impl AsRef<Path>) -> Result<Lock, Error> {
-        let file = OpenOptions::new()
-            .read(true)
-            .create(true)
-            .write(true)
-            .open(path.as_ref().join(".cargo-index-lock"))?;
-        file.lock_shared()?;
-        Ok(Lock { file })
+        if is_held(path.as_ref()) {
+            return Ok(Lock { file: None });
+        }
+        let file = open_lock_file(path.as_ref())?;
+        FileExt::lock_shared(&file)?;
+        Ok(Lock { file: Some(file) })
+    }
+}
+
+/// An exclusive lock on an index that lasts until it is dropped.
+///
+/// While it is held, the locks taken by each operation on the same thread
+/// succeed without waiting, so a series of operations is not interleaved
+/// with other processes. It can't be sent to another thread, since the
+/// lock is only recognized on the thread that took it.
+pub struct HeldLock {
+    path: PathBuf,
+    #[allow(unused)]
+    lock: Lock,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl HeldLock {
+    pub fn new(path: impl AsRef<Path>) -> Result<HeldLock, Error> {
+        let path = fs::canonicalize(path)?;
+        let lock = Lock::new_exclusive(&path)?;
+        HELD.with(|held| *held.borrow_mut().entry(path.clone()).or_default() += 1);
+        Ok(HeldLock {
+            path,
+            lock,
+            _not_send: PhantomData,
+        })
     }
 }
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(count) = held.get_mut(&self.path) {
+                *count -= 1;
+                if *count == 0 {
+                    held.remove(&self.path);
+                }
+            }
+        });
+    }
+}
+
+fn is_held(path: &Path) -> bool {
+    HELD.with(|held| {
+        let held = held.borrow();
+        !held.is_empty()
+            && fs::canonicalize(path)
+                .map(|path| held.contains_key(&path))
+                .unwrap_or(false)
+    })
+}
+
+fn open_lock_file(path: &Path) -> Result<File, Error> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.join(".cargo-index-lock"))?)
+}
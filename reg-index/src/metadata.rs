@@ -1,30 +1,247 @@
 use crate::{
-    util::{cargo_package, cksum, extract_crate},
-    IndexDependency, IndexPackage,
+    settings::PackageSettings,
+    util::{self, cargo_package, cksum, extract_crate, CommitOptions},
+    BuildMetadataPolicy, DependencyKind, IndexDependency, IndexPackage,
 };
 use anyhow::{bail, format_err, Context, Error};
+use percent_encoding::percent_decode_str;
 use same_file::is_same_file;
 use std::{
-    env,
+    collections::BTreeMap,
+    env, fs,
     path::{Path, PathBuf},
 };
 use url::Url;
 
+/// Options for generating the entry for a package.
+///
+/// Used by [`metadata_with_options`] and [`add_with_options`].
+///
+/// [`metadata_with_options`]: fn.metadata_with_options.html
+/// [`add_with_options`]: fn.add_with_options.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct MetadataOptions {
+    /// If `true`, dependencies from the same registry as the index are
+    /// written with the explicit registry URL instead of `null`.
+    ///
+    /// This can help tools that merge entries from multiple indexes, but
+    /// note that Cargo expects `null` for dependencies in the same registry.
+    pub explicit_registry: bool,
+    /// Rules to replace the registry of dependencies, such as to point
+    /// dependencies at internal mirrors.
+    ///
+    /// The key is the index URL of the original registry, or `crates-io`
+    /// for crates.io. URLs are compared with [`canonical_url`]. See
+    /// [`read_registry_rewrites`] to load these from a file.
+    ///
+    /// [`canonical_url`]: fn.canonical_url.html
+    /// [`read_registry_rewrites`]: fn.read_registry_rewrites.html
+    pub registry_rewrites: BTreeMap<String, Url>,
+    /// If `true`, run `cargo package` a second time and return an error if
+    /// the two `.crate` files are not byte-identical.
+    ///
+    /// This catches non-determinism, such as timestamps or generated files,
+    /// before the checksum is recorded in the index. This cannot be used
+    /// with a pre-built `.crate` file.
+    pub check_reproducible: bool,
+    /// If set, return an error if the checksum of the `.crate` file is not
+    /// this value, such as a checksum recorded from an earlier build.
+    pub expected_cksum: Option<String>,
+    /// How build metadata is treated when checking if the version is
+    /// already in the index.
+    ///
+    /// A forced add only replaces the entry with exactly the same version,
+    /// including build metadata, and otherwise adds a new entry.
+    pub build_metadata: BuildMetadataPolicy,
+    /// If `true`, dev-dependencies are left out of the entry.
+    ///
+    /// Cargo does not use dev-dependencies from the index, so this makes
+    /// entries smaller and avoids exposing test-only dependencies. Indexes
+    /// may mix entries with and without dev-dependencies.
+    pub omit_dev_deps: bool,
+    /// If `true`, the [`add`] family of functions return an error unless the
+    /// version is greater than every version of the package already in the
+    /// index, including yanked versions.
+    ///
+    /// Versions are compared by semver precedence, so prereleases are older
+    /// than their release and build metadata is ignored. Forced adds are not
+    /// checked.
+    ///
+    /// [`add`]: fn.add.html
+    pub require_newer: bool,
+    /// If `true`, the [`add`] family of functions run `cargo semver-checks`
+    /// to compare the package with the newest older version in the index,
+    /// and return an error if it finds breaking changes that are not allowed
+    /// by the version bump. `cargo-semver-checks` must be installed.
+    ///
+    /// The `.crate` file of the older version is read from the `upload`
+    /// directory, or from the `dl` location if it is a `file:` URL.
+    /// Versions from [`add_from_crates`] are not checked.
+    ///
+    /// [`add`]: fn.add.html
+    /// [`add_from_crates`]: fn.add_from_crates.html
+    pub semver_checks: bool,
+    /// If `true`, the [`add`] family of functions allow a package whose
+    /// name differs from another package in the index only by `-` and `_`
+    /// or by case. By default this is an error, as it is on crates.io, since
+    /// Cargo treats the names as the same package.
+    ///
+    /// [`add`]: fn.add.html
+    pub allow_name_conflicts: bool,
+    /// If `true`, the [`add`] family of functions fail if the working tree
+    /// of a git index has changes to entries or `config.json` that are not
+    /// committed.
+    ///
+    /// [`add`]: fn.add.html
+    pub require_clean: bool,
+    /// Options for the commit made by the [`add`] family of functions.
+    ///
+    /// [`add`]: fn.add.html
+    pub commit: CommitOptions,
+}
+
+impl MetadataOptions {
+    /// Sets whether dependencies from the same registry have an explicit URL.
+    pub fn explicit_registry(mut self, explicit: bool) -> Self {
+        self.explicit_registry = explicit;
+        self
+    }
+
+    /// Sets the rules to replace the registry of dependencies.
+    pub fn registry_rewrites(mut self, rewrites: BTreeMap<String, Url>) -> Self {
+        self.registry_rewrites = rewrites;
+        self
+    }
+
+    /// Sets whether `cargo package` must produce the same `.crate` file twice.
+    pub fn check_reproducible(mut self, check: bool) -> Self {
+        self.check_reproducible = check;
+        self
+    }
+
+    /// Sets the checksum the `.crate` file must have.
+    pub fn expected_cksum(mut self, cksum: impl Into<String>) -> Self {
+        self.expected_cksum = Some(cksum.into());
+        self
+    }
+
+    /// Sets how build metadata is treated when checking for the version.
+    pub fn build_metadata(mut self, policy: BuildMetadataPolicy) -> Self {
+        self.build_metadata = policy;
+        self
+    }
+
+    /// Sets whether dev-dependencies are left out of the entry.
+    pub fn omit_dev_deps(mut self, omit: bool) -> Self {
+        self.omit_dev_deps = omit;
+        self
+    }
+
+    /// Sets whether the version must be newer than every other version.
+    pub fn require_newer(mut self, require: bool) -> Self {
+        self.require_newer = require;
+        self
+    }
+
+    /// Sets whether to run `cargo semver-checks`.
+    pub fn semver_checks(mut self, check: bool) -> Self {
+        self.semver_checks = check;
+        self
+    }
+
+    /// Sets whether names that differ only by `-`, `_`, or case are allowed.
+    pub fn allow_name_conflicts(mut self, allow: bool) -> Self {
+        self.allow_name_conflicts = allow;
+        self
+    }
+
+    /// Sets whether the working tree of the index must be clean.
+    pub fn require_clean(mut self, require: bool) -> Self {
+        self.require_clean = require;
+        self
+    }
+
+    /// Sets the options for the commit.
+    pub fn commit(mut self, commit: CommitOptions) -> Self {
+        self.commit = commit;
+        self
+    }
+}
+
+/// Load registry rewrite rules for [`MetadataOptions`] from a file.
+///
+/// Each line has the form `FROM -> TO`, where `FROM` is an index URL or
+/// `crates-io`, and `TO` is the index URL to use instead. Blank lines and
+/// lines starting with `#` are ignored.
+///
+/// [`MetadataOptions`]: struct.MetadataOptions.html
+pub fn read_registry_rewrites(path: impl AsRef<Path>) -> Result<BTreeMap<String, Url>, Error> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+    let mut rewrites = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (from, to) = match line.split_once("->") {
+            Some((from, to)) => (from.trim(), to.trim()),
+            None => bail!(
+                "Expected `FROM -> TO` on line {} of `{}`, found `{}`.",
+                i + 1,
+                path.display(),
+                line
+            ),
+        };
+        let to = Url::parse(to).with_context(|| {
+            format!(
+                "Invalid URL `{}` on line {} of `{}`.",
+                to,
+                i + 1,
+                path.display()
+            )
+        })?;
+        rewrites.insert(from.to_string(), to);
+    }
+    Ok(rewrites)
+}
+
 pub(crate) struct MetaInfo {
     pub(crate) index_pkg: IndexPackage,
     pub(crate) crate_path: PathBuf,
+    pub(crate) settings: PackageSettings,
 }
 
 /// Get the metadata for a package *before* publishing it.
 ///
 /// This will get the metadata directly from a `.crate` file. See [`metadata`]
 /// for a variant of this function that takes a path to a `Cargo.toml`
-/// manifest, and for more details on how this works.
+/// manifest, and for more details on how this works. See
+/// [`metadata_from_crate_with_options`] to set [`MetadataOptions`].
 ///
 /// [`metadata`]: fn.metadata.html
+/// [`metadata_from_crate_with_options`]: fn.metadata_from_crate_with_options.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
 pub fn metadata_from_crate(
     index_url: &str,
     crate_path: impl AsRef<Path>,
+) -> Result<IndexPackage, Error> {
+    metadata_from_crate_with_options(index_url, crate_path, &MetadataOptions::default())
+}
+
+/// Get the metadata for a `.crate` file, with settings from `options`.
+///
+/// This is the same as [`metadata_from_crate`], see [`MetadataOptions`] for
+/// the settings.
+///
+/// [`metadata_from_crate`]: fn.metadata_from_crate.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
+pub fn metadata_from_crate_with_options(
+    index_url: &str,
+    crate_path: impl AsRef<Path>,
+    options: &MetadataOptions,
 ) -> Result<IndexPackage, Error> {
     let crate_path = crate_path.as_ref();
     let (_tmp_dir, pkg_path) = extract_crate(crate_path)?;
@@ -33,6 +250,7 @@ pub fn metadata_from_crate(
         Some(&pkg_path.join("Cargo.toml")),
         Some(crate_path),
         None,
+        options,
     )?
     .index_pkg)
 }
@@ -47,16 +265,45 @@ pub fn metadata_from_crate(
 /// [`metadata_from_crate`] for a variant of this function that takes a
 /// pre-existing `.crate` file.
 ///
+/// The name, version, and dependencies in the `Cargo.toml` of the generated
+/// `.crate` file must match the manifest. Otherwise an error is returned,
+/// since the `.crate` file is likely stale or for a different package.
+///
 /// The `index_url` should be the public URL that users use to access the
 /// index this package will be added to.
 ///
+/// See [`metadata_with_options`] to set [`MetadataOptions`].
+///
 /// [`metadata_from_crate`]: fn.metadata_from_crate.html
+/// [`metadata_with_options`]: fn.metadata_with_options.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
 pub fn metadata(
     index_url: &str,
     manifest_path: Option<&Path>,
     package_args: Option<&Vec<String>>,
 ) -> Result<IndexPackage, Error> {
-    Ok(metadata_reg(index_url, manifest_path, None, package_args)?.index_pkg)
+    metadata_with_options(
+        index_url,
+        manifest_path,
+        package_args,
+        &MetadataOptions::default(),
+    )
+}
+
+/// Get the metadata for a package, with settings from `options`.
+///
+/// This is the same as [`metadata`], see [`MetadataOptions`] for the
+/// settings.
+///
+/// [`metadata`]: fn.metadata.html
+/// [`MetadataOptions`]: struct.MetadataOptions.html
+pub fn metadata_with_options(
+    index_url: &str,
+    manifest_path: Option<&Path>,
+    package_args: Option<&Vec<String>>,
+    options: &MetadataOptions,
+) -> Result<IndexPackage, Error> {
+    Ok(metadata_reg(index_url, manifest_path, None, package_args, options)?.index_pkg)
 }
 
 pub(crate) fn metadata_reg(
@@ -64,6 +311,7 @@ pub(crate) fn metadata_reg(
     manifest_path: Option<&Path>,
     crate_path: Option<&Path>,
     package_args: Option<&Vec<String>>,
+    options: &MetadataOptions,
 ) -> Result<MetaInfo, Error> {
     let cwd = env::current_dir()?;
     let actual_manifest_path = match manifest_path {
@@ -89,13 +337,10 @@ pub(crate) fn metadata_reg(
         }
     }
     cmd.no_deps();
-    let metadata =
-        cmd.exec()
-            .map_err(|e| format_err!("{}", e))
-            .with_context(|| match manifest_path {
-                Some(path) => format_err!("Failed to read manifest at `{}`.", path.display()),
-                None => format_err!("Failed to read manifest from current directory."),
-            })?;
+    let (metadata, raw_metadata) = exec_metadata(&cmd).with_context(|| match manifest_path {
+        Some(path) => format_err!("Failed to read manifest at `{}`.", path.display()),
+        None => format_err!("Failed to read manifest from current directory."),
+    })?;
     // Pick the package that matches this manifest path.
     let pkg = metadata
         .packages
@@ -108,7 +353,12 @@ pub(crate) fn metadata_reg(
             )
         })?;
 
+    let settings = PackageSettings::from_metadata(&pkg.metadata, &actual_manifest_path)?;
+    if options.check_reproducible && crate_path.is_some() {
+        bail!("Cannot check that packaging is reproducible with a pre-built `.crate` file.");
+    }
     // Check the .crate file.
+    let packaged = crate_path.is_none();
     let crate_path = match crate_path {
         Some(path) => {
             if !path.exists() {
@@ -125,11 +375,48 @@ pub(crate) fn metadata_reg(
     };
 
     let cksum = cksum(&crate_path)?;
+    if options.check_reproducible {
+        let repackaged = cargo_package(
+            &actual_manifest_path,
+            metadata.target_directory.as_ref(),
+            pkg,
+            package_args,
+        )?;
+        let second = util::cksum(&repackaged)?;
+        if second != cksum {
+            bail!(
+                "Packaging `{}:{}` is not reproducible, the checksum changed from {} to {}.\n\
+                 Check for files that are generated or modified during the build.",
+                pkg.name,
+                pkg.version,
+                cksum,
+                second
+            );
+        }
+    }
+    if let Some(expected) = &options.expected_cksum {
+        if !expected.eq_ignore_ascii_case(&cksum) {
+            bail!(
+                "Checksum of `{}` is {}, expected {}.",
+                crate_path.display(),
+                cksum,
+                expected
+            );
+        }
+    }
+    // `cargo_metadata` doesn't know about artifact dependencies, so they are
+    // read from the JSON, where the dependencies are in the same order.
+    let raw_deps = raw_metadata["packages"]
+        .as_array()
+        .and_then(|pkgs| pkgs.iter().find(|p| p["id"] == pkg.id.repr.as_str()))
+        .and_then(|p| p["dependencies"].as_array());
     // Create the metadata.
-    let deps: Vec<IndexDependency> = pkg
+    let mut deps: Vec<IndexDependency> = pkg
         .dependencies
         .iter()
-        .map(|dep| {
+        .enumerate()
+        .filter(|(_, dep)| !(options.omit_dev_deps && dep.kind == DependencyKind::Development))
+        .map(|(i, dep)| {
             let (name, package) = match &dep.rename {
                 Some(new_name) => (new_name.clone(), Some(dep.name.clone())),
                 None => (dep.name.clone(), None),
@@ -138,45 +425,225 @@ pub(crate) fn metadata_reg(
                 .registry
                 .as_ref()
                 .map(|s| s.as_ref())
-                .or_else(|| {
-                    // None means it is from crates.io.
-                    Some("https://github.com/rust-lang/crates.io-index")
-                })
+                // None means it is from crates.io.
+                .or(Some(CRATES_IO_INDEX))
+                .map(|r| rewrite_registry(r, &options.registry_rewrites))
                 .and_then(|r| {
                     // In the index, None means it is from the same registry.
-                    if r == index_url {
+                    if !options.explicit_registry && canonical_url(r) == canonical_url(index_url) {
                         None
                     } else {
                         Some(Url::parse(r).unwrap())
                     }
                 });
-            IndexDependency {
-                name,
-                req: dep.req.clone(),
-                features: dep.features.clone(),
-                optional: dep.optional,
-                default_features: dep.uses_default_features,
-                target: dep.target.as_ref().map(|t| format!("{}", t)),
-                kind: dep.kind,
-                registry,
-                package,
+            let mut features = dep.features.clone();
+            features.sort();
+            let mut index_dep = IndexDependency::new(&name, dep.req.clone());
+            index_dep.features = features;
+            index_dep.optional = dep.optional;
+            index_dep.default_features = dep.uses_default_features;
+            index_dep.target = dep.target.as_ref().map(|t| format!("{}", t));
+            index_dep.kind = dep.kind;
+            index_dep.registry = registry;
+            index_dep.package = package;
+            if let Some(artifact) = raw_deps
+                .and_then(|deps| deps.get(i))
+                .map(|dep| &dep["artifact"])
+                .filter(|artifact| artifact.is_object())
+            {
+                index_dep.artifact = artifact["kinds"].as_array().map(|kinds| {
+                    kinds
+                        .iter()
+                        .filter_map(|kind| kind.as_str().map(|kind| kind.to_string()))
+                        .collect()
+                });
+                index_dep.bindep_target = artifact["target"].as_str().map(|t| t.to_string());
+                index_dep.lib = artifact["lib"].as_bool().unwrap_or(false);
             }
+            index_dep
         })
         .collect();
-    let index_pkg = IndexPackage {
-        name: pkg.name.clone(),
-        vers: pkg.version.clone(),
-        deps,
-        features: pkg.features.clone().into_iter().collect(),
-        features2: None,
-        cksum,
-        yanked: false,
-        links: pkg.links.clone(),
-        v: None,
-    };
+    // Sort so that the entry is the same regardless of the order that
+    // `cargo metadata` happens to list things.
+    deps.sort_by(|a, b| dep_sort_key(a).cmp(&dep_sort_key(b)));
+    let features = pkg
+        .features
+        .iter()
+        .map(|(name, values)| {
+            let mut values = values.clone();
+            values.sort();
+            (name.clone(), values)
+        })
+        .collect();
+    let mut index_pkg = IndexPackage::new(&pkg.name, pkg.version.clone(), &cksum);
+    index_pkg.deps = deps;
+    index_pkg.features = features;
+    index_pkg.links = pkg.links.clone();
+    if packaged {
+        check_packaged(
+            index_url,
+            &crate_path,
+            &actual_manifest_path,
+            &index_pkg,
+            options,
+        )?;
+    }
     let info = MetaInfo {
         index_pkg,
         crate_path,
+        settings,
     };
     Ok(info)
 }
+
+/// Checks that the `.crate` file from `cargo package` is for the manifest.
+///
+/// `cargo package` may package a different workspace member depending on
+/// the `package_args`, in which case the `.crate` file found for this
+/// package is left over from an earlier run.
+fn check_packaged(
+    index_url: &str,
+    crate_path: &Path,
+    manifest_path: &Path,
+    index_pkg: &IndexPackage,
+    options: &MetadataOptions,
+) -> Result<(), Error> {
+    let (_tmp_dir, pkg_path) = extract_crate(crate_path)?;
+    let options = MetadataOptions {
+        check_reproducible: false,
+        expected_cksum: None,
+        ..options.clone()
+    };
+    let packaged = metadata_reg(
+        index_url,
+        Some(&pkg_path.join("Cargo.toml")),
+        Some(crate_path),
+        None,
+        &options,
+    )?
+    .index_pkg;
+    // `cargo package` removes dev-dependencies that only have a path.
+    let deps = |pkg: &IndexPackage| -> Result<Vec<String>, Error> {
+        pkg.deps
+            .iter()
+            .filter(|dep| dep.kind != DependencyKind::Development)
+            .map(|dep| serde_json::to_string(dep).map_err(Error::from))
+            .collect()
+    };
+    let difference = if packaged.name != index_pkg.name {
+        format!("its name is `{}`", packaged.name)
+    } else if packaged.vers != index_pkg.vers || packaged.vers.build != index_pkg.vers.build {
+        format!("its version is `{}`", packaged.vers)
+    } else if deps(&packaged)? != deps(index_pkg)? {
+        "its dependencies are different".to_string()
+    } else {
+        return Ok(());
+    };
+    bail!(
+        "The packaged `{}` does not match the manifest at `{}`, {}.\n\
+         It may be left over from an earlier `cargo package`, or from a different \
+         workspace member.",
+        crate_path.display(),
+        manifest_path.display(),
+        difference
+    );
+}
+
+/// Run `cargo metadata`, returning both the parsed metadata and the JSON for
+/// fields `cargo_metadata` does not have.
+fn exec_metadata(
+    cmd: &cargo_metadata::MetadataCommand,
+) -> Result<(cargo_metadata::Metadata, serde_json::Value), Error> {
+    let output = cmd
+        .cargo_command()
+        .output()
+        .with_context(|| "Could not run `cargo metadata`.")?;
+    if !output.status.success() {
+        bail!(
+            "`cargo metadata` exited with an error: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    let json = stdout
+        .lines()
+        .find(|line| line.starts_with('{'))
+        .ok_or_else(|| format_err!("`cargo metadata` did not print any JSON."))?;
+    let metadata = cargo_metadata::MetadataCommand::parse(json)?;
+    Ok((metadata, serde_json::from_str(json)?))
+}
+
+fn dep_sort_key(dep: &IndexDependency) -> (&str, u8, Option<&str>, Option<&str>) {
+    let kind = match dep.kind {
+        DependencyKind::Normal => 0,
+        DependencyKind::Build => 1,
+        DependencyKind::Development => 2,
+        _ => 3,
+    };
+    (
+        &dep.name,
+        kind,
+        dep.target.as_deref(),
+        dep.registry.as_ref().map(|r| r.as_str()),
+    )
+}
+
+const CRATES_IO_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+
+/// Apply the first matching rewrite rule to a dependency's registry.
+fn rewrite_registry<'a>(registry: &'a str, rewrites: &'a BTreeMap<String, Url>) -> &'a str {
+    let canonical = canonical_url(registry);
+    rewrites
+        .iter()
+        .find(|(from, _)| {
+            let from = if from.as_str() == "crates-io" {
+                CRATES_IO_INDEX
+            } else {
+                from.as_str()
+            };
+            canonical_url(from) == canonical
+        })
+        .map(|(_, to)| to.as_str())
+        .unwrap_or(registry)
+}
+
+/// Canonicalize an index URL so that different spellings of the same
+/// registry compare equal.
+///
+/// This removes the `sparse+` and `registry+` prefixes, treats `http` as
+/// `https`, lowercases the host, decodes percent-encoded characters in the
+/// path, and removes a trailing `/` or `.git`. Strings that are not valid
+/// URLs are only trimmed of a trailing `/`.
+///
+/// This is used to decide if a dependency is from the same registry as the
+/// index, in which case its `registry` field is left empty.
+pub fn canonical_url(url: &str) -> String {
+    let url = url
+        .strip_prefix("sparse+")
+        .or_else(|| url.strip_prefix("registry+"))
+        .unwrap_or(url);
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.trim_end_matches('/').to_string(),
+    };
+    let scheme = match parsed.scheme() {
+        "http" => "https",
+        scheme => scheme,
+    };
+    let path = percent_decode_str(parsed.path()).decode_utf8_lossy();
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let mut canonical = format!("{}://", scheme);
+    if let Some(host) = parsed.host_str() {
+        canonical.push_str(&host.to_lowercase());
+    }
+    if let Some(port) = parsed.port() {
+        canonical.push_str(&format!(":{}", port));
+    }
+    canonical.push_str(path);
+    if let Some(query) = parsed.query() {
+        canonical.push('?');
+        canonical.push_str(query);
+    }
+    canonical
+}
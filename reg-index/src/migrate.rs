@@ -0,0 +1,109 @@
+use crate::{
+    add::{check_deps, check_new_version, git_add, upload_crate, write_entry, FileBackup},
+    fetch::Registry,
+    lfs::{self, LFS_CONFIG_FILES, LFS_PREFIX},
+    lock::Lock,
+    util::{open_repo, pkg_path, CommitOptions},
+    validate::validate_package_name,
+    BuildMetadataPolicy, IndexPackage,
+};
+use anyhow::{Context, Error};
+use std::path::{Path, PathBuf};
+
+/// The result of [`migrate_from`].
+///
+/// [`migrate_from`]: fn.migrate_from.html
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct MigrateReport {
+    /// Entries that were added to the index.
+    pub migrated: Vec<IndexPackage>,
+    /// Versions that were already in the index, as `name:version`.
+    pub skipped: Vec<String>,
+}
+
+/// Copy every package from another registry into the index.
+///
+/// `from` is the index URL of the other registry, in any form accepted by
+/// [`fetch_deps`]. The packages of a local or git index are found by
+/// walking it. A sparse index can't be listed, so its packages are found
+/// with the search endpoint of its web API, which is `api` or else the
+/// `api` in its `config.json`.
+///
+/// Every version of each package is downloaded, checked against the
+/// `cksum` of its entry, and placed in `upload`, which works the same as
+/// the `upload` argument of [`add`]. The `dl` of this index decides where
+/// Cargo downloads them from afterwards. Entries are copied unchanged,
+/// including whether they are yanked.
+///
+/// Versions that are already in the index are skipped, so an interrupted
+/// migration can be run again. Everything else is written in a single
+/// commit. If anything fails, the index is left unchanged, though
+/// `.crate` files that were already placed in a plain `upload` directory
+/// are not removed.
+///
+/// [`add`]: fn.add.html
+/// [`fetch_deps`]: fn.fetch_deps.html
+pub fn migrate_from(
+    index: impl AsRef<Path>,
+    from: &str,
+    api: Option<&str>,
+    upload: &str,
+    commit: &CommitOptions,
+) -> Result<MigrateReport, Error> {
+    let index = index.as_ref();
+    let repo = open_repo(index)?;
+    lfs::check_upload(Some(upload))?;
+    let registry = Registry::open(from)?;
+    let names = registry.names(api)?;
+
+    let lock = Lock::new_exclusive(index)?;
+    let tmp_dir = tempfile::tempdir()?;
+    let mut report = MigrateReport::default();
+    let mut backup = FileBackup::new(index);
+    if upload.starts_with(LFS_PREFIX) {
+        for path in LFS_CONFIG_FILES {
+            backup.save(PathBuf::from(path));
+        }
+    }
+    let mut lfs_objects = Vec::new();
+    let mut migrate_all = || -> Result<(), Error> {
+        for name in &names {
+            validate_package_name(name, "package name")?;
+            for pkg in registry.entries(name)? {
+                if check_new_version(index, &pkg, BuildMetadataPolicy::default()).is_err() {
+                    report.skipped.push(format!("{}:{}", pkg.name, pkg.vers));
+                    continue;
+                }
+                let crate_path = tmp_dir
+                    .path()
+                    .join(format!("{}-{}.crate", pkg.name, pkg.vers));
+                registry
+                    .download(&pkg, &crate_path)
+                    .with_context(|| format!("Failed to download `{}:{}`.", pkg.name, pkg.vers))?;
+                backup.save(pkg_path(&pkg.name)?);
+                write_entry(index, &pkg, true)?;
+                lfs_objects.extend(upload_crate(index, &pkg, &crate_path, Some(upload), None)?);
+                report.migrated.push(pkg);
+            }
+        }
+        // Dependencies are checked after everything is written, since the
+        // packages are visited by name rather than in dependency order.
+        for pkg in &report.migrated {
+            check_deps(index, pkg)?;
+        }
+        Ok(())
+    };
+    if let Err(e) = migrate_all() {
+        backup.restore();
+        return Err(e);
+    }
+    if !report.migrated.is_empty() {
+        let msg = format!("Migrating {} entries from {}", report.migrated.len(), from);
+        lfs::stage(repo.as_ref(), &lfs_objects)?;
+        git_add(repo.as_ref(), &backup.paths(), &msg, commit)
+            .with_context(|| "Failed to add to git repo.")?;
+    }
+    drop(lock);
+    Ok(report)
+}
@@ -0,0 +1,111 @@
+use crate::{
+    add::git_add,
+    history::entry_dates,
+    lock::Lock,
+    util::{crate_walker, expand_markers, open_repo, CommitOptions},
+    IndexPackage,
+};
+use anyhow::{format_err, Context, Error};
+use std::{fs, path::Path};
+
+/// Remove yanked versions that were yanked before `cutoff`.
+///
+/// `cutoff` is a time in seconds since the Unix epoch. The time a version
+/// was yanked comes from the git history of the index, see
+/// [`entry_dates`]. Versions without a yank in the history use the time
+/// they were added, and versions with no history at all are kept.
+///
+/// All removed entries are committed together. If `crates` is set, the
+/// `.crate` file of each removed version is deleted from that directory,
+/// which supports the same markers as the `upload` argument of [`add`].
+/// If `dry_run` is `true`, nothing is changed.
+///
+/// Returns the removed entries, sorted by name and version.
+///
+/// Removing versions can break packages that depend on them with an exact
+/// requirement, or lock files that refer to them, so this should only be
+/// used with a retention policy that users know about.
+///
+/// [`entry_dates`]: fn.entry_dates.html
+/// [`add`]: fn.add.html
+pub fn prune_yanked(
+    index: impl AsRef<Path>,
+    cutoff: i64,
+    crates: Option<&str>,
+    dry_run: bool,
+    commit: &CommitOptions,
+) -> Result<Vec<IndexPackage>, Error> {
+    let index = index.as_ref();
+    let repo = open_repo(index)?;
+    let dates = entry_dates(index, None)?;
+    let lock = Lock::new_exclusive(index)?;
+    let mut pruned = Vec::new();
+    let mut changed_paths = Vec::new();
+    for entry in crate_walker(index) {
+        let entry = entry?;
+        let path = entry.path();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+        // Lines that are kept are written back byte-for-byte.
+        let mut kept = String::new();
+        let mut file_pruned = Vec::new();
+        for line in contents.split_inclusive('\n') {
+            let pkg: IndexPackage = serde_json::from_str(line).with_context(|| {
+                format!(
+                    "Failed to deserialize line in `{}`:\n{}",
+                    path.display(),
+                    line
+                )
+            })?;
+            let yanked_at = dates
+                .get(&(pkg.name.clone(), pkg.vers.clone()))
+                .and_then(|dates| dates.yank_changed.or(dates.added));
+            match yanked_at {
+                Some(time) if pkg.yanked && time < cutoff => file_pruned.push(pkg),
+                _ => kept.push_str(line),
+            }
+        }
+        if file_pruned.is_empty() {
+            continue;
+        }
+        pruned.extend(file_pruned);
+        if dry_run {
+            continue;
+        }
+        if kept.is_empty() {
+            fs::remove_file(path)
+        } else {
+            fs::write(path, kept)
+        }
+        .with_context(|| format!("Failed to write `{}`.", path.display()))?;
+        changed_paths.push(path.strip_prefix(index).unwrap().to_path_buf());
+    }
+    pruned.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+    if dry_run || pruned.is_empty() {
+        return Ok(pruned);
+    }
+    let mut msg = format!("Pruning {} yanked versions\n\n", pruned.len());
+    for pkg in &pruned {
+        msg.push_str(&format!("{}#{}\n", pkg.name, pkg.vers));
+    }
+    git_add(repo.as_ref(), &changed_paths, &msg, commit)
+        .with_context(|| "Failed to add to git repo.")?;
+    drop(lock);
+    if let Some(crates) = crates {
+        for pkg in &pruned {
+            let crate_path = Path::new(&expand_markers(crates, pkg))
+                .join(format!("{}-{}.crate", pkg.name, pkg.vers));
+            if crate_path.exists() {
+                fs::remove_file(&crate_path).map_err(|e| {
+                    format_err!(
+                        "Failed to remove `{}`: {}\n\
+                         The entries were already removed from the index.",
+                        crate_path.display(),
+                        e
+                    )
+                })?;
+            }
+        }
+    }
+    Ok(pruned)
+}
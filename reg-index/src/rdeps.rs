@@ -0,0 +1,90 @@
+use crate::{list::_list, lock::Lock, util::crate_walker, IndexDependency};
+use anyhow::{bail, Error};
+use semver::{Version, VersionReq};
+use std::path::Path;
+
+/// An entry that depends on a package, returned by [`rdeps`].
+///
+/// [`rdeps`]: fn.rdeps.html
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct ReverseDependency {
+    /// The name of the package that has the dependency.
+    pub name: String,
+    /// The version of the package that has the dependency.
+    pub vers: Version,
+    /// Whether that version is yanked.
+    pub yanked: bool,
+    /// The dependency on the package.
+    pub dep: IndexDependency,
+}
+
+/// List the entries in the index that depend on a package.
+///
+/// Only dependencies from the same registry are included, including
+/// renamed ones. If `version_req` is set, only dependencies whose
+/// requirement matches at least one version of `pkg_name` in the index that
+/// matches `version_req` are included, such as to see what a yank of those
+/// versions affects. Entries of every kind of dependency, yanked or not,
+/// are included, sorted by name and version.
+///
+/// Returns an error if `pkg_name` is not in the index.
+pub fn rdeps(
+    index: impl AsRef<Path>,
+    pkg_name: &str,
+    version_req: Option<&str>,
+) -> Result<Vec<ReverseDependency>, Error> {
+    let index = index.as_ref();
+    let lock = Lock::new_shared(index)?;
+    let entries = _list(index, pkg_name, None)?;
+    if entries.is_empty() {
+        bail!("Package `{}` is not in the index.", pkg_name);
+    }
+    // Index files are named by the lowercase name, so use the real name.
+    let pkg_name = entries[0].name.clone();
+    let versions = match version_req {
+        Some(version_req) => {
+            let req = VersionReq::parse(version_req)?;
+            let versions: Vec<Version> = entries
+                .into_iter()
+                .map(|entry| entry.vers)
+                .filter(|vers| req.matches(vers))
+                .collect();
+            if versions.is_empty() {
+                bail!(
+                    "No versions of `{}` match version `{}`.",
+                    pkg_name,
+                    version_req
+                );
+            }
+            Some(versions)
+        }
+        None => None,
+    };
+    let mut rdeps = Vec::new();
+    for file in crate_walker(index) {
+        let file = file?;
+        for entry in _list(index, file.file_name().to_str().unwrap(), None)? {
+            for dep in &entry.deps {
+                if dep.registry.is_some() || dep.package.as_ref().unwrap_or(&dep.name) != &pkg_name
+                {
+                    continue;
+                }
+                if let Some(versions) = &versions {
+                    if !versions.iter().any(|vers| dep.req.matches(vers)) {
+                        continue;
+                    }
+                }
+                rdeps.push(ReverseDependency {
+                    name: entry.name.clone(),
+                    vers: entry.vers.clone(),
+                    yanked: entry.yanked,
+                    dep: dep.clone(),
+                });
+            }
+        }
+    }
+    drop(lock);
+    rdeps.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+    Ok(rdeps)
+}
@@ -0,0 +1,80 @@
+use anyhow::{bail, Context, Error};
+use std::{path::Path, process::Command};
+
+/// How many times [`with_remote_index`] starts over when another writer
+/// pushed to the remote first.
+///
+/// [`with_remote_index`]: fn.with_remote_index.html
+const ATTEMPTS: usize = 3;
+
+/// Returns `true` if `index` is the URL of a git repository rather than a
+/// local path, such as `https://`, `ssh://`, or `git@host:path`.
+pub fn is_remote_index(index: &str) -> bool {
+    if ["https://", "http://", "ssh://", "git://", "file://"]
+        .iter()
+        .any(|prefix| index.starts_with(prefix))
+    {
+        return true;
+    }
+    // The scp-like syntax git accepts for ssh, `user@host:path`.
+    match index.split_once(':') {
+        Some((host, _)) => host.contains('@') && !host.contains('/'),
+        None => false,
+    }
+}
+
+/// Run `f` on a temporary clone of the index at `url`, and push the result.
+///
+/// The repository is cloned with `git clone`, `f` is called with the path
+/// of the clone to change the index there, and then the current branch is
+/// pushed back to `url` with `git push`, so the usual git credential
+/// settings apply. Nothing is pushed if `f` returns an error.
+///
+/// If the push is rejected because the remote changed in the meantime, such
+/// as another publisher pushing first, this starts over with a fresh clone
+/// and calls `f` again, up to a few times.
+pub fn with_remote_index<T>(
+    url: &str,
+    mut f: impl FnMut(&Path) -> Result<T, Error>,
+) -> Result<T, Error> {
+    for _ in 0..ATTEMPTS {
+        let tmp_dir = tempfile::tempdir()?;
+        let path = tmp_dir.path().join("index");
+        git(tmp_dir.path(), &["clone", "--quiet", url, "index"])
+            .with_context(|| format!("Failed to clone `{}`.", url))?;
+        let result = f(&path)?;
+        let output = Command::new("git")
+            .args(["push", "--porcelain", "origin", "HEAD"])
+            .current_dir(&path)
+            .output()
+            .with_context(|| "Could not run `git push`.")?;
+        if output.status.success() {
+            return Ok(result);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.contains("(fetch first)") && !stdout.contains("(non-fast-forward)") {
+            bail!(
+                "Failed to push to `{}`:\n{}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+    }
+    bail!(
+        "Failed to push to `{}`, it changed during each of {} attempts.",
+        url,
+        ATTEMPTS
+    );
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<(), Error> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .with_context(|| "Could not run `git`.")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use crate::{
+    lock::Lock,
+    util::{crate_walker, pkg_path},
+    IndexError,
+};
+use anyhow::{Context, Error};
+use semver::{Version, VersionReq};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// A feature of the index format that not every version of Cargo supports.
+///
+/// The entries are read as raw JSON, so this includes fields that
+/// [`IndexPackage`] does not know about.
+///
+/// [`IndexPackage`]: struct.IndexPackage.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum SchemaFeature {
+    /// A dependency is renamed with the `package` field.
+    RenamedDependencies,
+    /// The entry has a `features2` field.
+    Features2,
+    /// A feature uses the `dep:` or `?/` syntax.
+    NamespacedFeatures,
+    /// The entry has a `rust_version` field.
+    RustVersion,
+    /// A dependency has the `artifact`, `bindep_target`, or `lib` fields.
+    ArtifactDependencies,
+}
+
+impl SchemaFeature {
+    /// A short name for the feature.
+    pub fn name(self) -> &'static str {
+        match self {
+            SchemaFeature::RenamedDependencies => "renamed dependencies",
+            SchemaFeature::Features2 => "features2",
+            SchemaFeature::NamespacedFeatures => "namespaced features",
+            SchemaFeature::RustVersion => "rust_version",
+            SchemaFeature::ArtifactDependencies => "artifact dependencies",
+        }
+    }
+
+    /// The versions of Cargo that can use entries with the feature.
+    pub fn min_cargo_version(self) -> CargoVersion {
+        match self {
+            SchemaFeature::RenamedDependencies => CargoVersion::Since(Version::new(1, 31, 0)),
+            SchemaFeature::Features2 | SchemaFeature::NamespacedFeatures => {
+                CargoVersion::Since(Version::new(1, 60, 0))
+            }
+            // Older versions ignore it and may pick a version that doesn't
+            // build with their compiler, but can still use the entry.
+            SchemaFeature::RustVersion => CargoVersion::Any,
+            SchemaFeature::ArtifactDependencies => CargoVersion::Nightly,
+        }
+    }
+}
+
+/// The versions of Cargo that support a [`SchemaFeature`].
+///
+/// These are ordered from the most to the least widely supported.
+///
+/// [`SchemaFeature`]: enum.SchemaFeature.html
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum CargoVersion {
+    /// Every version of Cargo that supports registries.
+    Any,
+    /// This version of Cargo and newer.
+    Since(Version),
+    /// Only nightly versions of Cargo with the feature enabled.
+    Nightly,
+}
+
+/// Result of [`schema_report`].
+///
+/// [`schema_report`]: fn.schema_report.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct SchemaReport {
+    /// The number of entries that were checked.
+    pub entries: usize,
+    /// The entries that use each feature, as `name:version`.
+    pub features: BTreeMap<SchemaFeature, Vec<String>>,
+}
+
+impl SchemaReport {
+    /// The versions of Cargo that can use every checked entry.
+    pub fn min_cargo_version(&self) -> CargoVersion {
+        self.features
+            .keys()
+            .map(|feature| feature.min_cargo_version())
+            .max()
+            .unwrap_or(CargoVersion::Any)
+    }
+}
+
+/// Report which [`SchemaFeature`]s the entries in the index use.
+///
+/// If `pkg_name` is set, only that package is checked. If `version_req` is
+/// set, only versions matching the semver requirement are checked. See
+/// [`schema_features`] to check a single entry.
+///
+/// [`SchemaFeature`]: enum.SchemaFeature.html
+/// [`schema_features`]: fn.schema_features.html
+pub fn schema_report(
+    index: impl AsRef<Path>,
+    pkg_name: Option<&str>,
+    version_req: Option<&str>,
+) -> Result<SchemaReport, Error> {
+    let index = index.as_ref();
+    let lock = Lock::new_shared(index)?;
+    let version_req = match version_req {
+        Some(version_req) => Some(VersionReq::parse(version_req)?),
+        None => None,
+    };
+    let paths = match pkg_name {
+        Some(pkg_name) => {
+            let path = index.join(pkg_path(pkg_name)?);
+            if !path.exists() {
+                return Err(IndexError::PackageNotFound {
+                    name: pkg_name.to_string(),
+                }
+                .into());
+            }
+            vec![path]
+        }
+        None => crate_walker(index)
+            .map(|entry| entry.map(|entry| entry.into_path()))
+            .collect::<Result<_, _>>()?,
+    };
+    let mut report = SchemaReport::default();
+    for path in paths {
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+        for line in contents.lines() {
+            let entry: Value = serde_json::from_str(line).with_context(|| {
+                format!("Could not deserialize `{}` line:\n{}", path.display(), line)
+            })?;
+            let name = entry["name"].as_str().unwrap_or_default();
+            let vers = entry["vers"].as_str().unwrap_or_default();
+            if let Some(version_req) = &version_req {
+                match Version::parse(vers) {
+                    Ok(vers) if version_req.matches(&vers) => {}
+                    _ => continue,
+                }
+            }
+            report.entries += 1;
+            for feature in entry_features(&entry) {
+                report
+                    .features
+                    .entry(feature)
+                    .or_default()
+                    .push(format!("{}:{}", name, vers));
+            }
+        }
+    }
+    drop(lock);
+    Ok(report)
+}
+
+/// Returns the [`SchemaFeature`]s used by a single line of the index.
+///
+/// [`SchemaFeature`]: enum.SchemaFeature.html
+pub fn schema_features(entry: &str) -> Result<Vec<SchemaFeature>, Error> {
+    let entry: Value = serde_json::from_str(entry)
+        .with_context(|| format!("Could not deserialize entry:\n{}", entry))?;
+    Ok(entry_features(&entry))
+}
+
+fn entry_features(entry: &Value) -> Vec<SchemaFeature> {
+    let mut features = Vec::new();
+    let deps = entry["deps"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if deps.iter().any(|dep| !dep["package"].is_null()) {
+        features.push(SchemaFeature::RenamedDependencies);
+    }
+    if !entry["features2"].is_null() {
+        features.push(SchemaFeature::Features2);
+    }
+    let namespaced = ["features", "features2"]
+        .iter()
+        .filter_map(|key| entry[key].as_object())
+        .flat_map(|map| map.values())
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_str)
+        .any(|value| value.starts_with("dep:") || value.contains("?/"));
+    if namespaced {
+        features.push(SchemaFeature::NamespacedFeatures);
+    }
+    if !entry["rust_version"].is_null() {
+        features.push(SchemaFeature::RustVersion);
+    }
+    let artifact = deps.iter().any(|dep| {
+        ["artifact", "bindep_target", "lib"]
+            .iter()
+            .any(|key| !dep[key].is_null())
+    });
+    if artifact {
+        features.push(SchemaFeature::ArtifactDependencies);
+    }
+    features
+}
@@ -0,0 +1,94 @@
+use crate::{list::list, snippet::config_snippet};
+use anyhow::{bail, Context, Error};
+use semver::Version;
+use std::{fs, path::Path, process::Command};
+
+/// Name of the registry in the generated `.cargo/config.toml`.
+const REGISTRY_NAME: &str = "selftest";
+
+/// Check that Cargo can use the index by downloading a package from it.
+///
+/// This creates a temporary project that depends on package `name` from
+/// the registry at `index_url`, and runs `cargo fetch` in it. If `build` is
+/// `true`, it also runs `cargo build`. The `index_url` is used as-is, so it
+/// can be a git URL or a `sparse+` URL.
+///
+/// The newest version in the `index` that is not yanked and matches the
+/// `version_req` requirement is used. Returns the version that was tested.
+///
+/// Cargo runs with a new, empty `CARGO_HOME`, so nothing is taken from the
+/// local cache. This also means the user's Cargo configuration, such as
+/// credentials or proxy settings, is not used.
+pub fn selftest(
+    index: impl AsRef<Path>,
+    index_url: &str,
+    name: &str,
+    version_req: Option<&str>,
+    build: bool,
+) -> Result<Version, Error> {
+    let index = index.as_ref();
+    let version = match list(index, name, version_req)?
+        .into_iter()
+        .filter(|pkg| !pkg.yanked)
+        .map(|pkg| pkg.vers)
+        .max()
+    {
+        Some(version) => version,
+        None => match version_req {
+            Some(req) => bail!(
+                "Package `{}` has no versions matching `{}` that are not yanked.",
+                name,
+                req
+            ),
+            None => bail!("Package `{}` has no versions that are not yanked.", name),
+        },
+    };
+
+    let tmp_dir = tempfile::tempdir()?;
+    let project = tmp_dir.path().join("project");
+    fs::create_dir_all(project.join("src"))?;
+    fs::create_dir_all(project.join(".cargo"))?;
+    let manifest = format!(
+        "[package]\n\
+         name = \"cargo-index-selftest\"\n\
+         version = \"0.0.0\"\n\
+         edition = \"2021\"\n\
+         publish = false\n\
+         \n\
+         [dependencies]\n\
+         {} = {{ version = \"={}\", registry = \"{}\" }}\n\
+         \n\
+         [workspace]\n",
+        name, version, REGISTRY_NAME
+    );
+    fs::write(project.join("Cargo.toml"), manifest)?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    fs::write(
+        project.join(".cargo/config.toml"),
+        config_snippet(index, index_url, REGISTRY_NAME, false)?,
+    )?;
+
+    let mut subcommands = vec!["fetch"];
+    if build {
+        subcommands.push("build");
+    }
+    for subcommand in subcommands {
+        let status = Command::new("cargo")
+            .arg(subcommand)
+            .current_dir(&project)
+            .env("CARGO_HOME", tmp_dir.path().join("cargo-home"))
+            .env_remove("CARGO_TARGET_DIR")
+            .status()
+            .with_context(|| format!("Could not run `cargo {}`.", subcommand))?;
+        if !status.success() {
+            bail!(
+                "`cargo {}` failed for `{}:{}` from the registry at `{}`.",
+                subcommand,
+                name,
+                version,
+                index_url
+            );
+        }
+    }
+    Ok(version)
+}
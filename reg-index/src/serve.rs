@@ -0,0 +1,481 @@
+use crate::{
+    add::_add_from_crate,
+    lfs,
+    list::{list, list_names},
+    metadata::MetadataOptions,
+    store::{fetch, store_for},
+    validate::validate_package_name,
+    yank::{set_yank_with_options, YankOptions},
+    IndexPackage,
+};
+use anyhow::{bail, format_err, Context, Error};
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Largest request body that is accepted, which bounds the size of a
+/// published `.crate` file.
+const MAX_BODY: usize = 50 * 1024 * 1024;
+
+/// Longest request line or header line that is accepted.
+const MAX_LINE: usize = 8 * 1024;
+
+/// Most bytes of a rejected request that are read and thrown away before
+/// closing the connection.
+const MAX_DRAIN: u64 = 64 * 1024;
+
+/// Most headers that are accepted in one request.
+const MAX_HEADERS: usize = 100;
+
+/// How long to wait for a client to send or receive data before giving up
+/// on the connection.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a client has to send a whole request, so it can't keep a
+/// connection open by sending a byte at a time.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Most connections that are handled at the same time. Others are answered
+/// with `503 Service Unavailable`.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Number of search results returned when `per_page` is not given.
+const DEFAULT_PER_PAGE: usize = 10;
+
+/// Serve the registry web API for an index.
+///
+/// This answers the requests Cargo makes for `cargo publish`, `cargo yank`,
+/// and `cargo search`, and downloads of `.crate` files, on connections from
+/// `listener`. Each connection is handled on its own thread, and changes to
+/// the index are serialized by the index lock. A connection is dropped if
+/// the client stops sending or receiving for 30 seconds, or takes more than
+/// 5 minutes to send its request. Errors accepting a connection are
+/// displayed on stderr, and the server keeps running.
+///
+/// Published crates are added with [`add_from_crate`], using `index_url`,
+/// `upload` and `options` the same way, so each one is a separate commit.
+/// Yanks use the commit options of `options`. Downloads are read from the
+/// `upload` directory, so `dl` in `config.json` should be the `/api/v1/crates`
+/// URL of this server, and `api` should be its root URL.
+///
+/// If `token` is set, publishing and yanking require Cargo to send it as
+/// the registry token. Downloads and searches are always allowed. A token
+/// is required unless `listener` is on a loopback address, so an index
+/// can't be changed by anyone on the network.
+///
+/// [`add_from_crate`]: fn.add_from_crate.html
+pub fn serve(
+    index_path: impl AsRef<Path>,
+    listener: &TcpListener,
+    index_url: &str,
+    upload: &str,
+    token: Option<&str>,
+    options: &MetadataOptions,
+) -> Result<(), Error> {
+    let addr = listener.local_addr()?;
+    if token.is_none() && !addr.ip().is_loopback() {
+        bail!(
+            "A token is required to serve on `{}`, which is not a loopback address.",
+            addr
+        );
+    }
+    let server = Server {
+        index_path: index_path.as_ref(),
+        index_url,
+        upload,
+        token,
+        options,
+    };
+    let active = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Warning: Failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                active.fetch_sub(1, Ordering::SeqCst);
+                let response = Response::error("503 Service Unavailable", "Too many connections.");
+                let _ = write_response(&stream, &response);
+                continue;
+            }
+            let (server, active) = (&server, &active);
+            let spawned = thread::Builder::new().spawn_scoped(scope, move || {
+                // A client that goes away mid-request only affects that request.
+                let _ = server.handle(stream);
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+            if let Err(e) = spawned {
+                active.fetch_sub(1, Ordering::SeqCst);
+                eprintln!("Warning: Failed to start a thread for a connection: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Server<'a> {
+    index_path: &'a Path,
+    index_url: &'a str,
+    upload: &'a str,
+    token: Option<&'a str>,
+    options: &'a MetadataOptions,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(value: serde_json::Value) -> Response {
+        Response {
+            status: "200 OK",
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    /// An error in the format crates.io uses, which Cargo displays.
+    fn error(status: &'static str, detail: impl ToString) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: json!({"errors": [{"detail": detail.to_string()}]})
+                .to_string()
+                .into_bytes(),
+        }
+    }
+}
+
+impl Server<'_> {
+    fn handle(&self, stream: TcpStream) -> Result<(), Error> {
+        let mut reader = DeadlineReader {
+            stream: &stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        };
+        let response = match read_request(&mut reader) {
+            Ok(request) => self.route(&request),
+            Err(e) => Response::error("400 Bad Request", format!("{:#}", e)),
+        };
+        write_response(&stream, &response)?;
+        // Read what is left of a rejected request before closing, otherwise
+        // the client may get a reset instead of the response.
+        reader.deadline = Instant::now() + IO_TIMEOUT;
+        io::copy(&mut reader.take(MAX_DRAIN), &mut io::sink())?;
+        Ok(())
+    }
+
+    fn route(&self, request: &Request) -> Response {
+        let Some(path) = request.path.strip_prefix("/api/v1/crates") else {
+            return Response::error("404 Not Found", "Not found.");
+        };
+        // Split before decoding, so an encoded `/` can't add a segment.
+        let parts: Vec<String> = path
+            .split('/')
+            .skip(1)
+            .map(|part| percent_decode_str(part).decode_utf8_lossy().into_owned())
+            .collect();
+        let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+        // The name is used to build a path in the index.
+        if let [name, _, _] = parts.as_slice() {
+            if validate_package_name(name, "package name").is_err() {
+                return Response::error("404 Not Found", "Not found.");
+            }
+        }
+        let result = match (request.method.as_str(), parts.as_slice()) {
+            ("GET", []) => Ok(self.search(&request.query)),
+            ("PUT", ["new"]) => self
+                .authorize(request)
+                .map(|()| self.publish(&request.body)),
+            ("DELETE", [name, version, "yank"]) => self
+                .authorize(request)
+                .map(|()| self.set_yank(name, version, true)),
+            ("PUT", [name, version, "unyank"]) => self
+                .authorize(request)
+                .map(|()| self.set_yank(name, version, false)),
+            ("GET", [name, version, "download"]) => return self.download(name, version),
+            _ => return Response::error("404 Not Found", "Not found."),
+        };
+        match result {
+            Ok(Ok(value)) => Response::json(value),
+            Ok(Err(e)) => Response::error("400 Bad Request", format!("{:#}", e)),
+            Err(response) => response,
+        }
+    }
+
+    /// Checks the registry token, returning the `403 Forbidden` response
+    /// to send if it is missing or wrong.
+    fn authorize(&self, request: &Request) -> Result<(), Response> {
+        let Some(token) = self.token else {
+            return Ok(());
+        };
+        let authorization = request.authorization.as_deref().unwrap_or_default();
+        if !constant_time_eq(authorization.as_bytes(), token.as_bytes()) {
+            return Err(Response::error(
+                "403 Forbidden",
+                "A valid registry token is required.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Handles `cargo publish`, whose body is the length-prefixed JSON
+    /// metadata followed by the length-prefixed `.crate` file.
+    fn publish(&self, body: &[u8]) -> Result<serde_json::Value, Error> {
+        let mut rest = body;
+        let metadata = take_chunk(&mut rest)?;
+        let crate_data = take_chunk(&mut rest)?;
+        let metadata: serde_json::Value = serde_json::from_slice(metadata)
+            .with_context(|| "Failed to parse publish metadata.")?;
+        let (Some(name), Some(vers)) = (metadata["name"].as_str(), metadata["vers"].as_str())
+        else {
+            bail!("Publish metadata is missing `name` or `vers`.");
+        };
+        if name.contains(['/', '\\']) || vers.contains(['/', '\\']) {
+            bail!("Invalid name or version `{}:{}`.", name, vers);
+        }
+        let tmp_dir = tempfile::tempdir()?;
+        let crate_path = tmp_dir.path().join(format!("{}-{}.crate", name, vers));
+        fs::write(&crate_path, crate_data)?;
+        // The entry is generated from the manifest in the `.crate` file, the
+        // metadata is only used to name it.
+        _add_from_crate(
+            self.index_path,
+            self.index_url,
+            &crate_path,
+            Some(self.upload),
+            self.options,
+        )?;
+        Ok(json!({
+            "warnings": {"invalid_categories": [], "invalid_badges": [], "other": []}
+        }))
+    }
+
+    fn set_yank(&self, name: &str, version: &str, yank: bool) -> Result<serde_json::Value, Error> {
+        let options = YankOptions {
+            commit: self.options.commit.clone(),
+            ..YankOptions::default()
+        };
+        set_yank_with_options(self.index_path, name, version, yank, &options)?;
+        Ok(json!({"ok": true}))
+    }
+
+    fn download(&self, name: &str, version: &str) -> Response {
+        let Some(pkg) = self.find(name, version) else {
+            return Response::error(
+                "404 Not Found",
+                format!("Version `{}` of `{}` not found.", version, name),
+            );
+        };
+        let tmp_dir = match tempfile::tempdir() {
+            Ok(tmp_dir) => tmp_dir,
+            Err(e) => return Response::error("500 Internal Server Error", e),
+        };
+        let path = if self.upload.starts_with(lfs::LFS_PREFIX) {
+            lfs::upload_dir(self.index_path, self.upload, &pkg)
+                .join(format!("{}-{}.crate", pkg.name, pkg.vers))
+        } else {
+            match fetch(&*store_for(self.upload), &pkg, tmp_dir.path()) {
+                Ok(path) => path,
+                Err(e) => return Response::error("404 Not Found", format!("{:#}", e)),
+            }
+        };
+        match fs::read(&path) {
+            Ok(body) => Response {
+                status: "200 OK",
+                content_type: "application/octet-stream",
+                body,
+            },
+            Err(e) => Response::error(
+                "404 Not Found",
+                format!("Failed to read `{}`: {}", path.display(), e),
+            ),
+        }
+    }
+
+    fn find(&self, name: &str, version: &str) -> Option<IndexPackage> {
+        list(self.index_path, name, None)
+            .ok()?
+            .into_iter()
+            .find(|pkg| pkg.vers.to_string() == version)
+    }
+
+    /// Handles `cargo search`, matching the query anywhere in the name.
+    fn search(&self, query: &str) -> Result<serde_json::Value, Error> {
+        let mut q = String::new();
+        let mut per_page = DEFAULT_PER_PAGE;
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(&value.replace('+', " "))
+                .decode_utf8_lossy()
+                .into_owned();
+            match key {
+                "q" => q = value,
+                "per_page" => per_page = value.parse().unwrap_or(DEFAULT_PER_PAGE),
+                _ => {}
+            }
+        }
+        let q = q.trim().to_lowercase().replace('-', "_");
+        let mut crates = Vec::new();
+        for name in list_names(self.index_path)? {
+            if !name.to_lowercase().replace('-', "_").contains(&q) {
+                continue;
+            }
+            let max_version = list(self.index_path, &name, None)?
+                .into_iter()
+                .filter(|pkg| !pkg.yanked)
+                .map(|pkg| pkg.vers)
+                .max();
+            if let Some(max_version) = max_version {
+                crates.push(json!({
+                    "name": name,
+                    "max_version": max_version.to_string(),
+                    "description": null,
+                }));
+            }
+        }
+        let total = crates.len();
+        crates.truncate(per_page);
+        Ok(json!({"crates": crates, "meta": {"total": total}}))
+    }
+}
+
+/// Writes a response and closes the sending side of the connection.
+fn write_response(mut stream: &TcpStream, response: &Response) -> Result<(), Error> {
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
+
+/// Reads from a connection until a deadline, waiting at most `IO_TIMEOUT`
+/// for each read.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Request took too long.",
+            ));
+        }
+        self.stream
+            .set_read_timeout(Some(remaining.min(IO_TIMEOUT)))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+fn read_request(reader: &mut impl Read) -> Result<Request, Error> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("Malformed request line `{}`.", line.trim());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        authorization: None,
+        body: Vec::new(),
+    };
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line)? == 0 {
+            bail!("Connection closed in the request headers.");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            bail!("Request has more than {} headers.", MAX_HEADERS);
+        }
+        let Some((key, value)) = header.split_once(':') else {
+            bail!("Malformed header `{}`.", header);
+        };
+        let value = value.trim();
+        if key.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| format_err!("Invalid Content-Length `{}`.", value))?;
+        } else if key.eq_ignore_ascii_case("authorization") {
+            request.authorization = Some(value.to_string());
+        }
+    }
+    if content_length > MAX_BODY {
+        bail!("Request body of {} bytes is too large.", content_length);
+    }
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Reads a line of at most `MAX_LINE` bytes, including the newline.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<usize, Error> {
+    let len = reader.take(MAX_LINE as u64).read_line(line)?;
+    if len == MAX_LINE && !line.ends_with('\n') {
+        bail!("Request line is longer than {} bytes.", MAX_LINE);
+    }
+    Ok(len)
+}
+
+/// Compares two byte strings in time that only depends on their lengths,
+/// so the token can't be guessed one byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Removes a chunk prefixed by its 32-bit little-endian length.
+fn take_chunk<'a>(rest: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    if rest.len() < 4 {
+        bail!("Publish request body is truncated.");
+    }
+    let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+    if rest.len() - 4 < len {
+        bail!("Publish request body is truncated.");
+    }
+    let chunk = &rest[4..4 + len];
+    *rest = &rest[4 + len..];
+    Ok(chunk)
+}
@@ -0,0 +1,187 @@
+use crate::util::CommitOptions;
+use anyhow::{bail, Context, Error};
+use serde::Deserialize;
+use std::path::{Component, Path};
+
+/// The commit trailer that names a reviewer of an added package.
+///
+/// Packages that list [`PackageSettings::reviewers`] can only be added with
+/// a trailer for each of them in [`CommitOptions::trailers`].
+///
+/// [`PackageSettings::reviewers`]: struct.PackageSettings.html#structfield.reviewers
+/// [`CommitOptions::trailers`]: struct.CommitOptions.html#structfield.trailers
+pub const REVIEWED_BY_TRAILER: &str = "Reviewed-by";
+
+/// The commit trailer that records each setting applied when a package is
+/// added.
+pub const INDEX_SETTING_TRAILER: &str = "Index-Setting";
+
+/// Settings for a single package, read from the `[package.metadata.index]`
+/// table of its `Cargo.toml`.
+///
+/// These let a package opt into behavior that would otherwise need options
+/// for every add, such as:
+///
+/// ```toml
+/// [package.metadata.index]
+/// upload-subpath = "internal"
+/// skip-dep-check = true
+/// require-newer = true
+/// semver-checks = true
+/// reviewers = ["Alice <alice@example.com>"]
+/// ```
+///
+/// The [`add`] family of functions honor them, and record each one that is
+/// set with an [`INDEX_SETTING_TRAILER`] in the commit.
+///
+/// [`add`]: fn.add.html
+/// [`INDEX_SETTING_TRAILER`]: constant.INDEX_SETTING_TRAILER.html
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct PackageSettings {
+    /// A directory under the `upload` path to place the `.crate` file in.
+    ///
+    /// It supports the same markers as `upload`, and must be a relative
+    /// path that stays inside it. It is ignored when there is no `upload`.
+    pub upload_subpath: Option<String>,
+    /// If `true`, dependencies from the same registry are not required to
+    /// be in the index already.
+    pub skip_dep_check: bool,
+    /// Same as [`MetadataOptions::require_newer`], for this package.
+    ///
+    /// [`MetadataOptions::require_newer`]: struct.MetadataOptions.html#structfield.require_newer
+    pub require_newer: bool,
+    /// Same as [`MetadataOptions::semver_checks`], for this package.
+    ///
+    /// [`MetadataOptions::semver_checks`]: struct.MetadataOptions.html#structfield.semver_checks
+    pub semver_checks: bool,
+    /// People who must review each version before it is added.
+    ///
+    /// Each needs a [`REVIEWED_BY_TRAILER`] in the commit whose value is
+    /// the same, or has the same name or `<email>`.
+    ///
+    /// [`REVIEWED_BY_TRAILER`]: constant.REVIEWED_BY_TRAILER.html
+    pub reviewers: Vec<String>,
+}
+
+impl PackageSettings {
+    /// Read the settings from the `metadata` table of a package.
+    pub(crate) fn from_metadata(
+        metadata: &serde_json::Value,
+        manifest_path: &Path,
+    ) -> Result<PackageSettings, Error> {
+        let settings = match metadata.get("index") {
+            Some(table) => PackageSettings::deserialize(table).with_context(|| {
+                format!(
+                    "Invalid `[package.metadata.index]` in `{}`.",
+                    manifest_path.display()
+                )
+            })?,
+            None => PackageSettings::default(),
+        };
+        if let Some(subpath) = &settings.upload_subpath {
+            if Path::new(subpath)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                bail!(
+                    "The `upload-subpath` `{}` in `{}` must be a relative path \
+                     that stays inside the upload directory.",
+                    subpath,
+                    manifest_path.display()
+                );
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The `upload` path with [`upload_subpath`] added.
+    ///
+    /// [`upload_subpath`]: #structfield.upload_subpath
+    pub(crate) fn upload(&self, upload: Option<&str>) -> Option<String> {
+        let upload = upload?;
+        Some(match &self.upload_subpath {
+            Some(subpath) => format!("{}/{}", upload.trim_end_matches(['/', '\\']), subpath),
+            None => upload.to_string(),
+        })
+    }
+
+    /// Returns an error if a reviewer does not have a trailer in the
+    /// commit.
+    pub(crate) fn check_reviewers(&self, name: &str, commit: &CommitOptions) -> Result<(), Error> {
+        let reviewed_by: Vec<&str> = commit
+            .trailers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(REVIEWED_BY_TRAILER))
+            .map(|(_, value)| value.trim())
+            .collect();
+        let missing: Vec<&str> = self
+            .reviewers
+            .iter()
+            .map(|reviewer| reviewer.trim())
+            .filter(|reviewer| !reviewed_by.iter().any(|r| same_person(r, reviewer)))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "Package `{}` requires review by {}.\n\
+                 Add a `{}` trailer for each reviewer, such as with `--reviewed-by`.",
+                name,
+                missing
+                    .iter()
+                    .map(|r| format!("`{}`", r))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                REVIEWED_BY_TRAILER
+            );
+        }
+        Ok(())
+    }
+
+    /// The trailers that record the settings for the package `name`.
+    pub(crate) fn trailers(&self, name: &str) -> Vec<(String, String)> {
+        let mut settings = Vec::new();
+        if let Some(subpath) = &self.upload_subpath {
+            settings.push(format!("upload-subpath={}", subpath));
+        }
+        for (set, key) in [
+            (self.skip_dep_check, "skip-dep-check"),
+            (self.require_newer, "require-newer"),
+            (self.semver_checks, "semver-checks"),
+        ] {
+            if set {
+                settings.push(key.to_string());
+            }
+        }
+        if !self.reviewers.is_empty() {
+            settings.push(format!("reviewers={}", self.reviewers.join(", ")));
+        }
+        settings
+            .into_iter()
+            .map(|setting| {
+                (
+                    INDEX_SETTING_TRAILER.to_string(),
+                    format!("{} {}", name, setting),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Whether two `Name <email>` strings refer to the same person, by either
+/// the whole string, the name, or the email.
+fn same_person(a: &str, b: &str) -> bool {
+    fn split(s: &str) -> (&str, Option<&str>) {
+        match s.split_once('<') {
+            Some((name, email)) => (name.trim(), Some(email.trim_end_matches('>').trim())),
+            None => (s.trim(), None),
+        }
+    }
+    let (a_name, a_email) = split(a);
+    let (b_name, b_email) = split(b);
+    a == b
+        || (!a_name.is_empty() && a_name == b_name)
+        || (a_email.is_some() && a_email == b_email)
+        || a_email == Some(b_name)
+        || b_email == Some(a_name)
+}
@@ -0,0 +1,140 @@
+use crate::{
+    lock::Lock,
+    util::{git_commit, open_git, signature, CommitOptions},
+};
+use anyhow::{bail, format_err, Error};
+use std::path::Path;
+
+/// Prefix of the git tags used to record snapshots.
+const TAG_PREFIX: &str = "snapshot/";
+
+/// A snapshot of the index, as returned by [`snapshot_list`].
+///
+/// [`snapshot_list`]: fn.snapshot_list.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// The name of the snapshot.
+    pub name: String,
+    /// The hash of the index commit the snapshot points to.
+    pub commit: String,
+    /// When the snapshot was created, in seconds since the Unix epoch.
+    pub time: i64,
+}
+
+/// Record the current state of the index as a snapshot named `name`.
+///
+/// The snapshot is an annotated git tag named `snapshot/<name>` pointing at
+/// the current commit. Since it includes `config.json`, restoring it also
+/// restores the `dl` and `api` settings. Returns an error if a snapshot
+/// with the same name already exists.
+pub fn snapshot_create(
+    index: impl AsRef<Path>,
+    name: &str,
+    commit: &CommitOptions,
+) -> Result<Snapshot, Error> {
+    let index = index.as_ref();
+    let repo = open_git(index)?;
+    let lock = Lock::new_shared(index)?;
+    let tag_name = tag_name(name)?;
+    if repo
+        .find_reference(&format!("refs/tags/{}", tag_name))
+        .is_ok()
+    {
+        bail!("Snapshot `{}` already exists.", name);
+    }
+    let head = repo.head()?.peel_to_commit()?;
+    let sig = signature(&repo, commit)?;
+    repo.tag(
+        &tag_name,
+        head.as_object(),
+        &sig,
+        commit
+            .message
+            .as_deref()
+            .unwrap_or(&format!("Snapshot {}", name)),
+        false,
+    )?;
+    drop(lock);
+    Ok(Snapshot {
+        name: name.to_string(),
+        commit: head.id().to_string(),
+        time: sig.when().seconds(),
+    })
+}
+
+/// Return the index to the state of the snapshot named `name`.
+///
+/// The commits since the snapshot are not removed. Instead, a new commit is
+/// added that reverts every change made since the snapshot, so the history
+/// is kept and the restore can itself be undone. Returns `false` if the
+/// index already matches the snapshot, in which case nothing is committed.
+pub fn snapshot_restore(
+    index: impl AsRef<Path>,
+    name: &str,
+    commit: &CommitOptions,
+) -> Result<bool, Error> {
+    let index = index.as_ref();
+    let repo = open_git(index)?;
+    let lock = Lock::new_exclusive(index)?;
+    let tag_name = tag_name(name)?;
+    let target = repo
+        .find_reference(&format!("refs/tags/{}", tag_name))
+        .map_err(|_| format_err!("Snapshot `{}` not found.", name))?
+        .peel_to_commit()?;
+    let head = repo.head()?.peel_to_commit()?;
+    if head.tree_id() == target.tree_id() {
+        return Ok(false);
+    }
+    let tree = target.tree()?;
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    checkout.force();
+    repo.checkout_tree(tree.as_object(), Some(&mut checkout))?;
+    let mut git_index = repo.index()?;
+    git_index.read_tree(&tree)?;
+    git_index.write()?;
+    let msg = format!(
+        "Restore snapshot {}\n\nThis reverts the index to commit {}.",
+        name,
+        target.id()
+    );
+    git_commit(&repo, &tree, &[&head], &msg, commit)?;
+    drop(lock);
+    Ok(true)
+}
+
+/// Returns the snapshots of the index, sorted by name.
+pub fn snapshot_list(index: impl AsRef<Path>) -> Result<Vec<Snapshot>, Error> {
+    let index = index.as_ref();
+    let repo = open_git(index)?;
+    let lock = Lock::new_shared(index)?;
+    let mut snapshots = Vec::new();
+    for tag_name in repo.tag_names(Some(&format!("{}*", TAG_PREFIX)))?.iter() {
+        let tag_name = match tag_name {
+            Some(tag_name) => tag_name,
+            None => continue,
+        };
+        let reference = repo.find_reference(&format!("refs/tags/{}", tag_name))?;
+        let commit = reference.peel_to_commit()?;
+        let time = match reference.peel_to_tag() {
+            Ok(tag) => tag.tagger().map_or(commit.time(), |sig| sig.when()),
+            Err(_) => commit.time(),
+        };
+        snapshots.push(Snapshot {
+            name: tag_name[TAG_PREFIX.len()..].to_string(),
+            commit: commit.id().to_string(),
+            time: time.seconds(),
+        });
+    }
+    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    drop(lock);
+    Ok(snapshots)
+}
+
+fn tag_name(name: &str) -> Result<String, Error> {
+    let tag_name = format!("{}{}", TAG_PREFIX, name);
+    if name.is_empty() || !git2::Reference::is_valid_name(&format!("refs/tags/{}", tag_name)) {
+        bail!("Invalid snapshot name `{}`.", name);
+    }
+    Ok(tag_name)
+}
@@ -0,0 +1,38 @@
+use crate::{load_config, validate::validate_package_name};
+use anyhow::Error;
+use std::path::Path;
+
+/// Generate a `.cargo/config.toml` snippet for using the index.
+///
+/// This includes a `[registries.<name>]` table so packages can depend on
+/// the registry with `registry = "<name>"`. If `replace_crates_io` is true,
+/// it also includes `[source]` tables that replace crates.io with this
+/// index, such as for a mirror.
+///
+/// The `index_url` should be the public URL that users use to access the
+/// index. The `index` path is used to read `config.json`.
+pub fn config_snippet(
+    index: impl AsRef<Path>,
+    index_url: &str,
+    name: &str,
+    replace_crates_io: bool,
+) -> Result<String, Error> {
+    validate_package_name(name, "registry name")?;
+    let config = load_config(index)?;
+    let url = toml::Value::String(index_url.to_string());
+    let mut snippet = format!("[registries.{}]\nindex = {}\n", name, url);
+    if config.api.is_none() {
+        snippet.push_str(
+            "# This index has no `api` in config.json, so `cargo publish` will not work.\n",
+        );
+    }
+    if replace_crates_io {
+        snippet.push_str(&format!(
+            "\n[source.crates-io]\nreplace-with = {}\n\n[source.{}]\nregistry = {}\n",
+            toml::Value::String(name.to_string()),
+            name,
+            url
+        ));
+    }
+    Ok(snippet)
+}
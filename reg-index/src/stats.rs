@@ -0,0 +1,96 @@
+use crate::{
+    list::_list,
+    lock::Lock,
+    store::{fetch, store_for},
+    util::crate_walker,
+};
+use anyhow::{Context, Error};
+use std::{fs, path::Path};
+
+/// Counts of the entries in an index, returned by [`stats`].
+///
+/// [`stats`]: fn.stats.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct IndexStats {
+    /// The number of packages.
+    pub crates: usize,
+    /// The number of versions of all packages.
+    pub versions: usize,
+    /// The number of yanked versions of all packages.
+    pub yanked: usize,
+    /// The total size in bytes of the `.crate` files, if they were given.
+    pub crate_size: Option<u64>,
+    /// The counts of each package, sorted by name.
+    pub packages: Vec<PackageStats>,
+}
+
+/// Counts of the entries of one package, part of [`IndexStats`].
+///
+/// [`IndexStats`]: struct.IndexStats.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct PackageStats {
+    /// The name of the package.
+    pub name: String,
+    /// The number of versions.
+    pub versions: usize,
+    /// The number of yanked versions.
+    pub yanked: usize,
+    /// The total size in bytes of the `.crate` files of every version, if
+    /// they were given.
+    pub crate_size: Option<u64>,
+}
+
+/// Count the packages and versions in the index.
+///
+/// If `crates` is set, it is the location of the `.crate` files, the same
+/// as for [`validate`], and their sizes are added up. Each file must exist.
+/// Files that are not in a local directory are downloaded to get their
+/// size.
+///
+/// [`validate`]: fn.validate.html
+pub fn stats(index: impl AsRef<Path>, crates: Option<&str>) -> Result<IndexStats, Error> {
+    let index = index.as_ref();
+    let lock = Lock::new_shared(index)?;
+    let store = crates.map(store_for);
+    let tmp_dir = tempfile::tempdir()?;
+    let mut stats = IndexStats::default();
+    if store.is_some() {
+        stats.crate_size = Some(0);
+    }
+    for file in crate_walker(index) {
+        let file = file?;
+        let entries = _list(index, file.file_name().to_str().unwrap(), None)?;
+        let Some(first) = entries.first() else {
+            continue;
+        };
+        let mut pkg_stats = PackageStats {
+            name: first.name.clone(),
+            versions: entries.len(),
+            yanked: entries.iter().filter(|entry| entry.yanked).count(),
+            crate_size: None,
+        };
+        if let Some(store) = &store {
+            let mut size = 0;
+            for entry in &entries {
+                let path = fetch(&**store, entry, tmp_dir.path())?;
+                size += fs::metadata(&path)
+                    .with_context(|| format!("Failed to read `{}`.", path.display()))?
+                    .len();
+                if store.local_path(entry).is_none() {
+                    fs::remove_file(&path)?;
+                }
+            }
+            pkg_stats.crate_size = Some(size);
+            stats.crate_size = stats.crate_size.map(|total| total + size);
+        }
+        stats.crates += 1;
+        stats.versions += pkg_stats.versions;
+        stats.yanked += pkg_stats.yanked;
+        stats.packages.push(pkg_stats);
+    }
+    drop(lock);
+    stats.packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(stats)
+}
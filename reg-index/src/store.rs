@@ -0,0 +1,240 @@
+use crate::{util::expand_markers, IndexPackage};
+use anyhow::{bail, Context, Error};
+use std::{
+    fmt::Debug,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Environment variable with the value of the `Authorization` header sent
+/// with HTTP uploads.
+pub const UPLOAD_AUTHORIZATION_ENV: &str = "CARGO_INDEX_UPLOAD_AUTHORIZATION";
+
+/// Where the `.crate` files of an index are stored.
+///
+/// [`add_with_options`] puts the `.crate` file of each new entry in the
+/// store set in [`AddOptions::store`], and [`validate_store`] reads them
+/// back to verify the checksums. [`DirStore`] and [`UrlStore`] implement
+/// the `upload` paths and URLs accepted elsewhere in this library; other
+/// storage can be supported by implementing this trait.
+///
+/// [`add_with_options`]: fn.add_with_options.html
+/// [`AddOptions::store`]: struct.AddOptions.html#structfield.store
+/// [`validate_store`]: fn.validate_store.html
+/// [`DirStore`]: struct.DirStore.html
+/// [`UrlStore`]: struct.UrlStore.html
+pub trait CrateStore: Debug + Send + Sync {
+    /// Stores the `.crate` file of `pkg`, which is at `crate_path`.
+    ///
+    /// This replaces the file if it is already stored.
+    fn put(&self, pkg: &IndexPackage, crate_path: &Path) -> Result<(), Error>;
+
+    /// Copies the `.crate` file of `pkg` to `dest`.
+    ///
+    /// Returns an error if it is not stored.
+    fn get(&self, pkg: &IndexPackage, dest: &Path) -> Result<(), Error>;
+
+    /// The path of the `.crate` file of `pkg` if the store keeps it on the
+    /// local filesystem, so it can be read without copying it.
+    fn local_path(&self, _pkg: &IndexPackage) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Stores `.crate` files in a local directory.
+///
+/// The template may have the `{crate}`, `{version}`, `{prefix}`,
+/// `{lowerprefix}`, and `{sha256-checksum}` markers, and the file is named
+/// `{crate}-{version}.crate` in the directory.
+#[derive(Clone, Debug)]
+pub struct DirStore {
+    template: String,
+}
+
+impl DirStore {
+    /// Creates a store for the directory `template`.
+    pub fn new(template: impl Into<String>) -> DirStore {
+        DirStore {
+            template: template.into(),
+        }
+    }
+}
+
+impl CrateStore for DirStore {
+    fn put(&self, pkg: &IndexPackage, crate_path: &Path) -> Result<(), Error> {
+        let dest = self.local_path(pkg).unwrap();
+        fs::create_dir_all(dest.parent().unwrap())?;
+        fs::copy(crate_path, &dest)
+            .with_context(|| format!("Failed to copy to `{}`.", dest.display()))?;
+        Ok(())
+    }
+
+    fn get(&self, pkg: &IndexPackage, dest: &Path) -> Result<(), Error> {
+        let path = self.local_path(pkg).unwrap();
+        if !path.exists() {
+            bail!("Could not find crate file: {}", path.display());
+        }
+        fs::copy(&path, dest).with_context(|| format!("Failed to copy `{}`.", path.display()))?;
+        Ok(())
+    }
+
+    fn local_path(&self, pkg: &IndexPackage) -> Option<PathBuf> {
+        Some(
+            Path::new(&expand_markers(&self.template, pkg))
+                .join(format!("{}-{}.crate", pkg.name, pkg.vers)),
+        )
+    }
+}
+
+/// Stores `.crate` files at `http://`, `https://`, or `s3://` URLs.
+///
+/// The template may have the same markers as [`DirStore`], and
+/// `/{crate}-{version}.crate` is added to the end.
+///
+/// HTTP uses `curl`, with a PUT to store a file, and credentials from
+/// `~/.netrc` or the [`UPLOAD_AUTHORIZATION_ENV`] variable. S3 uses
+/// `aws s3 cp`, so the usual AWS settings apply, including
+/// `AWS_ENDPOINT_URL` for S3-compatible storage.
+///
+/// [`DirStore`]: struct.DirStore.html
+/// [`UPLOAD_AUTHORIZATION_ENV`]: constant.UPLOAD_AUTHORIZATION_ENV.html
+#[derive(Clone, Debug)]
+pub struct UrlStore {
+    template: String,
+}
+
+impl UrlStore {
+    /// Creates a store for the URL `template`.
+    ///
+    /// Returns an error if it is not an `http://`, `https://`, or `s3://`
+    /// URL.
+    pub fn new(template: impl Into<String>) -> Result<UrlStore, Error> {
+        let template = template.into();
+        if !is_remote(&template) {
+            bail!(
+                "`{}` is not an `http://`, `https://`, or `s3://` URL.",
+                template
+            );
+        }
+        Ok(UrlStore { template })
+    }
+
+    fn url(&self, pkg: &IndexPackage) -> String {
+        format!(
+            "{}/{}-{}.crate",
+            expand_markers(&self.template, pkg).trim_end_matches('/'),
+            pkg.name,
+            pkg.vers
+        )
+    }
+}
+
+impl CrateStore for UrlStore {
+    fn put(&self, pkg: &IndexPackage, crate_path: &Path) -> Result<(), Error> {
+        let url = self.url(pkg);
+        let mut cmd = if url.starts_with("s3://") {
+            let mut cmd = Command::new("aws");
+            cmd.args(["s3", "cp", "--only-show-errors"])
+                .arg(crate_path)
+                .arg(&url);
+            cmd
+        } else {
+            let mut cmd = curl(&url);
+            cmd.arg("--upload-file").arg(crate_path);
+            cmd
+        };
+        run(&mut cmd, &url).with_context(|| format!("Failed to upload to `{}`.", url))
+    }
+
+    fn get(&self, pkg: &IndexPackage, dest: &Path) -> Result<(), Error> {
+        let url = self.url(pkg);
+        let mut cmd = if url.starts_with("s3://") {
+            let mut cmd = Command::new("aws");
+            cmd.args(["s3", "cp", "--only-show-errors"])
+                .arg(&url)
+                .arg(dest);
+            cmd
+        } else {
+            let mut cmd = curl(&url);
+            cmd.arg("--output").arg(dest);
+            cmd
+        };
+        run(&mut cmd, &url).with_context(|| format!("Failed to download `{}`.", url))
+    }
+}
+
+/// Returns `true` if `upload` is a URL for a [`UrlStore`] rather than a
+/// local directory.
+///
+/// [`UrlStore`]: struct.UrlStore.html
+pub(crate) fn is_remote(upload: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|prefix| upload.starts_with(prefix))
+}
+
+/// The store for an `upload` path or URL that is not `lfs:`.
+pub(crate) fn store_for(upload: &str) -> Box<dyn CrateStore> {
+    match UrlStore::new(upload) {
+        Ok(store) => Box::new(store),
+        Err(_) => Box::new(DirStore::new(upload)),
+    }
+}
+
+/// Returns the path of the `.crate` file of `pkg`, copying it into
+/// `tmp_dir` if the store isn't local.
+pub(crate) fn fetch(
+    store: &dyn CrateStore,
+    pkg: &IndexPackage,
+    tmp_dir: &Path,
+) -> Result<PathBuf, Error> {
+    if let Some(path) = store.local_path(pkg) {
+        if !path.exists() {
+            bail!("Could not find crate file: {}", path.display());
+        }
+        return Ok(path);
+    }
+    let path = tmp_dir.join(format!("{}-{}.crate", pkg.name, pkg.vers));
+    store.get(pkg, &path)?;
+    Ok(path)
+}
+
+fn curl(url: &str) -> Command {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--fail", "--silent", "--show-error", "--netrc-optional"]);
+    // The header is read from stdin so the credentials are not visible in
+    // the process list.
+    if std::env::var_os(UPLOAD_AUTHORIZATION_ENV).is_some() {
+        cmd.args(["--header", "@-"]);
+    }
+    cmd.arg(url);
+    cmd
+}
+
+fn run(cmd: &mut Command, url: &str) -> Result<(), Error> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Could not run `{}`, it is needed for `{}`.", program, url))?;
+    let mut stdin = child.stdin.take().unwrap();
+    if let Ok(authorization) = std::env::var(UPLOAD_AUTHORIZATION_ENV) {
+        if program == "curl" {
+            writeln!(stdin, "Authorization: {}", authorization)?;
+        }
+    }
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "`{}` failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(())
+}
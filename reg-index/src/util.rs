@@ -1,16 +1,352 @@
-use anyhow::{bail, Context, Error};
-use semver::Version;
+use crate::{
+    init::{index_protocol, Protocol, SPARSE_MARKER},
+    verify::{open_crate, EntryChecker},
+    CrateLimits, IndexPackage,
+};
+use anyhow::{bail, format_err, Context, Error};
 use sha2::Digest;
 use std::{
-    env, fs, io,
+    env,
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
 use walkdir::{DirEntry, WalkDir};
 
-pub(crate) fn signature(repo: &git2::Repository) -> Result<git2::Signature, Error> {
-    Ok(repo
-        .signature()
+/// Options for the git commits made to the index.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct CommitOptions {
+    /// The name to use for the author, and for the committer unless
+    /// `committer_name` is set.
+    ///
+    /// If not set, this comes from the git config, or the `GIT_AUTHOR_NAME`
+    /// or `GIT_COMMITTER_NAME` environment variables.
+    pub name: Option<String>,
+    /// The email to use for the author, and for the committer unless
+    /// `committer_email` is set.
+    ///
+    /// If not set, this comes from the git config, or the
+    /// `GIT_AUTHOR_EMAIL` or `GIT_COMMITTER_EMAIL` environment variables.
+    pub email: Option<String>,
+    /// The time of the commit, in seconds since the Unix epoch.
+    ///
+    /// If not set, this comes from the `SOURCE_DATE_EPOCH` environment
+    /// variable, or the current time. A fixed time makes rebuilding an index
+    /// from the same inputs produce the same commits.
+    pub timestamp: Option<i64>,
+    /// The name to use for the committer, if different from the author.
+    pub committer_name: Option<String>,
+    /// The email to use for the committer, if different from the author.
+    pub committer_email: Option<String>,
+    /// The commit message, instead of the one describing the change.
+    pub message: Option<String>,
+    /// Trailers to add to the end of the commit message, such as
+    /// `Yank-Reason`, as key/value pairs.
+    pub trailers: Vec<(String, String)>,
+    /// If `true`, sign the commit with GPG.
+    ///
+    /// This uses the `gpg.program` and `user.signingkey` git config values
+    /// like `git commit -S`, falling back to `gpg` and the committer email.
+    pub sign: bool,
+    /// The branch to commit to, instead of the current branch.
+    ///
+    /// The branch is created if it does not exist, and becomes the current
+    /// branch. Returns an error if the branch exists but does not point at
+    /// the current commit, since the changes are made to the checked out
+    /// files. In that case the files changed by the command are restored,
+    /// and other uncommitted changes are left alone.
+    pub branch: Option<String>,
+    /// The name or URL of a git remote to push the branch to after
+    /// committing.
+    ///
+    /// An empty string uses the remote that the branch tracks, or `origin`
+    /// if it does not track one. This runs `git push`, so the usual git
+    /// credential settings apply.
+    pub push: Option<String>,
+}
+
+impl CommitOptions {
+    /// Sets the name of the author.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the email of the author.
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    /// Sets the time of the commit, in seconds since the Unix epoch.
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the name of the committer.
+    pub fn committer_name(mut self, name: impl Into<String>) -> Self {
+        self.committer_name = Some(name.into());
+        self
+    }
+
+    /// Sets the email of the committer.
+    pub fn committer_email(mut self, email: impl Into<String>) -> Self {
+        self.committer_email = Some(email.into());
+        self
+    }
+
+    /// Sets the commit message.
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Adds a trailer to the end of the commit message.
+    pub fn trailer(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.trailers.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets whether to sign the commit with GPG.
+    pub fn sign(mut self, sign: bool) -> Self {
+        self.sign = sign;
+        self
+    }
+
+    /// Sets the branch to commit to.
+    pub fn branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    /// Sets the git remote to push the branch to.
+    pub fn push(mut self, remote: impl Into<String>) -> Self {
+        self.push = Some(remote.into());
+        self
+    }
+}
+
+/// Open the git repository of an index.
+///
+/// Returns `None` for a sparse index, which has no repository.
+pub(crate) fn open_repo(index: &Path) -> Result<Option<git2::Repository>, Error> {
+    match index_protocol(index) {
+        Protocol::Sparse => Ok(None),
+        Protocol::Git => open_git(index).map(Some),
+    }
+}
+
+/// Open the git repository of an index, for operations that need its git
+/// history.
+pub(crate) fn open_git(index: &Path) -> Result<git2::Repository, Error> {
+    if index_protocol(index) == Protocol::Sparse {
+        bail!(
+            "The index at `{}` is a sparse index, which has no git history.",
+            index.display()
+        );
+    }
+    git2::Repository::open(index)
+        .with_context(|| format!("Could not open index at `{}`.", index.display()))
+}
+
+/// Create a commit in the index, as described by `commit`.
+///
+/// `default_msg` is used unless [`CommitOptions::message`] is set, followed
+/// by any [`CommitOptions::trailers`]. Returns the id of the new commit.
+pub(crate) fn git_commit(
+    repo: &git2::Repository,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+    default_msg: &str,
+    commit: &CommitOptions,
+) -> Result<git2::Oid, Error> {
+    let mut msg = commit.message.as_deref().unwrap_or(default_msg).to_string();
+    if !commit.trailers.is_empty() {
+        msg.push_str("\n\n");
+        for (key, value) in &commit.trailers {
+            msg.push_str(&format!("{}: {}\n", key, value));
+        }
+    }
+    let msg = msg.as_str();
+    let author = signature(repo, commit)?;
+    let committer = match (&commit.committer_name, &commit.committer_email) {
+        (None, None) => author.clone(),
+        (name, email) => git2::Signature::new(
+            name.as_deref().or(author.name()).unwrap_or_default(),
+            email.as_deref().or(author.email()).unwrap_or_default(),
+            &author.when(),
+        )?,
+    };
+    let ref_name = match &commit.branch {
+        Some(branch) => {
+            let ref_name = format!("refs/heads/{}", branch);
+            if !git2::Reference::is_valid_name(&ref_name) {
+                bail!("Invalid branch name `{}`.", branch);
+            }
+            if let Ok(reference) = repo.find_reference(&ref_name) {
+                let tip = reference.peel_to_commit()?.id();
+                if parents.first().map(|parent| parent.id()) != Some(tip) {
+                    // Undo the changes, which were made to the current branch.
+                    if let Some(parent) = parents.first() {
+                        restore_paths(repo, parent, tree)?;
+                    }
+                    bail!(
+                        "Branch `{}` does not point at the current commit of the index.",
+                        branch
+                    );
+                }
+            }
+            Some(ref_name)
+        }
+        // `None` for a detached HEAD.
+        None => repo
+            .find_reference("HEAD")?
+            .symbolic_target()
+            .map(str::to_string),
+    };
+    let id = if commit.sign {
+        let buf = repo.commit_create_buffer(&author, &committer, msg, tree, parents)?;
+        let buf = std::str::from_utf8(&buf)?;
+        let gpgsig = gpg_sign(repo, buf, committer.email().unwrap_or_default())?;
+        repo.commit_signed(buf, &gpgsig, None)?
+    } else {
+        repo.commit(None, &author, &committer, msg, tree, parents)?
+    };
+    match &ref_name {
+        Some(ref_name) => {
+            repo.reference(ref_name, id, true, msg)?;
+            repo.set_head(ref_name)?;
+        }
+        None => repo.set_head_detached(id)?,
+    }
+    if let Some(remote) = &commit.push {
+        let ref_name =
+            ref_name.ok_or_else(|| format_err!("Cannot push a commit that is not on a branch."))?;
+        let remote = if remote.is_empty() {
+            let branch = ref_name.trim_start_matches("refs/heads/");
+            repo.config()?
+                .get_string(&format!("branch.{}.remote", branch))
+                .unwrap_or_else(|_| "origin".to_string())
+        } else {
+            remote.clone()
+        };
+        let status = Command::new("git")
+            .arg("push")
+            .arg("--quiet")
+            .arg(&remote)
+            .arg(format!("{}:{}", ref_name, ref_name))
+            .current_dir(repo.workdir().unwrap_or_else(|| repo.path()))
+            .status()
+            .with_context(|| "Could not run `git push`.")?;
+        if !status.success() {
+            bail!(
+                "Failed to push to `{}`, the commit was only made locally.",
+                remote
+            );
+        }
+    }
+    Ok(id)
+}
+
+/// Restore the files that differ between `parent` and `tree` to `parent`,
+/// in both the git index and the working tree.
+///
+/// Unlike a hard reset, changes to any other files are kept.
+fn restore_paths(
+    repo: &git2::Repository,
+    parent: &git2::Commit<'_>,
+    tree: &git2::Tree<'_>,
+) -> Result<(), Error> {
+    let diff = repo.diff_tree_to_tree(Some(&parent.tree()?), Some(tree), None)?;
+    let paths: Vec<&Path> = diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .collect();
+    if paths.is_empty() {
+        return Ok(());
+    }
+    repo.reset_default(Some(parent.as_object()), &paths)?;
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    // Files that were added are untracked after the reset.
+    checkout.force().remove_untracked(true);
+    for path in &paths {
+        checkout.path(path);
+    }
+    repo.checkout_tree(parent.as_object(), Some(&mut checkout))?;
+    Ok(())
+}
+
+/// Sign the contents of a commit, returning the ASCII-armored signature.
+fn gpg_sign(repo: &git2::Repository, buf: &str, email: &str) -> Result<String, Error> {
+    let config = repo.config()?;
+    let program = config
+        .get_string("gpg.program")
+        .unwrap_or_else(|_| "gpg".to_string());
+    let key = config
+        .get_string("user.signingkey")
+        .unwrap_or_else(|_| email.to_string());
+    let mut child = Command::new(&program)
+        .args(["--status-fd=2", "-bsau", &key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run `{}` to sign the commit.", program))?;
+    child.stdin.take().unwrap().write_all(buf.as_bytes())?;
+    let output = child.wait_with_output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !stderr.contains("[GNUPG:] SIG_CREATED ") {
+        bail!("Failed to sign the commit with `{}`:\n{}", program, stderr);
+    }
+    let gpgsig = String::from_utf8(output.stdout)?;
+    Ok(gpgsig.trim_end().to_string())
+}
+
+pub(crate) fn signature(
+    repo: &git2::Repository,
+    commit: &CommitOptions,
+) -> Result<git2::Signature<'static>, Error> {
+    let timestamp = match commit.timestamp {
+        Some(timestamp) => Some(timestamp),
+        None => match env::var("SOURCE_DATE_EPOCH") {
+            Ok(epoch) => Some(
+                epoch
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid `SOURCE_DATE_EPOCH` value `{}`.", epoch))?,
+            ),
+            Err(_) => None,
+        },
+    };
+    let default = match (&commit.name, &commit.email) {
+        (Some(_), Some(_)) => None,
+        _ => Some(default_signature(repo)?),
+    };
+    let name = commit
+        .name
+        .as_deref()
+        .or_else(|| default.as_ref().and_then(|sig| sig.name()))
+        .unwrap_or_default();
+    let email = commit
+        .email
+        .as_deref()
+        .or_else(|| default.as_ref().and_then(|sig| sig.email()))
+        .unwrap_or_default();
+    let sig = match timestamp {
+        Some(timestamp) => git2::Signature::new(name, email, &git2::Time::new(timestamp, 0))?,
+        None => git2::Signature::now(name, email)?,
+    };
+    Ok(sig)
+}
+
+fn default_signature(repo: &git2::Repository) -> Result<git2::Signature<'static>, Error> {
+    repo.signature()
         .or_else(|e| {
             let name = env::var("GIT_AUTHOR_NAME").or_else(|_| env::var("GIT_COMMITTER_NAME"));
             let email = env::var("GIT_AUTHOR_EMAIL").or_else(|_| env::var("GIT_COMMITTER_EMAIL"));
@@ -22,7 +358,7 @@ pub(crate) fn signature(repo: &git2::Repository) -> Result<git2::Signature, Erro
         .with_context(|| {
             "Could not determine git username/email for signature. \
              Be sure to set `user.name` and `user.email` in gitconfig."
-        })?)
+        })
 }
 
 /// Call `cargo package` to generate a `.crate` file.
@@ -49,7 +385,7 @@ pub(crate) fn cargo_package(
     }
     let crate_path = target_dir
         .join("package")
-        .join(&format!("{}-{}.crate", pkg.name, pkg.version));
+        .join(format!("{}-{}.crate", pkg.name, pkg.version));
     if !crate_path.exists() {
         bail!(
             "Could not find crate after `cargo package` at {:?}",
@@ -62,65 +398,274 @@ pub(crate) fn cargo_package(
 /// Compute checksum for a `.crate` file.
 pub(crate) fn cksum(path: &Path) -> Result<String, Error> {
     let mut hasher = sha2::Sha256::default();
-    let mut file = fs::File::open(&path)
+    let mut file = fs::File::open(path)
         .with_context(|| format!("Could not open crate file `{}`.", path.display()))?;
     io::copy(&mut file, &mut hasher).unwrap();
     Ok(hex::encode(hasher.finalize()))
 }
 
+/// Extract a `.crate` file to a temporary directory.
+///
+/// The archive is checked against the default [`CrateLimits`] before each
+/// entry is unpacked, since it may come from an untrusted source.
 pub(crate) fn extract_crate(crate_path: &Path) -> Result<(tempfile::TempDir, PathBuf), Error> {
-    let crate_file = fs::File::open(crate_path)
-        .with_context(|| format!("Failed to open `{}`.", crate_path.display()))?;
+    let limits = CrateLimits::default();
+    let mut tar = open_crate(crate_path, &limits)?;
+    let mut checker = EntryChecker::new(crate_path, &limits)?;
     let tmp_dir = tempfile::tempdir().unwrap();
-    let gz = flate2::read::GzDecoder::new(crate_file);
-    let mut tar = tar::Archive::new(gz);
-    let prefix = crate_path.file_stem().unwrap();
     for entry in tar.entries()? {
         let mut entry = entry.with_context(|| "Failed to iterate over archive.")?;
-        let entry_path = entry
-            .path()
-            .with_context(|| "Failed to read entry path.")?
-            .into_owned();
-        if !entry_path.starts_with(prefix) {
-            bail!(
-                "Expected .crate file to contain entries rooted in `{}` directory, found `{}`.",
-                prefix.to_str().unwrap(),
-                entry_path.display()
-            );
-        }
+        let entry_path = checker.check(&entry)?;
         entry
             .unpack_in(tmp_dir.path())
             .with_context(|| format!("Failed to unpack entry at `{}`.", entry_path.display()))?;
     }
-    let pkg_path = tmp_dir.path().join(prefix);
+    let pkg_path = tmp_dir.path().join(checker.prefix);
     Ok((tmp_dir, pkg_path))
 }
 
 /// Repo-relative path to a package.
-pub(crate) fn pkg_path(name: &str) -> PathBuf {
-    let name = name.to_lowercase();
+pub(crate) fn pkg_path(name: &str) -> Result<PathBuf, Error> {
+    reg_index_core::pkg_path(name).map(PathBuf::from)
+}
+
+/// The name that Cargo compares package names by, which ignores case and
+/// treats `-` and `_` as the same.
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+/// The `{prefix}` directory of a package in a download URL, without
+/// lowercasing the name.
+fn dl_prefix(name: &str) -> String {
     match name.len() {
-        1 => Path::new("1").join(&name),
-        2 => Path::new("2").join(&name),
-        3 => Path::new("3").join(&name[..1]).join(&name),
-        _ => Path::new(&name[0..2]).join(&name[2..4]).join(&name),
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[0..2], &name[2..4]),
+    }
+}
+
+/// The markers Cargo supports in the `dl` template of `config.json`.
+pub(crate) const DL_MARKERS: &[&str] = &[
+    "{crate}",
+    "{version}",
+    "{prefix}",
+    "{lowerprefix}",
+    "{sha256-checksum}",
+];
+
+/// Find the `{...}` markers in a `dl` template.
+///
+/// Returns an error if a `{` is not closed.
+pub(crate) fn find_markers(template: &str) -> Result<Vec<&str>, Error> {
+    let mut markers = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end + 1,
+            None => bail!("Unclosed `{{` in `dl` URL `{}`", template),
+        };
+        markers.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    Ok(markers)
+}
+
+/// Replace the markers Cargo supports in the `dl` template in a path
+/// template such as `--upload` or `--crates`.
+///
+/// Supports `{crate}`, `{version}`, `{prefix}`, `{lowerprefix}`, and
+/// `{sha256-checksum}`.
+pub(crate) fn expand_markers(template: &str, pkg: &IndexPackage) -> String {
+    template
+        .replace("{crate}", &pkg.name)
+        .replace("{version}", &pkg.vers.to_string())
+        .replace("{prefix}", &dl_prefix(&pkg.name))
+        .replace("{lowerprefix}", &dl_prefix(&pkg.name.to_lowercase()))
+        .replace("{sha256-checksum}", &pkg.cksum)
+}
+
+/// Undo the percent-encoding of `{` and `}` that `Url` does in the path.
+pub(crate) fn decode_braces(url: &str) -> String {
+    url.replace("%7B", "{")
+        .replace("%7D", "}")
+        .replace("%7b", "{")
+        .replace("%7d", "}")
+}
+
+/// The URL Cargo downloads a package from, given the `dl` setting of
+/// `config.json`.
+pub(crate) fn dl_url(dl: &str, pkg: &IndexPackage) -> String {
+    let dl = decode_braces(dl);
+    if DL_MARKERS.iter().any(|marker| dl.contains(marker)) {
+        expand_markers(&dl, pkg)
+    } else {
+        // Without any markers, Cargo appends `/{crate}/{version}/download`.
+        format!(
+            "{}/{}/{}/download",
+            dl.trim_end_matches('/'),
+            pkg.name,
+            pkg.vers
+        )
     }
 }
 
-pub(crate) fn vers_eq(v1: &Version, v2: &Version) -> bool {
-    // Unfortunately semver ignores build.
-    v1 == v2 && v1.build == v2.build
+/// Name of the file listing paths in the index that are not entries.
+pub(crate) const IGNORE_FILE: &str = ".indexignore";
+
+/// Returns the patterns in the `.indexignore` file of the index.
+///
+/// Each line is the path of a file or directory, relative to the root of
+/// the index, that is skipped along with everything inside it. A `*`
+/// matches any characters within one component of the path. Blank lines
+/// and lines starting with `#` are ignored.
+fn ignore_patterns(index: &Path) -> Vec<String> {
+    let contents = fs::read_to_string(index.join(IGNORE_FILE)).unwrap_or_default();
+    contents
+        .lines()
+        .map(|line| line.trim().trim_matches('/'))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn is_ignored(patterns: &[String], path: &Path) -> bool {
+    let components: Vec<_> = path.components().map(|c| c.as_os_str()).collect();
+    patterns.iter().any(|pattern| {
+        let parts: Vec<&str> = pattern.split('/').collect();
+        parts.len() <= components.len()
+            && parts.iter().zip(&components).all(|(part, component)| {
+                component
+                    .to_str()
+                    .is_some_and(|component| wildcard_match(part, component))
+            })
+    })
+}
+
+/// Matches `text` against `pattern`, where `*` matches any characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=text.len())
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| wildcard_match(rest, &text[i..]))
+        }
+    }
 }
 
+/// Whether a file or directory with this name is used by the index itself,
+/// instead of containing entries.
+fn is_index_file(name: &OsStr) -> bool {
+    name == "config.json"
+        || name == ".git"
+        || name == ".gitattributes"
+        || name == "README.md"
+        || name == IGNORE_FILE
+        || name == ".cargo-index-lock"
+        || name == SPARSE_MARKER
+}
+
+/// Walks the files of the index that contain entries.
+///
+/// This skips files used by the index itself, and anything listed in
+/// `.indexignore`, see [`ignore_patterns`].
 pub(crate) fn crate_walker(index: &Path) -> impl Iterator<Item = walkdir::Result<DirEntry>> {
+    let patterns = ignore_patterns(index);
+    let root = index.to_path_buf();
     WalkDir::new(index)
         .into_iter()
-        .filter_entry(|e| {
-            let name = e.file_name();
-            name != "config.json" && name != ".git" && name != ".cargo-index-lock"
+        .filter_entry(move |e| {
+            !is_index_file(e.file_name())
+                && !e
+                    .path()
+                    .strip_prefix(&root)
+                    .is_ok_and(|path| is_ignored(&patterns, path))
         })
         .filter(|e| match e {
             Ok(e) => e.file_type().is_file(),
             _ => true,
         })
 }
+
+/// Describes the files of a git index that differ from `HEAD`.
+///
+/// Only `config.json` and files that contain entries are included, since
+/// Cargo only sees what is committed. A sparse index has no working tree to
+/// compare, so it is always clean.
+pub(crate) fn worktree_status(index: &Path) -> Result<Vec<String>, Error> {
+    let Some(repo) = open_repo(index)? else {
+        return Ok(Vec::new());
+    };
+    let patterns = ignore_patterns(index);
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .exclude_submodules(true);
+    let mut problems = Vec::new();
+    for entry in repo.statuses(Some(&mut opts))?.iter() {
+        let Some(path) = entry.path() else {
+            continue;
+        };
+        let path = Path::new(path);
+        let included = path == Path::new("config.json")
+            || (!path.components().any(|c| is_index_file(c.as_os_str()))
+                && !is_ignored(&patterns, path));
+        if !included {
+            continue;
+        }
+        let status = entry.status();
+        if status.is_wt_new() {
+            problems.push(format!("File `{}` is not committed.", path.display()));
+        } else if status.is_wt_deleted() || status.is_index_deleted() {
+            problems.push(format!(
+                "File `{}` is deleted, but the deletion is not committed.",
+                path.display()
+            ));
+        } else {
+            problems.push(format!(
+                "File `{}` has uncommitted changes.",
+                path.display()
+            ));
+        }
+    }
+    problems.sort();
+    Ok(problems)
+}
+
+/// Calls `f` on each item on a thread per CPU, returning the results in the
+/// same order as `items`.
+pub(crate) fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        done.push((i, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (i, result) in worker.join().unwrap() {
+                results[i] = Some(result);
+            }
+        }
+    });
+    results.into_iter().map(Option::unwrap).collect()
+}
@@ -1,73 +1,214 @@
 use crate::{
     load_config,
     lock::Lock,
-    util::{cksum, crate_walker},
-    IndexPackage,
+    store::{fetch, store_for, CrateStore},
+    util::{
+        cksum, crate_walker, decode_braces, dl_url, find_markers, normalize_name, par_map,
+        worktree_status, DL_MARKERS,
+    },
+    IndexConfig, IndexPackage,
 };
-use anyhow::{bail, format_err, Context, Error};
+use anyhow::{bail, Context, Error};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     fs,
-    path::Path,
+    path::{Path, PathBuf},
+    process::Command,
 };
+use url::Url;
+
+pub(crate) use reg_index_core::validate_package_name;
 
 /// Validate an index.
 ///
 /// Errors are displayed on stdout. Returns an error if any problems are
 /// found. `crates` is an optional path to a directory that contains `.crate`
-/// files to verify checksums. Supports `{crate}` and `{version}` markers.
+/// files to verify checksums. Supports the `{crate}`, `{version}`,
+/// `{prefix}`, `{lowerprefix}`, and `{sha256-checksum}` markers. It can also
+/// be a URL, the same as the `upload` argument of [`add`].
+///
+/// See [`validate_report`] to get the problems instead of displaying them.
+///
+/// [`add`]: fn.add.html
+/// [`validate_report`]: fn.validate_report.html
 pub fn validate(index: impl AsRef<Path>, crates: Option<&str>) -> Result<(), Error> {
-    let index = index.as_ref();
+    print_problems(&validate_report(index, crates)?)
+}
+
+/// Display the problems found by [`validate_report`] on stdout, returning an
+/// error if there are any.
+///
+/// [`validate_report`]: fn.validate_report.html
+pub(crate) fn print_problems(problems: &[String]) -> Result<(), Error> {
+    for problem in problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        bail!("Found at least one error in the index.");
+    }
+}
+
+/// Validate an index, returning a description of each problem found.
+///
+/// This does the same checks as [`validate`]. An error is only returned if
+/// the index could not be read at all.
+///
+/// [`validate`]: fn.validate.html
+pub fn validate_report(
+    index: impl AsRef<Path>,
+    crates: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let store = crates.map(store_for);
+    validate_with(
+        index.as_ref(),
+        store.as_deref(),
+        &ValidateOptions::default(),
+    )
+}
+
+/// Validate an index, checking the `.crate` files in `store`.
+///
+/// This is the same as [`validate_report`], with the `.crate` files read
+/// from a [`CrateStore`].
+///
+/// [`validate_report`]: fn.validate_report.html
+/// [`CrateStore`]: trait.CrateStore.html
+pub fn validate_store(
+    index: impl AsRef<Path>,
+    store: &dyn CrateStore,
+) -> Result<Vec<String>, Error> {
+    validate_with(index.as_ref(), Some(store), &ValidateOptions::default())
+}
+
+/// Options for [`validate_with_options`], which enable additional checks.
+///
+/// New options may be added in future versions, so create this with
+/// `ValidateOptions::default()` and set the fields that are needed.
+///
+/// [`validate_with_options`]: fn.validate_with_options.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ValidateOptions {
+    /// Report dependencies whose requirement only matches yanked versions,
+    /// since Cargo will not select a yanked version for a new lock file.
+    /// Dependencies of yanked entries are not checked.
+    pub strict_yanked: bool,
+    /// Send an HTTP `HEAD` request with `curl` to the `dl` URL of each entry
+    /// to check that the `.crate` file can be downloaded. Credentials are
+    /// read from `.netrc` if it exists.
+    pub check_dl: bool,
+    /// Report files in the working tree of a git index that differ from
+    /// `HEAD`, such as entries that were added but not committed. Cargo only
+    /// sees what is committed, so these are not visible to users.
+    pub check_worktree: bool,
+    /// Report packages whose names are one edit apart, such as one
+    /// character added, removed, replaced, or two swapped, which may be
+    /// typosquatting or an accidental near-duplicate. Names shorter than
+    /// four characters are not checked, since most of them are similar.
+    pub similar_names: bool,
+    /// Names of other packages, such as popular packages on crates.io, to
+    /// also compare the names in the index to with `similar_names`.
+    pub popular_names: Vec<String>,
+}
+
+/// Validate an index with additional checks, returning a description of
+/// each problem found.
+///
+/// This is the same as [`validate_report`], with the additional checks of
+/// [`ValidateOptions`].
+///
+/// [`validate_report`]: fn.validate_report.html
+/// [`ValidateOptions`]: struct.ValidateOptions.html
+pub fn validate_with_options(
+    index: impl AsRef<Path>,
+    crates: Option<&str>,
+    options: &ValidateOptions,
+) -> Result<Vec<String>, Error> {
+    let store = crates.map(store_for);
+    validate_with(index.as_ref(), store.as_deref(), options)
+}
+
+fn validate_with(
+    index: &Path,
+    store: Option<&dyn CrateStore>,
+    options: &ValidateOptions,
+) -> Result<Vec<String>, Error> {
     if !index.exists() {
         bail!("Index does not exist at `{}`.", index.display());
     }
     let lock = Lock::new_exclusive(index)?;
-    load_config(index)?;
-    let mut crate_map = HashMap::new();
-    let mut found_err = _validate(&mut crate_map, index, crates)?;
-    found_err |= _validate_deps(&crate_map)?;
+    let config = load_config(index)?;
+    let problems = validate_config(index, &config, store, options)?;
     drop(lock);
-    if found_err {
-        bail!("Found at least one error in the index.");
-    } else {
-        Ok(())
+    Ok(problems)
+}
+
+/// Validate an index whose configuration is already loaded. The caller is
+/// responsible for locking.
+pub(crate) fn validate_config(
+    index: &Path,
+    config: &IndexConfig,
+    store: Option<&dyn CrateStore>,
+    options: &ValidateOptions,
+) -> Result<Vec<String>, Error> {
+    let tmp_dir = tempfile::tempdir()?;
+    let mut crate_map = BTreeMap::new();
+    let mut problems = Vec::new();
+    _validate(&mut problems, &mut crate_map, index, store, tmp_dir.path())?;
+    _validate_names(&mut problems, &crate_map);
+    if options.similar_names {
+        _validate_similar_names(&mut problems, &crate_map, &options.popular_names);
+    }
+    _validate_deps(&mut problems, &crate_map, options.strict_yanked);
+    let start = problems.len();
+    _validate_dl(&mut problems, config, store, &crate_map);
+    if options.check_dl && problems.len() == start {
+        _validate_dl_server(&mut problems, config, &crate_map);
+    }
+    _validate_cksums(&mut problems, &crate_map);
+    if options.check_worktree {
+        problems.extend(worktree_status(index)?);
     }
+    Ok(problems)
 }
 
 fn _validate(
-    crate_map: &mut HashMap<String, Vec<IndexPackage>>,
+    problems: &mut Vec<String>,
+    crate_map: &mut BTreeMap<String, Vec<IndexPackage>>,
     index: &Path,
-    crates: Option<&str>,
-) -> Result<bool, Error> {
-    let mut found_err = false;
-    macro_rules! t {
-        ($e:expr) => {
-            match $e {
-                Ok(e) => e,
-                Err(e) => {
-                    found_err = true;
-                    println!("{}", e);
-                    continue;
-                }
-            }
-        };
-    }
+    store: Option<&dyn CrateStore>,
+    tmp_dir: &Path,
+) -> Result<(), Error> {
     macro_rules! err {
         ($fmt:expr, $($arg:tt)+) => {
-            println!($fmt, $($arg)+);
-            found_err = true;
+            problems.push(format!($fmt, $($arg)+));
         };
     }
+    let mut paths = Vec::new();
     for entry in crate_walker(index) {
-        let entry = entry?;
-        let file_name = entry.file_name();
-        let path = entry.path();
-        let name = t!(file_name.to_str().ok_or_else(|| format_err!(
-            "Expected UTF-8 file name, got `{}` at `{}`.",
-            entry.file_name().to_string_lossy(),
-            path.display()
-        )));
+        paths.push(entry?.into_path());
+    }
+    // Sorted so the problems are reported in the same order each time.
+    paths.sort();
+    // Files that would be the same file on a case-insensitive filesystem.
+    let mut folded_paths: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut files = Vec::new();
+    for path in paths {
+        let Some(name) = path.file_name().unwrap().to_str() else {
+            err!(
+                "Expected UTF-8 file name, got `{}` at `{}`.",
+                path.file_name().unwrap().to_string_lossy(),
+                path.display()
+            );
+            continue;
+        };
         let parts = path.strip_prefix(index).unwrap();
+        folded_paths
+            .entry(parts.to_string_lossy().to_lowercase())
+            .or_default()
+            .push(path.clone());
         let correct = match name.len() {
             1 => Path::new("1").join(name) == parts,
             2 => Path::new("2").join(name) == parts,
@@ -78,69 +219,156 @@ fn _validate(
             err!("File `{}` is not in the correct location.", path.display());
             continue;
         }
-        let contents = t!(fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read `{}`.", path.display())));
-        let mut seen = HashSet::new();
-        for line in contents.lines() {
-            let pkg: IndexPackage = t!(serde_json::from_str(line).with_context(|| format!(
-                "Could not deserialize `{}` line:\n{}",
-                path.display(),
-                line
-            )));
-            let all_vers = crate_map.entry(pkg.name.clone()).or_default();
-            all_vers.push(pkg.clone());
-            if !seen.insert(pkg.vers.to_string()) {
-                err!(
-                    "Version `{}` appears multiple times in `{}`.",
-                    pkg.vers,
-                    pkg.name
-                );
+        files.push(path);
+    }
+    // Reading, parsing, and checksumming are done in parallel, since they
+    // are independent for each file.
+    let reports = par_map(&files, |path| validate_file(path, store, tmp_dir));
+    for (pkgs, file_problems) in reports {
+        problems.extend(file_problems);
+        for pkg in pkgs {
+            crate_map.entry(pkg.name.clone()).or_default().push(pkg);
+        }
+    }
+    for paths in folded_paths.values_mut().filter(|paths| paths.len() > 1) {
+        paths.sort();
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| format!("`{}`", path.display()))
+            .collect();
+        err!(
+            "Files {} would collide on a case-insensitive filesystem.",
+            paths.join(" and ")
+        );
+    }
+    Ok(())
+}
+
+/// Validates the entries of one package file, returning the entries and
+/// the problems found.
+fn validate_file(
+    path: &Path,
+    store: Option<&dyn CrateStore>,
+    tmp_dir: &Path,
+) -> (Vec<IndexPackage>, Vec<String>) {
+    let mut pkgs = Vec::new();
+    let mut problems = Vec::new();
+    macro_rules! t {
+        ($e:expr) => {
+            match $e {
+                Ok(e) => e,
+                Err(e) => {
+                    problems.push(e.to_string());
+                    continue;
+                }
             }
-            t!(validate_package_name(&pkg.name, "package name"));
-            if pkg.name.to_lowercase() != file_name.to_str().unwrap() {
+        };
+    }
+    macro_rules! err {
+        ($fmt:expr, $($arg:tt)+) => {
+            problems.push(format!($fmt, $($arg)+));
+        };
+    }
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    let contents = match fs::read_to_string(path)
+        .with_context(|| format!("Failed to read `{}`.", path.display()))
+    {
+        Ok(contents) => contents,
+        Err(e) => return (pkgs, vec![e.to_string()]),
+    };
+    if contents.contains('\r') {
+        err!(
+            "File `{}` has CRLF line endings, index files must use LF.",
+            path.display()
+        );
+    }
+    if !contents.ends_with('\n') {
+        err!("File `{}` does not end with a newline.", path.display());
+    }
+    let mut seen = HashSet::new();
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    for line in contents.lines() {
+        let pkg: IndexPackage = t!(serde_json::from_str(line).with_context(|| format!(
+            "Could not deserialize `{}` line:\n{}",
+            path.display(),
+            line
+        )));
+        if !names.contains(&pkg.name) {
+            let folded = pkg.name.to_lowercase();
+            if let Some(other) = names.iter().find(|name| name.to_lowercase() == folded) {
                 err!(
-                    "Package `{}:{}` does not match file name `{}`.",
+                    "Package names `{}` and `{}` in `{}` differ only in case, \
+                     Cargo treats them as the same package.",
+                    other,
                     pkg.name,
-                    pkg.vers,
                     path.display()
                 );
             }
-            // Features could potentially have significant validation.
-            // See `build_feature_map` in Cargo.
-            for dep in pkg.deps {
-                t!(validate_package_name(
-                    &dep.name,
-                    &format!("dependency of `{}:{}`", pkg.name, pkg.vers),
-                ));
+            names.insert(pkg.name.clone());
+        }
+        pkgs.push(pkg.clone());
+        if !seen.insert(pkg.vers.to_string()) {
+            err!(
+                "Version `{}` appears multiple times in `{}`.",
+                pkg.vers,
+                pkg.name
+            );
+        }
+        t!(validate_package_name(&pkg.name, "package name"));
+        if pkg.cksum.len() != 64
+            || !pkg
+                .cksum
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        {
+            err!(
+                "Package `{}:{}` has an invalid checksum `{}`, expected 64 lowercase hex characters.",
+                pkg.name,
+                pkg.vers,
+                pkg.cksum
+            );
+        }
+        if pkg.name.to_lowercase() != file_name {
+            err!(
+                "Package `{}:{}` does not match file name `{}`.",
+                pkg.name,
+                pkg.vers,
+                path.display()
+            );
+        }
+        problems.extend(validate_features(&pkg));
+        for dep in &pkg.deps {
+            t!(validate_package_name(
+                &dep.name,
+                &format!("dependency of `{}:{}`", pkg.name, pkg.vers),
+            ));
+        }
+        if let Some(store) = store {
+            let crate_path = t!(fetch(store, &pkg, tmp_dir));
+            let cksum = cksum(&crate_path);
+            if store.local_path(&pkg).is_none() {
+                let _ = fs::remove_file(&crate_path);
             }
-            if let Some(crates) = crates {
-                let replaced = crates
-                    .replace("{crate}", &pkg.name)
-                    .replace("{version}", &pkg.vers.to_string());
-                let crate_path =
-                    Path::new(&replaced).join(format!("{}-{}.crate", pkg.name, pkg.vers));
-                if !crate_path.exists() {
-                    err!("Could not find crate file: {}", crate_path.display());
-                    continue;
-                }
-                let cksum = t!(cksum(&crate_path));
-                if pkg.cksum != cksum {
-                    err!(
-                        "Checksum did not match for package `{}:{}`:\nindex: {}\nactual:{}",
-                        pkg.name,
-                        pkg.vers,
-                        pkg.cksum,
-                        cksum
-                    );
-                }
+            let cksum = t!(cksum);
+            if pkg.cksum != cksum {
+                err!(
+                    "Checksum did not match for package `{}:{}`:\nindex: {}\nactual:{}",
+                    pkg.name,
+                    pkg.vers,
+                    pkg.cksum,
+                    cksum
+                );
             }
         }
     }
-    Ok(found_err)
+    (pkgs, problems)
 }
 
-fn _validate_deps(crate_map: &HashMap<String, Vec<IndexPackage>>) -> Result<bool, Error> {
-    let mut found_err = false;
+fn _validate_deps(
+    problems: &mut Vec<String>,
+    crate_map: &BTreeMap<String, Vec<IndexPackage>>,
+    strict_yanked: bool,
+) {
     for versions in crate_map.values() {
         for pkg in versions {
             for dep in &pkg.deps {
@@ -150,36 +378,354 @@ fn _validate_deps(crate_map: &HashMap<String, Vec<IndexPackage>>) -> Result<bool
                     let dep_versions = crate_map.get(dep_name);
                     match dep_versions {
                         Some(dep_versions) => {
-                            if !dep_versions
+                            let mut matching = dep_versions
                                 .iter()
-                                .any(|dep_version| dep.req.matches(&dep_version.vers))
+                                .filter(|dep_version| dep.req.matches(&dep_version.vers))
+                                .peekable();
+                            if matching.peek().is_none() {
+                                problems.push(format!("Could not find dependency `{}` matching requirement `{}` from package `{}:{}`.",
+                                dep_name, dep.req, pkg.name, pkg.vers));
+                            } else if strict_yanked
+                                && !pkg.yanked
+                                && matching.all(|dep_version| dep_version.yanked)
                             {
-                                println!("Could not find dependency `{}` matching requirement `{}` from package `{}:{}`.",
-                                dep_name, dep.req, pkg.name, pkg.vers);
-                                found_err = true;
+                                problems.push(format!(
+                                    "Dependency `{}` requirement `{}` from package `{}:{}` \
+                                     only matches yanked versions.",
+                                    dep_name, dep.req, pkg.name, pkg.vers
+                                ));
                             }
                         }
                         None => {
-                            println!(
+                            problems.push(format!(
                                 "Could not find dependency name `{}` from package `{}:{}`.",
                                 dep_name, pkg.name, pkg.vers
-                            );
-                            found_err = true;
+                            ));
                         }
                     }
                 }
             }
         }
     }
-    Ok(found_err)
 }
 
-fn validate_package_name(name: &str, what: &str) -> Result<(), Error> {
-    if let Some(ch) = name
-        .chars()
-        .find(|ch| !ch.is_alphanumeric() && *ch != '_' && *ch != '-')
-    {
-        bail!("Invalid character `{}` in {}: `{}`", ch, what, name);
+/// Checks the markers in the `dl` template of `config.json`.
+///
+/// If `store` is local and `dl` is a `file:` URL, also checks that each
+/// `.crate` file would be downloaded from the location it is validated at.
+fn _validate_dl(
+    problems: &mut Vec<String>,
+    config: &IndexConfig,
+    store: Option<&dyn CrateStore>,
+    crate_map: &BTreeMap<String, Vec<IndexPackage>>,
+) {
+    let dl: &str = &decode_braces(config.dl.as_str());
+    let start = problems.len();
+    let markers = match find_markers(dl) {
+        Ok(markers) => markers,
+        Err(e) => {
+            problems.push(format!("{} in config.json.", e));
+            return;
+        }
+    };
+    for marker in &markers {
+        if !DL_MARKERS.contains(marker) {
+            problems.push(format!(
+                "Unknown marker `{}` in `dl` URL `{}` in config.json, \
+                 supported markers are: {}",
+                marker,
+                dl,
+                DL_MARKERS.join(", ")
+            ));
+        }
+    }
+    let has_markers = !markers.is_empty();
+    if has_markers {
+        if !dl.contains("{crate}") {
+            problems.push(format!(
+                "The `dl` URL `{}` in config.json is missing the `{{crate}}` marker, \
+                 all packages would download from the same location.",
+                dl
+            ));
+        }
+        if !dl.contains("{version}") && !dl.contains("{sha256-checksum}") {
+            problems.push(format!(
+                "The `dl` URL `{}` in config.json is missing the `{{version}}` marker, \
+                 all versions of a package would download from the same location.",
+                dl
+            ));
+        }
+    }
+    let store = match store {
+        Some(store) if config.dl.scheme() == "file" && problems.len() == start => store,
+        _ => return,
+    };
+    let mut pkgs: Vec<&IndexPackage> = crate_map.values().flatten().collect();
+    pkgs.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+    for pkg in pkgs {
+        let dl_path = Url::parse(&dl_url(dl, pkg))
+            .ok()
+            .and_then(|url| url.to_file_path().ok());
+        let Some(crate_path) = store.local_path(pkg) else {
+            return;
+        };
+        if dl_path.as_deref() != Some(crate_path.as_path()) {
+            // Only show the first one, the rest are likely the same problem.
+            problems.push(format!(
+                "The `dl` URL `{}` in config.json does not match the `.crate` layout, \
+                 package `{}:{}` is at `{}`.",
+                dl,
+                pkg.name,
+                pkg.vers,
+                crate_path.display()
+            ));
+            return;
+        }
+    }
+}
+
+/// Checks that the `.crate` file of each entry can be downloaded from the
+/// `dl` URL.
+fn _validate_dl_server(
+    problems: &mut Vec<String>,
+    config: &IndexConfig,
+    crate_map: &BTreeMap<String, Vec<IndexPackage>>,
+) {
+    let mut pkgs: Vec<&IndexPackage> = crate_map.values().flatten().collect();
+    pkgs.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+    let reports = par_map(&pkgs, |pkg| {
+        let url = dl_url(config.dl.as_str(), pkg);
+        head(&url).err().map(|e| {
+            format!(
+                "Package `{}:{}` could not be downloaded from `{}`: {}",
+                pkg.name, pkg.vers, url, e
+            )
+        })
+    });
+    problems.extend(reports.into_iter().flatten());
+}
+
+/// Sends a `HEAD` request for `url` with `curl`.
+fn head(url: &str) -> Result<(), Error> {
+    let output = Command::new("curl")
+        .args([
+            "--head",
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--location",
+            "--netrc-optional",
+        ])
+        .arg(url)
+        .output()
+        .with_context(|| "Could not run `curl`, is it installed?")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
     }
     Ok(())
 }
+
+/// Checks for packages whose names differ only by `-` and `_`, which Cargo
+/// treats as the same package. Names that differ only in case are in the
+/// same file and are checked with the entries of that file.
+fn _validate_names(problems: &mut Vec<String>, crate_map: &BTreeMap<String, Vec<IndexPackage>>) {
+    // Normalized name -> lowercase name -> name, so names that differ only in
+    // case are only reported once.
+    let mut by_normalized: BTreeMap<String, BTreeMap<String, &str>> = BTreeMap::new();
+    for name in crate_map.keys() {
+        by_normalized
+            .entry(normalize_name(name))
+            .or_default()
+            .entry(name.to_lowercase())
+            .or_insert(name);
+    }
+    for names in by_normalized.values() {
+        let names: Vec<&str> = names.values().copied().collect();
+        for pair in names.windows(2) {
+            problems.push(format!(
+                "Package names `{}` and `{}` differ only by `-` and `_`, \
+                 Cargo treats them as the same package.",
+                pair[0], pair[1]
+            ));
+        }
+    }
+}
+
+/// The shortest name checked for similar names.
+const MIN_SIMILAR_LEN: usize = 4;
+
+/// Checks for package names that are one edit apart from each other, or
+/// from one of `popular`.
+fn _validate_similar_names(
+    problems: &mut Vec<String>,
+    crate_map: &BTreeMap<String, Vec<IndexPackage>>,
+    popular: &[String],
+) {
+    let names: Vec<(&str, String)> = crate_map
+        .keys()
+        .map(|name| (name.as_str(), normalize_name(name)))
+        .filter(|(_, normalized)| normalized.chars().count() >= MIN_SIMILAR_LEN)
+        .collect();
+    let similar = |a: &str, b: &str| a != b && edit_distance(a, b) == 1;
+    for (i, (name, normalized)) in names.iter().enumerate() {
+        for (other, other_normalized) in &names[i + 1..] {
+            if similar(normalized, other_normalized) {
+                problems.push(format!(
+                    "Package names `{}` and `{}` are similar, one may be a typo of the other.",
+                    name, other
+                ));
+            }
+        }
+    }
+    for popular_name in popular {
+        let popular_normalized = normalize_name(popular_name);
+        for (name, normalized) in &names {
+            if similar(normalized, &popular_normalized) {
+                problems.push(format!(
+                    "Package name `{}` is similar to the popular package `{}`.",
+                    name, popular_name
+                ));
+            }
+        }
+    }
+}
+
+/// The number of characters added, removed, replaced, or swapped with the
+/// next one to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > 1 {
+        // Not exact, but all that matters is whether it is more than one.
+        return a.len().abs_diff(b.len());
+    }
+    // d[i][j] is the distance between a[..i] and b[..j].
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Checks for different entries that share the same checksum.
+fn _validate_cksums(problems: &mut Vec<String>, crate_map: &BTreeMap<String, Vec<IndexPackage>>) {
+    let mut by_cksum: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for versions in crate_map.values() {
+        for pkg in versions {
+            by_cksum
+                .entry(&pkg.cksum)
+                .or_default()
+                .push(format!("{}:{}", pkg.name, pkg.vers));
+        }
+    }
+    for (cksum, mut pkgs) in by_cksum {
+        // Versions listed twice are reported separately.
+        pkgs.sort();
+        pkgs.dedup();
+        if pkgs.len() > 1 {
+            problems.push(format!(
+                "Checksum `{}` is shared by multiple entries: {}",
+                cksum,
+                pkgs.join(", ")
+            ));
+        }
+    }
+}
+
+/// Checks the features of an entry the way `build_feature_map` in Cargo
+/// does, so the entry doesn't fail to load.
+fn validate_features(pkg: &IndexPackage) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut features = pkg.features.clone();
+    for (name, values) in pkg.features2.iter().flatten() {
+        if features.insert(name.clone(), values.clone()).is_some() {
+            problems.push(format!(
+                "Package `{}:{}` has feature `{}` in both `features` and `features2`.",
+                pkg.name, pkg.vers, name
+            ));
+        }
+    }
+    // Optional dependencies used with `dep:` don't have an implicit feature.
+    let dep_features: HashSet<&str> = features
+        .values()
+        .flatten()
+        .filter_map(|value| value.strip_prefix("dep:"))
+        .collect();
+    let find_dep = |name: &str| pkg.deps.iter().find(|dep| dep.name == name);
+    for (feature, values) in &features {
+        let mut problem = |msg: String| {
+            problems.push(format!(
+                "Package `{}:{}` feature `{}` {}",
+                pkg.name, pkg.vers, feature, msg
+            ))
+        };
+        if let Some(ch) = feature
+            .chars()
+            .find(|ch| !ch.is_alphanumeric() && !"_-+.".contains(*ch))
+        {
+            problem(format!("has an invalid character `{}` in its name.", ch));
+        }
+        for value in values {
+            if let Some(dep_name) = value.strip_prefix("dep:") {
+                match find_dep(dep_name) {
+                    Some(dep) if dep.optional => {}
+                    Some(_) => problem(format!(
+                        "includes `{}`, but `{}` is not an optional dependency.",
+                        value, dep_name
+                    )),
+                    None => problem(format!(
+                        "includes `{}`, but `{}` is not a dependency.",
+                        value, dep_name
+                    )),
+                }
+            } else if let Some((dep_name, _)) = value.split_once('/') {
+                let (dep_name, weak) = match dep_name.strip_suffix('?') {
+                    Some(dep_name) => (dep_name, true),
+                    None => (dep_name, false),
+                };
+                match find_dep(dep_name) {
+                    Some(dep) if weak && !dep.optional => problem(format!(
+                        "includes `{}` with a `?`, but `{}` is not an optional dependency.",
+                        value, dep_name
+                    )),
+                    Some(_) => {}
+                    None => problem(format!(
+                        "includes `{}`, but `{}` is not a dependency.",
+                        value, dep_name
+                    )),
+                }
+            } else if !features.contains_key(value) {
+                match find_dep(value) {
+                    Some(dep) if dep.optional && !dep_features.contains(value.as_str()) => {}
+                    Some(dep) if dep.optional => problem(format!(
+                        "includes `{}`, but `{}` is only available as `dep:{}`.",
+                        value, value, value
+                    )),
+                    Some(_) => problem(format!(
+                        "includes `{}`, but `{}` is not an optional dependency.",
+                        value, value
+                    )),
+                    None => problem(format!(
+                        "includes `{}`, which is neither a dependency nor another feature.",
+                        value
+                    )),
+                }
+            }
+        }
+    }
+    problems
+}
@@ -0,0 +1,229 @@
+use anyhow::{bail, format_err, Context, Error};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+/// Limits on the size of a `.crate` file.
+///
+/// The defaults match the limits used by crates.io.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CrateLimits {
+    /// Maximum size of the compressed `.crate` file in bytes.
+    pub max_size: u64,
+    /// Maximum total size of the files in the archive in bytes.
+    pub max_unpacked_size: u64,
+    /// Maximum number of entries in the archive.
+    pub max_entries: usize,
+}
+
+impl Default for CrateLimits {
+    fn default() -> CrateLimits {
+        CrateLimits {
+            max_size: 10 * 1024 * 1024,
+            max_unpacked_size: 512 * 1024 * 1024,
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: ManifestPackage,
+}
+
+#[derive(Deserialize)]
+struct ManifestPackage {
+    name: String,
+    version: String,
+}
+
+/// Check the structure of a `.crate` file without an index.
+///
+/// This checks that:
+///
+/// - The file and its contents are within the given `limits`.
+/// - Every entry is inside a root directory named after the file, such as
+///   `foo-0.1.0` for `foo-0.1.0.crate`.
+/// - No entry has an absolute path or a `..` component.
+/// - No symlink or hard link points outside of the root directory.
+/// - There is a `Cargo.toml` in the root directory that can be parsed, and
+///   its package name and version match the root directory.
+///
+/// Returns an error describing the first problem found.
+pub fn verify_crate(crate_path: impl AsRef<Path>, limits: &CrateLimits) -> Result<(), Error> {
+    let crate_path = crate_path.as_ref();
+    let mut tar = open_crate(crate_path, limits)?;
+    let mut checker = EntryChecker::new(crate_path, limits)?;
+    let mut manifest = None;
+    for entry in tar.entries()? {
+        let mut entry = entry.with_context(|| "Failed to iterate over archive.")?;
+        let entry_path = checker.check(&entry)?;
+        if entry_path == Path::new(checker.prefix).join("Cargo.toml") {
+            let mut contents = String::new();
+            entry
+                .read_to_string(&mut contents)
+                .with_context(|| format!("Failed to read `{}`.", entry_path.display()))?;
+            manifest = Some(contents);
+        }
+    }
+    let prefix = checker.prefix;
+    let manifest = manifest
+        .ok_or_else(|| format_err!("Could not find `{}/Cargo.toml` in the archive.", prefix))?;
+    let manifest: Manifest = toml::from_str(&manifest)
+        .with_context(|| format!("Failed to parse `{}/Cargo.toml`.", prefix))?;
+    let expected = format!("{}-{}", manifest.package.name, manifest.package.version);
+    if expected != prefix {
+        bail!(
+            "Package `{}:{}` in `{}/Cargo.toml` does not match the file name `{}`.",
+            manifest.package.name,
+            manifest.package.version,
+            prefix,
+            crate_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Open a `.crate` file for reading, checking its compressed size.
+pub(crate) fn open_crate(
+    crate_path: &Path,
+    limits: &CrateLimits,
+) -> Result<tar::Archive<GzDecoder<fs::File>>, Error> {
+    let crate_file = fs::File::open(crate_path)
+        .with_context(|| format!("Failed to open `{}`.", crate_path.display()))?;
+    let size = crate_file.metadata()?.len();
+    if size > limits.max_size {
+        bail!(
+            "`{}` is {} bytes, which is larger than the maximum of {} bytes.",
+            crate_path.display(),
+            size,
+            limits.max_size
+        );
+    }
+    Ok(tar::Archive::new(GzDecoder::new(crate_file)))
+}
+
+/// Checks each entry of a `.crate` file against the [`CrateLimits`] and
+/// the expected layout.
+pub(crate) struct EntryChecker<'a> {
+    crate_path: &'a Path,
+    limits: &'a CrateLimits,
+    /// The root directory name, such as `foo-0.1.0`.
+    pub(crate) prefix: &'a str,
+    count: usize,
+    unpacked_size: u64,
+}
+
+impl<'a> EntryChecker<'a> {
+    pub(crate) fn new(
+        crate_path: &'a Path,
+        limits: &'a CrateLimits,
+    ) -> Result<EntryChecker<'a>, Error> {
+        let prefix = match (
+            crate_path.extension().and_then(|ext| ext.to_str()),
+            crate_path.file_stem().and_then(|stem| stem.to_str()),
+        ) {
+            (Some("crate"), Some(stem)) => stem,
+            _ => bail!(
+                "Expected a file name of the form `name-version.crate`, found `{}`.",
+                crate_path.display()
+            ),
+        };
+        Ok(EntryChecker {
+            crate_path,
+            limits,
+            prefix,
+            count: 0,
+            unpacked_size: 0,
+        })
+    }
+
+    /// Check the next entry, returning its path.
+    pub(crate) fn check<R: Read>(&mut self, entry: &tar::Entry<'_, R>) -> Result<PathBuf, Error> {
+        self.count += 1;
+        if self.count > self.limits.max_entries {
+            bail!(
+                "`{}` has more than the maximum of {} entries.",
+                self.crate_path.display(),
+                self.limits.max_entries
+            );
+        }
+        self.unpacked_size += entry.header().size()?;
+        if self.unpacked_size > self.limits.max_unpacked_size {
+            bail!(
+                "`{}` unpacks to more than the maximum of {} bytes.",
+                self.crate_path.display(),
+                self.limits.max_unpacked_size
+            );
+        }
+        let entry_path = entry
+            .path()
+            .with_context(|| "Failed to read entry path.")?
+            .into_owned();
+        if !is_normal(&entry_path) {
+            bail!(
+                "Entry `{}` must be a relative path without `..` components.",
+                entry_path.display()
+            );
+        }
+        if !entry_path.starts_with(self.prefix) {
+            bail!(
+                "Expected .crate file to contain entries rooted in `{}` directory, found `{}`.",
+                self.prefix,
+                entry_path.display()
+            );
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .with_context(|| "Failed to read link name.")?
+                .ok_or_else(|| format_err!("Link `{}` has no target.", entry_path.display()))?;
+            // Symlinks are relative to the link's directory, hard links are
+            // relative to the root of the archive.
+            let base = if entry_type.is_symlink() {
+                entry_path.parent().unwrap_or(Path::new(""))
+            } else {
+                Path::new("")
+            };
+            if !link_in_root(base, &target, self.prefix) {
+                bail!(
+                    "Link `{}` points outside of the `{}` directory to `{}`.",
+                    entry_path.display(),
+                    self.prefix,
+                    target.display()
+                );
+            }
+        }
+        Ok(entry_path)
+    }
+}
+
+fn is_normal(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Whether `target`, relative to `base`, stays inside the `prefix`
+/// directory.
+fn link_in_root(base: &Path, target: &Path, prefix: &str) -> bool {
+    let mut parts: Vec<Component<'_>> = Vec::new();
+    for c in base.components().chain(target.components()) {
+        match c {
+            Component::Normal(_) => parts.push(c),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if parts.pop().is_none() {
+                    return false;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    parts.len() > 1 && parts[0] == Component::Normal(prefix.as_ref())
+}
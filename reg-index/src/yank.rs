@@ -1,16 +1,29 @@
 use crate::{
     add::git_add,
     lock::Lock,
-    util::{pkg_path, vers_eq},
-    IndexPackage,
+    util::{open_repo, pkg_path, CommitOptions},
+    BuildMetadataPolicy, IndexError,
 };
-use anyhow::{bail, Context, Error};
-use semver::Version;
-use std::{fs, path::Path};
+use anyhow::{bail, format_err, Context, Error};
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::{borrow::Cow, fs, path::Path};
+
+/// The subset of an index entry needed to find the line to yank.
+#[derive(Deserialize)]
+struct YankEntry {
+    vers: Version,
+    yanked: bool,
+}
 
 /// Yank a version in the index.
 ///
 /// This sets the `yank` field to true. This will fail if it is already set.
+/// Build metadata in the version is ignored, and the default
+/// [`CommitOptions`] are used, see [`set_yank_with_options`] to change that.
+///
+/// [`set_yank_with_options`]: fn.set_yank_with_options.html
+/// [`CommitOptions`]: struct.CommitOptions.html
 pub fn yank(index: impl AsRef<Path>, pkg_name: &str, version: &str) -> Result<(), Error> {
     set_yank(index, pkg_name, version, true)
 }
@@ -18,68 +31,136 @@ pub fn yank(index: impl AsRef<Path>, pkg_name: &str, version: &str) -> Result<()
 /// Unyank a version in the index.
 ///
 /// This sets the `yank` field to false. This will fail if it is not yanked.
+/// Build metadata in the version is ignored, and the default
+/// [`CommitOptions`] are used, see [`set_yank_with_options`] to change that.
+///
+/// [`set_yank_with_options`]: fn.set_yank_with_options.html
+/// [`CommitOptions`]: struct.CommitOptions.html
 pub fn unyank(index: impl AsRef<Path>, pkg_name: &str, version: &str) -> Result<(), Error> {
     set_yank(index, pkg_name, version, false)
 }
 
 /// Set the `yank` value of a package in the index.
 ///
-/// This will fail if it is already set to the given value.
+/// This will fail if it is already set to the given value. See
+/// [`set_yank_with_options`] for more settings.
+///
+/// [`set_yank_with_options`]: fn.set_yank_with_options.html
 pub fn set_yank(
     index: impl AsRef<Path>,
     pkg_name: &str,
     version: &str,
     yank: bool,
 ) -> Result<(), Error> {
-    let version = Version::parse(version)?;
+    set_yank_with_options(index, pkg_name, version, yank, &YankOptions::default())
+}
+
+/// Options for [`set_yank_with_options`].
+///
+/// New options may be added in future versions, so create this with
+/// `YankOptions::default()` and set the fields that are needed.
+///
+/// [`set_yank_with_options`]: fn.set_yank_with_options.html
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct YankOptions {
+    /// Whether the build metadata of the version must match the entry.
+    pub build_metadata: BuildMetadataPolicy,
+    /// How to commit the change.
+    pub commit: CommitOptions,
+}
+
+/// Set the `yank` value of a package in the index, with settings from
+/// `options`.
+///
+/// This is the same as [`set_yank`], see [`YankOptions`] for the settings.
+///
+/// [`set_yank`]: fn.set_yank.html
+/// [`YankOptions`]: struct.YankOptions.html
+pub fn set_yank_with_options(
+    index: impl AsRef<Path>,
+    pkg_name: &str,
+    version: &str,
+    yank: bool,
+    options: &YankOptions,
+) -> Result<(), Error> {
     let index = index.as_ref();
-    let repo = git2::Repository::open(index)
-        .with_context(|| format!("Could not open index at `{}`.", index.display()))?;
+    _set_yank(
+        index,
+        open_repo(index)?.as_ref(),
+        pkg_name,
+        version,
+        yank,
+        options,
+    )
+}
+
+/// [`set_yank_with_options`] with a repository that is already open.
+///
+/// [`set_yank_with_options`]: fn.set_yank_with_options.html
+pub(crate) fn _set_yank(
+    index: &Path,
+    repo: Option<&git2::Repository>,
+    pkg_name: &str,
+    version: &str,
+    yank: bool,
+    options: &YankOptions,
+) -> Result<(), Error> {
+    let version = Version::parse(version)?;
     let lock = Lock::new_exclusive(index)?;
-    let repo_path = pkg_path(pkg_name);
+    let repo_path = pkg_path(pkg_name)?;
     let path = index.join(&repo_path);
     if !path.exists() {
-        bail!("Package `{}` is not in the index.", pkg_name);
+        return Err(IndexError::PackageNotFound {
+            name: pkg_name.to_string(),
+        }
+        .into());
     }
     let contents = fs::read_to_string(&path)
         .with_context(|| format!("Failed to read `{}`.", path.display()))?;
-    let (lines, matches): (Vec<String>, Vec<u32>) = contents
-        .lines()
-        .map(|line| {
-            let mut pkg: IndexPackage = serde_json::from_str(line).with_context(|| {
-                format!(
-                    "Failed to deserialize line in `{}`:\n{}",
-                    path.display(),
-                    line
-                )
-            })?;
-            if vers_eq(&pkg.vers, &version) {
-                if pkg.yanked == yank {
-                    if yank {
-                        bail!("`{}:{}` is already yanked!", pkg_name, version);
-                    } else {
-                        bail!("`{}:{}` is not yanked!", pkg_name, version);
-                    }
-                }
-                pkg.yanked = yank;
-                let mut new_line = serde_json::to_string(&pkg)?;
-                new_line.push('\n');
-                Ok((new_line, 1))
+    // Only the matching line is rewritten, everything else is kept
+    // byte-for-byte.
+    let mut lines: Vec<Cow<'_, str>> = Vec::new();
+    let mut matches = 0;
+    for line in contents.split_inclusive('\n') {
+        let entry: YankEntry = serde_json::from_str(line).with_context(|| {
+            format!(
+                "Failed to deserialize line in `{}`:\n{}",
+                path.display(),
+                line
+            )
+        })?;
+        if !options.build_metadata.eq(&entry.vers, &version) {
+            lines.push(Cow::Borrowed(line));
+            continue;
+        }
+        matches += 1;
+        if entry.yanked == yank {
+            let (name, version) = (pkg_name.to_string(), version.to_string());
+            return Err(if yank {
+                IndexError::AlreadyYanked { name, version }
             } else {
-                let mut new_line = line.to_string();
-                new_line.push('\n');
-                Ok((new_line, 0))
+                IndexError::NotYanked { name, version }
             }
-        })
-        .collect::<Result<Vec<(String, u32)>, Error>>()?
-        .into_iter()
-        .unzip();
-    match matches.iter().sum() {
-        0 => bail!(
-            "Version `{}` for package `{}` not found.",
-            version,
-            pkg_name
-        ),
+            .into());
+        }
+        let new_line = set_yanked_field(line, yank).ok_or_else(|| {
+            format_err!(
+                "Could not find `yanked` field in `{}` line:\n{}",
+                path.display(),
+                line
+            )
+        })?;
+        lines.push(Cow::Owned(new_line));
+    }
+    match matches {
+        0 => {
+            return Err(IndexError::VersionNotFound {
+                name: pkg_name.to_string(),
+                version: version.to_string(),
+            }
+            .into())
+        }
         1 => {}
         _ => bail!(
             "Version `{}` for package `{}` found multiple times, is the index corrupt?",
@@ -91,10 +172,157 @@ pub fn set_yank(
         .with_context(|| format!("Failed to write `{}`.", path.display()))?;
     let what = if yank { "Yanking" } else { "Unyanking" };
     git_add(
-        &repo,
-        &repo_path,
+        repo,
+        &[&repo_path],
         &format!("{} crate `{}:{}`", what, pkg_name, version),
+        &options.commit,
     )?;
     drop(lock);
     Ok(())
 }
+
+/// Set the `yank` value of every version of a package that matches a
+/// version requirement, such as `<0.3.5`.
+///
+/// All of the versions are changed under one lock in a single commit.
+/// Versions that are already set to the given value are skipped. As with
+/// Cargo, prerelease versions only match if the requirement has a
+/// prerelease. Returns the versions that were changed, in the order of the
+/// index. If no version matches, returns [`IndexError::VersionNotFound`],
+/// the same as [`set_yank`].
+///
+/// [`IndexError::VersionNotFound`]: enum.IndexError.html#variant.VersionNotFound
+/// [`set_yank`]: fn.set_yank.html
+pub fn set_yank_matching(
+    index: impl AsRef<Path>,
+    pkg_name: &str,
+    version_req: &str,
+    yank: bool,
+    commit: &CommitOptions,
+) -> Result<Vec<Version>, Error> {
+    let req = VersionReq::parse(version_req)?;
+    let index = index.as_ref();
+    let repo = open_repo(index)?;
+    let lock = Lock::new_exclusive(index)?;
+    let repo_path = pkg_path(pkg_name)?;
+    let path = index.join(&repo_path);
+    if !path.exists() {
+        return Err(IndexError::PackageNotFound {
+            name: pkg_name.to_string(),
+        }
+        .into());
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+    let mut lines: Vec<Cow<'_, str>> = Vec::new();
+    let mut matches = 0;
+    let mut changed = Vec::new();
+    for line in contents.split_inclusive('\n') {
+        let entry: YankEntry = serde_json::from_str(line).with_context(|| {
+            format!(
+                "Failed to deserialize line in `{}`:\n{}",
+                path.display(),
+                line
+            )
+        })?;
+        if !req.matches(&entry.vers) {
+            lines.push(Cow::Borrowed(line));
+            continue;
+        }
+        matches += 1;
+        if entry.yanked == yank {
+            lines.push(Cow::Borrowed(line));
+            continue;
+        }
+        let new_line = set_yanked_field(line, yank).ok_or_else(|| {
+            format_err!(
+                "Could not find `yanked` field in `{}` line:\n{}",
+                path.display(),
+                line
+            )
+        })?;
+        lines.push(Cow::Owned(new_line));
+        changed.push(entry.vers);
+    }
+    if matches == 0 {
+        return Err(IndexError::VersionNotFound {
+            name: pkg_name.to_string(),
+            version: version_req.to_string(),
+        }
+        .into());
+    }
+    if changed.is_empty() {
+        bail!(
+            "All versions of `{}` matching `{}` are already {}.",
+            pkg_name,
+            version_req,
+            if yank { "yanked" } else { "unyanked" }
+        );
+    }
+    fs::write(&path, lines.join(""))
+        .with_context(|| format!("Failed to write `{}`.", path.display()))?;
+    let what = if yank { "Yanking" } else { "Unyanking" };
+    let versions: Vec<String> = changed.iter().map(|vers| vers.to_string()).collect();
+    git_add(
+        repo.as_ref(),
+        &[&repo_path],
+        &format!(
+            "{} crate `{}` versions matching `{}`\n\n{}",
+            what,
+            pkg_name,
+            version_req,
+            versions.join("\n")
+        ),
+        commit,
+    )?;
+    drop(lock);
+    Ok(changed)
+}
+
+/// Replace the value of the top-level `yanked` field in a JSON line.
+///
+/// Keys of nested objects, such as from fields that other tools added, are
+/// skipped. Returns `None` if the field could not be found.
+fn set_yanked_field(line: &str, yank: bool) -> Option<String> {
+    let bytes = line.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            b'"' => {
+                let start = i + 1;
+                i = start;
+                while *bytes.get(i)? != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                // A string followed by `:` is a key.
+                let after_colon = line[i + 1..]
+                    .trim_start()
+                    .strip_prefix(':')
+                    .map(str::trim_start);
+                if let (1, "yanked", Some(value)) = (depth, &line[start..i], after_colon) {
+                    let value_start = line.len() - value.len();
+                    for old in ["true", "false"] {
+                        if value.starts_with(old) {
+                            return Some(format!(
+                                "{}{}{}",
+                                &line[..value_start],
+                                yank,
+                                &line[value_start + old.len()..]
+                            ));
+                        }
+                    }
+                    return None;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
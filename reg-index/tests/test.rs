@@ -1,6 +1,3 @@
-use reg_index;
-use serde_json;
-
 #[test]
 fn test_features2() {
     let input = include_str!("input_features2");
@@ -13,3 +10,54 @@ fn test_features2() {
         assert_eq!(pkg_json, serde_json::to_string(&pkg).unwrap());
     }
 }
+
+#[test]
+fn test_canonical_url() {
+    let canonical = "https://example.com/my-index";
+    for url in [
+        "https://example.com/my-index",
+        "https://example.com/my-index/",
+        "https://EXAMPLE.com/my-index.git",
+        "http://example.com/my%2Dindex",
+        "sparse+https://example.com/my-index/",
+        "registry+https://example.com/my-index.git",
+    ] {
+        assert_eq!(reg_index::canonical_url(url), canonical, "{}", url);
+    }
+    assert_ne!(
+        reg_index::canonical_url("https://example.com:8080/my-index"),
+        canonical
+    );
+    assert_eq!(
+        reg_index::canonical_url("file:///path/to/index/"),
+        "file:///path/to/index"
+    );
+}
+
+#[test]
+fn test_format_time() {
+    assert_eq!(reg_index::format_time(0), "1970-01-01T00:00:00Z");
+    assert_eq!(reg_index::format_time(951_827_696), "2000-02-29T12:34:56Z");
+    assert_eq!(reg_index::format_time(-1), "1969-12-31T23:59:59Z");
+}
+
+#[test]
+fn test_is_remote_index() {
+    for index in [
+        "https://github.com/org/my-index.git",
+        "ssh://git@github.com/org/my-index.git",
+        "git@github.com:org/my-index.git",
+        "file:///path/to/index",
+    ] {
+        assert!(reg_index::is_remote_index(index), "{}", index);
+    }
+    for index in [
+        "index",
+        "../my-index",
+        "/path/to/index",
+        "C:\\index",
+        "a/b@c:d",
+    ] {
+        assert!(!reg_index::is_remote_index(index), "{}", index);
+    }
+}
@@ -1,7 +1,19 @@
-use anyhow::{bail, Error};
-use clap::{crate_version, Arg, ArgAction, ArgMatches, Command};
+use anyhow::{bail, format_err, Context, Error};
+use clap::builder::PossibleValuesParser;
+use clap::parser::ValueSource;
+use clap::{crate_version, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use reg_index::MetadataOptions;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::net::TcpListener;
 use std::path::Path;
-use std::process::exit;
+use std::process::{exit, Command as ProcessCommand, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+mod setup;
 
 fn main() {
     if let Err(e) = run() {
@@ -22,6 +34,54 @@ If the `--crate` flag is passed, it will add the given crate. If
 `Cargo.toml` file. If neither flag is given, it will look in the current
 directory for a `Cargo.toml` manifest.
 
+If `--force` would change an existing entry, the old and new entries are
+displayed and you are asked to continue, unless `--yes` is passed.
+
+If `--changed-since` is passed, every package in the workspace with files
+that changed since the given git revision is added, one commit each. A
+package is added after the packages it depends on with a `path` dependency.
+Packages with `publish = false` are skipped. `--workspace` does the same for
+every package in the workspace.
+
+If `--crates-dir` is passed, every `.crate` file in the directory is added,
+each after the crates it depends on from the same registry. Use `--one-commit`
+to add them in a single commit.
+
+If `--semver-checks` is passed, `cargo-semver-checks` must be installed. The
+`.crate` file of the previous version is read from the `--upload` directory,
+or from the `dl` location of the index if it is a `file:` URL.
+
+If `--upload` starts with `lfs:`, such as `lfs:crates/{crate}`, the `.crate`
+file is stored in the index repository with Git LFS instead, at the given path
+within the index. The first directory must not have markers, and is added to
+`.gitattributes` and `.indexignore`. Clones of the index need `git-lfs`
+installed, and the LFS objects are pushed with `git lfs push`.
+
+If `--upload` is an `http://` or `https://` URL, such as
+`https://files.example.com/crates/{crate}`, the `.crate` file is sent there
+with an HTTP PUT using `curl`. Credentials are read from `~/.netrc`, or the
+`CARGO_INDEX_UPLOAD_AUTHORIZATION` environment variable is sent as the
+`Authorization` header. An `s3://bucket/path` URL uploads with `aws s3 cp`,
+using the usual AWS configuration; set `AWS_ENDPOINT_URL` for S3-compatible
+storage. The file name `{crate}-{version}.crate` is added to the URL.
+
+A package can set options for itself in a `[package.metadata.index]` table
+in its `Cargo.toml`:
+
+    [package.metadata.index]
+    upload-subpath = \"internal\"   # placed in this directory under `--upload`
+    skip-dep-check = true          # dependencies need not be in the index yet
+    require-newer = true           # same as `--require-newer`
+    semver-checks = true           # same as `--semver-checks`
+    reviewers = [\"Alice <alice@example.com>\"]  # needs `--reviewed-by` for each
+
+Each setting that is used is recorded in the commit with an `Index-Setting`
+trailer.
+
+If `--metadata-json` is passed with `--crate`, the entry from the JSON file
+(such as from `cargo index metadata`) is added without running Cargo, after
+checking that the checksum matches the `.crate` file.
+
 All arguments at the end of the command line following `--` will be passed
 as-is to `cargo package` when generating the `.crate` file.
 ";
@@ -38,6 +98,24 @@ All arguments at the end of the command line following `--` will be passed
 as-is to `cargo package` when generating the `.crate` file.
 ";
 
+#[cfg(not(feature = "parquet"))]
+const EXPORT_FORMATS: &[&str] = &["jsonl", "csv"];
+#[cfg(feature = "parquet")]
+const EXPORT_FORMATS: &[&str] = &["jsonl", "csv", "parquet", "parquet-deps"];
+
+/// The arguments added by `arg_commit_options`.
+const COMMIT_ARGS: [&str; 9] = [
+    "git-name",
+    "git-email",
+    "commit-timestamp",
+    "git-committer-name",
+    "git-committer-email",
+    "commit-message",
+    "sign-commit",
+    "branch",
+    "push",
+];
+
 trait AppExt: Sized {
     fn _arg(self, arg: Arg) -> Self;
 
@@ -70,6 +148,19 @@ trait AppExt: Sized {
         )
     }
 
+    fn arg_remote_index(self) -> Self {
+        self._arg(
+            Arg::new("index")
+                .long("index")
+                .value_name("INDEX")
+                .required(true)
+                .help(
+                    "Path to index, or the git URL of an index to clone, change, \
+                     and push back.",
+                ),
+        )
+    }
+
     fn arg_index_url(self) -> Self {
         self._arg(
             Arg::new("index-url")
@@ -114,6 +205,184 @@ trait AppExt: Sized {
         )
     }
 
+    fn arg_webhook(self) -> Self {
+        self._arg(
+            Arg::new("webhook")
+                .long("webhook")
+                .value_name("URL")
+                .action(ArgAction::Append)
+                .help(
+                    "URL to POST a JSON description of each change to. Defaults to \
+                     the CARGO_INDEX_WEBHOOK environment variable.",
+                ),
+        )
+    }
+
+    fn arg_hook(self) -> Self {
+        self._arg(
+            Arg::new("hook")
+                .long("hook")
+                .value_name("COMMAND")
+                .action(ArgAction::Append)
+                .help(
+                    "Shell command to run after each change, with {crate}, {version}, \
+                     and {action} replaced. Defaults to the CARGO_INDEX_HOOK \
+                     environment variable.",
+                ),
+        )
+    }
+
+    fn arg_yes(self) -> Self {
+        self._arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation before making destructive changes."),
+        )
+    }
+
+    fn arg_color(self) -> Self {
+        self._arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .help("Coloring of the output: auto, always, or never."),
+        )
+    }
+
+    fn arg_metadata_options(self) -> Self {
+        self._arg(
+            Arg::new("explicit-registry")
+                .long("explicit-registry")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Write the registry URL for dependencies from the same registry \
+                     instead of leaving it empty.",
+                ),
+        )
+        ._arg(
+            Arg::new("registry-rewrites")
+                .long("registry-rewrites")
+                .value_name("FILE")
+                .help(
+                    "File with lines of the form `FROM -> TO` to replace the registry of \
+                     dependencies. FROM is an index URL or `crates-io`.",
+                ),
+        )
+        ._arg(
+            Arg::new("no-dev-deps")
+                .long("no-dev-deps")
+                .action(ArgAction::SetTrue)
+                .help("Leave dev-dependencies out of the entry."),
+        )
+        ._arg(
+            Arg::new("reproducible")
+                .long("reproducible")
+                .action(ArgAction::SetTrue)
+                .help("Package the crate twice and fail if the .crate files are different."),
+        )
+        ._arg(
+            Arg::new("expect-cksum")
+                .long("expect-cksum")
+                .value_name("SHA256")
+                .help("Fail if the checksum of the .crate file is not this value."),
+        )
+    }
+
+    /// `--version-req` for `yank` and `unyank`, which is used instead of
+    /// `--version` to change every matching version.
+    fn arg_version_req(self, help: &'static str) -> Self {
+        self._arg(
+            Arg::new("version-req")
+                .long("version-req")
+                .value_name("REQ")
+                .conflicts_with_all(["version", "build-metadata"])
+                .help(help),
+        )
+    }
+
+    fn arg_build_metadata(self) -> Self {
+        self._arg(
+            Arg::new("build-metadata")
+                .long("build-metadata")
+                .value_name("POLICY")
+                .value_parser(PossibleValuesParser::new(["ignore", "require"]))
+                .help(
+                    "Whether versions that only differ in build metadata (the `+abc` in \
+                     `1.2.3+abc`) are the same version (default ignore).",
+                ),
+        )
+    }
+
+    fn arg_commit_options(self) -> Self {
+        self._arg(
+            Arg::new("git-name")
+                .long("git-name")
+                .value_name("NAME")
+                .help("Name of the author of the git commit, instead of the git config."),
+        )
+        ._arg(
+            Arg::new("git-email")
+                .long("git-email")
+                .value_name("EMAIL")
+                .help("Email of the author of the git commit, instead of the git config."),
+        )
+        ._arg(
+            Arg::new("commit-timestamp")
+                .long("commit-timestamp")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(i64))
+                .help(
+                    "Time of the git commit in seconds since the Unix epoch, \
+                     instead of SOURCE_DATE_EPOCH or the current time.",
+                ),
+        )
+        ._arg(
+            Arg::new("git-committer-name")
+                .long("git-committer-name")
+                .value_name("NAME")
+                .help("Name of the committer of the git commit, if different from the author."),
+        )
+        ._arg(
+            Arg::new("git-committer-email")
+                .long("git-committer-email")
+                .value_name("EMAIL")
+                .help("Email of the committer of the git commit, if different from the author."),
+        )
+        ._arg(
+            Arg::new("commit-message")
+                .long("commit-message")
+                .value_name("MESSAGE")
+                .help("Message of the git commit, instead of one describing the change."),
+        )
+        ._arg(
+            Arg::new("sign-commit")
+                .long("sign-commit")
+                .action(ArgAction::SetTrue)
+                .help("Sign the git commit with GPG."),
+        )
+        ._arg(
+            Arg::new("branch")
+                .long("branch")
+                .value_name("BRANCH")
+                .help("Commit to BRANCH instead of the current branch, creating it if needed."),
+        )
+        ._arg(
+            Arg::new("push")
+                .long("push")
+                .value_name("REMOTE")
+                .num_args(0..=1)
+                .default_missing_value("")
+                .help(
+                    "Push the branch to the git remote REMOTE after committing. \
+                     Without REMOTE, uses the remote the branch tracks, or `origin`.",
+                ),
+        )
+    }
+
     fn arg_package_args(self) -> Self {
         self._arg(Arg::new("package-args").action(ArgAction::Append))
     }
@@ -144,15 +413,103 @@ fn run() -> Result<(), Error> {
                         .trailing_var_arg(true)
                         .arg_manifest()
                         .arg_crate()
-                        .arg_index()
+                        .arg_remote_index()
+                        .arg_hook()
+                        .arg_webhook()
                         .arg_index_url()
+                        .mut_arg("index-url", |arg| {
+                            arg.required(false).required_unless_present("metadata-json")
+                        })
                         .arg_force()
+                        .arg_yes()
+                        .arg_metadata_options()
+                        .arg_build_metadata()
+                        .arg_commit_options()
+                        .arg(
+                            Arg::new("metadata-json")
+                            .long("metadata-json")
+                            .value_name("FILE")
+                            .requires("crate")
+                            .conflicts_with_all(["force", "check"])
+                            .help("Add the entry in FILE, as generated by `cargo index metadata`, \
+                                after checking it against the --crate file instead of running Cargo."))
+                        .arg(
+                            Arg::new("changed-since")
+                            .long("changed-since")
+                            .value_name("REV")
+                            .conflicts_with_all(["crate", "metadata-json", "check"])
+                            .help("Add every workspace package with files that changed since \
+                                the git revision REV, in dependency order."))
+                        .arg(
+                            Arg::new("workspace")
+                            .long("workspace")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with_all(["crate", "metadata-json", "changed-since", "check"])
+                            .help("Add every publishable workspace package, in dependency order."))
+                        .arg(
+                            Arg::new("crates-dir")
+                            .long("crates-dir")
+                            .value_name("DIR")
+                            .conflicts_with_all(["crate", "manifest-path", "metadata-json", "changed-since", "workspace", "check", "force"])
+                            .help("Add every .crate file in DIR, in dependency order."))
+                        .arg(
+                            Arg::new("one-commit")
+                            .long("one-commit")
+                            .action(ArgAction::SetTrue)
+                            .requires("crates-dir")
+                            .help("With --crates-dir, add all of the crates in a single commit, \
+                                or none of them if one fails."))
+                        .arg(
+                            Arg::new("require-newer")
+                            .long("require-newer")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with_all(["force", "metadata-json"])
+                            .help("Fail unless the version is newer than every version of the \
+                                package in the index."))
+                        .arg(
+                            Arg::new("require-clean")
+                            .long("require-clean")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("metadata-json")
+                            .help("Fail if the index has changes to entries or config.json \
+                                that are not committed."))
+                        .arg(
+                            Arg::new("allow-name-conflict")
+                            .long("allow-name-conflict")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("metadata-json")
+                            .help("Allow a package whose name differs from another package in \
+                                the index only by `-` and `_` or by case."))
+                        .arg(
+                            Arg::new("semver-checks")
+                            .long("semver-checks")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with_all(["force", "metadata-json"])
+                            .help("Run `cargo semver-checks` against the previous version in \
+                                the index and fail if the version bump is too small."))
+                        .arg(
+                            Arg::new("reviewed-by")
+                            .long("reviewed-by")
+                            .value_name("NAME")
+                            .action(ArgAction::Append)
+                            .help("Record a reviewer in the commit, as required by packages \
+                                that list `reviewers` in `[package.metadata.index]`."))
+                        .arg(
+                            Arg::new("check")
+                            .long("check")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with_all(["force", "upload"])
+                            .help("Don't modify the index, instead fail if adding the \
+                                package would change the index."))
                         .arg(
                             Arg::new("upload")
                             .long("upload")
                             .value_name("DIR")
                             .help("If set, will copy the crate into the given directory. \
-                                Use {crate} and {version} to be included in the directory path.")
+                                Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                to be included in the directory path. \
+                                Start with `lfs:` to store the crate in the index with Git LFS. \
+                                An http(s):// or s3:// URL uploads to a web server or S3 bucket.")
                             )
                         .arg_package_args()
                 )
@@ -160,6 +517,7 @@ fn run() -> Result<(), Error> {
                     Command::new("init")
                         .about("Create a new index.")
                         .arg_index()
+                        .arg_commit_options()
                         .arg(
                             Arg::new("dl")
                             .long("dl")
@@ -174,6 +532,46 @@ fn run() -> Result<(), Error> {
                             .long("api")
                             .value_name("API")
                             .help("URL of API host such as https://example.com"))
+                        .arg(
+                            Arg::new("force")
+                            .long("force")
+                            .action(ArgAction::SetTrue)
+                            .help("Create the index even if the URLs look broken."))
+                        .arg(
+                            Arg::new("auth-required")
+                            .long("auth-required")
+                            .action(ArgAction::SetTrue)
+                            .help("Require Cargo to send a token for every request, \
+                                including fetching the index and downloads."))
+                        .arg(
+                            Arg::new("protocol")
+                            .long("protocol")
+                            .value_name("PROTOCOL")
+                            .value_parser(["git", "sparse"])
+                            .default_value("git")
+                            .help("How Cargo reads the index. A `sparse` index is plain \
+                                files without git, for static file hosting, and is \
+                                remembered by the other commands."))
+                        .arg(
+                            Arg::new("with-readme")
+                            .long("with-readme")
+                            .value_name("NAME")
+                            .help("Add a README.md describing how to use the index as \
+                                the registry NAME."))
+                        .arg_index_url()
+                        .mut_arg("index-url", |arg| {
+                            arg.required(false)
+                                .requires("with-readme")
+                                .help("Public URL of the index to show in the README.md.")
+                        })
+                )
+                .subcommand(
+                    Command::new("log")
+                        .about("Show when each version of a package was added and yanked.")
+                        .arg_index()
+                        .arg_package("Name of the package.", true)
+                        .arg_version("Only show versions matching this requirement.", false)
+                        .disable_version_flag(true)
                 )
                 .subcommand(
                     Command::new("metadata")
@@ -183,22 +581,52 @@ fn run() -> Result<(), Error> {
                         .arg_manifest()
                         .arg_crate()
                         .arg_index_url()
+                        .arg_metadata_options()
                         .arg_package_args()
                 )
                 .subcommand(
                     Command::new("yank")
                         .about("Yank a crate from an index.")
-                        .arg_index()
+                        .arg_remote_index()
+                        .arg_hook()
+                        .arg_webhook()
                         .arg_package("Name of the package to yank.", true)
-                        .arg_version("Version to yank.", true)
+                        .arg_version("Version to yank.", false)
+                        .arg_version_req("Yank every version that matches a requirement, \
+                            such as `<0.3.5`, in one commit.")
+                        .group(
+                            ArgGroup::new("versions")
+                                .args(["version", "version-req"])
+                                .required(true)
+                        )
+                        .arg_build_metadata()
+                        .arg_commit_options()
+                        .arg(
+                            Arg::new("reason")
+                                .long("reason")
+                                .value_name("TEXT")
+                                .help("Why the version is yanked, recorded in the commit \
+                                    and included by `advisories`.")
+                        )
                         .disable_version_flag(true)
                 )
                 .subcommand(
                     Command::new("unyank")
                         .about("Un-yank a crate from an index.")
-                        .arg_index()
+                        .arg_remote_index()
+                        .arg_hook()
+                        .arg_webhook()
                         .arg_package("Name of the package to unyank.", true)
-                        .arg_version("Version to unyank.", true)
+                        .arg_version("Version to unyank.", false)
+                        .arg_version_req("Unyank every version that matches a requirement, \
+                            such as `<0.3.5`, in one commit.")
+                        .group(
+                            ArgGroup::new("versions")
+                                .args(["version", "version-req"])
+                                .required(true)
+                        )
+                        .arg_build_metadata()
+                        .arg_commit_options()
                         .disable_version_flag(true)
                 )
                 .subcommand(
@@ -207,92 +635,1043 @@ fn run() -> Result<(), Error> {
                         .arg_index()
                         .arg_package("Name of the package to search for.", false)
                         .arg_version("Version requirement to search for.", false)
+                        .arg(
+                            Arg::new("with-dates")
+                            .long("with-dates")
+                            .action(ArgAction::SetTrue)
+                            .help("Include when each entry was added and last yanked or \
+                                unyanked, from the git history."))
+                        .arg(
+                            Arg::new("include-prerelease")
+                            .long("include-prerelease")
+                            .action(ArgAction::SetTrue)
+                            .help("Include prerelease versions that would otherwise match \
+                                the version requirement."))
+                        .arg(
+                            Arg::new("exclude-prerelease")
+                            .long("exclude-prerelease")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("include-prerelease")
+                            .help("Never include prerelease versions."))
+                        .arg(
+                            Arg::new("yanked")
+                            .long("yanked")
+                            .action(ArgAction::SetTrue)
+                            .help("Only include yanked versions."))
+                        .arg(
+                            Arg::new("no-yanked")
+                            .long("no-yanked")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("yanked")
+                            .help("Don't include yanked versions."))
+                        .arg(
+                            Arg::new("latest")
+                            .long("latest")
+                            .action(ArgAction::SetTrue)
+                            .help("Only include the newest matching version of each package."))
+                        .arg(
+                            Arg::new("count")
+                            .long("count")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with_all(["with-dates", "names-only"])
+                            .help("Only display the number of matching entries."))
+                        .arg(
+                            Arg::new("names-only")
+                            .long("names-only")
+                            .alias("names")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("with-dates")
+                            .help("Only display the names of packages with matching entries."))
+                        .arg(
+                            Arg::new("format")
+                            .long("format")
+                            .value_name("FORMAT")
+                            .value_parser(["auto", "json", "json-array", "table", "csv", "toml"])
+                            .default_value("auto")
+                            .help("Display entries as JSON lines, a single JSON array, a table, \
+                                CSV with a header row, or TOML. `auto` uses a table if the \
+                                output is a terminal, and JSON lines otherwise."))
+                        .arg_color()
+                        .arg_build_metadata()
                         .disable_version_flag(true)
                 )
                 .subcommand(
-                    Command::new("validate")
-                        .about("Validate the format of an index.")
+                    Command::new("advisories")
+                        .about("Print an advisory feed of the yanked versions in the index.")
                         .arg_index()
+                        .arg_package("Only include yanked versions of this package.", false)
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .value_parser(PossibleValuesParser::new(["osv", "json"]))
+                                .default_value("osv")
+                                .help("Output format, OSV advisories or a simple JSON list.")
+                        )
+                        .arg(
+                            Arg::new("id-prefix")
+                                .long("id-prefix")
+                                .value_name("PREFIX")
+                                .default_value("YANK-")
+                                .help("Prefix of the OSV advisory ids, followed by the name and version.")
+                        )
+                        .arg(
+                            Arg::new("out-dir")
+                                .long("out-dir")
+                                .value_name("DIR")
+                                .help("Write each OSV advisory to `<id>.json` in DIR, \
+                                    instead of printing them as an array.")
+                        )
+                )
+                .subcommand(
+                    Command::new("check")
+                        .about("Check that the index has an up-to-date entry for a package.")
+                        .trailing_var_arg(true)
+                        .arg_manifest()
+                        .arg_crate()
+                        .arg_remote_index()
+                        .arg_index_url()
+                        .arg_metadata_options()
+                        .arg_build_metadata()
+                        .arg_package_args()
+                )
+                .subcommand(
+                    Command::new("config")
+                        .about("View or change the config.json file of the index.")
+                        .subcommand_required(true)
+                        .arg_required_else_help(true)
+                        .subcommand(
+                            Command::new("get")
+                                .about("Print config.json, or a single field of it.")
+                                .arg_index()
+                                .arg(Arg::new("key").value_name("KEY"))
+                        )
+                        .subcommand(
+                            Command::new("set")
+                                .about("Set a field of config.json and commit it.")
+                                .arg_index()
+                                .arg_commit_options()
+                                .arg(
+                                    Arg::new("key")
+                                    .value_name("KEY")
+                                    .required(true)
+                                    .value_parser(CONFIG_KEYS))
+                                .arg(Arg::new("value").value_name("VALUE").required(true))
+                                .arg(
+                                    Arg::new("force")
+                                    .long("force")
+                                    .action(ArgAction::SetTrue)
+                                    .help("Change the URL even if it looks broken."))
+                        )
+                        .subcommand(
+                            Command::new("unset")
+                                .about("Remove an optional field of config.json and commit it.")
+                                .arg_index()
+                                .arg_commit_options()
+                                .arg(
+                                    Arg::new("key")
+                                    .value_name("KEY")
+                                    .required(true)
+                                    .value_parser(["api", "auth-required"]))
+                        )
+                )
+                .subcommand(
+                    Command::new("config-snippet")
+                        .about("Print a `.cargo/config.toml` snippet for using the index.")
+                        .arg_index()
+                        .arg_index_url()
+                        .arg(
+                            Arg::new("name")
+                                .long("name")
+                                .value_name("NAME")
+                                .required(true)
+                                .help("Name of the registry in the Cargo config.")
+                        )
+                        .arg(
+                            Arg::new("replace-crates-io")
+                                .long("replace-crates-io")
+                                .action(ArgAction::SetTrue)
+                                .help("Include source replacement to use the index instead of crates.io.")
+                        )
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("Compare the entries of two indexes or two revisions of an index.")
+                        .arg_index()
+                        .arg(
+                            Arg::new("old-index")
+                                .long("old-index")
+                                .value_name("PATH")
+                                .help("Path of another index to compare against.")
+                        )
+                        .arg(
+                            Arg::new("old-rev")
+                                .long("old-rev")
+                                .value_name("REV")
+                                .help("Git revision of the index to compare against.")
+                        )
+                        .arg(
+                            Arg::new("new-rev")
+                                .long("new-rev")
+                                .value_name("REV")
+                                .requires("old-rev")
+                                .default_value("HEAD")
+                                .help("Git revision of the index to compare.")
+                        )
+                        .group(
+                            ArgGroup::new("old")
+                                .args(["old-index", "old-rev"])
+                                .required(true)
+                        )
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Export entries in the index to a single file.")
+                        .arg_index()
+                        .arg_package("Name of the package to export.", false)
+                        .arg_version("Version requirement to export.", false)
+                        .disable_version_flag(true)
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .value_parser(PossibleValuesParser::new(EXPORT_FORMATS))
+                                .default_value("jsonl")
+                                .help("Output format.")
+                        )
+                        .arg(
+                            Arg::new("out")
+                                .long("out")
+                                .value_name("PATH")
+                                .help("File to write to. Defaults to stdout.")
+                        )
+                )
+                .subcommand(
+                    Command::new("fetch-deps")
+                        .about("Download the .crate files of dependencies from other registries.")
+                        .arg_index()
+                        .arg_package("Only fetch the dependencies of this package.", false)
+                        .arg(
+                            Arg::new("dl-dir")
+                                .long("dl-dir")
+                                .value_name("DIR")
+                                .required(true)
+                                .help("Directory to place the .crate files in. \
+                                    Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                    to be included in the directory path.")
+                        )
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Import entries from a JSON lines file.")
+                        .arg_index()
+                        .arg_commit_options()
+                        .arg(
+                            Arg::new("file")
+                                .value_name("FILE")
+                                .required(true)
+                                .help("Path to the file to import, such as one created by `export`.")
+                        )
+                )
+                .subcommand(
+                    Command::new("migrate-from")
+                        .about("Copy every package from another registry into the index.")
+                        .arg_index()
+                        .arg_commit_options()
+                        .arg(
+                            Arg::new("from")
+                                .value_name("INDEX_URL")
+                                .required(true)
+                                .help("Index URL of the registry to copy from. \
+                                    Use the `sparse+` prefix for a sparse index.")
+                        )
+                        .arg(
+                            Arg::new("api")
+                                .long("api")
+                                .value_name("URL")
+                                .help("Web API of the registry, used to list the packages of a sparse index. \
+                                    Defaults to the `api` in its config.json.")
+                        )
+                        .arg(
+                            Arg::new("upload")
+                                .long("upload")
+                                .value_name("DIR")
+                                .required(true)
+                                .help("Directory to place the .crate files in. \
+                                    Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                    to be included in the directory path. \
+                                    Start with `lfs:` to store them in the index with Git LFS. \
+                                An http(s):// or s3:// URL uploads to a web server or S3 bucket.")
+                        )
+                )
+                .subcommand(
+                    Command::new("names")
+                        .about("List the names of all packages in the index.")
+                        .arg_index()
+                )
+                .subcommand(
+                    Command::new("prune-yanked")
+                        .about("Remove versions that were yanked a long time ago.")
+                        .arg_index()
+                        .arg_commit_options()
+                        .arg_yes()
+                        .arg(
+                            Arg::new("older-than")
+                                .long("older-than")
+                                .value_name("AGE")
+                                .required(true)
+                                .value_parser(parse_age)
+                                .help("Remove versions yanked longer ago than AGE, \
+                                    such as `90d`. Units are s, m, h, d, and w.")
+                        )
                         .arg(
                             Arg::new("crates")
                                 .long("crates")
                                 .value_name("DIR")
-                                .help("Optional path to the location of all .crate files. \
-                                    If set, will validate the files exist and that the checksums are correct. \
-                                    Use {crate} and {version} to be included in the directory path.")
+                                .help("Also delete the .crate files of removed versions from DIR. \
+                                    Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                    to be included in the directory path.")
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Display the versions that would be removed without changing anything.")
                         )
                 )
-        )
-        .get_matches();
-    let submatches = matches
-        .subcommand_matches("index")
-        .expect("Expected `index` subcommand.");
-
-    match submatches.subcommand() {
-        Some(("init", args)) => init(args),
-        Some(("add", args)) => add(args),
-        Some(("metadata", args)) => metadata(args),
-        Some(("yank", args)) => yank(args),
-        Some(("unyank", args)) => unyank(args),
-        Some(("list", args)) => list(args),
-        Some(("validate", args)) => validate(args),
-        _ => {
-            // Enforced by SubcommandRequiredElseHelp.
-            unreachable!()
-        }
-    }?;
-
-    Ok(())
-}
-
-fn package_args(args: &ArgMatches) -> Option<Vec<String>> {
-    args.get_many::<String>("package-args")
-        .map(|values| values.cloned().collect())
-}
-
-fn init(args: &ArgMatches) -> Result<(), Error> {
-    let path = args.get_one::<String>("index").unwrap();
-    reg_index::init(
-        path,
-        args.get_one::<String>("dl").unwrap(),
-        args.get_one::<String>("api").map(String::as_str),
-    )?;
-    println!("Index created at `{}`.", path);
-    Ok(())
-}
-
-fn add(args: &ArgMatches) -> Result<(), Error> {
-    let index_path = args.get_one::<String>("index").unwrap();
-    let index_url = args.get_one::<String>("index-url").unwrap();
-    let krate = args.get_one::<String>("crate").map(Path::new);
-    let upload = args.get_one::<String>("upload").map(String::as_str);
-    let manifest_path = args.get_one::<String>("manifest-path").map(Path::new);
-    let force = args.get_flag("force");
-    let package_args = package_args(args);
-    let reg_pkg = match (manifest_path, krate) {
-        (Some(_), None) | (None, None) => {
-            if force {
-                reg_index::force_add(
-                    index_path,
-                    index_url,
-                    manifest_path,
-                    upload,
-                    package_args.as_ref(),
-                )
-            } else {
-                reg_index::add(
-                    index_path,
-                    index_url,
-                    manifest_path,
-                    upload,
-                    package_args.as_ref(),
+                .subcommand(
+                    Command::new("rdeps")
+                        .about("List the entries that depend on a package.")
+                        .arg_index()
+                        .arg_package("Name of the package to find the dependents of.", true)
+                        .arg_version("Only include dependencies that match a version \
+                            matching this requirement.", false)
+                        .arg(
+                            Arg::new("no-yanked")
+                                .long("no-yanked")
+                                .action(ArgAction::SetTrue)
+                                .help("Don't include yanked entries.")
+                        )
+                        .disable_version_flag(true)
                 )
+                .subcommand(
+                    Command::new("schema-report")
+                        .about("Report the index features used by entries and the Cargo version they need.")
+                        .arg_index()
+                        .arg_package("Only check this package.", false)
+                        .arg_version("Only check versions matching this requirement.", false)
+                        .disable_version_flag(true)
+                )
+                .subcommand(
+                    Command::new("selftest")
+                        .about("Check that Cargo can download a package from the registry.")
+                        .arg_index()
+                        .arg_index_url()
+                        .arg_package("Name of the package to depend on.", true)
+                        .arg_version("Version requirement of the package to depend on.", false)
+                        .arg(
+                            Arg::new("build")
+                                .long("build")
+                                .action(ArgAction::SetTrue)
+                                .help("Also build the package after fetching it.")
+                        )
+                        .disable_version_flag(true)
+                )
+                .subcommand(
+                    Command::new("serve")
+                        .about("Serve the registry web API for publishing, yanking, and downloads.")
+                        .arg_index()
+                        .arg_index_url()
+                        .arg_metadata_options()
+                        .arg_commit_options()
+                        .arg(
+                            Arg::new("upload")
+                                .long("upload")
+                                .value_name("DIR")
+                                .required(true)
+                                .help("Directory that published crates are stored in and \
+                                    downloaded from. Use {crate}, {version}, {prefix}, \
+                                    {lowerprefix}, and {sha256-checksum} to be included in \
+                                    the directory path. This can also be an http(s):// or \
+                                    s3:// URL.")
+                        )
+                        .arg(
+                            Arg::new("addr")
+                                .long("addr")
+                                .value_name("ADDR")
+                                .default_value("127.0.0.1:8080")
+                                .help("Address to listen on.")
+                        )
+                        .arg(
+                            Arg::new("token")
+                                .long("token")
+                                .value_name("TOKEN")
+                                .help("Require this token from Cargo to publish and yank. \
+                                    Required unless the address is a loopback address.")
+                        )
+                )
+                .subcommand(
+                    Command::new("setup")
+                        .about("Interactively create an index and set up Cargo to use it.")
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Display an entry in a readable layout.")
+                        .arg_index()
+                        .arg_package("Name of the package to show.", true)
+                        .arg_version("Version to show.", true)
+                        .disable_version_flag(true)
+                )
+                .subcommand(
+                    Command::new("snapshot")
+                        .about("Save or restore the state of the index.")
+                        .subcommand_required(true)
+                        .arg_required_else_help(true)
+                        .subcommand(
+                            Command::new("create")
+                                .about("Record the current state of the index.")
+                                .arg_index()
+                                .arg_commit_options()
+                                .arg(Arg::new("name").value_name("NAME").required(true))
+                        )
+                        .subcommand(
+                            Command::new("restore")
+                                .about("Return the index to a snapshot with a new commit.")
+                                .arg_index()
+                                .arg_commit_options()
+                                .arg_yes()
+                                .arg(Arg::new("name").value_name("NAME").required(true))
+                        )
+                        .subcommand(
+                            Command::new("list")
+                                .about("List the snapshots of the index.")
+                                .arg_index()
+                        )
+                )
+                .subcommand(
+                    Command::new("stats")
+                        .about("Display statistics about the index.")
+                        .arg_index()
+                        .arg(
+                            Arg::new("by-date")
+                                .long("by-date")
+                                .value_name("PERIOD")
+                                .value_parser(["day", "week"])
+                                .help("Count publishes and yanks from the git history per day or week.")
+                        )
+                        .arg(
+                            Arg::new("by-author")
+                                .long("by-author")
+                                .action(ArgAction::SetTrue)
+                                .help("Count publishes and yanks from the git history per commit author.")
+                        )
+                        .group(
+                            ArgGroup::new("grouping")
+                                .args(["by-date", "by-author"])
+                        )
+                        .arg(
+                            Arg::new("crates")
+                                .long("crates")
+                                .value_name("DIR")
+                                .conflicts_with("grouping")
+                                .help("Location of all .crate files, to add up their sizes. \
+                                    Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                    to be included in the directory path.")
+                        )
+                        .arg(
+                            Arg::new("top")
+                                .long("top")
+                                .value_name("N")
+                                .value_parser(clap::value_parser!(usize))
+                                .default_value("10")
+                                .conflicts_with("grouping")
+                                .help("Number of the largest packages to display.")
+                        )
+                        .arg_color()
+                )
+                .subcommand(
+                    Command::new("validate")
+                        .about("Validate the format of an index.")
+                        .arg_index()
+                        .arg(
+                            Arg::new("crates")
+                                .long("crates")
+                                .value_name("DIR")
+                                .help("Optional path to the location of all .crate files. \
+                                    If set, will validate the files exist and that the checksums are correct. \
+                                    Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                    to be included in the directory path.")
+                        )
+                        .arg(
+                            Arg::new("strict-yanked")
+                                .long("strict-yanked")
+                                .action(ArgAction::SetTrue)
+                                .help("Report dependencies whose requirement only matches yanked versions.")
+                        )
+                        .arg(
+                            Arg::new("check-dl")
+                                .long("check-dl")
+                                .action(ArgAction::SetTrue)
+                                .help("Send a HEAD request to the `dl` URL of each entry to check \
+                                    that the .crate file can be downloaded.")
+                        )
+                        .arg(
+                            Arg::new("check-worktree")
+                                .long("check-worktree")
+                                .action(ArgAction::SetTrue)
+                                .help("Report entries and config.json changes in the working tree \
+                                    that are not committed.")
+                        )
+                        .arg(
+                            Arg::new("similar-names")
+                                .long("similar-names")
+                                .action(ArgAction::SetTrue)
+                                .help("Report package names that are one edit apart, which may be \
+                                    typosquatting.")
+                        )
+                        .arg(
+                            Arg::new("popular-names")
+                                .long("popular-names")
+                                .value_name("FILE")
+                                .requires("similar-names")
+                                .help("With --similar-names, also compare to the package names \
+                                    in FILE, one per line, such as popular crates.io packages.")
+                        )
+                        .arg_color()
+                )
+                .subcommand(
+                    Command::new("verify-crate")
+                        .about("Check the structure of a `.crate` file.")
+                        .arg(
+                            Arg::new("file")
+                                .value_name("FILE")
+                                .required(true)
+                                .help("Path to the `.crate` file.")
+                        )
+                        .arg(
+                            Arg::new("max-size")
+                                .long("max-size")
+                                .value_name("BYTES")
+                                .value_parser(clap::value_parser!(u64))
+                                .help("Maximum size of the `.crate` file (default 10MiB).")
+                        )
+                        .arg(
+                            Arg::new("max-unpacked-size")
+                                .long("max-unpacked-size")
+                                .value_name("BYTES")
+                                .value_parser(clap::value_parser!(u64))
+                                .help("Maximum size of the unpacked contents (default 512MiB).")
+                        )
+                )
+                .subcommand(
+                    Command::new("watch")
+                        .about("Add .crate files that are placed in a directory.")
+                        .arg_index()
+                        .arg_index_url()
+                        .arg_metadata_options()
+                        .arg_build_metadata()
+                        .arg_commit_options()
+                        .arg(
+                            Arg::new("dir")
+                                .long("dir")
+                                .value_name("DIR")
+                                .required(true)
+                                .help("Directory to watch for .crate files. Added files are moved \
+                                    to `processed/` inside it, and files that fail to `failed/`.")
+                        )
+                        .arg(
+                            Arg::new("upload")
+                                .long("upload")
+                                .value_name("DIR")
+                                .help("If set, will copy the crate into the given directory. \
+                                    Use {crate}, {version}, {prefix}, {lowerprefix}, and {sha256-checksum} \
+                                    to be included in the directory path. \
+                                    Start with `lfs:` to store the crate in the index with Git LFS. \
+                                An http(s):// or s3:// URL uploads to a web server or S3 bucket.")
+                        )
+                        .arg(
+                            Arg::new("interval")
+                                .long("interval")
+                                .value_name("SECONDS")
+                                .value_parser(clap::value_parser!(u64))
+                                .default_value("5")
+                                .help("How often to look for new files.")
+                        )
+                        .arg(
+                            Arg::new("once")
+                                .long("once")
+                                .action(ArgAction::SetTrue)
+                                .help("Add the files that are there now and exit, such as from cron.")
+                        )
+                )
+                .subcommand(
+                    Command::new("who-published")
+                        .about("Show the commit that added a version to the index.")
+                        .arg_index()
+                        .arg_package("Name of the package.", true)
+                        .arg_version("Version of the package.", true)
+                        .disable_version_flag(true)
+                )
+        )
+        .get_matches();
+    let submatches = matches
+        .subcommand_matches("index")
+        .expect("Expected `index` subcommand.");
+
+    match submatches.subcommand() {
+        Some(("init", args)) => init(args),
+        Some(("add", args)) => with_index(args, add).and_then(|changes| notify(args, &changes)),
+        Some(("log", args)) => log(args),
+        Some(("metadata", args)) => metadata(args),
+        Some(("yank", args)) => with_index(args, yank).and_then(|changes| notify(args, &changes)),
+        Some(("unyank", args)) => {
+            with_index(args, unyank).and_then(|changes| notify(args, &changes))
+        }
+        Some(("list", args)) => list(args),
+        Some(("advisories", args)) => advisories(args),
+        Some(("check", args)) => with_index(args, check),
+        Some(("config", args)) => config(args),
+        Some(("config-snippet", args)) => config_snippet(args),
+        Some(("diff", args)) => diff(args),
+        Some(("export", args)) => export(args),
+        Some(("fetch-deps", args)) => fetch_deps(args),
+        Some(("import", args)) => import(args),
+        Some(("migrate-from", args)) => migrate_from(args),
+        Some(("names", args)) => names(args),
+        Some(("prune-yanked", args)) => prune_yanked(args),
+        Some(("rdeps", args)) => rdeps(args),
+        Some(("schema-report", args)) => schema_report(args),
+        Some(("selftest", args)) => selftest(args),
+        Some(("serve", args)) => serve(args),
+        Some(("setup", _args)) => setup::setup(&mut io::stdin().lock()),
+        Some(("show", args)) => show(args),
+        Some(("snapshot", args)) => snapshot(args),
+        Some(("stats", args)) => stats(args),
+        Some(("validate", args)) => validate(args),
+        Some(("verify-crate", args)) => verify_crate(args),
+        Some(("watch", args)) => watch(args),
+        Some(("who-published", args)) => who_published(args),
+        _ => {
+            // Enforced by SubcommandRequiredElseHelp.
+            unreachable!()
+        }
+    }?;
+
+    Ok(())
+}
+
+fn package_args(args: &ArgMatches) -> Option<Vec<String>> {
+    args.get_many::<String>("package-args")
+        .map(|values| values.cloned().collect())
+}
+
+fn metadata_options(args: &ArgMatches) -> Result<MetadataOptions, Error> {
+    let mut options = MetadataOptions::default();
+    options.explicit_registry = args.get_flag("explicit-registry");
+    if let Some(path) = args.get_one::<String>("registry-rewrites") {
+        options.registry_rewrites = reg_index::read_registry_rewrites(path)?;
+    }
+    options.omit_dev_deps = args.get_flag("no-dev-deps");
+    options.check_reproducible = args.get_flag("reproducible");
+    options.expected_cksum = args.get_one::<String>("expect-cksum").cloned();
+    Ok(options)
+}
+
+fn commit_options(args: &ArgMatches) -> reg_index::CommitOptions {
+    let mut commit = reg_index::CommitOptions::default();
+    commit.name = args.get_one::<String>("git-name").cloned();
+    commit.email = args.get_one::<String>("git-email").cloned();
+    commit.timestamp = args.get_one::<i64>("commit-timestamp").copied();
+    commit.committer_name = args.get_one::<String>("git-committer-name").cloned();
+    commit.committer_email = args.get_one::<String>("git-committer-email").cloned();
+    commit.message = args.get_one::<String>("commit-message").cloned();
+    commit.sign = args.get_flag("sign-commit");
+    commit.branch = args.get_one::<String>("branch").cloned();
+    commit.push = args.get_one::<String>("push").cloned();
+    commit
+}
+
+fn build_metadata(args: &ArgMatches) -> reg_index::BuildMetadataPolicy {
+    match args.get_one::<String>("build-metadata").map(String::as_str) {
+        Some("require") => reg_index::BuildMetadataPolicy::Require,
+        _ => reg_index::BuildMetadataPolicy::Ignore,
+    }
+}
+
+fn init(args: &ArgMatches) -> Result<(), Error> {
+    let path = args.get_one::<String>("index").unwrap();
+    let dl = args.get_one::<String>("dl").unwrap();
+    let api = args.get_one::<String>("api").map(String::as_str);
+    match reg_index::check_urls(dl, api) {
+        Ok(warnings) => {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+        }
+        Err(e) if args.get_flag("force") => eprintln!("Warning: {}", e),
+        Err(e) => bail!("{:#}\nUse `--force` to create the index anyway.", e),
+    }
+    let protocol = match args.get_one::<String>("protocol").unwrap().as_str() {
+        "sparse" => reg_index::Protocol::Sparse,
+        _ => reg_index::Protocol::Git,
+    };
+    if protocol == reg_index::Protocol::Sparse {
+        if let Some(id) = COMMIT_ARGS
+            .iter()
+            .find(|id| args.value_source(id) == Some(ValueSource::CommandLine))
+        {
+            bail!(
+                "`--{}` can't be used with `--protocol sparse`, which does not make commits.",
+                id
+            );
+        }
+    }
+    let options = reg_index::InitOptions::default()
+        .protocol(protocol)
+        .auth_required(args.get_flag("auth-required"))
+        .commit(commit_options(args));
+    match args.get_one::<String>("with-readme") {
+        Some(name) => reg_index::init_with_readme(
+            path,
+            dl,
+            api,
+            name,
+            args.get_one::<String>("index-url").map(String::as_str),
+            &options,
+        )?,
+        None => reg_index::init_with_options(path, dl, api, &options)?,
+    }
+    println!("Index created at `{}`.", path);
+    Ok(())
+}
+
+/// Run a subcommand on the `--index` path, or on a temporary clone that is
+/// pushed back if it is a git URL.
+fn with_index<T>(
+    args: &ArgMatches,
+    f: fn(&ArgMatches, &Path) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let index = args.get_one::<String>("index").unwrap();
+    if reg_index::is_remote_index(index) {
+        reg_index::with_remote_index(index, |path| f(args, path))
+    } else {
+        f(args, Path::new(index))
+    }
+}
+
+fn add(args: &ArgMatches, index_path: &Path) -> Result<Vec<Change>, Error> {
+    if args.get_flag("check") {
+        return check(args, index_path).map(|()| Vec::new());
+    }
+    if let Some(json_path) = args.get_one::<String>("metadata-json") {
+        return add_metadata_json(args, index_path, json_path);
+    }
+    let index_url = args.get_one::<String>("index-url").unwrap();
+    let krate = args.get_one::<String>("crate").map(Path::new);
+    let upload = args.get_one::<String>("upload").map(String::as_str);
+    let manifest_path = args.get_one::<String>("manifest-path").map(Path::new);
+    let force = args.get_flag("force");
+    let package_args = package_args(args);
+    let mut options = metadata_options(args)?;
+    options.build_metadata = build_metadata(args);
+    options.commit = commit_options(args);
+    for reviewer in args.get_many::<String>("reviewed-by").into_iter().flatten() {
+        options
+            .commit
+            .trailers
+            .push((reg_index::REVIEWED_BY_TRAILER.to_string(), reviewer.clone()));
+    }
+    options.require_newer = args.get_flag("require-newer");
+    options.semver_checks = args.get_flag("semver-checks");
+    options.allow_name_conflicts = args.get_flag("allow-name-conflict");
+    options.require_clean = args.get_flag("require-clean");
+    let mut changes = Vec::new();
+    let manifests = if let Some(since) = args.get_one::<String>("changed-since") {
+        let manifests = reg_index::changed_packages(manifest_path, since)?;
+        if manifests.is_empty() {
+            println!("No packages changed since `{}`.", since);
+        }
+        Some(manifests)
+    } else if args.get_flag("workspace") {
+        Some(reg_index::workspace_packages(manifest_path)?)
+    } else {
+        None
+    };
+    if let Some(manifests) = manifests {
+        for manifest in manifests {
+            let mut add_options = reg_index::AddOptions::default()
+                .manifest_path(manifest)
+                .force(force)
+                .metadata(options.clone());
+            add_options.upload = upload.map(str::to_string);
+            add_options.package_args = package_args.clone();
+            let add_options = confirm_force(args, add_options);
+            let reg_pkg = reg_index::add_with_options(index_path, index_url, &add_options)?;
+            changes.push(added(index_path, &reg_pkg));
+        }
+        return Ok(changes);
+    }
+    if let Some(dir) = args.get_one::<String>("crates-dir") {
+        let crate_paths = reg_index::sort_crates(index_url, dir, &options)?;
+        if crate_paths.is_empty() {
+            println!("No .crate files found in `{}`.", dir);
+        }
+        if args.get_flag("one-commit") {
+            let reg_pkgs =
+                reg_index::add_from_crates(index_path, index_url, &crate_paths, upload, &options)?;
+            for reg_pkg in reg_pkgs {
+                changes.push(added(index_path, &reg_pkg));
+            }
+        } else {
+            for krate in crate_paths {
+                let mut add_options = reg_index::AddOptions::default()
+                    .crate_file(krate)
+                    .metadata(options.clone());
+                add_options.upload = upload.map(str::to_string);
+                let reg_pkg = reg_index::add_with_options(index_path, index_url, &add_options)?;
+                changes.push(added(index_path, &reg_pkg));
             }
         }
-        (None, Some(krate)) => reg_index::add_from_crate(index_path, index_url, krate, upload),
+        return Ok(changes);
+    }
+    if manifest_path.is_some() && krate.is_some() {
+        bail!("Both --crate and --manifest-path cannot be specified.");
+    }
+    let mut add_options = reg_index::AddOptions::default()
+        .force(force)
+        .metadata(options);
+    add_options.manifest_path = manifest_path.map(Path::to_path_buf);
+    add_options.crate_file = krate.map(Path::to_path_buf);
+    add_options.upload = upload.map(str::to_string);
+    add_options.package_args = package_args;
+    let add_options = confirm_force(args, add_options);
+    let reg_pkg = reg_index::add_with_options(index_path, index_url, &add_options)?;
+    changes.push(added(index_path, &reg_pkg));
+    Ok(changes)
+}
+
+/// A change made by `add`, `yank`, or `unyank`, passed to `--hook` commands
+/// and `--webhook` URLs.
+struct Change {
+    action: &'static str,
+    name: String,
+    version: String,
+    cksum: Option<String>,
+    commit: Option<String>,
+}
+
+/// Reports that a package was added.
+fn added(index_path: &Path, pkg: &reg_index::IndexPackage) -> Change {
+    println!("{}:{} successfully added!", pkg.name, pkg.vers);
+    Change {
+        action: "add",
+        name: pkg.name.clone(),
+        version: pkg.vers.to_string(),
+        cksum: Some(pkg.cksum.clone()),
+        commit: head_commit(index_path),
+    }
+}
+
+/// A yank or unyank of an existing entry.
+fn changed(index_path: &Path, action: &'static str, pkg: &str, version: &str) -> Change {
+    let cksum = reg_index::list(index_path, pkg, Some(&format!("={}", version)))
+        .ok()
+        .and_then(|pkgs| pkgs.into_iter().next())
+        .map(|pkg| pkg.cksum);
+    Change {
+        action,
+        name: pkg.to_string(),
+        version: version.to_string(),
+        cksum,
+        commit: head_commit(index_path),
+    }
+}
+
+/// The commit the index is at, or `None` if it isn't a git repository.
+fn head_commit(index_path: &Path) -> Option<String> {
+    let output = ProcessCommand::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(index_path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Run the hooks and webhooks for the changes.
+fn notify(args: &ArgMatches, changes: &[Change]) -> Result<(), Error> {
+    run_hooks(args, changes)?;
+    post_webhooks(args, changes)
+}
+
+/// Run each `--hook` command, or `CARGO_INDEX_HOOK`, for each change.
+///
+/// The change has already been made, so a failing hook is only a warning.
+fn run_hooks(args: &ArgMatches, changes: &[Change]) -> Result<(), Error> {
+    let mut hooks: Vec<String> = args
+        .get_many::<String>("hook")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    if hooks.is_empty() {
+        hooks.extend(std::env::var("CARGO_INDEX_HOOK").ok());
+    }
+    for change in changes {
+        for hook in &hooks {
+            // Package names and versions can't have shell metacharacters.
+            let cmd = hook
+                .replace("{crate}", &change.name)
+                .replace("{version}", &change.version)
+                .replace("{action}", change.action);
+            let mut shell = if cfg!(windows) {
+                let mut shell = ProcessCommand::new("cmd");
+                shell.arg("/C");
+                shell
+            } else {
+                let mut shell = ProcessCommand::new("sh");
+                shell.arg("-c");
+                shell
+            };
+            let status = shell
+                .arg(&cmd)
+                .env("CARGO_INDEX_CRATE", &change.name)
+                .env("CARGO_INDEX_VERSION", &change.version)
+                .env("CARGO_INDEX_ACTION", change.action)
+                .status()
+                .with_context(|| format!("Could not run hook `{}`.", cmd))?;
+            if !status.success() {
+                eprintln!("Warning: Hook `{}` failed with {}.", cmd, status);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// POST each change as JSON to each `--webhook` URL, or
+/// `CARGO_INDEX_WEBHOOK`.
+///
+/// Like hooks, a failing webhook is only a warning.
+fn post_webhooks(args: &ArgMatches, changes: &[Change]) -> Result<(), Error> {
+    let mut urls: Vec<String> = args
+        .get_many::<String>("webhook")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    if urls.is_empty() {
+        urls.extend(std::env::var("CARGO_INDEX_WEBHOOK").ok());
+    }
+    for change in changes {
+        let payload = serde_json::json!({
+            "action": change.action,
+            "name": change.name,
+            "version": change.version,
+            "cksum": change.cksum,
+            "commit": change.commit,
+        });
+        for url in &urls {
+            if let Err(e) = post_json(url, &payload) {
+                eprintln!("Warning: Webhook `{}` failed: {:#}", url, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn post_json(url: &str, payload: &serde_json::Value) -> Result<(), Error> {
+    let mut child = ProcessCommand::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--netrc-optional"])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| "Could not run `curl`, it is needed for webhooks.")?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(payload.to_string().as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
+
+/// For a forced add, show how it would change an existing entry, and ask
+/// to continue unless `--yes` was passed.
+///
+/// The question is asked with the entry that is generated for the add, so
+/// the package is only built once.
+fn confirm_force(args: &ArgMatches, options: reg_index::AddOptions) -> reg_index::AddOptions {
+    if !options.force || args.get_flag("yes") {
+        return options;
+    }
+    options.confirm_replace(|old, new| {
+        let old_json = serde_json::to_string(old)?;
+        let new_json = serde_json::to_string(new)?;
+        if old_json == new_json {
+            return Ok(());
+        }
+        println!(
+            "This will replace the entry for `{}:{}`:",
+            new.name, new.vers
+        );
+        println!("- {}", old_json);
+        println!("+ {}", new_json);
+        confirm_or_abort()
+    })
+}
+
+/// Ask to continue with a destructive change.
+fn confirm_or_abort() -> Result<(), Error> {
+    match setup::confirm(&mut io::stdin().lock(), "Continue?") {
+        Ok(true) => Ok(()),
+        Ok(false) => bail!("Aborted."),
+        Err(e) => bail!("{}\nUse `--yes` to skip confirmation.", e),
+    }
+}
+
+fn add_metadata_json(
+    args: &ArgMatches,
+    index_path: &Path,
+    json_path: &str,
+) -> Result<Vec<Change>, Error> {
+    let krate = args.get_one::<String>("crate").unwrap();
+    let upload = args.get_one::<String>("upload").map(String::as_str);
+    let json = fs::read_to_string(json_path)
+        .with_context(|| format!("Failed to read `{}`.", json_path))?;
+    let index_pkg: reg_index::IndexPackage =
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse `{}`.", json_path))?;
+    let reg_pkg =
+        reg_index::add_from_metadata(index_path, &index_pkg, krate, upload, &commit_options(args))?;
+    Ok(vec![added(index_path, &reg_pkg)])
+}
+
+fn check(args: &ArgMatches, index_path: &Path) -> Result<(), Error> {
+    let index_url = args.get_one::<String>("index-url").unwrap();
+    let manifest_path = args.get_one::<String>("manifest-path").map(Path::new);
+    let krate = args.get_one::<String>("crate").map(Path::new);
+    let package_args = package_args(args);
+    let mut options = metadata_options(args)?;
+    options.build_metadata = build_metadata(args);
+    let reg_pkg = match (manifest_path, krate) {
+        (Some(_), None) | (None, None) => reg_index::check(
+            index_path,
+            index_url,
+            manifest_path,
+            package_args.as_ref(),
+            &options,
+        ),
+        (None, Some(krate)) => reg_index::check_from_crate(index_path, index_url, krate, &options),
         (Some(_), Some(_)) => bail!("Both --crate and --manifest-path cannot be specified."),
     }?;
-    println!("{}:{} successfully added!", reg_pkg.name, reg_pkg.vers);
+    println!("{}:{} is up to date.", reg_pkg.name, reg_pkg.vers);
     Ok(())
 }
 
@@ -301,48 +1680,260 @@ fn metadata(args: &ArgMatches) -> Result<(), Error> {
     let manifest_path = args.get_one::<String>("manifest-path").map(Path::new);
     let krate = args.get_one::<String>("crate").map(Path::new);
     let package_args = package_args(args);
+    let options = metadata_options(args)?;
     let reg_pkg = match (manifest_path, krate) {
-        (Some(_), None) | (None, None) => {
-            reg_index::metadata(index_url, manifest_path, package_args.as_ref())
+        (Some(_), None) | (None, None) => reg_index::metadata_with_options(
+            index_url,
+            manifest_path,
+            package_args.as_ref(),
+            &options,
+        ),
+        (None, Some(krate)) => {
+            reg_index::metadata_from_crate_with_options(index_url, krate, &options)
         }
-        (None, Some(krate)) => reg_index::metadata_from_crate(index_url, krate),
         (Some(_), Some(_)) => bail!("Both --crate and --manifest-path cannot be specified."),
     }?;
     println!("{}", serde_json::to_string(&reg_pkg)?);
     Ok(())
 }
 
-fn yank(args: &ArgMatches) -> Result<(), Error> {
+fn advisories(args: &ArgMatches) -> Result<(), Error> {
+    let advisories = reg_index::yank_advisories(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("package").map(String::as_str),
+    )?;
+    let id_prefix = args.get_one::<String>("id-prefix").unwrap();
+    let format = args.get_one::<String>("format").unwrap();
+    let out_dir = args.get_one::<String>("out-dir");
+    if out_dir.is_some() && format != "osv" {
+        bail!("--out-dir is only supported with the `osv` format.");
+    }
+    let docs: Vec<serde_json::Value> = match format.as_str() {
+        "osv" => advisories.iter().map(|a| a.to_osv(id_prefix)).collect(),
+        _ => advisories
+            .iter()
+            .map(|a| {
+                serde_json::json!({
+                    "name": a.name,
+                    "version": a.vers.to_string(),
+                    "yanked_at": reg_index::format_time(a.time),
+                    "commit": a.commit,
+                    "author": a.author_name,
+                    "reason": a.reason,
+                })
+            })
+            .collect(),
+    };
+    match out_dir {
+        Some(out_dir) => {
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("Failed to create `{}`.", out_dir))?;
+            for doc in &docs {
+                let path = Path::new(out_dir).join(format!("{}.json", doc["id"].as_str().unwrap()));
+                fs::write(&path, serde_json::to_string_pretty(doc)?)
+                    .with_context(|| format!("Failed to write `{}`.", path.display()))?;
+            }
+            println!("Wrote {} advisories to `{}`.", docs.len(), out_dir);
+        }
+        None => println!("{}", serde_json::to_string_pretty(&docs)?),
+    }
+    Ok(())
+}
+
+fn yank(args: &ArgMatches, index_path: &Path) -> Result<Vec<Change>, Error> {
     let pkg = args.get_one::<String>("package").unwrap();
+    let mut commit = commit_options(args);
+    if let Some(reason) = args.get_one::<String>("reason") {
+        commit.trailers.push((
+            reg_index::YANK_REASON_TRAILER.to_string(),
+            reason.replace('\n', " "),
+        ));
+    }
+    if let Some(version_req) = args.get_one::<String>("version-req") {
+        return set_yank_matching(index_path, pkg, version_req, true, &commit);
+    }
     let version = args.get_one::<String>("version").unwrap();
-    reg_index::yank(args.get_one::<String>("index").unwrap(), pkg, version)?;
+    let mut options = reg_index::YankOptions::default();
+    options.build_metadata = build_metadata(args);
+    options.commit = commit;
+    reg_index::set_yank_with_options(index_path, pkg, version, true, &options)?;
     println!("{}:{} yanked!", pkg, version);
-    Ok(())
+    Ok(vec![changed(index_path, "yank", pkg, version)])
 }
 
-fn unyank(args: &ArgMatches) -> Result<(), Error> {
+fn unyank(args: &ArgMatches, index_path: &Path) -> Result<Vec<Change>, Error> {
     let pkg = args.get_one::<String>("package").unwrap();
+    if let Some(version_req) = args.get_one::<String>("version-req") {
+        return set_yank_matching(index_path, pkg, version_req, false, &commit_options(args));
+    }
     let version = args.get_one::<String>("version").unwrap();
-    reg_index::unyank(args.get_one::<String>("index").unwrap(), pkg, version)?;
+    let mut options = reg_index::YankOptions::default();
+    options.build_metadata = build_metadata(args);
+    options.commit = commit_options(args);
+    reg_index::set_yank_with_options(index_path, pkg, version, false, &options)?;
     println!("{}:{} unyanked!", pkg, version);
-    Ok(())
+    Ok(vec![changed(index_path, "unyank", pkg, version)])
+}
+
+/// Yanks or unyanks every version that matches a requirement, for `yank`
+/// and `unyank` with `--version-req`.
+fn set_yank_matching(
+    index_path: &Path,
+    pkg: &str,
+    version_req: &str,
+    yank: bool,
+    commit: &reg_index::CommitOptions,
+) -> Result<Vec<Change>, Error> {
+    let versions = reg_index::set_yank_matching(index_path, pkg, version_req, yank, commit)?;
+    let action = if yank { "yank" } else { "unyank" };
+    let mut changes = Vec::new();
+    for vers in versions {
+        println!("{}:{} {}ed!", pkg, vers, action);
+        changes.push(changed(index_path, action, pkg, &vers.to_string()));
+    }
+    Ok(changes)
 }
 
 fn list(args: &ArgMatches) -> Result<(), Error> {
     let pkg = args.get_one::<String>("package").map(String::as_str);
     let version = args.get_one::<String>("version").map(String::as_str);
+    let index = args.get_one::<String>("index").unwrap();
+    let dates = if args.get_flag("with-dates") {
+        Some(reg_index::entry_dates(index, pkg)?)
+    } else {
+        None
+    };
+    let mut options = reg_index::ListOptions::default();
+    options.prerelease = if args.get_flag("include-prerelease") {
+        reg_index::Prerelease::Include
+    } else if args.get_flag("exclude-prerelease") {
+        reg_index::Prerelease::Exclude
+    } else {
+        reg_index::Prerelease::Cargo
+    };
+    options.build_metadata = build_metadata(args);
+    options.yanked = if args.get_flag("yanked") {
+        reg_index::Yanked::Only
+    } else if args.get_flag("no-yanked") {
+        reg_index::Yanked::Exclude
+    } else {
+        reg_index::Yanked::Include
+    };
+    options.latest = args.get_flag("latest");
+    let count_only = args.get_flag("count");
+    let names_only = args.get_flag("names-only");
+    let format = match args.get_one::<String>("format").unwrap().as_str() {
+        "auto" if io::stdout().is_terminal() => "table",
+        "auto" => "json",
+        format => format,
+    };
     let mut count = 0;
-    reg_index::list_all(
-        args.get_one::<String>("index").unwrap(),
-        pkg,
-        version,
-        |entries| {
-            for entry in entries {
-                count += 1;
-                println!("{}", serde_json::to_string(&entry).unwrap());
+    let mut names = BTreeSet::new();
+    let mut rows = Vec::new();
+    let mut jsons = Vec::new();
+    reg_index::list_with_options(index, pkg, version, &options, |entries| {
+        for entry in entries {
+            count += 1;
+            if count_only {
+                continue;
             }
-        },
-    )?;
+            if names_only {
+                names.insert(entry.name);
+                continue;
+            }
+            if format == "table" || format == "csv" {
+                let style = if entry.yanked {
+                    Style::Red
+                } else {
+                    Style::Plain
+                };
+                let mut row = vec![
+                    entry.name.clone(),
+                    entry.vers.to_string(),
+                    if entry.yanked { "yes" } else { "no" }.to_string(),
+                    entry.deps.len().to_string(),
+                    if format == "table" {
+                        entry.cksum.chars().take(12).collect()
+                    } else {
+                        entry.cksum.clone()
+                    },
+                ];
+                if let Some(dates) = &dates {
+                    let entry_dates = dates
+                        .get(&(entry.name, entry.vers))
+                        .cloned()
+                        .unwrap_or_default();
+                    for time in [entry_dates.added, entry_dates.yank_changed] {
+                        row.push(time.map_or_else(|| "-".to_string(), reg_index::format_time));
+                    }
+                }
+                rows.push((row, style));
+                continue;
+            }
+            let mut json = serde_json::to_string(&entry).unwrap();
+            if let Some(dates) = &dates {
+                let entry_dates = dates
+                    .get(&(entry.name.clone(), entry.vers.clone()))
+                    .cloned()
+                    .unwrap_or_default();
+                let added = entry_dates.added.map(reg_index::format_time);
+                let yank_changed = entry_dates.yank_changed.map(reg_index::format_time);
+                // Append the fields to the end of the object.
+                json = format!(
+                    "{},\"added\":{},\"yank_changed\":{}}}",
+                    &json[..json.len() - 1],
+                    serde_json::to_string(&added).unwrap(),
+                    serde_json::to_string(&yank_changed).unwrap()
+                );
+            }
+            if format == "json" {
+                println!("{}", json);
+            } else {
+                jsons.push(json);
+            }
+        }
+    })?;
+    if count_only {
+        println!("{}", count);
+        return Ok(());
+    }
+    for name in names {
+        println!("{}", name);
+    }
+    match format {
+        "json-array" if !jsons.is_empty() => println!("[{}]", jsons.join(",")),
+        "toml" if !jsons.is_empty() => print!("{}", list_toml(&jsons)?),
+        "table" if !rows.is_empty() => {
+            let mut headings = vec!["NAME", "VERSION", "YANKED", "DEPS", "CKSUM"];
+            if dates.is_some() {
+                headings.extend(["ADDED", "YANK CHANGED"]);
+            }
+            print_styled_table(&headings, rows, use_color(args));
+        }
+        "csv" if !rows.is_empty() => {
+            let mut headings = vec!["name", "vers", "yanked", "deps", "cksum"];
+            if dates.is_some() {
+                headings.extend(["added", "yank_changed"]);
+            }
+            println!("{}", headings.join(","));
+            // None of the fields can contain a comma or quote.
+            for (row, _) in rows {
+                println!("{}", row.join(","));
+            }
+        }
+        _ => {}
+    }
+    if count == 0 && options.yanked != reg_index::Yanked::Include {
+        let kind = if options.yanked == reg_index::Yanked::Only {
+            "yanked"
+        } else {
+            "un-yanked"
+        };
+        match pkg {
+            Some(pkg) => bail!("No {} entries found for `{}`.", kind, pkg),
+            None => bail!("No {} entries found.", kind),
+        }
+    }
     if count == 0 {
         match (pkg, version) {
             (Some(pkg), Some(version)) => bail!(
@@ -361,9 +1952,815 @@ fn list(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// Formats entries as a TOML array of tables named `entry`.
+fn list_toml(jsons: &[String]) -> Result<String, Error> {
+    /// TOML has no null, so fields that are null are left out.
+    fn strip_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.retain(|_, value| !value.is_null());
+                map.values_mut().for_each(strip_nulls);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+            _ => {}
+        }
+    }
+    let mut entries = Vec::new();
+    for json in jsons {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        strip_nulls(&mut value);
+        entries.push(value);
+    }
+    let doc = toml::Value::try_from(serde_json::json!({ "entry": entries }))?;
+    Ok(toml::to_string(&doc)?)
+}
+
+/// Fields of config.json that `config set` can change.
+const CONFIG_KEYS: [&str; 3] = ["dl", "api", "auth-required"];
+
+fn config(args: &ArgMatches) -> Result<(), Error> {
+    let (cmd, args) = args.subcommand().unwrap();
+    let index = args.get_one::<String>("index").unwrap();
+    let key = args.get_one::<String>("key").map(String::as_str);
+    let path = Path::new(index).join("config.json");
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read `{}`.", path.display()))?;
+    let raw: serde_json::Value = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to deserialize `{}`.", path.display()))?;
+    match cmd {
+        "get" => {
+            let Some(key) = key else {
+                println!("{}", contents.trim_end());
+                return Ok(());
+            };
+            match &raw[key] {
+                serde_json::Value::Null => bail!("`{}` is not set in config.json.", key),
+                serde_json::Value::String(value) => println!("{}", value),
+                value => println!("{}", value),
+            }
+        }
+        "set" => {
+            let key = key.unwrap();
+            let value = args.get_one::<String>("value").unwrap();
+            // The URLs in the file have the markers as written, unlike `Url`.
+            let mut dl = raw["dl"].as_str().unwrap_or_default();
+            let mut api = raw["api"].as_str();
+            match key {
+                "dl" => dl = value,
+                "api" => api = Some(value),
+                _ => {}
+            }
+            if key != "auth-required" {
+                match reg_index::check_urls(dl, api) {
+                    Ok(warnings) => {
+                        for warning in warnings {
+                            eprintln!("Warning: {}", warning);
+                        }
+                    }
+                    Err(e) if args.get_flag("force") => eprintln!("Warning: {}", e),
+                    Err(e) => bail!("{:#}\nUse `--force` to change it anyway.", e),
+                }
+            }
+            let mut config = reg_index::load_config(index)?;
+            match key {
+                "dl" => {
+                    config.dl = Url::parse(value)
+                        .with_context(|| format!("`{}` is not a valid URL.", value))?
+                }
+                "api" => {
+                    config.api = Some(
+                        Url::parse(value)
+                            .with_context(|| format!("`{}` is not a valid URL.", value))?,
+                    )
+                }
+                _ => {
+                    config.auth_required = value
+                        .parse()
+                        .map_err(|_| format_err!("`auth-required` must be `true` or `false`."))?
+                }
+            }
+            reg_index::save_config(index, &config, &commit_options(args))?;
+            println!("Set `{}` in config.json.", key);
+        }
+        "unset" => {
+            let key = key.unwrap();
+            reg_index::update_config(index, &commit_options(args), |config| match key {
+                "api" => config.api = None,
+                _ => config.auth_required = false,
+            })?;
+            println!("Removed `{}` from config.json.", key);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn config_snippet(args: &ArgMatches) -> Result<(), Error> {
+    let snippet = reg_index::config_snippet(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("index-url").unwrap(),
+        args.get_one::<String>("name").unwrap(),
+        args.get_flag("replace-crates-io"),
+    )?;
+    print!("{}", snippet);
+    Ok(())
+}
+
+fn diff(args: &ArgMatches) -> Result<(), Error> {
+    let index = args.get_one::<String>("index").unwrap();
+    let changes = match args.get_one::<String>("old-index") {
+        Some(old) => reg_index::diff_indexes(old, index)?,
+        None => reg_index::diff_revisions(
+            index,
+            args.get_one::<String>("old-rev").unwrap(),
+            args.get_one::<String>("new-rev").unwrap(),
+        )?,
+    };
+    if changes.is_empty() {
+        println!("No differences.");
+    }
+    for change in changes {
+        let kind = match change.kind {
+            reg_index::HistoryEventKind::Add => "added",
+            reg_index::HistoryEventKind::Remove => "removed",
+            reg_index::HistoryEventKind::Yank => "yanked",
+            reg_index::HistoryEventKind::Unyank => "unyanked",
+            reg_index::HistoryEventKind::Update => "cksum changed",
+            _ => "changed",
+        };
+        println!("{}:{} {}", change.name, change.vers, kind);
+    }
+    Ok(())
+}
+
+fn export(args: &ArgMatches) -> Result<(), Error> {
+    let index = args.get_one::<String>("index").unwrap();
+    let pkg = args.get_one::<String>("package").map(String::as_str);
+    let version = args.get_one::<String>("version").map(String::as_str);
+    let format = args.get_one::<String>("format").unwrap();
+    let out = args.get_one::<String>("out");
+    #[cfg(feature = "parquet")]
+    {
+        let table = match format.as_str() {
+            "parquet" => Some(reg_index::ParquetTable::Entries),
+            "parquet-deps" => Some(reg_index::ParquetTable::Dependencies),
+            _ => None,
+        };
+        if let Some(table) = table {
+            let Some(out) = out else {
+                bail!("--out is required for the `{}` format.", format);
+            };
+            let f =
+                fs::File::create(out).with_context(|| format!("Failed to create `{}`.", out))?;
+            let count = reg_index::export_parquet(index, pkg, version, table, f)?;
+            println!("Exported {} rows to `{}`.", count, out);
+            return Ok(());
+        }
+    }
+    let format = format.parse()?;
+    match out {
+        Some(out) => {
+            let f =
+                fs::File::create(out).with_context(|| format!("Failed to create `{}`.", out))?;
+            let count = reg_index::export(index, pkg, version, format, BufWriter::new(f))?;
+            println!("Exported {} entries to `{}`.", count, out);
+        }
+        None => {
+            let stdout = io::stdout();
+            reg_index::export(index, pkg, version, format, stdout.lock())?;
+        }
+    }
+    Ok(())
+}
+
+fn fetch_deps(args: &ArgMatches) -> Result<(), Error> {
+    let report = reg_index::fetch_deps(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("package").map(String::as_str),
+        args.get_one::<String>("dl-dir").unwrap(),
+    )?;
+    for pkg in &report.fetched {
+        println!("Fetched {}", pkg);
+    }
+    println!(
+        "Fetched {} crates, {} already present.",
+        report.fetched.len(),
+        report.present.len()
+    );
+    if !report.failed.is_empty() {
+        for (what, reason) in &report.failed {
+            eprintln!("Could not fetch {}: {}", what, reason);
+        }
+        bail!("Failed to fetch {} crates.", report.failed.len());
+    }
+    Ok(())
+}
+
+fn import(args: &ArgMatches) -> Result<(), Error> {
+    let path = args.get_one::<String>("file").unwrap();
+    let f = fs::File::open(path).with_context(|| format!("Failed to open `{}`.", path))?;
+    let entries = reg_index::import(
+        args.get_one::<String>("index").unwrap(),
+        io::BufReader::new(f),
+        &commit_options(args),
+    )?;
+    println!("Imported {} entries.", entries.len());
+    Ok(())
+}
+
+fn migrate_from(args: &ArgMatches) -> Result<(), Error> {
+    let report = reg_index::migrate_from(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("from").unwrap(),
+        args.get_one::<String>("api").map(String::as_str),
+        args.get_one::<String>("upload").unwrap(),
+        &commit_options(args),
+    )?;
+    for pkg in &report.migrated {
+        println!("Migrated {}:{}", pkg.name, pkg.vers);
+    }
+    println!(
+        "Migrated {} entries, {} already in the index.",
+        report.migrated.len(),
+        report.skipped.len()
+    );
+    Ok(())
+}
+
+fn names(args: &ArgMatches) -> Result<(), Error> {
+    for name in reg_index::list_names(args.get_one::<String>("index").unwrap())? {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+fn rdeps(args: &ArgMatches) -> Result<(), Error> {
+    let pkg = args.get_one::<String>("package").unwrap();
+    let rdeps = reg_index::rdeps(
+        args.get_one::<String>("index").unwrap(),
+        pkg,
+        args.get_one::<String>("version").map(String::as_str),
+    )?;
+    let rows: Vec<Vec<String>> = rdeps
+        .into_iter()
+        .filter(|rdep| !(rdep.yanked && args.get_flag("no-yanked")))
+        .map(|rdep| {
+            let mut name = rdep.name;
+            if rdep.yanked {
+                name.push_str(" (yanked)");
+            }
+            let mut kind = rdep.dep.kind.to_string();
+            if rdep.dep.optional {
+                kind.push_str(" (optional)");
+            }
+            vec![name, rdep.vers.to_string(), rdep.dep.req.to_string(), kind]
+        })
+        .collect();
+    if rows.is_empty() {
+        println!("No entries depend on `{}`.", pkg);
+    } else {
+        print_table(&["NAME", "VERSION", "REQ", "KIND"], rows);
+    }
+    Ok(())
+}
+
+fn prune_yanked(args: &ArgMatches) -> Result<(), Error> {
+    let age = *args.get_one::<i64>("older-than").unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let dry_run = args.get_flag("dry-run");
+    if !dry_run && !args.get_flag("yes") {
+        let pruned = reg_index::prune_yanked(
+            args.get_one::<String>("index").unwrap(),
+            now - age,
+            None,
+            true,
+            &commit_options(args),
+        )?;
+        if pruned.is_empty() {
+            println!("No yanked versions older than the cutoff.");
+            return Ok(());
+        }
+        println!("This will remove:");
+        for pkg in &pruned {
+            println!("  {}:{}", pkg.name, pkg.vers);
+        }
+        confirm_or_abort()?;
+    }
+    let pruned = reg_index::prune_yanked(
+        args.get_one::<String>("index").unwrap(),
+        now - age,
+        args.get_one::<String>("crates").map(String::as_str),
+        dry_run,
+        &commit_options(args),
+    )?;
+    let what = if dry_run { "Would remove" } else { "Removed" };
+    for pkg in &pruned {
+        println!("{} {}:{}", what, pkg.name, pkg.vers);
+    }
+    if pruned.is_empty() {
+        println!("No yanked versions older than the cutoff.");
+    }
+    Ok(())
+}
+
+/// Parses an age such as `90d` into seconds.
+fn parse_age(age: &str) -> Result<i64, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (count, unit) = age.split_at(split);
+    let count: i64 = count
+        .parse()
+        .map_err(|_| "expected a number followed by a unit, such as `90d`".to_string())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown unit `{}`, expected s, m, h, d, or w",
+                unit
+            ))
+        }
+    };
+    count
+        .checked_mul(unit)
+        .ok_or_else(|| format!("`{}` is too large", age))
+}
+
+fn schema_report(args: &ArgMatches) -> Result<(), Error> {
+    let report = reg_index::schema_report(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("package").map(String::as_str),
+        args.get_one::<String>("version").map(String::as_str),
+    )?;
+    let cargo_version = |version: reg_index::CargoVersion| match version {
+        reg_index::CargoVersion::Since(v) => format!("{}.{}", v.major, v.minor),
+        reg_index::CargoVersion::Nightly => "nightly".to_string(),
+        _ => "any".to_string(),
+    };
+    if !report.features.is_empty() {
+        let rows = report
+            .features
+            .iter()
+            .map(|(feature, entries)| {
+                vec![
+                    feature.name().to_string(),
+                    cargo_version(feature.min_cargo_version()),
+                    entries.len().to_string(),
+                ]
+            })
+            .collect();
+        print_table(&["FEATURE", "CARGO", "ENTRIES"], rows);
+        println!();
+    }
+    println!(
+        "{} entries checked, minimum Cargo version: {}",
+        report.entries,
+        cargo_version(report.min_cargo_version())
+    );
+    Ok(())
+}
+
+fn selftest(args: &ArgMatches) -> Result<(), Error> {
+    let pkg = args.get_one::<String>("package").unwrap();
+    let build = args.get_flag("build");
+    let version = reg_index::selftest(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("index-url").unwrap(),
+        pkg,
+        args.get_one::<String>("version").map(String::as_str),
+        build,
+    )?;
+    let action = if build {
+        "fetched and built"
+    } else {
+        "fetched"
+    };
+    println!("{}:{} {} successfully.", pkg, version, action);
+    Ok(())
+}
+
+fn show(args: &ArgMatches) -> Result<(), Error> {
+    let pkg = args.get_one::<String>("package").unwrap();
+    let version = args.get_one::<String>("version").unwrap();
+    let entries = reg_index::list(
+        args.get_one::<String>("index").unwrap(),
+        pkg,
+        Some(&format!("={}", version)),
+    )?;
+    // The requirement ignores build metadata, so prefer an exact match.
+    let entry = match entries
+        .iter()
+        .find(|entry| entry.vers.to_string() == *version)
+        .or_else(|| entries.first())
+    {
+        Some(entry) => entry,
+        None => bail!("Version `{}` for package `{}` not found.", version, pkg),
+    };
+    println!("{} {}", entry.name, entry.vers);
+    println!("yanked: {}", entry.yanked);
+    println!("cksum:  {}", entry.cksum);
+    println!("links:  {}", entry.links.as_deref().unwrap_or("-"));
+
+    println!();
+    let features = entry
+        .features
+        .iter()
+        .chain(entry.features2.iter().flatten());
+    let rows: Vec<Vec<String>> = features
+        .map(|(name, values)| vec![name.clone(), values.join(", ")])
+        .collect();
+    if rows.is_empty() {
+        println!("No features.");
+    } else {
+        print_table(&["FEATURE", "ENABLES"], rows);
+    }
+
+    println!();
+    let rows: Vec<Vec<String>> = entry
+        .deps
+        .iter()
+        .map(|dep| {
+            let mut name = dep.name.clone();
+            if let Some(package) = &dep.package {
+                name = format!("{} ({})", name, package);
+            }
+            if let Some(artifact) = &dep.artifact {
+                name = format!("{} (artifact: {})", name, artifact.join(", "));
+            }
+            if dep.optional {
+                name.push_str(" (optional)");
+            }
+            vec![
+                name,
+                dep.req.to_string(),
+                dep.kind.to_string(),
+                dep.target.clone().unwrap_or_else(|| "-".to_string()),
+                dep.registry
+                    .as_ref()
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        println!("No dependencies.");
+    } else {
+        print_table(&["DEPENDENCY", "REQ", "KIND", "TARGET", "REGISTRY"], rows);
+    }
+    Ok(())
+}
+
+/// Print rows with each column padded to the widest value.
+fn print_table(headings: &[&str], rows: Vec<Vec<String>>) {
+    let rows = rows.into_iter().map(|row| (row, Style::Plain)).collect();
+    print_styled_table(headings, rows, false);
+}
+
+/// Like [`print_table`], but with a style for each row.
+///
+/// If `color` is `false`, the output is the same as `print_table`.
+fn print_styled_table(headings: &[&str], rows: Vec<(Vec<String>, Style)>, color: bool) {
+    let mut widths: Vec<usize> = headings.iter().map(|heading| heading.len()).collect();
+    for (row, _) in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+    let headings = headings.iter().map(|heading| heading.to_string()).collect();
+    for (row, style) in std::iter::once((headings, Style::Bold)).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        println!("{}", style.paint(line.join("  ").trim_end(), color));
+    }
+}
+
+/// Terminal styles for human-readable output.
+#[derive(Clone, Copy)]
+enum Style {
+    Plain,
+    Bold,
+    Red,
+}
+
+impl Style {
+    /// Wraps `text` in the ANSI escape codes for the style if `color` is set.
+    fn paint(self, text: &str, color: bool) -> String {
+        let code = match self {
+            Style::Plain => return text.to_string(),
+            _ if !color => return text.to_string(),
+            Style::Bold => "1",
+            Style::Red => "31",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+/// Whether to color the output, from the `--color` flag.
+///
+/// `auto` colors if stdout is a terminal, unless the `NO_COLOR`
+/// environment variable is set.
+fn use_color(args: &ArgMatches) -> bool {
+    match args.get_one::<String>("color").unwrap().as_str() {
+        "always" => true,
+        "never" => false,
+        _ => {
+            io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty())
+        }
+    }
+}
+
+fn snapshot(args: &ArgMatches) -> Result<(), Error> {
+    let (cmd, args) = args.subcommand().unwrap();
+    let index = args.get_one::<String>("index").unwrap();
+    match cmd {
+        "create" => {
+            let name = args.get_one::<String>("name").unwrap();
+            let snapshot = reg_index::snapshot_create(index, name, &commit_options(args))?;
+            println!("Snapshot `{}` created at {}.", name, snapshot.commit);
+        }
+        "restore" => {
+            let name = args.get_one::<String>("name").unwrap();
+            if !args.get_flag("yes") {
+                let snapshot = reg_index::snapshot_list(index)?
+                    .into_iter()
+                    .find(|snapshot| snapshot.name == *name)
+                    .ok_or_else(|| format_err!("Snapshot `{}` not found.", name))?;
+                println!(
+                    "This will revert every change to the index since snapshot `{}` \
+                     at commit {}.",
+                    name, snapshot.commit
+                );
+                confirm_or_abort()?;
+            }
+            if reg_index::snapshot_restore(index, name, &commit_options(args))? {
+                println!("Index restored to snapshot `{}`.", name);
+            } else {
+                println!("Index already matches snapshot `{}`.", name);
+            }
+        }
+        "list" => {
+            for snapshot in reg_index::snapshot_list(index)? {
+                println!(
+                    "{} {} {}",
+                    snapshot.name,
+                    snapshot.commit,
+                    reg_index::format_time(snapshot.time)
+                );
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn stats(args: &ArgMatches) -> Result<(), Error> {
+    let index = args.get_one::<String>("index").unwrap();
+    let (grouping, heading) = match args.get_one::<String>("by-date").map(String::as_str) {
+        Some("day") => (reg_index::ActivityGrouping::Day, "DATE"),
+        Some("week") => (reg_index::ActivityGrouping::Week, "WEEK"),
+        Some(_) => unreachable!(),
+        None if args.get_flag("by-author") => (reg_index::ActivityGrouping::Author, "AUTHOR"),
+        None => return index_stats(args),
+    };
+    let groups = reg_index::activity(index, grouping)?;
+    let width = groups
+        .iter()
+        .map(|group| group.key.len())
+        .max()
+        .unwrap_or(0)
+        .max(heading.len());
+    let header = format!(
+        "{:width$}  {:>8}  {:>8}  {:>8}",
+        heading,
+        "ADDED",
+        "YANKED",
+        "UNYANKED",
+        width = width
+    );
+    println!("{}", Style::Bold.paint(&header, use_color(args)));
+    for group in groups {
+        println!(
+            "{:width$}  {:>8}  {:>8}  {:>8}",
+            group.key,
+            group.added,
+            group.yanked,
+            group.unyanked,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Display the counts of the whole index and its largest packages.
+fn index_stats(args: &ArgMatches) -> Result<(), Error> {
+    let stats = reg_index::stats(
+        args.get_one::<String>("index").unwrap(),
+        args.get_one::<String>("crates").map(String::as_str),
+    )?;
+    println!("crates:   {}", stats.crates);
+    println!("versions: {}", stats.versions);
+    println!("yanked:   {}", stats.yanked);
+    if let Some(size) = stats.crate_size {
+        println!("size:     {}", format_size(size));
+    }
+    let mut packages = stats.packages;
+    if packages.is_empty() {
+        return Ok(());
+    }
+    // Largest by size if it is known, otherwise by number of versions.
+    packages.sort_by(|a, b| {
+        (b.crate_size, b.versions, &a.name).cmp(&(a.crate_size, a.versions, &b.name))
+    });
+    packages.truncate(*args.get_one::<usize>("top").unwrap());
+    let rows = packages
+        .into_iter()
+        .map(|pkg| {
+            let mut row = vec![pkg.name, pkg.versions.to_string(), pkg.yanked.to_string()];
+            row.extend(pkg.crate_size.map(format_size));
+            row
+        })
+        .collect();
+    println!();
+    if stats.crate_size.is_some() {
+        print_table(&["PACKAGE", "VERSIONS", "YANKED", "SIZE"], rows);
+    } else {
+        print_table(&["PACKAGE", "VERSIONS", "YANKED"], rows);
+    }
+    Ok(())
+}
+
+/// Formats a number of bytes with a binary unit, such as `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in ["KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    if unit == "B" {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
+}
+
 fn validate(args: &ArgMatches) -> Result<(), Error> {
-    reg_index::validate(
+    let mut options = reg_index::ValidateOptions::default();
+    options.strict_yanked = args.get_flag("strict-yanked");
+    options.check_dl = args.get_flag("check-dl");
+    options.check_worktree = args.get_flag("check-worktree");
+    options.similar_names = args.get_flag("similar-names");
+    if let Some(path) = args.get_one::<String>("popular-names") {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read popular names from `{}`.", path))?;
+        options.popular_names = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+    }
+    let problems = reg_index::validate_with_options(
         args.get_one::<String>("index").unwrap(),
         args.get_one::<String>("crates").map(String::as_str),
-    )
+        &options,
+    )?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let color = use_color(args);
+    for problem in &problems {
+        println!("{}", Style::Red.paint(problem, color));
+    }
+    bail!("Found at least one error in the index.");
+}
+
+fn verify_crate(args: &ArgMatches) -> Result<(), Error> {
+    let path = args.get_one::<String>("file").unwrap();
+    let mut limits = reg_index::CrateLimits::default();
+    if let Some(max_size) = args.get_one::<u64>("max-size") {
+        limits.max_size = *max_size;
+    }
+    if let Some(max_unpacked_size) = args.get_one::<u64>("max-unpacked-size") {
+        limits.max_unpacked_size = *max_unpacked_size;
+    }
+    reg_index::verify_crate(path, &limits)?;
+    println!("`{}` is a valid .crate file.", path);
+    Ok(())
+}
+
+fn serve(args: &ArgMatches) -> Result<(), Error> {
+    let index_path = args.get_one::<String>("index").unwrap();
+    let index_url = args.get_one::<String>("index-url").unwrap();
+    let upload = args.get_one::<String>("upload").unwrap();
+    let addr = args.get_one::<String>("addr").unwrap();
+    let token = args.get_one::<String>("token").map(String::as_str);
+    let mut options = metadata_options(args)?;
+    options.commit = commit_options(args);
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Failed to listen on `{}`.", addr))?;
+    let local_addr = listener.local_addr()?;
+    if token.is_none() && !local_addr.ip().is_loopback() {
+        bail!(
+            "`--token` is required to listen on `{}`, which is not a loopback address.",
+            local_addr
+        );
+    }
+    println!("Listening on http://{}", local_addr);
+    reg_index::serve(index_path, &listener, index_url, upload, token, &options)
+}
+
+fn watch(args: &ArgMatches) -> Result<(), Error> {
+    let index_path = args.get_one::<String>("index").unwrap();
+    let index_url = args.get_one::<String>("index-url").unwrap();
+    let dir = args.get_one::<String>("dir").unwrap();
+    let upload = args.get_one::<String>("upload").map(String::as_str);
+    let interval = Duration::from_secs(*args.get_one::<u64>("interval").unwrap());
+    let mut options = metadata_options(args)?;
+    options.build_metadata = build_metadata(args);
+    options.commit = commit_options(args);
+    loop {
+        let report = reg_index::ingest_dir(index_path, index_url, dir, upload, &options)?;
+        for (path, pkg) in &report.added {
+            println!("Added {}:{} from `{}`.", pkg.name, pkg.vers, path.display());
+        }
+        for (path, reason) in &report.failed {
+            eprintln!("Failed to add `{}`: {}", path.display(), reason);
+        }
+        if args.get_flag("once") {
+            return Ok(());
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn log(args: &ArgMatches) -> Result<(), Error> {
+    let pkg = args.get_one::<String>("package").unwrap();
+    let version_req = args
+        .get_one::<String>("version")
+        .map(|req| semver::VersionReq::parse(req))
+        .transpose()?;
+    let rows: Vec<Vec<String>> =
+        reg_index::history(args.get_one::<String>("index").unwrap(), Some(pkg))?
+            .into_iter()
+            .filter(|event| {
+                version_req
+                    .as_ref()
+                    .map_or(true, |req| req.matches(&event.vers))
+            })
+            .map(|event| {
+                let action = match event.kind {
+                    reg_index::HistoryEventKind::Add => "add",
+                    reg_index::HistoryEventKind::Yank => "yank",
+                    reg_index::HistoryEventKind::Unyank => "unyank",
+                    reg_index::HistoryEventKind::Update => "update",
+                    reg_index::HistoryEventKind::Remove => "remove",
+                    _ => "change",
+                };
+                vec![
+                    reg_index::format_time(event.time),
+                    event.vers.to_string(),
+                    action.to_string(),
+                    format!("{} <{}>", event.author_name, event.author_email),
+                    event.commit,
+                ]
+            })
+            .collect();
+    if rows.is_empty() {
+        bail!("No history found for `{}`.", pkg);
+    }
+    print_table(&["DATE", "VERSION", "ACTION", "AUTHOR", "COMMIT"], rows);
+    Ok(())
+}
+
+fn who_published(args: &ArgMatches) -> Result<(), Error> {
+    let pkg = args.get_one::<String>("package").unwrap();
+    let version = args.get_one::<String>("version").unwrap();
+    let publication =
+        reg_index::who_published(args.get_one::<String>("index").unwrap(), pkg, version)?;
+    println!("Commit:    {}", publication.commit);
+    println!("Date:      {}", reg_index::format_time(publication.time));
+    println!(
+        "Author:    {} <{}>",
+        publication.author_name, publication.author_email
+    );
+    println!(
+        "Committer: {} <{}>",
+        publication.committer_name, publication.committer_email
+    );
+    for (key, value) in &publication.trailers {
+        println!("{}: {}", key, value);
+    }
+    Ok(())
 }
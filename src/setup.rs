@@ -0,0 +1,156 @@
+//! The interactive `cargo index setup` command.
+
+use anyhow::{bail, format_err, Context, Error};
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use url::Url;
+
+/// Walk through creating an index, adding it to the Cargo config, and
+/// publishing a test crate, reading answers from `input`.
+pub fn setup(input: &mut impl BufRead) -> Result<(), Error> {
+    println!("This will create a new registry index and set up Cargo to use it.");
+    let path = prompt(input, "Path to create the index at", Some("index"))?;
+    let (dl, api) = loop {
+        let dl = prompt(
+            input,
+            "URL to download .crate files from, such as \
+             https://example.com/{crate}/{crate}-{version}.crate",
+            None,
+        )?;
+        let api = prompt(
+            input,
+            "URL of the API, leave empty if there is none",
+            Some(""),
+        )?;
+        let api = if api.is_empty() { None } else { Some(api) };
+        match reg_index::check_urls(&dl, api.as_deref()) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    println!("Warning: {}", warning);
+                }
+                break (dl, api);
+            }
+            Err(e) => println!("{:#}\nPlease try again.", e),
+        }
+    };
+    reg_index::init(&path, &dl, api.as_deref())?;
+    println!("Index created at `{}`.", path);
+
+    let abs_path = fs::canonicalize(&path)
+        .with_context(|| format!("Failed to find absolute path of `{}`.", path))?;
+    let file_url = Url::from_file_path(&abs_path)
+        .map_err(|_| format_err!("Could not convert `{}` to a URL.", abs_path.display()))?;
+    let index_url = prompt(
+        input,
+        "Public URL of the index that users will use",
+        Some(file_url.as_str()),
+    )?;
+    let name = prompt(
+        input,
+        "Name of the registry in the Cargo config",
+        Some("my-registry"),
+    )?;
+    let snippet = reg_index::config_snippet(&path, &index_url, &name, false)?;
+    if confirm(input, "Add the registry to your Cargo config?")? {
+        let config_path = cargo_home()?.join("config.toml");
+        let mut contents = fs::read_to_string(&config_path).unwrap_or_default();
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        contents.push_str(&snippet);
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&config_path, contents)
+            .with_context(|| format!("Failed to write `{}`.", config_path.display()))?;
+        println!("Added `{}` to `{}`.", name, config_path.display());
+    } else {
+        println!("Add the following to your Cargo config:\n\n{}", snippet);
+    }
+
+    if confirm(input, "Publish a test crate?")? {
+        let upload = prompt(
+            input,
+            "Directory to copy .crate files to, leave empty to skip",
+            Some(""),
+        )?;
+        let upload = if upload.is_empty() {
+            None
+        } else {
+            Some(upload)
+        };
+        let tmp_dir = tempfile::tempdir()?;
+        let project = tmp_dir.path().join(format!("{}-hello", name));
+        cargo_new(&project)?;
+        let pkg = reg_index::add(
+            &path,
+            &index_url,
+            Some(&project.join("Cargo.toml")),
+            upload.as_deref(),
+            None,
+        )?;
+        println!("{}:{} successfully added!", pkg.name, pkg.vers);
+    }
+    println!("Setup complete.");
+    Ok(())
+}
+
+/// Ask a question, returning the trimmed answer or the default if empty.
+fn prompt(
+    input: &mut impl BufRead,
+    question: &str,
+    default: Option<&str>,
+) -> Result<String, Error> {
+    loop {
+        match default {
+            Some("") | None => print!("{}: ", question),
+            Some(default) => print!("{} [{}]: ", question, default),
+        }
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            bail!("Unexpected end of input.");
+        }
+        let answer = line.trim();
+        match (answer.is_empty(), default) {
+            (false, _) => return Ok(answer.to_string()),
+            (true, Some(default)) => return Ok(default.to_string()),
+            (true, None) => println!("A value is required."),
+        }
+    }
+}
+
+/// Ask a yes/no question, defaulting to no.
+pub fn confirm(input: &mut impl BufRead, question: &str) -> Result<bool, Error> {
+    let answer = prompt(input, &format!("{} [y/N]", question), Some(""))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn cargo_home() -> Result<PathBuf, Error> {
+    if let Some(home) = env::var_os("CARGO_HOME") {
+        return Ok(PathBuf::from(home));
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| Path::new(&home).join(".cargo"))
+        .ok_or_else(|| format_err!("Could not find the Cargo home directory."))
+}
+
+fn cargo_new(path: &Path) -> Result<(), Error> {
+    let status = Command::new("cargo")
+        .args(["new", "--lib", "--vcs=none", "--quiet"])
+        .arg(path)
+        .status()
+        .with_context(|| "Could not run `cargo new`.")?;
+    if !status.success() {
+        bail!("`cargo new` failed to run.");
+    }
+    Ok(())
+}
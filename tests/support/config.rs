@@ -1,30 +1,22 @@
-use super::{root, PathExt, TestIndex};
-use std::fs;
+use super::{root, TestIndex};
 
-pub struct CargoConfig {
-    result: Vec<String>,
-}
+/// A [`reg_index_testkit::CargoConfig`] written to the root of the test.
+pub struct CargoConfig(reg_index_testkit::CargoConfig);
 
 impl CargoConfig {
     pub fn new() -> CargoConfig {
-        CargoConfig { result: Vec::new() }
+        CargoConfig(reg_index_testkit::CargoConfig::new())
     }
 
-    pub fn alt(mut self, index: &TestIndex) -> CargoConfig {
-        self.result.push(format!(
-            r#"
-            [registries.myalt]
-            index = '{}'
-        "#,
+    pub fn alt(self, index: &TestIndex) -> CargoConfig {
+        CargoConfig(self.0.raw(&format!(
+            "[registries.myalt]\nindex = '{}'\n",
             index.index_url
-        ));
-        self
+        )))
     }
 
     pub fn build(self) {
-        let dot_cargo = root().join(".cargo");
-        assert!(!dot_cargo.exists());
-        dot_cargo.mkdir_p();
-        fs::write(&dot_cargo.join("config"), self.result.join("")).unwrap();
+        assert!(!root().join(".cargo").exists());
+        self.0.write(root());
     }
 }
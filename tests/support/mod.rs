@@ -5,8 +5,10 @@ use std::{
     cell::Cell,
     env,
     ffi::{OsStr, OsString},
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, Stdio},
     str,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -36,7 +38,7 @@ thread_local!(static TASK_ID: usize = NEXT_ID.fetch_add(1, Ordering::SeqCst));
 
 fn init() {
     static GLOBAL_INIT: Once = Once::new();
-    thread_local!(static LOCAL_INIT: Cell<bool> = Cell::new(false));
+    thread_local!(static LOCAL_INIT: Cell<bool> = const { Cell::new(false) });
     GLOBAL_INIT.call_once(|| {
         global_root().mkdir_p();
         // Appveyor runs without git user/email configured.
@@ -67,7 +69,7 @@ fn global_root() -> PathBuf {
 /// The root directory for the current test.
 pub fn root() -> PathBuf {
     init();
-    global_root().join(&TASK_ID.with(|my_id| format!("t{}", my_id)))
+    global_root().join(TASK_ID.with(|my_id| format!("t{}", my_id)))
 }
 
 /// A builder for constructing and running a `cargo index` command and
@@ -76,6 +78,8 @@ pub struct TestBuilder {
     ran: bool,
     args: Vec<OsString>,
     cwd: Option<PathBuf>,
+    env: Vec<(OsString, OsString)>,
+    stdin: Option<String>,
     status: i32,
     expected_stderr: Option<String>,
     expected_stderr_contains: Option<String>,
@@ -127,16 +131,37 @@ impl TestBuilder {
         self
     }
 
+    pub fn env(&mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> &mut Self {
+        self.env
+            .push((key.as_ref().to_os_string(), value.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn stdin(&mut self, input: impl ToString) -> &mut Self {
+        self.stdin = Some(input.to_string());
+        self
+    }
+
     pub fn run(&mut self) -> (String, String) {
         self.ran = true;
         let mut cmd = Command::new(env!("CARGO_BIN_EXE_cargo-index"));
         if let Some(cwd) = &self.cwd {
             cmd.current_dir(cwd);
         }
-        let output = cmd
-            .args(&self.args)
-            .output()
-            .expect("Failed to launch cargo-index.");
+        cmd.args(&self.args)
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = cmd.spawn().expect("Failed to launch cargo-index.");
+        let input = self.stdin.take().unwrap_or_default();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
         let stdout = String::from_utf8(output.stdout).unwrap();
         let stderr = String::from_utf8(output.stderr).unwrap();
         if output.status.code() != Some(self.status) {
@@ -171,6 +196,7 @@ impl Drop for TestBuilder {
 pub struct IndexBuilder {
     name: String,
     api: bool,
+    sparse: bool,
 }
 
 impl IndexBuilder {
@@ -178,6 +204,7 @@ impl IndexBuilder {
         IndexBuilder {
             name: "registry".to_string(),
             api: true,
+            sparse: false,
         }
     }
     pub fn name(mut self, name: &str) -> Self {
@@ -188,8 +215,12 @@ impl IndexBuilder {
         self.api = api;
         self
     }
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
     pub fn build(self) -> TestIndex {
-        TestIndex::new(&self.name, self.api)
+        TestIndex::new(&self.name, self.api, self.sparse)
     }
 }
 
@@ -204,12 +235,13 @@ pub struct TestIndex {
     pub dl_path: PathBuf,
     pub dl_pattern_path: PathBuf,
     pub dl_pattern_url: String,
+    #[allow(dead_code)]
     pub api_path: PathBuf,
     pub api_url: String,
 }
 
 impl TestIndex {
-    pub fn new(name: &str, api: bool) -> TestIndex {
+    pub fn new(name: &str, api: bool, sparse: bool) -> TestIndex {
         let base = root().join(name);
         let index_path = base.join("index");
         let index_url = Url::from_file_path(&index_path).unwrap().to_string();
@@ -225,13 +257,16 @@ impl TestIndex {
         api_path.mkdir_p();
         proc.arg("--index")
             .arg(&index_path)
-            .arg(&format!("--dl={}", dl_pattern_url));
+            .arg(format!("--dl={}", dl_pattern_url));
         if api {
-            proc.arg(&format!("--api={}", api_url));
+            proc.arg(format!("--api={}", api_url));
+        }
+        if sparse {
+            proc.arg("--protocol=sparse");
         }
         proc.run();
         assert!(index_path.exists());
-        assert!(index_path.join(".git").exists());
+        assert_eq!(index_path.join(".git").exists(), !sparse);
 
         TestIndex {
             index_path,
@@ -250,6 +285,26 @@ impl TestIndex {
         pkg.index_add(self);
         pkg
     }
+
+    /// Number of commits in the index repository.
+    pub fn commit_count(&self) -> usize {
+        commit_count(&self.index_path)
+    }
+}
+
+/// Number of commits in the git repository at `path`.
+pub fn commit_count(path: impl AsRef<Path>) -> usize {
+    let output = Command::new("git")
+        .args(["rev-list", "--count", "HEAD"])
+        .current_dir(path)
+        .output()
+        .expect("Failed to run git.");
+    assert!(output.status.success());
+    str::from_utf8(&output.stdout)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap()
 }
 
 /// Create a `TestBuilder` for running `cargo index`.
@@ -258,16 +313,101 @@ pub fn cargo_index(cmd: &str) -> TestBuilder {
         ran: false,
         args: vec![OsString::from("index"), OsString::from(cmd)],
         cwd: None,
+        env: Vec::new(),
+        stdin: None,
         status: 0,
         expected_stderr: None,
         expected_stderr_contains: None,
     }
 }
 
+/// A running `cargo index serve`, which is stopped when dropped.
+pub struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    /// Start `cargo index serve` on a free port with the given arguments.
+    pub fn start(index: &TestIndex, args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_cargo-index"))
+            .args(["index", "serve", "--addr=127.0.0.1:0"])
+            .arg("--index")
+            .arg(&index.index_path)
+            .arg("--index-url")
+            .arg(&index.index_url)
+            .arg("--upload")
+            .arg(&index.dl_pattern_path)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to launch cargo-index.");
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("Listening on http://")
+            .unwrap_or_else(|| panic!("unexpected output {:?}", line))
+            .to_string();
+        Server { child, addr }
+    }
+
+    /// Make a request, returning the status code and body.
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: &[u8],
+    ) -> (u32, Vec<u8>) {
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            self.addr,
+            body.len()
+        )
+        .into_bytes();
+        if let Some(token) = token {
+            request.extend(format!("Authorization: {}\r\n", token).bytes());
+        }
+        request.extend(b"\r\n");
+        request.extend(body);
+        self.send(&request)
+    }
+
+    /// Open a connection to the server.
+    pub fn connect(&self) -> TcpStream {
+        TcpStream::connect(&self.addr).unwrap()
+    }
+
+    /// Send the bytes of a request as is, returning the status code and
+    /// body.
+    pub fn send(&self, request: &[u8]) -> (u32, Vec<u8>) {
+        let mut stream = self.connect();
+        stream.write_all(request).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = str::from_utf8(&response[..split]).unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[split + 4..].to_vec())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
 pub fn cargo_package(path: impl AsRef<Path>) {
     let path = path.as_ref();
     let output = Command::new("cargo")
-        .args(&["package", "--allow-dirty"])
+        .args(["package", "--allow-dirty"])
         .current_dir(path)
         .output()
         .unwrap_or_else(|e| panic!("Failed to run `cargo package`: {}", e));
@@ -1,9 +1,11 @@
 mod support;
 use self::support::{
-    cargo_index, init_index, matches, package, validate, CargoConfig, IndexBuilder,
+    cargo_index, commit_count, init_index, matches, package, validate, CargoConfig, IndexBuilder,
+    Server,
 };
-use reg_index::IndexPackage;
+use reg_index::{DependencyKind, IndexPackage, MetadataOptions};
 use std::fs;
+use std::io::{Read, Write};
 use std::path::Path;
 
 #[test]
@@ -29,6 +31,41 @@ fn test_init_api() {
     validate(&index, false);
 }
 
+#[test]
+fn test_init_auth_required() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("index");
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--auth-required")
+        .run();
+    assert_eq!(
+        fs::read_to_string(path.join("config.json")).unwrap(),
+        "{\n  \"dl\": \"https://example.com/{crate}/{version}\",\n  \"auth-required\": true\n}"
+    );
+    // It is part of the initial commit.
+    assert_eq!(commit_count(&path), 1);
+    let mut config = reg_index::load_config(&path).unwrap();
+    assert!(config.auth_required);
+    config.auth_required = false;
+    reg_index::save_config(&path, &config, &reg_index::CommitOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(path.join("config.json")).unwrap(),
+        "{\n  \"dl\": \"https://example.com/{crate}/{version}\"\n}"
+    );
+
+    let sparse = tmp_dir.path().join("sparse");
+    cargo_index("init")
+        .index(&sparse)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--protocol=sparse")
+        .arg("--auth-required")
+        .run();
+    assert!(reg_index::load_config(&sparse).unwrap().auth_required);
+    assert!(!sparse.join(".git").exists());
+}
+
 #[test]
 fn test_init_bad_path() {
     let tmp_dir = tempfile::tempdir().unwrap();
@@ -39,6 +76,55 @@ fn test_init_bad_path() {
         .run();
 }
 
+#[test]
+fn test_init_urls() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("index");
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=example.com/{crate}")
+        .with_status(1)
+        .with_stderr(
+            "Error: `--dl` value `example.com/{crate}` is not a valid absolute URL: \
+             relative URL without a base\n\
+             Use `--force` to create the index anyway.",
+        )
+        .run();
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{vers}")
+        .with_status(1)
+        .with_stderr(
+            "Error: Unknown marker `{vers}` in `--dl` URL `https://example.com/{crate}/{vers}`, \
+             supported markers are: {crate}, {version}, {prefix}, {lowerprefix}, {sha256-checksum}\n\
+             Use `--force` to create the index anyway.",
+        )
+        .run();
+    assert!(!path.exists());
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=http://example.com/api/v1/crates/download")
+        .arg("--api=https://example.com/api/v1")
+        .with_stderr(
+            "Warning: The `--dl` URL `http://example.com/api/v1/crates/download` ends with \
+             `/download` but has no markers, Cargo will append `/{crate}/{version}/download` to it.\n\
+             Warning: The `--dl` URL `http://example.com/api/v1/crates/download` uses `http`, \
+             consider using `https`.\n\
+             Warning: The `--api` URL `https://example.com/api/v1` should not include the \
+             `/api/v1` path, Cargo will append `/api/v1` to it.",
+        )
+        .run();
+    assert!(path.join("config.json").exists());
+    let forced = tmp_dir.path().join("forced");
+    cargo_index("init")
+        .index(&forced)
+        .arg("--dl=https://example.com/{crate}/{vers}")
+        .arg("--force")
+        .with_stderr_contains("Warning: Unknown marker `{vers}`")
+        .run();
+    assert!(forced.join("config.json").exists());
+}
+
 #[test]
 fn test_metadata() {
     let foo_pkg = package("foo", "0.1.0").build();
@@ -124,6 +210,123 @@ fn test_add_crate() {
     validate(&index, true);
 }
 
+#[test]
+fn test_upload_http() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Accepts one request, answering with `status`, and returns the request
+    /// line, headers, and body.
+    fn accept(
+        listener: TcpListener,
+        status: &'static str,
+    ) -> std::thread::JoinHandle<(String, Vec<u8>)> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            (head, body)
+        })
+    }
+
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    let krate = foo_pkg.join("target/package/foo-0.1.0.crate");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upload = format!("http://{}/crates/{{crate}}", listener.local_addr().unwrap());
+    let server = accept(listener, "403 Forbidden");
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url("https://example.com")
+        .arg("--crate")
+        .arg(&krate)
+        .arg("--upload")
+        .arg(&upload)
+        .with_status(1)
+        .with_stderr_contains("Caused by: `curl` failed:")
+        .run();
+    server.join().unwrap();
+    assert!(!index.index_path.join("3/f/foo").exists());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upload = format!("http://{}/crates/{{crate}}", listener.local_addr().unwrap());
+    let server = accept(listener, "201 Created");
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url("https://example.com")
+        .arg("--crate")
+        .arg(&krate)
+        .arg("--upload")
+        .arg(&upload)
+        .env(reg_index::UPLOAD_AUTHORIZATION_ENV, "Bearer secret")
+        .run();
+    let (head, body) = server.join().unwrap();
+    assert!(head.starts_with("PUT /crates/foo/foo-0.1.0.crate HTTP/1.1\r\n"));
+    assert!(head.contains("Authorization: Bearer secret\r\n"));
+    assert_eq!(body, fs::read(&krate).unwrap());
+    assert!(index.index_path.join("3/f/foo").exists());
+}
+
+#[test]
+fn test_upload_markers() {
+    let index = init_index();
+    let pkg = package("Bar2", "0.1.0").build();
+    pkg.cargo_package();
+    let krate = pkg.join("target/package/Bar2-0.1.0.crate");
+    let pattern = index
+        .dl_path
+        .join("{prefix}/{lowerprefix}/{crate}/{sha256-checksum}");
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url("https://example.com")
+        .arg("--crate")
+        .arg(krate)
+        .arg("--upload")
+        .arg(&pattern)
+        .run();
+    let config = index.index_path.join("config.json");
+    let dl = index.dl_pattern_url.replace(
+        "/{crate}/{crate}-",
+        "/{prefix}/{lowerprefix}/{crate}/{sha256-checksum}/{crate}-",
+    );
+    fs::write(&config, format!("{{\"dl\": \"{}\"}}", dl)).unwrap();
+    let entry = fs::read_to_string(index.index_path.join("ba/r2/bar2")).unwrap();
+    let entry: IndexPackage = serde_json::from_str(&entry).unwrap();
+    assert!(index
+        .dl_path
+        .join("Ba/r2/ba/r2/Bar2")
+        .join(&entry.cksum)
+        .join("Bar2-0.1.0.crate")
+        .exists());
+    cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--crates")
+        .arg(&pattern)
+        .run();
+}
+
 #[test]
 fn test_add_errors() {
     let index = init_index();
@@ -164,6 +367,26 @@ fn test_add_force() {
     );
 }
 
+#[test]
+fn test_add_appends() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    // Existing lines should be left untouched, even if they are not
+    // formatted the way cargo-index would write them.
+    let path = index.index_path.join("3/f/foo");
+    let existing = fs::read_to_string(&path).unwrap().replace(",", ", ");
+    fs::write(&path, existing.trim_end()).unwrap();
+    index.add_package("foo", "0.1.1");
+    let contents = fs::read_to_string(&path).unwrap();
+    let mut lines = contents.lines();
+    assert_eq!(lines.next().unwrap(), existing.trim_end());
+    matches(
+        lines.next().unwrap(),
+        "{\"name\":\"foo\",\"vers\":\"0.1.1\",\"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":false,\"links\":null}",
+    );
+    assert!(lines.next().is_none());
+}
+
 #[test]
 fn test_add_renamed() {
     let index = init_index();
@@ -194,6 +417,85 @@ fn test_add_renamed() {
     validate(&index, true);
 }
 
+#[test]
+fn test_rdeps() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    index.add_package("bar", "0.1.0");
+    index.add_package("bar", "1.0.0");
+    package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            baralt = { version = "0.1", package = "bar", registry = "myalt" }
+        "#,
+        )
+        .build()
+        .index_add(&index);
+    package("baz", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "baz"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "1.0", registry = "myalt", optional = true }
+        "#,
+        )
+        .build()
+        .index_add(&index);
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=baz")
+        .arg("--version=0.1.0")
+        .run();
+
+    let (stdout, _) = cargo_index("rdeps")
+        .index(&index.index_path)
+        .arg("-p=bar")
+        .run();
+    assert_eq!(
+        stdout,
+        "NAME          VERSION  REQ   KIND\n\
+         baz (yanked)  0.1.0    ^1.0  normal (optional)\n\
+         foo           0.1.0    ^0.1  normal\n"
+    );
+    let (stdout, _) = cargo_index("rdeps")
+        .index(&index.index_path)
+        .arg("-p=bar")
+        .arg("--version=^0.1")
+        .run();
+    assert_eq!(
+        stdout,
+        "NAME  VERSION  REQ   KIND\nfoo   0.1.0    ^0.1  normal\n"
+    );
+    let (stdout, _) = cargo_index("rdeps")
+        .index(&index.index_path)
+        .arg("-p=bar")
+        .arg("--version=^1")
+        .arg("--no-yanked")
+        .run();
+    assert_eq!(stdout, "No entries depend on `bar`.\n");
+    cargo_index("rdeps")
+        .index(&index.index_path)
+        .arg("-p=bar")
+        .arg("--version=^2")
+        .with_status(1)
+        .with_stderr("Error: No versions of `bar` match version `^2`.")
+        .run();
+    cargo_index("rdeps")
+        .index(&index.index_path)
+        .arg("-p=qux")
+        .with_status(1)
+        .with_stderr("Error: Package `qux` is not in the index.")
+        .run();
+}
+
 #[test]
 fn test_add_alt_registry() {
     let index = init_index();
@@ -228,6 +530,209 @@ fn test_add_alt_registry() {
     validate(&index, true);
 }
 
+#[test]
+fn test_same_registry_sparse() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    let (stdout, _stderr) = cargo_index("metadata")
+        .index_url(&format!("sparse+{}/", alt_index.index_url))
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .run();
+    let reg_pkg: IndexPackage = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(reg_pkg.deps.len(), 1);
+    assert!(reg_pkg.deps[0].registry.is_none());
+}
+
+#[test]
+fn test_explicit_registry() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    cargo_index("add")
+        .index(&alt_index.index_path)
+        .index_url(&alt_index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--explicit-registry")
+        .run();
+    let reg_pkg: IndexPackage =
+        serde_json::from_str(&fs::read_to_string(alt_index.index_path.join("3/f/foo")).unwrap())
+            .unwrap();
+    assert_eq!(
+        reg_pkg.deps[0].registry.as_ref().unwrap().as_str(),
+        alt_index.index_url
+    );
+}
+
+#[test]
+fn test_metadata_sorted() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let _baz_pkg = alt_index.add_package("baz", "0.1.0");
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [features]
+            zed = ["baz", "bar"]
+            alpha = []
+            [dependencies]
+            baz = { version = "0.1", registry = "myalt", optional = true }
+            bar = { version = "0.1", registry = "myalt", optional = true }
+            [build-dependencies]
+            baz = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    let (stdout, _) = cargo_index("metadata")
+        .index_url(&alt_index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .run();
+    let reg_pkg: IndexPackage = serde_json::from_str(&stdout).unwrap();
+    let deps: Vec<_> = reg_pkg
+        .deps
+        .iter()
+        .map(|dep| (dep.name.as_str(), dep.kind))
+        .collect();
+    assert_eq!(
+        deps,
+        [
+            ("bar", DependencyKind::Normal),
+            ("baz", DependencyKind::Normal),
+            ("baz", DependencyKind::Build),
+        ]
+    );
+    assert_eq!(
+        reg_pkg.features.keys().collect::<Vec<_>>(),
+        ["alpha", "bar", "baz", "zed"]
+    );
+    assert_eq!(reg_pkg.features["zed"], ["bar", "baz"]);
+}
+
+#[test]
+fn test_no_dev_deps() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let index = init_index();
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let manifest = |version: &str| {
+        format!(
+            r#"
+            [package]
+            name = "foo"
+            version = "{}"
+            [dev-dependencies]
+            bar = {{ version = "0.1", registry = "myalt" }}
+            "#,
+            version
+        )
+    };
+    let foo_pkg = package("foo", "0.1.0")
+        .file("Cargo.toml", &manifest("0.1.0"))
+        .build();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--no-dev-deps")
+        .run();
+    let foo_pkg = package("foo", "0.2.0")
+        .file("Cargo.toml", &manifest("0.2.0"))
+        .build();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .run();
+    let entries = reg_index::list(&index.index_path, "foo", None).unwrap();
+    assert!(entries[0].deps.is_empty());
+    assert_eq!(entries[1].deps.len(), 1);
+    assert_eq!(entries[1].deps[0].kind, DependencyKind::Development);
+    validate(&index, false);
+}
+
+#[test]
+fn test_registry_rewrites() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    let rewrites = foo_pkg.join("rewrites");
+    fs::write(
+        &rewrites,
+        format!(
+            "# Use the internal mirrors.\n\
+             crates-io -> https://mirror.example.com/crates-io\n\
+             {}/ -> https://mirror.example.com/alt\n",
+            alt_index.index_url
+        ),
+    )
+    .unwrap();
+    let (stdout, _stderr) = cargo_index("metadata")
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--registry-rewrites")
+        .arg(&rewrites)
+        .run();
+    let reg_pkg: IndexPackage = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        reg_pkg.deps[0].registry.as_ref().unwrap().as_str(),
+        "https://mirror.example.com/alt"
+    );
+
+    fs::write(&rewrites, "crates-io https://mirror.example.com\n").unwrap();
+    cargo_index("metadata")
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--registry-rewrites")
+        .arg(&rewrites)
+        .with_status(1)
+        .with_stderr(format!(
+            "Error: Expected `FROM -> TO` on line 1 of `{}`, found `crates-io https://mirror.example.com`.",
+            rewrites.display()
+        ))
+        .run();
+}
+
 #[test]
 fn test_add_crates_io() {
     let alt_index = IndexBuilder::new().name("alt").build();
@@ -362,12 +867,422 @@ fn test_yank() {
 }
 
 #[test]
-fn test_yank_errors() {
+fn test_artifact_deps() {
     let index = init_index();
     index.add_package("foo", "0.1.0");
+    // Artifact dependencies need nightly Cargo to package, so the entry is
+    // written directly.
+    let path = index.index_path.join("3/f/foo");
+    let mut entry = IndexPackage::from_line(fs::read_to_string(&path).unwrap().trim()).unwrap();
+    let mut dep = reg_index::IndexDependency::new("bar", "^1.0".parse().unwrap());
+    dep.artifact = Some(vec!["bin".to_string()]);
+    dep.bindep_target = Some("x86_64-unknown-linux-gnu".to_string());
+    entry.deps.push(dep);
+    fs::write(&path, format!("{}\n", entry.to_line())).unwrap();
     cargo_index("yank")
         .index(&index.index_path)
-        .arg("-p=bar")
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .run();
+    matches(&stdout,
+        "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[{\"name\":\"bar\",\"req\":\"^1.0\",\"features\":[],\
+         \"optional\":false,\"default_features\":true,\"target\":null,\"kind\":\"normal\",\"registry\":null,\
+         \"package\":null,\"artifact\":[\"bin\"],\"bindep_target\":\"x86_64-unknown-linux-gnu\"}],\
+         \"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":true,\"links\":null}\n");
+}
+
+#[test]
+fn test_unknown_fields() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let path = index.index_path.join("3/f/foo");
+    let line = fs::read_to_string(&path).unwrap();
+    let line = line.replace(
+        "\"links\":null}",
+        "\"links\":null,\"rust_version\":\"1.70\"}",
+    );
+    fs::write(&path, &line).unwrap();
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .run();
+    matches(
+        &stdout,
+        "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\
+         \"yanked\":true,\"links\":null,\"rust_version\":\"1.70\"}\n",
+    );
+    validate(&index, true);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_hooks() {
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    let log = index.index_path.parent().unwrap().join("hook.log");
+    let hook = format!("echo {{action}} {{crate}} {{version}} >> {}", log.display());
+    cargo_index("add")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .arg("--hook")
+        .arg(&hook)
+        .arg("--hook")
+        .arg("exit 1")
+        .with_stderr_contains("Warning: Hook `exit 1` failed with exit status: 1.")
+        .run();
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .env("CARGO_INDEX_HOOK", &hook)
+        .run();
+    cargo_index("unyank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .arg("--hook")
+        .arg(format!(
+            "echo $CARGO_INDEX_ACTION $CARGO_INDEX_CRATE $CARGO_INDEX_VERSION >> {}",
+            log.display()
+        ))
+        .run();
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "add foo 0.1.0\nyank foo 0.1.0\nunyank foo 0.1.0\n"
+    );
+}
+
+#[test]
+fn test_webhooks() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Accepts one request, answering with `status`, and returns its body.
+    fn accept(listener: TcpListener, status: &'static str) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            body
+        })
+    }
+
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = accept(listener, "200 OK");
+    let failing = TcpListener::bind("127.0.0.1:0").unwrap();
+    let failing_url = format!("http://{}/hook", failing.local_addr().unwrap());
+    let failing_server = accept(failing, "500 Internal Server Error");
+    cargo_index("add")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .arg("--webhook")
+        .arg(&url)
+        .arg("--webhook")
+        .arg(&failing_url)
+        .with_stderr_contains(format!("Warning: Webhook `{}` failed:", failing_url))
+        .run();
+    failing_server.join().unwrap();
+    let payload: serde_json::Value = serde_json::from_slice(&server.join().unwrap()).unwrap();
+    let pkg = &reg_index::list(&index.index_path, "foo", None).unwrap()[0];
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&index.index_path)
+        .output()
+        .unwrap();
+    let head = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert_eq!(
+        payload,
+        serde_json::json!({
+            "action": "add",
+            "name": "foo",
+            "version": "0.1.0",
+            "cksum": pkg.cksum,
+            "commit": head,
+        })
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = accept(listener, "204 No Content");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .env("CARGO_INDEX_WEBHOOK", &url)
+        .run();
+    let payload: serde_json::Value = serde_json::from_slice(&server.join().unwrap()).unwrap();
+    assert_eq!(payload["action"], "yank");
+    assert_eq!(payload["cksum"], pkg.cksum.as_str());
+    assert_ne!(payload["commit"], head.as_str());
+}
+
+#[test]
+fn test_sparse() {
+    let index = IndexBuilder::new().sparse(true).build();
+    assert_eq!(
+        reg_index::index_protocol(&index.index_path),
+        reg_index::Protocol::Sparse
+    );
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    matches(&fs::read_to_string(index.index_path.join("3/f/foo")).unwrap(),
+        "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":true,\"links\":null}\n\
+         {\"name\":\"foo\",\"vers\":\"0.1.1\",\"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":false,\"links\":null}\n");
+    assert!(!index.index_path.join(".git").exists());
+    validate(&index, true);
+    cargo_index("who-published")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.1")
+        .with_status(1)
+        .with_stderr_contains("sparse index, which has no git history")
+        .run();
+}
+
+#[test]
+fn test_diff() {
+    let index = init_index();
+    let git = |dir: &Path, args: &[&str]| -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    index.add_package("foo", "0.1.0");
+    let base = git(&index.index_path, &["rev-parse", "HEAD"]);
+    let base = base.trim();
+    index.add_package("foo", "0.1.1");
+    index.add_package("bar", "0.1.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _) = cargo_index("diff")
+        .index(&index.index_path)
+        .arg("--old-rev")
+        .arg(base)
+        .run();
+    assert_eq!(
+        stdout,
+        "bar:0.1.0 added\n\
+         foo:0.1.0 yanked\n\
+         foo:0.1.1 added\n"
+    );
+    let (stdout, _) = cargo_index("diff")
+        .index(&index.index_path)
+        .arg("--old-rev=HEAD")
+        .arg("--new-rev")
+        .arg(base)
+        .run();
+    assert_eq!(
+        stdout,
+        "bar:0.1.0 removed\n\
+         foo:0.1.0 unyanked\n\
+         foo:0.1.1 removed\n"
+    );
+
+    // A mirror with a different checksum and a missing package.
+    let mirror = index.index_path.parent().unwrap().join("mirror");
+    git(
+        index.index_path.parent().unwrap(),
+        &["clone", "--quiet", "index", "mirror"],
+    );
+    let (stdout, _) = cargo_index("diff")
+        .index(&index.index_path)
+        .arg("--old-index")
+        .arg(&mirror)
+        .run();
+    assert_eq!(stdout, "No differences.\n");
+    fs::remove_file(mirror.join("3/b/bar")).unwrap();
+    let foo = fs::read_to_string(mirror.join("3/f/foo")).unwrap();
+    let cksum = &reg_index::list(&index.index_path, "foo", Some("=0.1.1")).unwrap()[0].cksum;
+    fs::write(
+        mirror.join("3/f/foo"),
+        foo.replace(cksum.as_str(), &"0".repeat(64)),
+    )
+    .unwrap();
+    let (stdout, _) = cargo_index("diff")
+        .index(&index.index_path)
+        .arg("--old-index")
+        .arg(&mirror)
+        .run();
+    assert_eq!(
+        stdout,
+        "bar:0.1.0 added\n\
+         foo:0.1.1 cksum changed\n"
+    );
+
+    cargo_index("diff")
+        .index(&index.index_path)
+        .arg("--old-rev=nope")
+        .with_status(1)
+        .with_stderr_contains("Error: Could not find revision `nope`.")
+        .run();
+}
+
+#[test]
+fn test_remote_index() {
+    let index = init_index();
+    let git = |dir: &Path, args: &[&str]| -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let base = index.index_path.parent().unwrap();
+    git(base, &["clone", "--quiet", "--bare", "index", "remote.git"]);
+    let remote = base.join("remote.git");
+    let remote_url = url::Url::from_file_path(&remote).unwrap().to_string();
+
+    let foo_pkg = package("foo", "0.1.0").build();
+    cargo_index("add")
+        .index(&remote_url)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .run();
+    cargo_index("yank")
+        .index(&remote_url)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    assert_eq!(
+        git(&remote, &["log", "--format=%s", "-2"]),
+        "Yanking crate `foo:0.1.0`\nUpdating crate `foo#0.1.0`\n"
+    );
+    assert!(git(&remote, &["show", "HEAD:3/f/foo"]).contains("\"yanked\":true"));
+    // The local index the remote was cloned from is untouched.
+    assert!(!index.index_path.join("3/f/foo").exists());
+
+    // Another writer pushes while the first attempt is running, so it
+    // starts over on a fresh clone.
+    let other = base.join("other");
+    git(base, &["clone", "--quiet", &remote_url, "other"]);
+    let mut attempts = 0;
+    reg_index::with_remote_index(&remote_url, |path| {
+        attempts += 1;
+        if attempts == 1 {
+            fs::write(other.join("a"), "").unwrap();
+            git(&other, &["add", "a"]);
+            git(
+                &other,
+                &[
+                    "-c",
+                    "user.name=Other",
+                    "-c",
+                    "user.email=other@example.com",
+                    "commit",
+                    "--quiet",
+                    "-m",
+                    "Other",
+                ],
+            );
+            git(&other, &["push", "--quiet", "origin", "HEAD"]);
+        }
+        reg_index::set_yank(path, "foo", "0.1.0", false)
+    })
+    .unwrap();
+    assert_eq!(attempts, 2);
+    assert_eq!(
+        git(&remote, &["log", "--format=%s", "-2"]),
+        "Unyanking crate `foo:0.1.0`\nOther\n"
+    );
+
+    cargo_index("yank")
+        .index(
+            url::Url::from_file_path(base.join("missing.git"))
+                .unwrap()
+                .as_str(),
+        )
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .with_status(1)
+        .with_stderr_contains("Failed to clone")
+        .run();
+}
+
+#[test]
+fn test_yank_preserves_lines() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    let path = index.index_path.join("3/f/foo");
+    // Fields from other tools are kept, even if they have a `yanked` key.
+    let original = fs::read_to_string(&path)
+        .unwrap()
+        .replace(":", ": ")
+        .replace(
+            "\"vers\": \"0.1.1\"",
+            "\"x-mirror\": {\"yanked\": false}, \"vers\": \"0.1.1\"",
+        );
+    fs::write(&path, &original).unwrap();
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.1")
+        .run();
+    let contents = fs::read_to_string(&path).unwrap();
+    let mut original_lines = original.lines();
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), original_lines.next());
+    let original_line = original_lines.next().unwrap();
+    let (before, after) = original_line.rsplit_once("\"yanked\": false").unwrap();
+    assert_eq!(
+        lines.next().unwrap(),
+        format!("{}\"yanked\": true{}", before, after)
+    );
+}
+
+#[test]
+fn test_yank_errors() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=bar")
         .arg("--version=0.1.0")
         .with_status(1)
         .with_stderr("Error: Package `bar` is not in the index.")
@@ -401,6 +1316,140 @@ fn test_yank_errors() {
         .run();
 }
 
+#[test]
+fn test_yank_matching() {
+    let index = init_index();
+    for vers in ["0.1.0", "0.2.0", "0.3.0", "0.3.5"] {
+        index.add_package("foo", vers);
+    }
+    reg_index::yank(&index.index_path, "foo", "0.2.0").unwrap();
+    let commits = || {
+        let output = std::process::Command::new("git")
+            .args(["rev-list", "--count", "HEAD"])
+            .current_dir(&index.index_path)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    let before = commits();
+    let yank = |req: &str| {
+        let mut builder = cargo_index("yank");
+        builder
+            .index(&index.index_path)
+            .arg("-p=foo")
+            .arg(format!("--version-req={}", req));
+        builder
+    };
+    let (stdout, _stderr) = yank("<0.3.5").run();
+    assert_eq!(stdout, "foo:0.1.0 yanked!\nfoo:0.3.0 yanked!\n");
+    assert_eq!(
+        commits().parse::<u32>().unwrap(),
+        before.parse::<u32>().unwrap() + 1
+    );
+    let yanked: Vec<(String, bool)> = reg_index::list(&index.index_path, "foo", None)
+        .unwrap()
+        .into_iter()
+        .map(|pkg| (pkg.vers.to_string(), pkg.yanked))
+        .collect();
+    assert_eq!(
+        yanked,
+        [
+            ("0.1.0".to_string(), true),
+            ("0.2.0".to_string(), true),
+            ("0.3.0".to_string(), true),
+            ("0.3.5".to_string(), false),
+        ]
+    );
+    yank("<0.3.5")
+        .with_status(1)
+        .with_stderr("Error: All versions of `foo` matching `<0.3.5` are already yanked.")
+        .run();
+    yank(">1")
+        .with_status(1)
+        .with_stderr("Error: Version `>1` for package `foo` not found.")
+        .run();
+    let err = reg_index::set_yank_matching(
+        &index.index_path,
+        "foo",
+        ">1",
+        true,
+        &reg_index::CommitOptions::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reg_index::IndexError>(),
+        Some(reg_index::IndexError::VersionNotFound { .. })
+    ));
+    // A partial version is not treated as a requirement.
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.3")
+        .with_status(1)
+        .with_stderr("Error: unexpected end of input while parsing minor version number")
+        .run();
+    let (stdout, _stderr) = cargo_index("unyank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version-req=<0.3")
+        .run();
+    assert_eq!(stdout, "foo:0.1.0 unyanked!\nfoo:0.2.0 unyanked!\n");
+}
+
+#[test]
+fn test_index_errors() {
+    use reg_index::IndexError;
+
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let kind = |err: anyhow::Error| err.downcast_ref::<IndexError>().cloned();
+    let yank = |name: &str, version: &str, yank: bool| {
+        reg_index::set_yank(&index.index_path, name, version, yank).unwrap_err()
+    };
+    assert_eq!(
+        kind(yank("bar", "0.1.0", true)),
+        Some(IndexError::PackageNotFound {
+            name: "bar".to_string()
+        })
+    );
+    assert_eq!(
+        kind(yank("foo", "0.2.0", true)),
+        Some(IndexError::VersionNotFound {
+            name: "foo".to_string(),
+            version: "0.2.0".to_string()
+        })
+    );
+    assert_eq!(
+        kind(yank("foo", "0.1.0", false)),
+        Some(IndexError::NotYanked {
+            name: "foo".to_string(),
+            version: "0.1.0".to_string()
+        })
+    );
+
+    let foo_pkg = package("foo", "0.1.0").build();
+    let err = reg_index::add(
+        &index.index_path,
+        &index.index_url,
+        Some(&foo_pkg.join("Cargo.toml")),
+        None,
+        None,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Package `foo` version `0.1.0` is already in the index."
+    );
+    assert_eq!(
+        kind(err),
+        Some(IndexError::VersionAlreadyExists {
+            name: "foo".to_string(),
+            version: "0.1.0".to_string()
+        })
+    );
+}
+
 #[test]
 fn test_list() {
     let index = init_index();
@@ -501,13 +1550,13 @@ fn test_add_inferred_ws_path() {
 fn test_package_args() {
     let foo_pkg = package("foo", "0.1.0").file("src/lib.rs", "asdf").build();
     cargo_index("metadata")
-        .cwd(&foo_pkg.path())
+        .cwd(foo_pkg.path())
         .index_url("https://example.com")
         .with_stderr_contains("asdf")
         .with_status(1)
         .run();
     let (stdout, _stderr) = cargo_index("metadata")
-        .cwd(&foo_pkg.path())
+        .cwd(foo_pkg.path())
         .index_url("https://example.com")
         .arg("--")
         .arg("--no-verify")
@@ -518,3 +1567,3478 @@ fn test_package_args() {
          \"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":false,\"links\":null}\n",
     );
 }
+
+#[test]
+fn test_add_from_crates() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let crates: Vec<_> = [("bar", "0.1.0"), ("foo", "0.2.0")]
+        .iter()
+        .map(|(name, version)| {
+            let pkg = package(name, version).build();
+            pkg.cargo_package();
+            pkg.join(format!("target/package/{}-{}.crate", name, version))
+        })
+        .collect();
+    let commits = index.commit_count();
+    let added = reg_index::add_from_crates(
+        &index.index_path,
+        &index.index_url,
+        &crates,
+        Some(index.dl_pattern_path.to_str().unwrap()),
+        &MetadataOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(added.len(), 2);
+    assert_eq!(index.commit_count(), commits + 1);
+    assert_eq!(
+        fs::read_to_string(index.index_path.join("3/f/foo"))
+            .unwrap()
+            .lines()
+            .count(),
+        2
+    );
+    validate(&index, true);
+
+    // A failure in the batch leaves the index untouched.
+    let baz_pkg = package("baz", "0.1.0").build();
+    baz_pkg.cargo_package();
+    let crates = [
+        baz_pkg.join("target/package/baz-0.1.0.crate"),
+        crates[0].clone(),
+    ];
+    let err = reg_index::add_from_crates(
+        &index.index_path,
+        &index.index_url,
+        &crates,
+        None,
+        &MetadataOptions::default(),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Package `bar` version `0.1.0` is already in the index."
+    );
+    assert!(!index.index_path.join("3/b/baz").exists());
+    assert_eq!(index.commit_count(), commits + 1);
+    validate(&index, true);
+
+    // A failed upload leaves both the index and the upload directory
+    // untouched.
+    let qux_pkg = package("qux", "0.1.0").build();
+    qux_pkg.cargo_package();
+    // A file in the way of the `qux` directory.
+    fs::write(index.dl_path.join("qux"), "").unwrap();
+    let crates = [
+        baz_pkg.join("target/package/baz-0.1.0.crate"),
+        qux_pkg.join("target/package/qux-0.1.0.crate"),
+    ];
+    reg_index::add_from_crates(
+        &index.index_path,
+        &index.index_url,
+        &crates,
+        Some(index.dl_pattern_path.to_str().unwrap()),
+        &MetadataOptions::default(),
+    )
+    .err()
+    .unwrap();
+    assert!(!index.dl_path.join("baz/baz-0.1.0.crate").exists());
+    assert!(!index.index_path.join("3/b/baz").exists());
+    assert_eq!(index.commit_count(), commits + 1);
+    fs::remove_file(index.dl_path.join("qux")).unwrap();
+    validate(&index, true);
+}
+
+#[test]
+fn test_add_with_options() {
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    let options = reg_index::AddOptions::default()
+        .manifest_path(foo_pkg.join("Cargo.toml"))
+        .upload(index.dl_pattern_path.to_str().unwrap());
+    let added = reg_index::add_with_options(&index.index_path, &index.index_url, &options).unwrap();
+    assert_eq!(added.name, "foo");
+    assert!(index.dl_path.join("foo/foo-0.1.0.crate").exists());
+
+    // Replace the entry from a `.crate` file with different contents.
+    fs::write(foo_pkg.join("src/lib.rs"), "pub fn foo() {}").unwrap();
+    foo_pkg.cargo_package();
+    let options =
+        reg_index::AddOptions::default().crate_file(foo_pkg.join("target/package/foo-0.1.0.crate"));
+    let err = reg_index::add_with_options(&index.index_path, &index.index_url, &options)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Package `foo` version `0.1.0` is already in the index."
+    );
+    let options = options.force(true);
+    let replaced =
+        reg_index::add_with_options(&index.index_path, &index.index_url, &options).unwrap();
+    assert_ne!(replaced.cksum, added.cksum);
+    assert_eq!(
+        reg_index::list(&index.index_path, "foo", None).unwrap()[0].cksum,
+        replaced.cksum
+    );
+
+    let options = options.package_args(["--no-verify"]);
+    assert!(reg_index::add_with_options(&index.index_path, &index.index_url, &options).is_err());
+}
+
+#[test]
+fn test_add_concurrent() {
+    // Publishing the same version at the same time adds it only once.
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    let crate_path = foo_pkg.join("target/package/foo-0.1.0.crate");
+    let barrier = std::sync::Barrier::new(4);
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    reg_index::add_from_crate(
+                        &index.index_path,
+                        &index.index_url,
+                        &crate_path,
+                        None,
+                    )
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    for result in results {
+        if let Err(e) = result {
+            assert_eq!(
+                e.to_string(),
+                "Package `foo` version `0.1.0` is already in the index."
+            );
+        }
+    }
+    assert_eq!(
+        fs::read_to_string(index.index_path.join("3/f/foo"))
+            .unwrap()
+            .lines()
+            .count(),
+        1
+    );
+    validate(&index, false);
+}
+
+#[test]
+fn test_index_handle() {
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    let mut handle = reg_index::Index::open(&index.index_path).unwrap();
+    assert_eq!(handle.protocol(), reg_index::Protocol::Git);
+    let options = reg_index::AddOptions::default()
+        .manifest_path(foo_pkg.join("Cargo.toml"))
+        .upload(index.dl_pattern_path.to_str().unwrap());
+    handle.add(&index.index_url, &options).unwrap();
+    handle.yank("foo", "0.1.0").unwrap();
+    assert!(handle.list("foo", None).unwrap()[0].yanked);
+    assert_eq!(handle.list_names().unwrap(), ["foo"]);
+    handle
+        .update_config(&Default::default(), |config| config.auth_required = true)
+        .unwrap();
+    assert!(handle.config().auth_required);
+    assert!(
+        reg_index::load_config(&index.index_path)
+            .unwrap()
+            .auth_required
+    );
+    handle.validate(None).unwrap();
+
+    // Other threads wait until the handle is dropped.
+    let (tx, rx) = std::sync::mpsc::channel();
+    let index_path = index.index_path.clone();
+    let thread = std::thread::spawn(move || {
+        tx.send(reg_index::list_names(&index_path).unwrap())
+            .unwrap()
+    });
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(500))
+        .is_err());
+    drop(handle);
+    assert_eq!(rx.recv().unwrap(), ["foo"]);
+    thread.join().unwrap();
+}
+
+#[test]
+fn test_crate_store() {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps the `.crate` files in memory.
+    #[derive(Debug, Default)]
+    struct MemoryStore(Mutex<HashMap<String, Vec<u8>>>);
+
+    impl reg_index::CrateStore for MemoryStore {
+        fn put(&self, pkg: &IndexPackage, crate_path: &Path) -> Result<(), anyhow::Error> {
+            let key = format!("{}-{}", pkg.name, pkg.vers);
+            self.0.lock().unwrap().insert(key, fs::read(crate_path)?);
+            Ok(())
+        }
+
+        fn get(&self, pkg: &IndexPackage, dest: &Path) -> Result<(), anyhow::Error> {
+            let key = format!("{}-{}", pkg.name, pkg.vers);
+            match self.0.lock().unwrap().get(&key) {
+                Some(data) => Ok(fs::write(dest, data)?),
+                None => anyhow::bail!("`{}` is not stored.", key),
+            }
+        }
+    }
+
+    let index = init_index();
+    let store = Arc::new(MemoryStore::default());
+    let foo_pkg = package("foo", "0.1.0").build();
+    let options = reg_index::AddOptions::default()
+        .manifest_path(foo_pkg.join("Cargo.toml"))
+        .store(store.clone());
+    reg_index::add_with_options(&index.index_path, &index.index_url, &options).unwrap();
+    assert!(store.0.lock().unwrap().contains_key("foo-0.1.0"));
+    assert!(!index.dl_path.join("foo").exists());
+    let problems = reg_index::validate_store(&index.index_path, &*store).unwrap();
+    assert!(problems.is_empty(), "{:?}", problems);
+
+    store.0.lock().unwrap().clear();
+    let problems = reg_index::validate_store(&index.index_path, &*store).unwrap();
+    assert_eq!(problems, ["`foo-0.1.0` is not stored."]);
+
+    let options = options.upload(index.dl_pattern_path.to_str().unwrap());
+    let err = reg_index::add_with_options(&index.index_path, &index.index_url, &options)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Only one of `upload` and `store` can be set."
+    );
+}
+
+#[test]
+fn test_add_crates_dir() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let zzz_pkg = alt_index.add_package("zzz", "0.1.0");
+    // `aaa` sorts first by name, but depends on `zzz`.
+    let aaa_pkg = package("aaa", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "aaa"
+            version = "0.1.0"
+            [dependencies]
+            zzz = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    aaa_pkg.cargo_package();
+    let dir = alt_index.index_path.parent().unwrap().join("crates");
+    fs::create_dir_all(&dir).unwrap();
+    for (pkg, name) in [(&zzz_pkg, "zzz-0.1.0"), (&aaa_pkg, "aaa-0.1.0")] {
+        fs::copy(
+            pkg.join(format!("target/package/{}.crate", name)),
+            dir.join(format!("{}.crate", name)),
+        )
+        .unwrap();
+    }
+    fs::write(dir.join("README"), "not a crate").unwrap();
+
+    for (index, one_commit) in [
+        (init_index(), false),
+        (IndexBuilder::new().name("one").build(), true),
+    ] {
+        let commits = index.commit_count();
+        let mut add = cargo_index("add");
+        add.index(&index.index_path)
+            .index_url(&alt_index.index_url)
+            .arg("--crates-dir")
+            .arg(&dir);
+        if one_commit {
+            add.arg("--one-commit");
+        }
+        let (stdout, _stderr) = add.run();
+        assert_eq!(
+            stdout,
+            "zzz:0.1.0 successfully added!\naaa:0.1.0 successfully added!\n"
+        );
+        assert_eq!(
+            index.commit_count(),
+            commits + if one_commit { 1 } else { 2 }
+        );
+        validate(&index, false);
+    }
+}
+
+#[test]
+fn test_names() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    index.add_package("bar", "0.1.0");
+    index.add_package("a", "0.1.0");
+    let (stdout, _stderr) = cargo_index("names").index(&index.index_path).run();
+    assert_eq!(stdout, "a\nbar\nfoo\n");
+}
+
+#[test]
+fn test_export() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    index.add_package("bar", "0.1.0");
+    let out = index.index_path.parent().unwrap().join("dump.jsonl");
+    let (stdout, _stderr) = cargo_index("export")
+        .index(&index.index_path)
+        .arg("--format=jsonl")
+        .arg("--out")
+        .arg(&out)
+        .run();
+    assert_eq!(
+        stdout,
+        format!("Exported 3 entries to `{}`.\n", out.display())
+    );
+    let dump = fs::read_to_string(&out).unwrap();
+    assert_eq!(dump.lines().count(), 3);
+    for line in dump.lines() {
+        serde_json::from_str::<IndexPackage>(line).unwrap();
+    }
+
+    let (stdout, _stderr) = cargo_index("export")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.1")
+        .run();
+    matches(&stdout,
+        "{\"name\":\"foo\",\"vers\":\"0.1.1\",\"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":false,\"links\":null}\n");
+}
+
+#[test]
+fn test_export_csv() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            links = "foo,sys"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+            [build-dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .file("build.rs", "fn main() {}")
+        .build();
+    foo_pkg.index_add(&index);
+    let (stdout, _stderr) = cargo_index("export")
+        .index(&index.index_path)
+        .arg("--format=csv")
+        .run();
+    matches(
+        &stdout,
+        "name,vers,cksum,yanked,links,deps,dev_deps,build_deps\n\
+         bar,0.1.0,<CKSUM>,false,,0,0,0\n\
+         foo,0.1.0,<CKSUM>,false,\"foo,sys\",1,0,1\n",
+    );
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_export_parquet() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    foo_pkg.index_add(&index);
+    let base = index.index_path.parent().unwrap();
+    for (format, expected) in [("parquet", 2), ("parquet-deps", 1)] {
+        let out = base.join(format!("{}.parquet", format));
+        let (stdout, _stderr) = cargo_index("export")
+            .index(&index.index_path)
+            .arg(format!("--format={}", format))
+            .arg("--out")
+            .arg(&out)
+            .run();
+        assert_eq!(
+            stdout,
+            format!("Exported {} rows to `{}`.\n", expected, out.display())
+        );
+        let contents = fs::read(&out).unwrap();
+        assert!(contents.starts_with(b"PAR1"));
+        assert!(contents.ends_with(b"PAR1"));
+    }
+    cargo_index("export")
+        .index(&index.index_path)
+        .arg("--format=parquet")
+        .with_status(1)
+        .with_stderr("Error: --out is required for the `parquet` format.")
+        .run();
+}
+
+#[test]
+fn test_import() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    foo_pkg.index_add(&index);
+    let dump = index.index_path.parent().unwrap().join("dump.jsonl");
+    cargo_index("export")
+        .index(&index.index_path)
+        .arg("--out")
+        .arg(&dump)
+        .run();
+
+    // Put `foo` first to check that the dependency is resolved within the
+    // dump regardless of order.
+    let contents = fs::read_to_string(&dump).unwrap();
+    let mut lines: Vec<_> = contents.lines().collect();
+    lines.sort_by_key(|line| !line.contains("\"foo\""));
+    fs::write(&dump, lines.join("\n")).unwrap();
+
+    let new_index = IndexBuilder::new().name("new").build();
+    let (stdout, _stderr) = cargo_index("import")
+        .index(&new_index.index_path)
+        .arg(&dump)
+        .run();
+    assert_eq!(stdout, "Imported 2 entries.\n");
+    assert_eq!(new_index.commit_count(), 2);
+    for path in ["3/b/bar", "3/f/foo"] {
+        assert_eq!(
+            fs::read_to_string(new_index.index_path.join(path)).unwrap(),
+            fs::read_to_string(index.index_path.join(path)).unwrap()
+        );
+    }
+    validate(&new_index, false);
+
+    // Importing again fails without changing anything.
+    cargo_index("import")
+        .index(&new_index.index_path)
+        .arg(&dump)
+        .with_status(1)
+        .with_stderr("Error: Package `foo` version `0.1.0` is already in the index.")
+        .run();
+    assert_eq!(new_index.commit_count(), 2);
+
+    // A dependency missing from both the index and the dump.
+    let only_foo = index.index_path.parent().unwrap().join("foo.jsonl");
+    fs::write(&only_foo, lines[0]).unwrap();
+    let other_index = IndexBuilder::new().name("other").build();
+    cargo_index("import")
+        .index(&other_index.index_path)
+        .arg(&only_foo)
+        .with_status(1)
+        .with_stderr("Error: Package `foo` dependency `bar:^0.1` not found in index.")
+        .run();
+    assert!(!other_index.index_path.join("3/f/foo").exists());
+
+    // Names that can't be turned into a path in the index are rejected.
+    for (name, error) in [
+        ("", "Error: The package name cannot be empty."),
+        ("aéb", "Error: Invalid character `é` in package name: `aéb`"),
+    ] {
+        let bad = index.index_path.parent().unwrap().join("bad.jsonl");
+        fs::write(
+            &bad,
+            lines[0].replace("\"name\":\"foo\"", &format!("\"name\":\"{}\"", name)),
+        )
+        .unwrap();
+        cargo_index("import")
+            .index(&other_index.index_path)
+            .arg(&bad)
+            .with_status(1)
+            .with_stderr(error)
+            .run();
+    }
+}
+
+#[test]
+fn test_list_prerelease() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "1.0.0-beta.1");
+    index.add_package("foo", "1.0.0");
+    let list = |args: &[&str]| -> Vec<String> {
+        let mut builder = cargo_index("list");
+        builder.index(&index.index_path).arg("-p=foo");
+        for arg in args {
+            builder.arg(arg);
+        }
+        let (stdout, _stderr) = builder.run();
+        stdout
+            .lines()
+            .map(|line| {
+                let pkg: IndexPackage = serde_json::from_str(line).unwrap();
+                pkg.vers.to_string()
+            })
+            .collect()
+    };
+    assert_eq!(list(&[]), ["0.1.0", "1.0.0"]);
+    assert_eq!(
+        list(&["--include-prerelease"]),
+        ["0.1.0", "1.0.0-beta.1", "1.0.0"]
+    );
+    assert_eq!(list(&["--version=^1.0"]), ["1.0.0"]);
+    assert_eq!(
+        list(&["--version=^1.0", "--include-prerelease"]),
+        ["1.0.0-beta.1", "1.0.0"]
+    );
+    assert_eq!(list(&["--version=>=1.0.0-beta"]), ["1.0.0-beta.1", "1.0.0"]);
+    assert_eq!(
+        list(&["--version=>=1.0.0-beta", "--exclude-prerelease"]),
+        ["1.0.0"]
+    );
+}
+
+#[test]
+fn test_list_with_dates() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--with-dates")
+        .run();
+    let time = "\"[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}Z\"";
+    let re = regex::Regex::new(&format!(
+        "^\\{{\"name\":\"foo\",\"vers\":\"0.1.0\",.*\"yanked\":true,\"links\":null,\
+         \"added\":{time},\"yank_changed\":{time}\\}}\n\
+         \\{{\"name\":\"foo\",\"vers\":\"0.1.1\",.*\"yanked\":false,\"links\":null,\
+         \"added\":{time},\"yank_changed\":null\\}}\n$",
+        time = time
+    ))
+    .unwrap();
+    assert!(re.is_match(&stdout), "{}", stdout);
+}
+
+#[test]
+fn test_stats_by_date() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    index.add_package("bar", "0.1.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _stderr) = cargo_index("stats")
+        .index(&index.index_path)
+        .arg("--by-date=day")
+        .run();
+    let re = regex::Regex::new(
+        "^DATE           ADDED    YANKED  UNYANKED\n\
+         [0-9]{4}-[0-9]{2}-[0-9]{2}         3         1         0\n$",
+    )
+    .unwrap();
+    assert!(re.is_match(&stdout), "{}", stdout);
+
+    let (stdout, _stderr) = cargo_index("stats")
+        .index(&index.index_path)
+        .arg("--by-author")
+        .run();
+    assert_eq!(
+        stdout,
+        "AUTHOR                              ADDED    YANKED  UNYANKED\n\
+         Index Admin <admin@example.com>         3         1         0\n"
+    );
+
+    cargo_index("stats")
+        .index(&index.index_path)
+        .arg("--by-author")
+        .arg("--top=1")
+        .with_status(2)
+        .run();
+}
+
+#[test]
+fn test_log() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    index.add_package("bar", "0.1.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _stderr) = cargo_index("log")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .run();
+    let re = regex::Regex::new(
+        "^DATE                  VERSION  ACTION  AUTHOR                           COMMIT\n\
+         [0-9-]{10}T[0-9:]{8}Z  0\\.1\\.0    add     Index Admin <admin@example.com>  [0-9a-f]{40}\n\
+         [0-9-]{10}T[0-9:]{8}Z  0\\.1\\.1    add     Index Admin <admin@example.com>  [0-9a-f]{40}\n\
+         [0-9-]{10}T[0-9:]{8}Z  0\\.1\\.0    yank    Index Admin <admin@example.com>  [0-9a-f]{40}\n$",
+    )
+    .unwrap();
+    assert!(re.is_match(&stdout), "{}", stdout);
+
+    let (stdout, _stderr) = cargo_index("log")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.1")
+        .run();
+    assert_eq!(stdout.lines().count(), 2);
+    cargo_index("log")
+        .index(&index.index_path)
+        .arg("-p=baz")
+        .with_status(1)
+        .with_stderr("Error: No history found for `baz`.")
+        .run();
+}
+
+#[test]
+fn test_stats() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    index.add_package("bar", "0.1.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (stdout, _stderr) = cargo_index("stats").index(&index.index_path).run();
+    assert_eq!(
+        stdout,
+        "crates:   2\n\
+         versions: 3\n\
+         yanked:   1\n\
+         \n\
+         PACKAGE  VERSIONS  YANKED\n\
+         foo      2         1\n\
+         bar      1         0\n"
+    );
+
+    let crates = index.dl_pattern_path.to_str().unwrap();
+    let stats = reg_index::stats(&index.index_path, Some(crates)).unwrap();
+    let size = |name: &str, vers: &str| {
+        fs::metadata(
+            index
+                .dl_path
+                .join(name)
+                .join(format!("{}-{}.crate", name, vers)),
+        )
+        .unwrap()
+        .len()
+    };
+    let total = size("foo", "0.1.0") + size("foo", "0.1.1") + size("bar", "0.1.0");
+    assert_eq!(stats.crate_size, Some(total));
+    assert_eq!(stats.packages[0].name, "bar");
+    assert_eq!(stats.packages[0].crate_size, Some(size("bar", "0.1.0")));
+    let (stdout, _stderr) = cargo_index("stats")
+        .index(&index.index_path)
+        .arg("--crates")
+        .arg(crates)
+        .arg("--top=1")
+        .run();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[3].starts_with("size:     "), "{}", stdout);
+    assert_eq!(
+        lines[5].split_whitespace().collect::<Vec<_>>(),
+        ["PACKAGE", "VERSIONS", "YANKED", "SIZE"]
+    );
+    assert!(lines[6].starts_with("foo "), "{}", stdout);
+    assert_eq!(lines.len(), 7);
+
+    fs::remove_file(index.dl_path.join("bar/bar-0.1.0.crate")).unwrap();
+    cargo_index("stats")
+        .index(&index.index_path)
+        .arg("--crates")
+        .arg(crates)
+        .with_status(1)
+        .with_stderr_contains("Could not find crate file:")
+        .run();
+}
+
+#[test]
+fn test_validate_dl() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let config = index.index_path.join("config.json");
+    fs::write(
+        &config,
+        r#"{"dl": "https://example.com/{crate}/{vers}/download"}"#,
+    )
+    .unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .with_status(1)
+        .run();
+    assert_eq!(
+        stdout,
+        "Unknown marker `{vers}` in `dl` URL `https://example.com/{crate}/{vers}/download` \
+         in config.json, supported markers are: {crate}, {version}, {prefix}, {lowerprefix}, {sha256-checksum}\n\
+         The `dl` URL `https://example.com/{crate}/{vers}/download` in config.json is missing \
+         the `{version}` marker, all versions of a package would download from the same location.\n"
+    );
+
+    // A `file:` URL that doesn't match where the `.crate` files are.
+    fs::write(
+        &config,
+        format!(
+            "{{\"dl\": \"{}\"}}",
+            index.dl_pattern_url.replace("/{crate}/", "/")
+        ),
+    )
+    .unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--crates")
+        .arg(&index.dl_pattern_path)
+        .with_status(1)
+        .run();
+    assert!(
+        stdout.contains("in config.json does not match the `.crate` layout, package `foo:0.1.0`"),
+        "{}",
+        stdout
+    );
+    validate(&index, false);
+}
+
+#[test]
+fn test_validate_cksums() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("bar", "0.1.0");
+    index.add_package("baz", "0.1.0");
+    let foo = fs::read_to_string(index.index_path.join("3/f/foo")).unwrap();
+    let foo_pkg: IndexPackage = serde_json::from_str(&foo).unwrap();
+    let bar_path = index.index_path.join("3/b/bar");
+    let bar = fs::read_to_string(&bar_path).unwrap();
+    let bar_pkg: IndexPackage = serde_json::from_str(&bar).unwrap();
+    fs::write(&bar_path, bar.replace(&bar_pkg.cksum, &foo_pkg.cksum)).unwrap();
+    let baz_path = index.index_path.join("3/b/baz");
+    let baz = fs::read_to_string(&baz_path).unwrap();
+    let baz_pkg: IndexPackage = serde_json::from_str(&baz).unwrap();
+    fs::write(
+        &baz_path,
+        baz.replace(&baz_pkg.cksum, &baz_pkg.cksum.to_uppercase()),
+    )
+    .unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .with_status(1)
+        .with_stderr("Error: Found at least one error in the index.")
+        .run();
+    assert_eq!(
+        stdout,
+        format!(
+            "Package `baz:0.1.0` has an invalid checksum `{}`, expected 64 lowercase hex characters.\n\
+             Checksum `{}` is shared by multiple entries: bar:0.1.0, foo:0.1.0\n",
+            baz_pkg.cksum.to_uppercase(),
+            foo_pkg.cksum
+        )
+    );
+}
+
+#[test]
+fn test_verify_crate() {
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    let krate = foo_pkg.join("target/package/foo-0.1.0.crate");
+    let (stdout, _stderr) = cargo_index("verify-crate").arg(&krate).run();
+    assert_eq!(
+        stdout,
+        format!("`{}` is a valid .crate file.\n", krate.display())
+    );
+
+    cargo_index("verify-crate")
+        .arg(&krate)
+        .arg("--max-size=10")
+        .with_status(1)
+        .with_stderr_contains("which is larger than the maximum of 10 bytes.")
+        .run();
+
+    let renamed = foo_pkg.join("target/package/bar-0.1.0.crate");
+    fs::copy(&krate, &renamed).unwrap();
+    cargo_index("verify-crate")
+        .arg(&renamed)
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: Expected .crate file to contain entries rooted in `bar-0.1.0` directory, \
+             found `foo-0.1.0/",
+        )
+        .run();
+}
+
+#[test]
+fn test_add_crate_malicious() {
+    let index = init_index();
+    let dir = tempfile::tempdir().unwrap();
+    let krate = dir.path().join("evil-0.1.0.crate");
+    let gz = flate2::write::GzEncoder::new(
+        fs::File::create(&krate).unwrap(),
+        flate2::Compression::default(),
+    );
+    let mut tar = tar::Builder::new(gz);
+    let manifest = b"[package]\nname = \"evil\"\nversion = \"0.1.0\"\n";
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, "evil-0.1.0/Cargo.toml", &manifest[..])
+        .unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    tar.append_link(&mut header, "evil-0.1.0/src", "../../etc")
+        .unwrap();
+    tar.into_inner().unwrap().finish().unwrap();
+
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .arg("--crate")
+        .arg(&krate)
+        .with_status(1)
+        .with_stderr(
+            "Error: Link `evil-0.1.0/src` points outside of the `evil-0.1.0` directory to `../../etc`.",
+        )
+        .run();
+    cargo_index("verify-crate")
+        .arg(&krate)
+        .with_status(1)
+        .with_stderr_contains("Error: Link `evil-0.1.0/src` points outside")
+        .run();
+}
+
+#[test]
+fn test_fetch_deps() {
+    let index = init_index();
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    foo_pkg.index_add(&index);
+    let (stdout, _stderr) = cargo_index("fetch-deps")
+        .index(&index.index_path)
+        .arg("--dl-dir")
+        .arg(&index.dl_pattern_path)
+        .run();
+    assert_eq!(
+        stdout,
+        "Fetched bar:0.1.0\nFetched 1 crates, 0 already present.\n"
+    );
+    assert!(index.dl_path.join("bar/bar-0.1.0.crate").exists());
+
+    let (stdout, _stderr) = cargo_index("fetch-deps")
+        .index(&index.index_path)
+        .arg("--dl-dir")
+        .arg(&index.dl_pattern_path)
+        .arg("-p")
+        .arg("foo")
+        .run();
+    assert_eq!(stdout, "Fetched 0 crates, 1 already present.\n");
+}
+
+#[test]
+fn test_migrate_from() {
+    let index = init_index();
+    let old_index = IndexBuilder::new().name("old").build();
+    CargoConfig::new().alt(&old_index).build();
+    old_index.add_package("bar", "0.1.0");
+    old_index.add_package("bar", "0.2.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    foo_pkg.index_add(&old_index);
+    cargo_index("yank")
+        .index(&old_index.index_path)
+        .arg("-p=bar")
+        .arg("--version=0.2.0")
+        .run();
+
+    let (stdout, _stderr) = cargo_index("migrate-from")
+        .index(&index.index_path)
+        .arg(&old_index.index_url)
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .run();
+    assert_eq!(
+        stdout,
+        "Migrated bar:0.1.0\n\
+         Migrated bar:0.2.0\n\
+         Migrated foo:0.1.0\n\
+         Migrated 3 entries, 0 already in the index.\n"
+    );
+    for path in ["3/b/bar", "3/f/foo"] {
+        assert_eq!(
+            fs::read_to_string(index.index_path.join(path)).unwrap(),
+            fs::read_to_string(old_index.index_path.join(path)).unwrap()
+        );
+    }
+    assert!(index.dl_path.join("bar/bar-0.2.0.crate").exists());
+    assert!(index.dl_path.join("foo/foo-0.1.0.crate").exists());
+    validate(&index, true);
+
+    old_index.add_package("bar", "0.3.0");
+    let commits = index.commit_count();
+    let (stdout, _stderr) = cargo_index("migrate-from")
+        .index(&index.index_path)
+        .arg(&old_index.index_url)
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .run();
+    assert_eq!(
+        stdout,
+        "Migrated bar:0.3.0\nMigrated 1 entries, 3 already in the index.\n"
+    );
+    assert_eq!(index.commit_count(), commits + 1);
+
+    cargo_index("migrate-from")
+        .index(&index.index_path)
+        .arg("sparse+https://example.invalid/index/")
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .with_status(1)
+        .with_stderr_contains("Failed to download")
+        .run();
+}
+
+#[test]
+fn test_watch() {
+    let index = init_index();
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    alt_index.add_package("foo", "0.1.0");
+    let incoming = index.index_path.parent().unwrap().join("incoming");
+    fs::create_dir_all(&incoming).unwrap();
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    // `bar` depends on `foo`, and is tried first since the files are
+    // sorted, so it is only added on the second pass.
+    let bar_pkg = package("bar", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "bar"
+            version = "0.1.0"
+            [dependencies]
+            foo = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build();
+    bar_pkg.cargo_package();
+    for pkg in [&foo_pkg, &bar_pkg] {
+        let name = pkg.path().file_name().unwrap().to_str().unwrap();
+        fs::copy(
+            pkg.join(format!("target/package/{}.crate", name)),
+            incoming.join(format!("{}.crate", name)),
+        )
+        .unwrap();
+    }
+    fs::write(incoming.join("broken-0.1.0.crate"), "not a crate").unwrap();
+    fs::write(incoming.join(".partial.crate"), "still uploading").unwrap();
+
+    // `bar` refers to `foo` in the same registry when added with the URL
+    // of the alt index.
+    let (stdout, stderr) = cargo_index("watch")
+        .index(&index.index_path)
+        .index_url(&alt_index.index_url)
+        .arg("--dir")
+        .arg(&incoming)
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .arg("--once")
+        .run();
+    assert_eq!(
+        stdout,
+        format!(
+            "Added foo:0.1.0 from `{0}`.\nAdded bar:0.1.0 from `{1}`.\n",
+            incoming.join("processed/foo-0.1.0.crate").display(),
+            incoming.join("processed/bar-0.1.0.crate").display(),
+        )
+    );
+    assert!(stderr.contains(&format!(
+        "Failed to add `{}`:",
+        incoming.join("failed/broken-0.1.0.crate").display()
+    )));
+    assert!(incoming.join("failed/broken-0.1.0.crate.error").exists());
+    assert!(incoming.join(".partial.crate").exists());
+    assert!(index.dl_path.join("bar/bar-0.1.0.crate").exists());
+    validate(&index, true);
+
+    let (stdout, _stderr) = cargo_index("watch")
+        .index(&index.index_path)
+        .index_url(&alt_index.index_url)
+        .arg("--dir")
+        .arg(&incoming)
+        .arg("--once")
+        .run();
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn test_serve() {
+    let index = init_index();
+    let server = Server::start(&index, &["--token=secret"]);
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    let krate = fs::read(foo_pkg.join("target/package/foo-0.1.0.crate")).unwrap();
+    let metadata = br#"{"name":"foo","vers":"0.1.0"}"#;
+    let mut body = Vec::new();
+    body.extend((metadata.len() as u32).to_le_bytes());
+    body.extend(metadata);
+    body.extend((krate.len() as u32).to_le_bytes());
+    body.extend(&krate);
+
+    let (status, response) = server.request("PUT", "/api/v1/crates/new", None, &body);
+    assert_eq!(status, 403);
+    assert_eq!(
+        String::from_utf8(response).unwrap(),
+        r#"{"errors":[{"detail":"A valid registry token is required."}]}"#
+    );
+    assert!(!index.index_path.join("3/f/foo").exists());
+    let (status, response) = server.request("PUT", "/api/v1/crates/new", Some("secret"), &body);
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&response));
+    matches(&fs::read_to_string(index.index_path.join("3/f/foo")).unwrap(),
+        "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[],\"features\":{},\"cksum\":\"<CKSUM>\",\"yanked\":false,\"links\":null}\n");
+    validate(&index, true);
+
+    let (status, response) = server.request("GET", "/api/v1/crates/foo/0.1.0/download", None, b"");
+    assert_eq!(status, 200);
+    assert_eq!(response, krate);
+    let (status, _) = server.request("GET", "/api/v1/crates/foo/0.2.0/download", None, b"");
+    assert_eq!(status, 404);
+    // Names that could build a path outside of the index are not found.
+    for path in [
+        "/api/v1/crates/..ab/0.1.0/download",
+        "/api/v1/crates/%2E%2E%2E%2E/0.1.0/download",
+        "/api/v1/crates/..%2Fab/0.1.0/download",
+        "/api/v1/crates/foo%2F0.1.0/download",
+    ] {
+        let (status, _) = server.request("GET", path, None, b"");
+        assert_eq!(status, 404, "{}", path);
+    }
+    let (status, _) = server.request(
+        "DELETE",
+        "/api/v1/crates/..ab/0.1.0/yank",
+        Some("secret"),
+        b"",
+    );
+    assert_eq!(status, 404);
+
+    let (status, response) = server.request("GET", "/api/v1/crates?q=fo&per_page=5", None, b"");
+    assert_eq!(status, 200);
+    assert_eq!(
+        String::from_utf8(response).unwrap(),
+        r#"{"crates":[{"description":null,"max_version":"0.1.0","name":"foo"}],"meta":{"total":1}}"#
+    );
+
+    let (status, _) = server.request(
+        "DELETE",
+        "/api/v1/crates/foo/0.1.0/yank",
+        Some("secret"),
+        b"",
+    );
+    assert_eq!(status, 200);
+    assert!(fs::read_to_string(index.index_path.join("3/f/foo"))
+        .unwrap()
+        .contains("\"yanked\":true"));
+    let (status, response) = server.request(
+        "DELETE",
+        "/api/v1/crates/foo/0.1.0/yank",
+        Some("secret"),
+        b"",
+    );
+    assert_eq!(status, 400);
+    assert_eq!(
+        String::from_utf8(response).unwrap(),
+        r#"{"errors":[{"detail":"`foo:0.1.0` is already yanked!"}]}"#
+    );
+    let (status, _) = server.request(
+        "PUT",
+        "/api/v1/crates/foo/0.1.0/unyank",
+        Some("secret"),
+        b"",
+    );
+    assert_eq!(status, 200);
+    assert!(fs::read_to_string(index.index_path.join("3/f/foo"))
+        .unwrap()
+        .contains("\"yanked\":false"));
+    let (status, _) = server.request(
+        "PUT",
+        "/api/v1/crates/foo/0.1.0/unyank",
+        Some("secreT"),
+        b"",
+    );
+    assert_eq!(status, 403);
+
+    // Oversized requests are rejected without reading the rest.
+    let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+    let (status, response) = server.send(long_line.as_bytes());
+    assert_eq!(status, 400);
+    assert_eq!(
+        String::from_utf8(response).unwrap(),
+        r#"{"errors":[{"detail":"Request line is longer than 8192 bytes."}]}"#
+    );
+    let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(101));
+    let (status, response) = server.send(many_headers.as_bytes());
+    assert_eq!(status, 400);
+    assert_eq!(
+        String::from_utf8(response).unwrap(),
+        r#"{"errors":[{"detail":"Request has more than 100 headers."}]}"#
+    );
+}
+
+#[test]
+fn test_serve_slow_client() {
+    let index = init_index();
+    let server = Server::start(&index, &[]);
+    // A client that has not finished its request doesn't hold up others.
+    let mut slow = server.connect();
+    slow.write_all(b"GET /api/v1/crates HTTP/1.1\r\n").unwrap();
+    let start = std::time::Instant::now();
+    let (status, _) = server.request("GET", "/api/v1/crates?q=foo", None, b"");
+    assert_eq!(status, 200);
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    slow.write_all(b"\r\n").unwrap();
+    let mut response = String::new();
+    slow.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+}
+
+#[test]
+fn test_serve_requires_token() {
+    let index = init_index();
+    cargo_index("serve")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .arg("--addr=0.0.0.0:0")
+        .with_status(1)
+        .with_stderr_contains("Error: `--token` is required to listen on `0.0.0.0:")
+        .run();
+}
+
+#[test]
+fn test_config_snippet() {
+    let index = IndexBuilder::new().api(false).build();
+    let (stdout, _stderr) = cargo_index("config-snippet")
+        .index(&index.index_path)
+        .index_url("https://example.com/index")
+        .arg("--name=my-reg")
+        .arg("--replace-crates-io")
+        .run();
+    assert_eq!(
+        stdout,
+        "[registries.my-reg]\n\
+         index = \"https://example.com/index\"\n\
+         # This index has no `api` in config.json, so `cargo publish` will not work.\n\
+         \n\
+         [source.crates-io]\n\
+         replace-with = \"my-reg\"\n\
+         \n\
+         [source.my-reg]\n\
+         registry = \"https://example.com/index\"\n"
+    );
+    cargo_index("config-snippet")
+        .index(&index.index_path)
+        .index_url("https://example.com/index")
+        .arg("--name=my reg")
+        .with_status(1)
+        .with_stderr_contains("registry name")
+        .run();
+}
+
+#[test]
+fn test_setup() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let cargo_home = tmp_dir.path().join("cargo-home");
+    let dl_dir = tmp_dir.path().join("dl");
+    let dl_url = format!(
+        "{}/{{crate}}/{{crate}}-{{version}}.crate",
+        url::Url::from_file_path(&dl_dir).unwrap()
+    );
+    let input = [
+        "",                    // Index path.
+        "example.com/{crate}", // Broken dl.
+        "",                    // No api.
+        &dl_url,               // dl.
+        "",                    // No api.
+        "",                    // Default index URL.
+        "myreg",               // Registry name.
+        "y",                   // Update the Cargo config.
+        "y",                   // Publish a test crate.
+        dl_dir.join("{crate}").to_str().unwrap(),
+    ]
+    .join("\n");
+    let (stdout, _stderr) = cargo_index("setup")
+        .cwd(tmp_dir.path())
+        .env("CARGO_HOME", &cargo_home)
+        .stdin(input)
+        .run();
+    assert!(stdout.contains("is not a valid absolute URL"), "{}", stdout);
+    assert!(
+        stdout.contains("myreg-hello:0.1.0 successfully added!"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.ends_with("Setup complete.\n"), "{}", stdout);
+    let index_path = tmp_dir.path().join("index");
+    let config = fs::read_to_string(cargo_home.join("config.toml")).unwrap();
+    assert_eq!(
+        config,
+        format!(
+            "[registries.myreg]\nindex = \"{}\"\n\
+             # This index has no `api` in config.json, so `cargo publish` will not work.\n",
+            url::Url::from_file_path(fs::canonicalize(&index_path).unwrap()).unwrap()
+        )
+    );
+    assert!(index_path.join("my/re/myreg-hello").exists());
+    assert!(dl_dir.join("myreg-hello/myreg-hello-0.1.0.crate").exists());
+}
+
+#[test]
+fn test_check() {
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.index_add(&index);
+    let (stdout, _) = cargo_index("check")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .run();
+    assert_eq!(stdout, "foo:0.1.0 is up to date.\n");
+    cargo_index("add")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .arg("--check")
+        .run();
+
+    // Changing the source changes the checksum.
+    fs::write(foo_pkg.join("src/lib.rs"), "pub fn f() {}").unwrap();
+    cargo_index("add")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .arg("--check")
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: Checksum for package `foo` version `0.1.0` does not match the index.",
+        )
+        .run();
+
+    // A new version is not in the index.
+    let foo_pkg = package("foo", "0.2.0").build();
+    cargo_index("check")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .with_status(1)
+        .with_stderr_contains("Error: Package `foo` version `0.2.0` is not in the index.")
+        .run();
+    assert_eq!(index.commit_count(), 2);
+}
+
+#[test]
+fn test_reproducible() {
+    let foo_pkg = package("foo", "0.1.0").build();
+    let (stdout, _) = cargo_index("metadata")
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--reproducible")
+        .run();
+    let reg_pkg: IndexPackage = serde_json::from_str(&stdout).unwrap();
+    cargo_index("metadata")
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--expect-cksum")
+        .arg(&reg_pkg.cksum)
+        .run();
+    cargo_index("metadata")
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--expect-cksum=1234")
+        .with_status(1)
+        .with_stderr_contains(format!("is {}, expected 1234.", reg_pkg.cksum))
+        .run();
+
+    // A build script that writes into the source directory.
+    let bar_pkg = package("bar", "0.1.0").build();
+    fs::write(
+        bar_pkg.join("build.rs"),
+        format!(
+            "fn main() {{\n\
+             let now = format!(\"{{:?}}\", std::time::SystemTime::now());\n\
+             std::fs::write({:?}, now).unwrap();\n\
+             }}",
+            bar_pkg.join("generated.txt")
+        ),
+    )
+    .unwrap();
+    cargo_index("metadata")
+        .index_url("https://example.com")
+        .manifest(bar_pkg.join("Cargo.toml"))
+        .arg("--reproducible")
+        .with_status(1)
+        .with_stderr_contains("Error: Packaging `bar:0.1.0` is not reproducible")
+        .run();
+}
+
+#[test]
+fn test_package_settings() {
+    let index = init_index();
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    alt_index.add_package("bar", "1.0.0");
+    let manifest = |settings: &str| {
+        format!(
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = {{ version = "1.0", registry = "myalt" }}
+            [package.metadata.index]
+            {}
+            "#,
+            settings
+        )
+    };
+    // `bar` is from the same registry as far as the index is concerned, but
+    // isn't in it.
+    let add = |pkg_root: &Path, args: &[&str]| {
+        let mut builder = cargo_index("add");
+        builder
+            .manifest(pkg_root.join("Cargo.toml"))
+            .index(&index.index_path)
+            .index_url(&alt_index.index_url)
+            .arg("--upload")
+            .arg(&index.dl_pattern_path);
+        for arg in args {
+            builder.arg(arg);
+        }
+        builder
+    };
+
+    let pkg = package("foo", "0.1.0")
+        .file("Cargo.toml", &manifest(""))
+        .build();
+    add(pkg.path(), &[])
+        .with_status(1)
+        .with_stderr_contains("Error: Package `foo` dependency `bar:^1.0` not found in index.")
+        .run();
+
+    let pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            &manifest(
+                r#"upload-subpath = "internal"
+                skip-dep-check = true
+                reviewers = ["Alice <alice@example.com>", "Bob"]"#,
+            ),
+        )
+        .build();
+    add(pkg.path(), &["--reviewed-by=Bob <bob@example.com>"])
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: Package `foo` requires review by `Alice <alice@example.com>`.\n\
+             Add a `Reviewed-by` trailer for each reviewer, such as with `--reviewed-by`.",
+        )
+        .run();
+    add(
+        pkg.path(),
+        &[
+            "--reviewed-by=Bob <bob@example.com>",
+            "--reviewed-by=alice@example.com",
+        ],
+    )
+    .run();
+    assert!(index.dl_path.join("foo/internal/foo-0.1.0.crate").exists());
+    let output = std::process::Command::new("git")
+        .args(["log", "-1", "--format=%B"])
+        .current_dir(&index.index_path)
+        .output()
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "Updating crate `foo#0.1.0`\n\
+         \n\
+         Reviewed-by: Bob <bob@example.com>\n\
+         Reviewed-by: alice@example.com\n\
+         Index-Setting: foo upload-subpath=internal\n\
+         Index-Setting: foo skip-dep-check\n\
+         Index-Setting: foo reviewers=Alice <alice@example.com>, Bob\n\n"
+    );
+
+    for (settings, error) in [
+        (
+            "upload-subpath = \"../elsewhere\"",
+            "must be a relative path that stays inside the upload directory",
+        ),
+        (
+            "skip-dep-checks = true",
+            "Invalid `[package.metadata.index]`",
+        ),
+    ] {
+        let pkg = package("foo", "0.2.0")
+            .file("Cargo.toml", &manifest(settings).replace("0.1.0", "0.2.0"))
+            .build();
+        add(pkg.path(), &[])
+            .with_status(1)
+            .with_stderr_contains(error)
+            .run();
+    }
+}
+
+#[test]
+fn test_advisories() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.2.0");
+    index.add_package("bar", "1.0.0");
+    let yank = |name: &str, version: &str, reason: Option<&str>| {
+        let mut builder = cargo_index("yank");
+        builder
+            .index(&index.index_path)
+            .arg(format!("-p={}", name))
+            .arg(format!("--version={}", version))
+            .arg("--commit-timestamp=1600000000");
+        if let Some(reason) = reason {
+            builder.arg(format!("--reason={}", reason));
+        }
+        builder.run();
+    };
+    yank("foo", "0.1.0", Some("Miscompiles on aarch64, see INC-42."));
+    yank("foo", "0.2.0", Some("Published by mistake."));
+    yank("bar", "1.0.0", None);
+    cargo_index("unyank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.2.0")
+        .run();
+
+    let (stdout, _stderr) = cargo_index("advisories")
+        .index(&index.index_path)
+        .arg("--format=json")
+        .run();
+    let docs: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let docs = docs.as_array().unwrap();
+    assert_eq!(docs.len(), 2);
+    assert_eq!(docs[0]["name"], "bar");
+    assert_eq!(docs[0]["reason"], serde_json::Value::Null);
+    assert_eq!(docs[1]["name"], "foo");
+    assert_eq!(docs[1]["version"], "0.1.0");
+    assert_eq!(docs[1]["yanked_at"], "2020-09-13T12:26:40Z");
+    assert_eq!(docs[1]["author"], "Index Admin");
+    assert_eq!(docs[1]["reason"], "Miscompiles on aarch64, see INC-42.");
+
+    let out_dir = index.index_path.parent().unwrap().join("osv");
+    let (stdout, _stderr) = cargo_index("advisories")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--id-prefix=ACME-YANK-")
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .run();
+    assert_eq!(
+        stdout,
+        format!("Wrote 1 advisories to `{}`.\n", out_dir.display())
+    );
+    let osv: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(out_dir.join("ACME-YANK-foo-0.1.0.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(osv["id"], "ACME-YANK-foo-0.1.0");
+    assert_eq!(osv["published"], "2020-09-13T12:26:40Z");
+    assert_eq!(osv["details"], "Miscompiles on aarch64, see INC-42.");
+    assert_eq!(
+        osv["affected"],
+        serde_json::json!([{
+            "package": {"ecosystem": "crates.io", "name": "foo"},
+            "versions": ["0.1.0"],
+        }])
+    );
+
+    cargo_index("advisories")
+        .index(&index.index_path)
+        .arg("--format=json")
+        .arg("--out-dir=osv")
+        .with_status(1)
+        .with_stderr("Error: --out-dir is only supported with the `osv` format.")
+        .run();
+}
+
+#[test]
+fn test_who_published() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.1.1");
+    let status = std::process::Command::new("git")
+        .args([
+            "-c",
+            "user.name=Committer",
+            "-c",
+            "user.email=committer@example.com",
+            "commit",
+            "--amend",
+            "--quiet",
+            "-m",
+            "Add foo 0.1.1\n\nSigned-off-by: Releaser <releaser@example.com>",
+        ])
+        .current_dir(&index.index_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let (stdout, _stderr) = cargo_index("who-published")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.1")
+        .run();
+    let re = regex::Regex::new(
+        "^Commit:    [0-9a-f]{40}\n\
+         Date:      [0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}Z\n\
+         Author:    Index Admin <admin@example.com>\n\
+         Committer: Committer <committer@example.com>\n\
+         Signed-off-by: Releaser <releaser@example.com>\n$",
+    )
+    .unwrap();
+    assert!(re.is_match(&stdout), "{}", stdout);
+
+    cargo_index("who-published")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.2.0")
+        .with_status(1)
+        .with_stderr("Error: Could not find when `foo:0.2.0` was added in the index history.")
+        .run();
+}
+
+#[test]
+fn test_update_config() {
+    let index = init_index();
+    let config = index.index_path.join("config.json");
+    let contents = fs::read_to_string(&config).unwrap();
+    fs::write(
+        &config,
+        contents.replace("\n}", ",\n  \"auth-required\": true\n}"),
+    )
+    .unwrap();
+    let commit = reg_index::CommitOptions::default();
+    let new_config = reg_index::update_config(&index.index_path, &commit, |config| {
+        config.dl = "https://example.com/dl/{crate}/{version}".parse().unwrap();
+        config.api = None;
+    })
+    .unwrap();
+    assert!(new_config.api.is_none());
+    assert_eq!(
+        fs::read_to_string(&config).unwrap(),
+        "{\n  \"dl\": \"https://example.com/dl/{crate}/{version}\",\n  \"auth-required\": true\n}"
+    );
+    assert_eq!(index.commit_count(), 2);
+
+    let mut loaded = reg_index::load_config(&index.index_path).unwrap();
+    assert!(loaded.auth_required);
+    loaded.api = Some("https://example.com/".parse().unwrap());
+    reg_index::save_config(&index.index_path, &loaded, &commit).unwrap();
+    assert_eq!(
+        fs::read_to_string(&config).unwrap(),
+        "{\n  \"dl\": \"https://example.com/dl/{crate}/{version}\",\n  \
+         \"api\": \"https://example.com\",\n  \"auth-required\": true\n}"
+    );
+    assert_eq!(index.commit_count(), 3);
+    validate(&index, false);
+}
+
+#[test]
+fn test_config_command() {
+    let index = init_index();
+    let (stdout, _stderr) = cargo_index("config")
+        .arg("get")
+        .index(&index.index_path)
+        .arg("dl")
+        .run();
+    assert_eq!(stdout, format!("{}\n", index.dl_pattern_url));
+    cargo_index("config")
+        .arg("get")
+        .index(&index.index_path)
+        .arg("auth-required")
+        .with_status(1)
+        .with_stderr("Error: `auth-required` is not set in config.json.")
+        .run();
+
+    cargo_index("config")
+        .arg("set")
+        .index(&index.index_path)
+        .arg("dl")
+        .arg("https://example.com/dl/{crate}/{vers}")
+        .with_status(1)
+        .with_stderr(
+            "Error: Unknown marker `{vers}` in `--dl` URL `https://example.com/dl/{crate}/{vers}`, \
+             supported markers are: {crate}, {version}, {prefix}, {lowerprefix}, {sha256-checksum}\n\
+             Use `--force` to change it anyway.",
+        )
+        .run();
+    cargo_index("config")
+        .arg("set")
+        .index(&index.index_path)
+        .arg("dl")
+        .arg("https://example.com/dl/{crate}/{version}")
+        .run();
+    cargo_index("config")
+        .arg("set")
+        .index(&index.index_path)
+        .arg("auth-required")
+        .arg("true")
+        .run();
+    cargo_index("config")
+        .arg("unset")
+        .index(&index.index_path)
+        .arg("api")
+        .run();
+    let (stdout, _stderr) = cargo_index("config")
+        .arg("get")
+        .index(&index.index_path)
+        .run();
+    assert_eq!(
+        stdout,
+        "{\n  \"dl\": \"https://example.com/dl/{crate}/{version}\",\n  \"auth-required\": true\n}\n"
+    );
+    assert_eq!(index.commit_count(), 4);
+}
+
+#[test]
+fn test_build_metadata() {
+    let index = init_index();
+    index.add_package("foo", "1.0.0+abc");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=1.0.0")
+        .run();
+    cargo_index("unyank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=1.0.0")
+        .arg("--build-metadata=require")
+        .with_status(1)
+        .with_stderr("Error: Version `1.0.0` for package `foo` not found.")
+        .run();
+    cargo_index("unyank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=1.0.0+abc")
+        .arg("--build-metadata=require")
+        .run();
+
+    let foo_pkg = package("foo", "1.0.0+def").build();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .with_status(1)
+        .with_stderr_contains("Error: Package `foo` version `1.0.0+def` is already in the index.")
+        .run();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--build-metadata=require")
+        .run();
+
+    let count = |args: &[&str]| -> usize {
+        let mut builder = cargo_index("list");
+        builder.index(&index.index_path).arg("-p=foo");
+        for arg in args {
+            builder.arg(arg);
+        }
+        builder.run().0.lines().count()
+    };
+    assert_eq!(count(&["--version==1.0.0+def"]), 2);
+    assert_eq!(
+        count(&["--version==1.0.0+def", "--build-metadata=require"]),
+        1
+    );
+
+    // A forced add only replaces the entry with the same build metadata.
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--force")
+        .run();
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .run();
+    let versions: Vec<_> = stdout
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<IndexPackage>(line)
+                .unwrap()
+                .vers
+                .to_string()
+        })
+        .collect();
+    assert_eq!(versions, ["1.0.0+abc", "1.0.0+def"]);
+}
+
+#[test]
+fn test_add_metadata_json() {
+    let index = init_index();
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    let krate = foo_pkg.join("target/package/foo-0.1.0.crate");
+    let (stdout, _stderr) = cargo_index("metadata")
+        .index_url(&index.index_url)
+        .arg("--crate")
+        .arg(&krate)
+        .run();
+    let json_path = foo_pkg.join("entry.json");
+    fs::write(&json_path, stdout.replace("\"cksum\":\"", "\"cksum\":\"0")).unwrap();
+    cargo_index("add")
+        .index(&index.index_path)
+        .arg("--metadata-json")
+        .arg(&json_path)
+        .arg("--crate")
+        .arg(&krate)
+        .with_status(1)
+        .with_stderr_contains("does not match the entry.")
+        .run();
+
+    fs::write(&json_path, &stdout).unwrap();
+    let (stdout2, _stderr) = cargo_index("add")
+        .index(&index.index_path)
+        .arg("--metadata-json")
+        .arg(&json_path)
+        .arg("--crate")
+        .arg(&krate)
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .run();
+    assert_eq!(stdout2, "foo:0.1.0 successfully added!\n");
+    assert_eq!(
+        fs::read_to_string(index.index_path.join("3/f/foo")).unwrap(),
+        stdout
+    );
+    assert_eq!(index.commit_count(), 2);
+    validate(&index, true);
+}
+
+#[test]
+fn test_show() {
+    let alt_index = IndexBuilder::new().name("alt").build();
+    CargoConfig::new().alt(&alt_index).build();
+    let index = init_index();
+    let _bar_pkg = alt_index.add_package("bar", "0.1.0");
+    let _baz_pkg = alt_index.add_package("baz", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            links = "foo"
+            build = "build.rs"
+            [features]
+            default = ["bar"]
+            extra = []
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt", optional = true }
+            [target.'cfg(unix)'.build-dependencies]
+            baz2 = { version = "0.1", registry = "myalt", package = "baz" }
+            "#,
+        )
+        .file("build.rs", "fn main() {}")
+        .build();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .run();
+    let (stdout, _stderr) = cargo_index("show")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    matches(
+        &stdout,
+        &format!(
+            "foo 0.1.0\n\
+             yanked: false\n\
+             cksum:  <CKSUM>\n\
+             links:  foo\n\
+             \n\
+             FEATURE  ENABLES\n\
+             bar      dep:bar\n\
+             default  bar\n\
+             extra\n\
+             \n\
+             DEPENDENCY      REQ   KIND    TARGET     REGISTRY\n\
+             bar (optional)  ^0.1  normal  -          {url}\n\
+             baz2 (baz)      ^0.1  build   cfg(unix)  {url}\n",
+            url = alt_index.index_url
+        ),
+    );
+    cargo_index("show")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.2.0")
+        .with_status(1)
+        .with_stderr("Error: Version `0.2.0` for package `foo` not found.")
+        .run();
+}
+
+#[test]
+fn test_git_identity() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("index");
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--git-name=Bot")
+        .arg("--git-email=bot@example.com")
+        .run();
+    let foo_pkg = package("foo", "0.1.0").build();
+    cargo_index("add")
+        .index(&path)
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--git-name=Bot")
+        .arg("--git-email=bot@example.com")
+        .run();
+    cargo_index("yank")
+        .index(&path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .arg("--git-email=bot@example.com")
+        .run();
+    let output = std::process::Command::new("git")
+        .args(["log", "--format=%an <%ae> %cn <%ce>"])
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "Index Admin <bot@example.com> Index Admin <bot@example.com>\n\
+         Bot <bot@example.com> Bot <bot@example.com>\n\
+         Bot <bot@example.com> Bot <bot@example.com>\n"
+    );
+}
+
+#[test]
+fn test_commit_timestamp() {
+    // The same inputs with a fixed time produce the same commits.
+    let foo_pkg = package("foo", "0.1.0").build();
+    foo_pkg.cargo_package();
+    let crate_path = foo_pkg.join("target/package/foo-0.1.0.crate");
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let heads: Vec<String> = ["index1", "index2"]
+        .iter()
+        .map(|name| {
+            let path = tmp_dir.path().join(name);
+            cargo_index("init")
+                .index(&path)
+                .arg("--dl=https://example.com/{crate}/{version}")
+                .arg("--commit-timestamp=1700000000")
+                .run();
+            cargo_index("add")
+                .index(&path)
+                .arg("--crate")
+                .arg(&crate_path)
+                .index_url("https://example.com")
+                .env("SOURCE_DATE_EPOCH", "1700000100")
+                .run();
+            let output = std::process::Command::new("git")
+                .args(["log", "--format=%H %at %ct"])
+                .current_dir(&path)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        })
+        .collect();
+    assert_eq!(heads[0], heads[1]);
+    let times: Vec<&str> = heads[0]
+        .lines()
+        .map(|line| line.split_once(' ').unwrap().1)
+        .collect();
+    assert_eq!(times, ["1700000100 1700000100", "1700000000 1700000000"]);
+
+    cargo_index("init")
+        .index(tmp_dir.path().join("index3"))
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .env("SOURCE_DATE_EPOCH", "yesterday")
+        .with_status(1)
+        .with_stderr_contains("Invalid `SOURCE_DATE_EPOCH` value `yesterday`.")
+        .run();
+}
+
+#[test]
+fn test_line_endings() {
+    let index = init_index();
+    assert_eq!(
+        fs::read_to_string(index.index_path.join(".gitattributes")).unwrap(),
+        "* text eol=lf\n"
+    );
+    index.add_package("foo", "0.1.0");
+    index.add_package("bar", "0.1.0");
+    validate(&index, false);
+
+    let foo_path = index.index_path.join("3/f/foo");
+    let contents = fs::read_to_string(&foo_path).unwrap();
+    fs::write(&foo_path, contents.replace('\n', "\r\n")).unwrap();
+    let bar_path = index.index_path.join("3/b/bar");
+    let contents = fs::read_to_string(&bar_path).unwrap();
+    fs::write(&bar_path, contents.trim_end()).unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .with_status(1)
+        .with_stderr("Error: Found at least one error in the index.")
+        .run();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            format!("File `{}` does not end with a newline.", bar_path.display()),
+            format!(
+                "File `{}` has CRLF line endings, index files must use LF.",
+                foo_path.display()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_add_changed_since() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    let ws = package("ws", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [workspace]
+            members = ["a", "b", "c", "private"]
+            "#,
+        )
+        .file(
+            "a/Cargo.toml",
+            r#"
+            [package]
+            name = "a"
+            version = "0.1.0"
+
+            [dependencies]
+            b = { path = "../b", version = "0.1", registry = "myalt" }
+            "#,
+        )
+        .file("a/src/lib.rs", "")
+        .file(
+            "b/Cargo.toml",
+            r#"
+            [package]
+            name = "b"
+            version = "0.1.0"
+            "#,
+        )
+        .file("b/src/lib.rs", "")
+        .file(
+            "c/Cargo.toml",
+            r#"
+            [package]
+            name = "c"
+            version = "0.1.0"
+            "#,
+        )
+        .file("c/src/lib.rs", "")
+        .file(
+            "private/Cargo.toml",
+            r#"
+            [package]
+            name = "private"
+            version = "0.1.0"
+            publish = false
+            "#,
+        )
+        .file("private/src/lib.rs", "")
+        .file(".gitignore", "target\nCargo.lock\n")
+        .build();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(ws.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "initial"]);
+    git(&["tag", "base"]);
+
+    let add = |since: &str| {
+        let mut builder = cargo_index("add");
+        builder
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .manifest(ws.join("Cargo.toml"))
+            .arg("--changed-since")
+            .arg(since)
+            .arg("--upload")
+            .arg(&index.dl_pattern_path);
+        builder
+    };
+    let (stdout, _stderr) = add("base").run();
+    assert_eq!(stdout, "No packages changed since `base`.\n");
+
+    fs::write(ws.join("a/src/lib.rs"), "pub fn a() {}").unwrap();
+    fs::write(ws.join("b/src/lib.rs"), "pub fn b() {}").unwrap();
+    fs::write(ws.join("private/src/lib.rs"), "pub fn p() {}").unwrap();
+    git(&["commit", "-q", "-a", "-m", "update"]);
+    let (stdout, _stderr) = add("base").run();
+    assert_eq!(
+        stdout,
+        "b:0.1.0 successfully added!\n\
+         a:0.1.0 successfully added!\n"
+    );
+    assert_eq!(index.commit_count(), 3);
+    validate(&index, true);
+
+    add("nope")
+        .with_status(1)
+        .with_stderr_contains("Error: Could not find git revision `nope`.")
+        .run();
+}
+
+#[test]
+fn test_add_workspace() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    let ws = package("ws", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [workspace]
+            members = ["a", "b", "c", "private"]
+            "#,
+        )
+        .file(
+            "a/Cargo.toml",
+            r#"
+            [package]
+            name = "a"
+            version = "0.1.0"
+
+            [dependencies]
+            b = { path = "../b", version = "0.1", registry = "myalt" }
+            "#,
+        )
+        .file("a/src/lib.rs", "")
+        .file(
+            "b/Cargo.toml",
+            r#"
+            [package]
+            name = "b"
+            version = "0.1.0"
+            "#,
+        )
+        .file("b/src/lib.rs", "")
+        .file(
+            "c/Cargo.toml",
+            r#"
+            [package]
+            name = "c"
+            version = "0.1.0"
+            "#,
+        )
+        .file("c/src/lib.rs", "")
+        .file(
+            "private/Cargo.toml",
+            r#"
+            [package]
+            name = "private"
+            version = "0.1.0"
+            publish = false
+            "#,
+        )
+        .file("private/src/lib.rs", "")
+        .build();
+    let (stdout, _stderr) = cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(ws.join("Cargo.toml"))
+        .arg("--workspace")
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .run();
+    assert_eq!(
+        stdout,
+        "b:0.1.0 successfully added!\n\
+         c:0.1.0 successfully added!\n\
+         a:0.1.0 successfully added!\n"
+    );
+    assert!(!index.index_path.join("pr/iv/private").exists());
+    validate(&index, true);
+}
+
+#[test]
+fn test_require_newer() {
+    let index = init_index();
+    index.add_package("foo", "0.2.0");
+    let add = |version: &str| {
+        let pkg = package("foo", version).build();
+        let mut builder = cargo_index("add");
+        builder
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .manifest(pkg.join("Cargo.toml"))
+            .arg("--require-newer");
+        builder
+    };
+    add("0.1.0")
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: Package `foo` version `0.1.0` is not newer than version `0.2.0` in the index.",
+        )
+        .run();
+    add("0.2.0-alpha.1")
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: Package `foo` version `0.2.0-alpha.1` is not newer than version `0.2.0` in the index.",
+        )
+        .run();
+    add("0.3.0-alpha.1").run();
+    add("0.3.0").run();
+    // Without the flag, older versions can still be added.
+    package("foo", "0.1.0").build().index_add(&index);
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--include-prerelease")
+        .run();
+    let versions: Vec<String> = stdout
+        .lines()
+        .map(|line| {
+            let pkg: IndexPackage = serde_json::from_str(line).unwrap();
+            pkg.vers.to_string()
+        })
+        .collect();
+    assert_eq!(versions, ["0.2.0", "0.3.0-alpha.1", "0.3.0", "0.1.0"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_semver_checks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    // A stand-in for cargo-semver-checks that logs how it was called.
+    let bin = support::root().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let log = support::root().join("semver-checks.log");
+    let stub = bin.join("cargo-semver-checks");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\n\
+             if [ \"$2\" = \"--version\" ]; then exit 0; fi\n\
+             test -f \"$6/Cargo.toml\" || exit 2\n\
+             echo \"$2 $3 $4 $5\" >> {}\n\
+             exit ${{SEMVER_STATUS:-0}}\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let add = |name: &str, version: &str| {
+        let pkg = package(name, version).build();
+        let mut builder = cargo_index("add");
+        builder
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .manifest(pkg.join("Cargo.toml"))
+            .arg("--semver-checks")
+            .env("PATH", &path);
+        builder
+    };
+
+    // No previous version to compare with.
+    add("bar", "0.1.0").run();
+    assert!(!log.exists());
+
+    add("foo", "0.2.0")
+        .arg("--upload")
+        .arg(&index.dl_pattern_path)
+        .run();
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "check-release --package foo --baseline-root\n"
+    );
+
+    add("foo", "0.2.1")
+        .env("SEMVER_STATUS", "1")
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: `cargo semver-checks` failed comparing `foo:0.2.1` with version `0.2.0`.",
+        )
+        .run();
+}
+
+#[test]
+fn test_snapshot() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let foo_path = index.index_path.join("3/f/foo");
+    let foo_entry = fs::read_to_string(&foo_path).unwrap();
+    let snapshot = |cmd: &str, name: Option<&str>| {
+        let mut builder = cargo_index("snapshot");
+        builder.arg(cmd).index(&index.index_path);
+        if cmd == "restore" {
+            builder.arg("--yes");
+        }
+        if let Some(name) = name {
+            builder.arg(name);
+        }
+        builder
+    };
+    let (stdout, _stderr) = snapshot("create", Some("before")).run();
+    assert!(stdout.starts_with("Snapshot `before` created at "));
+    snapshot("create", Some("before"))
+        .with_status(1)
+        .with_stderr("Error: Snapshot `before` already exists.")
+        .run();
+
+    index.add_package("bar", "0.1.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    assert_eq!(index.commit_count(), 4);
+
+    let (stdout, _stderr) = snapshot("restore", Some("before")).run();
+    assert_eq!(stdout, "Index restored to snapshot `before`.\n");
+    assert_eq!(index.commit_count(), 5);
+    assert_eq!(fs::read_to_string(&foo_path).unwrap(), foo_entry);
+    assert!(!index.index_path.join("3/b/bar").exists());
+    validate(&index, false);
+    let (stdout, _stderr) = snapshot("restore", Some("before")).run();
+    assert_eq!(stdout, "Index already matches snapshot `before`.\n");
+    assert_eq!(index.commit_count(), 5);
+
+    snapshot("restore", Some("missing"))
+        .with_status(1)
+        .with_stderr("Error: Snapshot `missing` not found.")
+        .run();
+    snapshot("create", Some("bad..name"))
+        .with_status(1)
+        .with_stderr("Error: Invalid snapshot name `bad..name`.")
+        .run();
+
+    snapshot("create", Some("after")).run();
+    let (stdout, _stderr) = snapshot("list", None).run();
+    let names: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(names, ["after", "before"]);
+}
+
+#[test]
+fn test_prune_yanked() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.2.0");
+    index.add_package("bar", "0.1.0");
+    let yank = |name: &str, version: &str, timestamp: Option<&str>| {
+        let mut builder = cargo_index("yank");
+        builder
+            .index(&index.index_path)
+            .arg("-p")
+            .arg(name)
+            .arg("--version")
+            .arg(version);
+        if let Some(timestamp) = timestamp {
+            builder.arg("--commit-timestamp").arg(timestamp);
+        }
+        builder.run();
+    };
+    yank("foo", "0.1.0", Some("1000000"));
+    yank("bar", "0.1.0", Some("1000000"));
+    yank("foo", "0.2.0", None);
+    let commits = index.commit_count();
+    let prune = || {
+        let mut builder = cargo_index("prune-yanked");
+        builder
+            .index(&index.index_path)
+            .arg("--older-than=30d")
+            .arg("--crates")
+            .arg(&index.dl_pattern_path);
+        builder
+    };
+
+    let (stdout, _stderr) = prune().arg("--dry-run").run();
+    assert_eq!(
+        stdout,
+        "Would remove bar:0.1.0\n\
+         Would remove foo:0.1.0\n"
+    );
+    assert_eq!(index.commit_count(), commits);
+    assert!(index.index_path.join("3/b/bar").exists());
+
+    prune()
+        .stdin("n\n")
+        .with_status(1)
+        .with_stderr("Error: Aborted.")
+        .run();
+    prune()
+        .with_status(1)
+        .with_stderr(
+            "Error: Unexpected end of input.\n\
+             Use `--yes` to skip confirmation.",
+        )
+        .run();
+    assert_eq!(index.commit_count(), commits);
+
+    let (stdout, _stderr) = prune().stdin("y\n").run();
+    assert_eq!(
+        stdout,
+        "This will remove:\n  \
+         bar:0.1.0\n  \
+         foo:0.1.0\n\
+         Continue? [y/N]: \
+         Removed bar:0.1.0\n\
+         Removed foo:0.1.0\n"
+    );
+    assert_eq!(index.commit_count(), commits + 1);
+    assert!(!index.index_path.join("3/b/bar").exists());
+    assert!(!index.dl_path.join("bar/bar-0.1.0.crate").exists());
+    assert!(!index.dl_path.join("foo/foo-0.1.0.crate").exists());
+    assert!(index.dl_path.join("foo/foo-0.2.0.crate").exists());
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .run();
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains(r#""vers":"0.2.0""#));
+    validate(&index, true);
+
+    let (stdout, _stderr) = prune().run();
+    assert_eq!(stdout, "No yanked versions older than the cutoff.\n");
+
+    cargo_index("prune-yanked")
+        .index(&index.index_path)
+        .arg("--older-than=3 days")
+        .with_status(2)
+        .with_stderr_contains("unknown unit ` days`, expected s, m, h, d, or w")
+        .run();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_commit_options() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let foo_pkg = package("foo", "0.1.0").build();
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("index");
+    let remote = tmp_dir.path().join("remote.git");
+    let git = |dir: &Path, args: &[&str]| -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    git(tmp_dir.path(), &["init", "--quiet", "--bare", "remote.git"]);
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--branch=registry")
+        .run();
+    assert_eq!(git(&path, &["branch", "--show-current"]), "registry\n");
+
+    cargo_index("add")
+        .index(&path)
+        .index_url("https://example.com")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .arg("--commit-message=Release foo")
+        .arg("--git-committer-name=Release Bot")
+        .arg("--git-committer-email=bot@example.com")
+        .arg("--push")
+        .arg(&remote)
+        .run();
+    assert_eq!(
+        git(&path, &["log", "-1", "--format=%s|%an|%cn <%ce>"]),
+        "Release foo|Index Admin|Release Bot <bot@example.com>\n"
+    );
+    assert_eq!(
+        git(&remote, &["rev-parse", "registry"]),
+        git(&path, &["rev-parse", "HEAD"])
+    );
+
+    // A stand-in for gpg that produces a fake signature.
+    let gpg = tmp_dir.path().join("fake-gpg");
+    fs::write(
+        &gpg,
+        "#!/bin/sh\n\
+         cat > /dev/null\n\
+         echo '[GNUPG:] SIG_CREATED D 1 8 00 0 ABC' >&2\n\
+         printf -- '-----BEGIN PGP SIGNATURE-----\\nfake\\n-----END PGP SIGNATURE-----\\n'\n",
+    )
+    .unwrap();
+    fs::set_permissions(&gpg, fs::Permissions::from_mode(0o755)).unwrap();
+    git(&path, &["config", "gpg.program", gpg.to_str().unwrap()]);
+    // `--push` without a remote uses `origin`.
+    git(
+        &path,
+        &["remote", "add", "origin", remote.to_str().unwrap()],
+    );
+    cargo_index("yank")
+        .index(&path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .arg("--sign-commit")
+        .arg("--push")
+        .run();
+    assert_eq!(
+        git(&remote, &["rev-parse", "registry"]),
+        git(&path, &["rev-parse", "HEAD"])
+    );
+    let raw = git(&path, &["cat-file", "commit", "HEAD"]);
+    assert!(
+        raw.contains("gpgsig -----BEGIN PGP SIGNATURE-----"),
+        "{}",
+        raw
+    );
+    assert!(
+        raw.contains("-----END PGP SIGNATURE-----\n\nYanking crate `foo:0.1.0`"),
+        "{}",
+        raw
+    );
+
+    cargo_index("unyank")
+        .index(&path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .arg("--branch=master")
+        .run();
+    git(&path, &["checkout", "--quiet", "registry"]);
+    // Uncommitted changes to other files are kept when the commit fails.
+    let config_path = path.join("config.json");
+    let config = fs::read_to_string(&config_path).unwrap();
+    fs::write(&config_path, format!("{}\n", config)).unwrap();
+    cargo_index("unyank")
+        .index(&path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .arg("--branch=master")
+        .with_status(1)
+        .with_stderr("Error: Branch `master` does not point at the current commit of the index.")
+        .run();
+    assert_eq!(
+        git(&path, &["status", "--porcelain", "--untracked-files=no"]),
+        " M config.json\n"
+    );
+}
+
+#[test]
+fn test_list_count_and_names() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "1.0.0");
+    index.add_package("bar", "1.0.0");
+    let list = |args: &[&str]| -> String {
+        let mut builder = cargo_index("list");
+        builder.index(&index.index_path);
+        for arg in args {
+            builder.arg(arg);
+        }
+        builder.run().0
+    };
+    assert_eq!(list(&["--count"]), "3\n");
+    assert_eq!(list(&["--count", "-p=foo"]), "2\n");
+    assert_eq!(list(&["--count", "--version=^1"]), "2\n");
+    assert_eq!(list(&["--count", "--version=^2"]), "0\n");
+    assert_eq!(list(&["--names-only"]), "bar\nfoo\n");
+    assert_eq!(list(&["--names-only", "--version=^0.1"]), "foo\n");
+    assert_eq!(list(&["--names"]), "bar\nfoo\n");
+    cargo_index("list")
+        .index(&index.index_path)
+        .arg("--names-only")
+        .arg("--version=^2")
+        .with_status(1)
+        .with_stderr("Error: No packages matching version requirement `^2` found.")
+        .run();
+}
+
+#[test]
+fn test_list_yanked_and_latest() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "1.0.0");
+    index.add_package("foo", "2.0.0-beta.1");
+    index.add_package("bar", "1.0.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=1.0.0")
+        .run();
+    let list = |args: &[&str]| -> String {
+        let mut builder = cargo_index("list");
+        builder.index(&index.index_path).arg("--format=csv");
+        for arg in args {
+            builder.arg(arg);
+        }
+        let stdout = builder.run().0;
+        // Name, version, and yanked of each row.
+        stdout
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').take(3).collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    assert_eq!(list(&["--yanked"]), "foo 1.0.0 yes");
+    assert_eq!(list(&["--no-yanked"]), "bar 1.0.0 no\nfoo 0.1.0 no");
+    assert_eq!(list(&["--latest"]), "bar 1.0.0 no\nfoo 1.0.0 yes");
+    assert_eq!(
+        list(&["--latest", "--no-yanked"]),
+        "bar 1.0.0 no\nfoo 0.1.0 no"
+    );
+    assert_eq!(
+        list(&["--latest", "--include-prerelease", "-p=foo"]),
+        "foo 2.0.0-beta.1 no"
+    );
+    cargo_index("list")
+        .index(&index.index_path)
+        .arg("--yanked")
+        .arg("-p=bar")
+        .with_status(1)
+        .with_stderr("Error: No yanked entries found for `bar`.")
+        .run();
+}
+
+#[test]
+fn test_list_formats() {
+    let index = init_index();
+    CargoConfig::new().alt(&index).build();
+    index.add_package("bar", "0.1.0");
+    package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            [dependencies]
+            bar = { version = "0.1", registry = "myalt" }
+        "#,
+        )
+        .build()
+        .index_add(&index);
+    let list = |format: &str| -> String {
+        cargo_index("list")
+            .index(&index.index_path)
+            .arg(format!("--format={}", format))
+            .run()
+            .0
+    };
+    let json = list("json");
+    let entries: Vec<serde_json::Value> = json
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let array: serde_json::Value = serde_json::from_str(&list("json-array")).unwrap();
+    assert_eq!(array, serde_json::Value::from(entries.clone()));
+
+    let cksum = |i: usize| entries[i]["cksum"].as_str().unwrap().to_string();
+    assert_eq!(
+        list("csv"),
+        format!(
+            "name,vers,yanked,deps,cksum\n\
+             bar,0.1.0,no,0,{}\n\
+             foo,0.1.0,no,1,{}\n",
+            cksum(0),
+            cksum(1)
+        )
+    );
+
+    let toml: toml::Value = list("toml").parse().unwrap();
+    let toml_entries = toml["entry"].as_array().unwrap();
+    assert_eq!(toml_entries.len(), 2);
+    assert_eq!(toml_entries[1]["name"].as_str(), Some("foo"));
+    assert_eq!(toml_entries[1]["cksum"].as_str(), Some(cksum(1).as_str()));
+    assert_eq!(toml_entries[1]["deps"][0]["name"].as_str(), Some("bar"));
+    // Null fields such as `links` are left out.
+    assert!(toml_entries[1].get("links").is_none());
+}
+
+#[test]
+fn test_table_and_color_output() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "1.0.0");
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    let (json, _) = cargo_index("list").index(&index.index_path).run();
+    let cksums: Vec<String> = json
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["cksum"].as_str().unwrap()[..12].to_string()
+        })
+        .collect();
+    let (stdout, _) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("--format=table")
+        .run();
+    assert_eq!(
+        stdout,
+        format!(
+            "NAME  VERSION  YANKED  DEPS  CKSUM\n\
+             foo   0.1.0    yes     0     {}\n\
+             foo   1.0.0    no      0     {}\n",
+            cksums[0], cksums[1]
+        )
+    );
+    let (stdout, _) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("--format=table")
+        .arg("--color=always")
+        .run();
+    assert_eq!(
+        stdout,
+        format!(
+            "\x1b[1mNAME  VERSION  YANKED  DEPS  CKSUM\x1b[0m\n\
+             \x1b[31mfoo   0.1.0    yes     0     {}\x1b[0m\n\
+             foo   1.0.0    no      0     {}\n",
+            cksums[0], cksums[1]
+        )
+    );
+    // Output that is not a terminal stays JSON lines.
+    let (stdout, _) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("--color=always")
+        .run();
+    assert_eq!(stdout, json);
+
+    let (stdout, _) = cargo_index("stats")
+        .index(&index.index_path)
+        .arg("--by-author")
+        .arg("--color=always")
+        .run();
+    assert!(stdout.starts_with("\x1b[1mAUTHOR"));
+
+    let foo_path = index.index_path.join("3/f/foo");
+    let contents = fs::read_to_string(&foo_path).unwrap();
+    fs::write(&foo_path, contents.trim_end()).unwrap();
+    for (color, expected) in [
+        (
+            "never",
+            format!(
+                "File `{}` does not end with a newline.\n",
+                foo_path.display()
+            ),
+        ),
+        (
+            "always",
+            format!(
+                "\x1b[31mFile `{}` does not end with a newline.\x1b[0m\n",
+                foo_path.display()
+            ),
+        ),
+    ] {
+        let (stdout, _) = cargo_index("validate")
+            .index(&index.index_path)
+            .arg(format!("--color={}", color))
+            .with_status(1)
+            .with_stderr("Error: Found at least one error in the index.")
+            .run();
+        assert_eq!(stdout, expected);
+    }
+}
+
+#[test]
+fn test_init_with_readme() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("index");
+    let foo_pkg = package("foo", "0.1.0").build();
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--api=https://example.com/")
+        .arg("--with-readme=internal")
+        .index_url("https://example.com/git/index")
+        .run();
+    assert_eq!(
+        fs::read_to_string(path.join("README.md")).unwrap(),
+        "# `internal` registry index\n\
+         \n\
+         This repository is the index of the `internal` Cargo registry. It is managed\n\
+         with [cargo-index](https://github.com/ehuss/cargo-index), entries should not\n\
+         be edited by hand.\n\
+         \n\
+         - Download URL: `https://example.com/{crate}/{version}`\n\
+         - API URL: `https://example.com`\n\
+         \n\
+         ## Usage\n\
+         \n\
+         Add the registry to `.cargo/config.toml`:\n\
+         \n\
+         ```toml\n\
+         [registries.internal]\n\
+         index = \"https://example.com/git/index\"\n\
+         ```\n\
+         \n\
+         Then depend on packages from the registry in `Cargo.toml`:\n\
+         \n\
+         ```toml\n\
+         [dependencies]\n\
+         example = { version = \"1.0\", registry = \"internal\" }\n\
+         ```\n\
+         \n\
+         Publish packages with `cargo publish --registry internal`.\n"
+    );
+    assert_eq!(
+        fs::read_to_string(path.join(".git/description")).unwrap(),
+        "Index of the `internal` Cargo registry.\n"
+    );
+    // The README is not mistaken for a package.
+    cargo_index("add")
+        .index(&path)
+        .index_url("https://example.com/git/index")
+        .manifest(foo_pkg.join("Cargo.toml"))
+        .run();
+    let (stdout, _stderr) = cargo_index("names").index(&path).run();
+    assert_eq!(stdout, "foo\n");
+    cargo_index("validate").index(&path).run();
+
+    let path = tmp_dir.path().join("no-api");
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--with-readme=internal")
+        .run();
+    let readme = fs::read_to_string(path.join("README.md")).unwrap();
+    assert!(readme.contains("- API URL: none, `cargo publish` is not supported.\n"));
+    assert!(readme.contains("index = \"<URL of this repository>\"\n"));
+    assert!(!readme.contains("cargo publish --registry"));
+
+    // A sparse index gets the README without a git repository.
+    let path = tmp_dir.path().join("sparse");
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--with-readme=internal")
+        .arg("--protocol=sparse")
+        .run();
+    assert!(path.join("README.md").exists());
+    assert!(!path.join(".git").exists());
+    assert_eq!(
+        reg_index::index_protocol(&path),
+        reg_index::Protocol::Sparse
+    );
+}
+
+#[test]
+fn test_init_sparse_commit_options() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("index");
+    cargo_index("init")
+        .index(&path)
+        .arg("--dl=https://example.com/{crate}/{version}")
+        .arg("--protocol=sparse")
+        .arg("--git-name=Index Admin")
+        .with_status(1)
+        .with_stderr(
+            "Error: `--git-name` can't be used with `--protocol sparse`, \
+             which does not make commits.",
+        )
+        .run();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_add_force_confirm() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let foo_pkg = package("foo", "0.1.0")
+        .file(
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+
+            [features]
+            extra = []
+            "#,
+        )
+        .build();
+    let force = || {
+        let mut builder = cargo_index("add");
+        builder
+            .manifest(foo_pkg.join("Cargo.toml"))
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .arg("--force");
+        builder
+    };
+    let (stdout, _stderr) = force()
+        .stdin("n\n")
+        .with_status(1)
+        .with_stderr_contains("Error: Aborted.")
+        .run();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "This will replace the entry for `foo:0.1.0`:");
+    assert!(lines[1].starts_with("- ") && lines[1].contains(r#""features":{}"#));
+    assert!(lines[2].starts_with("+ ") && lines[2].contains(r#""features":{"extra":[]}"#));
+    assert_eq!(index.commit_count(), 2);
+
+    force().arg("--yes").run();
+    assert_eq!(index.commit_count(), 3);
+    // No prompt when the entry would not change.
+    let (stdout, _stderr) = force().run();
+    assert_eq!(stdout, "foo:0.1.0 successfully added!\n");
+
+    // The same applies to a `.crate` file.
+    let bar_pkg = package("foo", "0.1.0").build();
+    bar_pkg.cargo_package();
+    let force_crate = || {
+        let mut builder = cargo_index("add");
+        builder
+            .arg("--crate")
+            .arg(bar_pkg.join("target/package/foo-0.1.0.crate"))
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .arg("--force");
+        builder
+    };
+    let commits = index.commit_count();
+    let (stdout, _stderr) = force_crate()
+        .stdin("n\n")
+        .with_status(1)
+        .with_stderr_contains("Error: Aborted.")
+        .run();
+    assert_eq!(
+        stdout.lines().next().unwrap(),
+        "This will replace the entry for `foo:0.1.0`:"
+    );
+    assert_eq!(index.commit_count(), commits);
+    force_crate().stdin("y\n").run();
+    assert_eq!(index.commit_count(), commits + 1);
+    assert_eq!(
+        reg_index::list(&index.index_path, "foo", None).unwrap()[0].features,
+        Default::default()
+    );
+}
+
+#[test]
+fn test_selftest() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.2.0");
+    let selftest = |args: &[&str]| {
+        let mut builder = cargo_index("selftest");
+        builder.index(&index.index_path).index_url(&index.index_url);
+        for arg in args {
+            builder.arg(arg);
+        }
+        builder
+    };
+    let (stdout, _) = selftest(&["-p=foo"]).run();
+    assert_eq!(stdout, "foo:0.2.0 fetched successfully.\n");
+    let (stdout, _) = selftest(&["-p=foo", "--version=^0.1", "--build"]).run();
+    assert_eq!(stdout, "foo:0.1.0 fetched and built successfully.\n");
+
+    cargo_index("yank")
+        .index(&index.index_path)
+        .arg("-p=foo")
+        .arg("--version=0.1.0")
+        .run();
+    selftest(&["-p=foo", "--version=^0.1"])
+        .with_status(1)
+        .with_stderr("Error: Package `foo` has no versions matching `^0.1` that are not yanked.")
+        .run();
+
+    // A missing `.crate` file is only found by Cargo.
+    fs::remove_file(index.dl_path.join("foo/foo-0.2.0.crate")).unwrap();
+    selftest(&["-p=foo"])
+        .with_status(1)
+        .with_stderr_contains(format!(
+            "Error: `cargo fetch` failed for `foo:0.2.0` from the registry at `{}`.",
+            index.index_url
+        ))
+        .run();
+}
+
+#[test]
+fn test_schema_report() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let report = |args: &[&str]| {
+        let mut builder = cargo_index("schema-report");
+        builder.index(&index.index_path);
+        for arg in args {
+            builder.arg(arg);
+        }
+        builder.run().0
+    };
+    assert_eq!(
+        report(&[]),
+        "1 entries checked, minimum Cargo version: any\n"
+    );
+
+    // Entries written by newer tools, with fields `IndexPackage` doesn't know.
+    let cksum = "0".repeat(64);
+    let bar_path = index.index_path.join("3/b/bar");
+    fs::create_dir_all(bar_path.parent().unwrap()).unwrap();
+    fs::write(
+        &bar_path,
+        format!(
+            "{{\"name\":\"bar\",\"vers\":\"1.0.0\",\"deps\":[],\"features\":{{}},\
+             \"features2\":{{\"serde\":[\"dep:serde\"]}},\"cksum\":\"{cksum}\",\
+             \"yanked\":false,\"links\":null,\"v\":2,\"rust_version\":\"1.70\"}}\n\
+             {{\"name\":\"bar\",\"vers\":\"2.0.0\",\"deps\":[{{\"name\":\"tool\",\
+             \"req\":\"^1\",\"features\":[],\"optional\":false,\"default_features\":true,\
+             \"target\":null,\"kind\":\"build\",\"package\":null,\"artifact\":[\"bin\"]}}],\
+             \"features\":{{}},\"cksum\":\"{cksum}\",\"yanked\":false,\"links\":null}}\n"
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        report(&["-p=bar", "--version=^1"]),
+        "FEATURE              CARGO  ENTRIES\n\
+         features2            1.60   1\n\
+         namespaced features  1.60   1\n\
+         rust_version         any    1\n\
+         \n\
+         1 entries checked, minimum Cargo version: 1.60\n"
+    );
+    assert_eq!(
+        report(&[]),
+        "FEATURE                CARGO    ENTRIES\n\
+         features2              1.60     1\n\
+         namespaced features    1.60     1\n\
+         rust_version           any      1\n\
+         artifact dependencies  nightly  1\n\
+         \n\
+         3 entries checked, minimum Cargo version: nightly\n"
+    );
+    cargo_index("schema-report")
+        .index(&index.index_path)
+        .arg("-p=baz")
+        .with_status(1)
+        .with_stderr("Error: Package `baz` is not in the index.")
+        .run();
+}
+
+#[test]
+fn test_validate_case_collisions() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    validate(&index, false);
+
+    let foo_path = index.index_path.join("3/f/foo");
+    let line = fs::read_to_string(&foo_path).unwrap();
+    let entry = |name: &str, vers: &str, cksum: char| {
+        line.replace("\"foo\"", &format!("\"{}\"", name))
+            .replace("\"0.1.0\"", &format!("\"{}\"", vers))
+            .replace(
+                &line[line.find("\"cksum\":\"").unwrap() + 9..][..64],
+                &cksum.to_string().repeat(64),
+            )
+    };
+    fs::write(&foo_path, format!("{}{}", line, entry("FOO", "0.2.0", 'a'))).unwrap();
+    let upper_path = index.index_path.join("3/F/Foo");
+    fs::create_dir_all(upper_path.parent().unwrap()).unwrap();
+    fs::write(&upper_path, entry("Foo", "0.3.0", 'b')).unwrap();
+
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .with_status(1)
+        .with_stderr("Error: Found at least one error in the index.")
+        .run();
+    let lines: Vec<&str> = stdout.lines().collect();
+    for expected in [
+        format!(
+            "Package names `foo` and `FOO` in `{}` differ only in case, \
+             Cargo treats them as the same package.",
+            foo_path.display()
+        ),
+        format!(
+            "Files `{}` and `{}` would collide on a case-insensitive filesystem.",
+            upper_path.display(),
+            foo_path.display()
+        ),
+    ] {
+        assert!(lines.contains(&expected.as_str()), "{}", stdout);
+    }
+}
+
+#[test]
+fn test_add_checks_packaged_crate() {
+    let index = init_index();
+    let manifest = |deps: &str| {
+        format!(
+            "[package]\n\
+             name = \"foo\"\n\
+             version = \"0.1.0\"\n\
+             \n\
+             [dependencies]\n\
+             {}\n\
+             \n\
+             [workspace]\n\
+             members = [\"helper\"]\n",
+            deps
+        )
+    };
+    let pkg = package("foo", "0.1.0")
+        .file("Cargo.toml", &manifest(""))
+        .file(
+            "helper/Cargo.toml",
+            "[package]\nname = \"helper\"\nversion = \"0.1.0\"\n",
+        )
+        .file("helper/src/lib.rs", "")
+        .build();
+    pkg.cargo_package();
+    // The old `.crate` file is used when `cargo package` packages `helper`.
+    fs::write(
+        pkg.join("Cargo.toml"),
+        manifest("helper = { path = \"helper\", version = \"0.1.0\" }"),
+    )
+    .unwrap();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(pkg.join("Cargo.toml"))
+        .arg("--")
+        .arg("-p=helper")
+        .with_status(1)
+        .with_stderr_contains(format!(
+            "Error: The packaged `{}` does not match the manifest at `{}`, \
+             its dependencies are different.",
+            pkg.join("target/package/foo-0.1.0.crate").display(),
+            pkg.join("Cargo.toml").display()
+        ))
+        .run();
+    assert_eq!(index.commit_count(), 1);
+}
+
+#[test]
+fn test_indexignore() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    let files = [
+        ".github/workflows/ci.yml",
+        "policies/retention.txt",
+        "CONTRIBUTING.md",
+        "notes-2024.txt",
+    ];
+    for file in files {
+        let path = index.index_path.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not an entry\n").unwrap();
+    }
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .with_status(1)
+        .run();
+    assert_eq!(stdout.lines().count(), 4, "{}", stdout);
+
+    fs::write(
+        index.index_path.join(".indexignore"),
+        "# Files that are not entries.\n\
+         .github\n\
+         /policies/\n\
+         *.md\n\
+         notes-*.txt\n",
+    )
+    .unwrap();
+    validate(&index, false);
+    let (stdout, _stderr) = cargo_index("list")
+        .index(&index.index_path)
+        .arg("--names-only")
+        .run();
+    assert_eq!(stdout, "foo\n");
+    let (stdout, _stderr) = cargo_index("names").index(&index.index_path).run();
+    assert_eq!(stdout, "foo\n");
+}
+
+#[test]
+fn test_upload_lfs() {
+    let index = init_index();
+    let add = |pkg: &Path, upload: &str| {
+        let mut builder = cargo_index("add");
+        builder
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .manifest(pkg.join("Cargo.toml"))
+            .arg(format!("--upload={}", upload));
+        builder
+    };
+    let foo = package("foo", "0.1.0").build();
+    add(foo.path(), "lfs:{crate}")
+        .with_status(1)
+        .with_stderr_contains(
+            "Error: The `lfs:` upload path `{crate}` must start with a directory that has \
+             no markers and is longer than two characters, such as `lfs:crates/{crate}`.",
+        )
+        .run();
+    assert_eq!(index.commit_count(), 1);
+    add(foo.path(), "lfs:crates/{crate}").run();
+    let bar = package("bar", "0.1.0").build();
+    add(bar.path(), "lfs:crates/{crate}").run();
+
+    let (json, _) = cargo_index("list").index(&index.index_path).run();
+    let entry: serde_json::Value = serde_json::from_str(json.lines().nth(1).unwrap()).unwrap();
+    let cksum = entry["cksum"].as_str().unwrap();
+    let crate_path = index.index_path.join("crates/foo/foo-0.1.0.crate");
+    let contents = fs::read(&crate_path).unwrap();
+    let object = index
+        .index_path
+        .join(".git/lfs/objects")
+        .join(&cksum[..2])
+        .join(&cksum[2..4])
+        .join(cksum);
+    assert_eq!(fs::read(object).unwrap(), contents);
+    let output = std::process::Command::new("git")
+        .args(["show", "HEAD:crates/foo/foo-0.1.0.crate"])
+        .current_dir(&index.index_path)
+        .output()
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+            cksum,
+            contents.len()
+        )
+    );
+    assert_eq!(
+        fs::read_to_string(index.index_path.join(".gitattributes")).unwrap(),
+        "* text eol=lf\ncrates/** filter=lfs diff=lfs merge=lfs -text\n"
+    );
+    assert_eq!(
+        fs::read_to_string(index.index_path.join(".indexignore")).unwrap(),
+        "crates\n"
+    );
+    assert_eq!(index.commit_count(), 3);
+    validate(&index, false);
+}
+
+#[test]
+fn test_validate_report_order() {
+    let index = init_index();
+    let mut expected = Vec::new();
+    for i in 0..16 {
+        let name = format!("pkg{:02}", i);
+        index.add_package(&name, "0.1.0");
+        let path = index.index_path.join("pk").join(&name[2..4]).join(&name);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.trim_end()).unwrap();
+        expected.push(format!(
+            "File `{}` does not end with a newline.",
+            path.display()
+        ));
+    }
+    expected.sort();
+    for _ in 0..3 {
+        let problems = reg_index::validate_report(&index.index_path, None).unwrap();
+        assert_eq!(problems, expected);
+    }
+}
+
+#[test]
+fn test_validate_features() {
+    let index = init_index();
+    index.add_package("bar", "0.1.0");
+    index.add_package("foo", "0.1.0");
+    let foo_path = index.index_path.join("3/f/foo");
+    let mut entry: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&foo_path).unwrap()).unwrap();
+    let dep = |name: &str, optional: bool| {
+        serde_json::json!({
+            "name": name, "req": "^0.1", "features": [], "optional": optional,
+            "default_features": true, "target": null, "kind": "normal", "package": "bar",
+        })
+    };
+    entry["deps"] = serde_json::json!([dep("req", false), dep("opt", true), dep("hidden", true)]);
+    entry["features"] = serde_json::json!({
+        "ok": ["opt", "opt/x", "opt?/x", "req/x", "dep:hidden", "other"],
+        "other": [],
+        "bad-dep": ["dep:req", "dep:missing"],
+        "bad-weak": ["req?/x", "missing/x"],
+        "bad-name": ["missing", "req", "hidden"],
+        "bad name": [],
+    });
+    entry["features2"] = serde_json::json!({"other": []});
+    entry["v"] = serde_json::json!(2);
+    fs::write(&foo_path, format!("{}\n", entry)).unwrap();
+    let problems = reg_index::validate_report(&index.index_path, None).unwrap();
+    assert_eq!(
+        problems,
+        [
+            "Package `foo:0.1.0` has feature `other` in both `features` and `features2`.",
+            "Package `foo:0.1.0` feature `bad name` has an invalid character ` ` in its name.",
+            "Package `foo:0.1.0` feature `bad-dep` includes `dep:req`, \
+             but `req` is not an optional dependency.",
+            "Package `foo:0.1.0` feature `bad-dep` includes `dep:missing`, \
+             but `missing` is not a dependency.",
+            "Package `foo:0.1.0` feature `bad-name` includes `missing`, \
+             which is neither a dependency nor another feature.",
+            "Package `foo:0.1.0` feature `bad-name` includes `req`, \
+             but `req` is not an optional dependency.",
+            "Package `foo:0.1.0` feature `bad-name` includes `hidden`, \
+             but `hidden` is only available as `dep:hidden`.",
+            "Package `foo:0.1.0` feature `bad-weak` includes `req?/x` with a `?`, \
+             but `req` is not an optional dependency.",
+            "Package `foo:0.1.0` feature `bad-weak` includes `missing/x`, \
+             but `missing` is not a dependency.",
+        ]
+    );
+}
+
+#[test]
+fn test_name_conflicts() {
+    let index = init_index();
+    index.add_package("foo-bar", "0.1.0");
+    let add = |name: &str, vers: &str| {
+        let pkg = package(name, vers).build();
+        let mut builder = cargo_index("add");
+        builder
+            .index(&index.index_path)
+            .index_url(&index.index_url)
+            .manifest(pkg.join("Cargo.toml"));
+        builder
+    };
+    for name in ["foo_bar", "Foo-Bar"] {
+        add(name, "0.3.0")
+            .with_status(1)
+            .with_stderr_contains(format!(
+                "Error: Package name `{}` conflicts with `foo-bar` in the index, \
+                 they differ only by `-` and `_` or by case.",
+                name
+            ))
+            .run();
+    }
+    // New versions of the same name are fine.
+    package("foo-bar", "0.2.0").build().index_add(&index);
+    add("foo_bar", "0.1.0").arg("--allow-name-conflict").run();
+    let problems = reg_index::validate_report(&index.index_path, None).unwrap();
+    assert_eq!(
+        problems,
+        [
+            "Package names `foo-bar` and `foo_bar` differ only by `-` and `_`, \
+          Cargo treats them as the same package."
+        ]
+    );
+}
+
+#[test]
+fn test_validate_strict_yanked() {
+    let index = init_index();
+    index.add_package("bar", "0.1.0");
+    index.add_package("bar", "0.2.0");
+    index.add_package("foo", "0.1.0");
+    index.add_package("foo", "0.2.0");
+    reg_index::yank(&index.index_path, "bar", "0.2.0").unwrap();
+    let foo_path = index.index_path.join("3/f/foo");
+    let dep = |name: &str, req: &str| {
+        serde_json::json!({
+            "name": name, "req": req, "features": [], "optional": false,
+            "default_features": true, "target": null, "kind": "normal", "package": "bar",
+        })
+    };
+    let entries: Vec<String> = fs::read_to_string(&foo_path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["deps"] = serde_json::json!([dep("bar", "^0.2"), dep("old", "^0.1")]);
+            // Yanked entries are not checked.
+            entry["yanked"] = serde_json::json!(entry["vers"] == "0.2.0");
+            entry.to_string()
+        })
+        .collect();
+    fs::write(&foo_path, format!("{}\n", entries.join("\n"))).unwrap();
+    cargo_index("validate").index(&index.index_path).run();
+    let (stdout, _) = cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--strict-yanked")
+        .with_status(1)
+        .with_stderr("Error: Found at least one error in the index.")
+        .run();
+    assert_eq!(
+        stdout,
+        "Dependency `bar` requirement `^0.2` from package `foo:0.1.0` only matches yanked versions.\n"
+    );
+}
+
+#[test]
+fn test_validate_check_dl() {
+    let index = init_index();
+    package("foo", "0.1.0").build().index_add(&index);
+    package("foo", "0.2.0").build().index_add(&index);
+    cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--check-dl")
+        .run();
+    fs::remove_file(index.dl_path.join("foo/foo-0.1.0.crate")).unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--check-dl")
+        .with_status(1)
+        .run();
+    let prefix = format!(
+        "Package `foo:0.1.0` could not be downloaded from `{}`: ",
+        index
+            .dl_pattern_url
+            .replace("{crate}", "foo")
+            .replace("{version}", "0.1.0")
+    );
+    assert!(stdout.starts_with(&prefix), "{}", stdout);
+    assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+    // Without the flag, the server is not checked.
+    validate(&index, false);
+}
+
+#[test]
+fn test_check_worktree() {
+    let index = init_index();
+    index.add_package("foo", "0.1.0");
+    cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--check-worktree")
+        .run();
+    let foo_path = index.index_path.join("3/f/foo");
+    let entry = fs::read_to_string(&foo_path).unwrap();
+    fs::write(
+        &foo_path,
+        entry.replace("\"yanked\":false", "\"yanked\":true"),
+    )
+    .unwrap();
+    // An entry that was added, but the commit was undone.
+    index.add_package("bar", "0.1.0");
+    let status = std::process::Command::new("git")
+        .args(["reset", "HEAD~1"])
+        .current_dir(&index.index_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Files that are not entries are not checked.
+    fs::write(index.index_path.join("README.md"), "readme").unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--check-worktree")
+        .with_status(1)
+        .run();
+    assert_eq!(
+        stdout,
+        "File `3/b/bar` is not committed.\n\
+         File `3/f/foo` has uncommitted changes.\n"
+    );
+    let pkg = package("baz", "0.1.0").build();
+    cargo_index("add")
+        .index(&index.index_path)
+        .index_url(&index.index_url)
+        .manifest(pkg.join("Cargo.toml"))
+        .arg("--require-clean")
+        .with_status(1)
+        .with_stderr_contains("File `3/b/bar` is not committed.")
+        .run();
+    // Without the flags, the working tree is not checked.
+    validate(&index, false);
+}
+
+#[test]
+fn test_validate_similar_names() {
+    let index = init_index();
+    for name in ["serde", "sedre", "serde_json", "foo", "fop", "tokoi"] {
+        index.add_package(name, "0.1.0");
+    }
+    validate(&index, false);
+    let popular = index.index_path.parent().unwrap().join("popular.txt");
+    fs::write(&popular, "# crates.io\nserde\ntokio\n").unwrap();
+    let (stdout, _stderr) = cargo_index("validate")
+        .index(&index.index_path)
+        .arg("--similar-names")
+        .arg("--popular-names")
+        .arg(&popular)
+        .with_status(1)
+        .run();
+    assert_eq!(
+        stdout,
+        "Package names `sedre` and `serde` are similar, one may be a typo of the other.\n\
+         Package name `sedre` is similar to the popular package `serde`.\n\
+         Package name `tokoi` is similar to the popular package `tokio`.\n"
+    );
+}